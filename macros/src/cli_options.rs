@@ -9,7 +9,7 @@ pub fn cli_options(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let mut tokens = input.into_iter().peekable();
     let main_struct = match_struct(&mut tokens).expect("expected struct");
 
-    if let Some(_) = tokens.peek() {
+    if tokens.peek().is_some() {
         panic!("expected end of input");
     }
 
@@ -68,7 +68,7 @@ fn impl_code(main_struct: &MyOwnStruct) -> proc_macro2::TokenStream {
         .map(|s| update_arg(&s.strct))
         .collect();
 
-    update_args_implementations.push(update_arg(&main_struct));
+    update_args_implementations.push(update_arg(main_struct));
 
     let mut default_implementations: Vec<proc_macro2::TokenStream> = main_struct
         .structs
@@ -76,7 +76,7 @@ fn impl_code(main_struct: &MyOwnStruct) -> proc_macro2::TokenStream {
         .map(|s| default_impl(&s.strct))
         .collect();
 
-    default_implementations.push(default_impl(&main_struct));
+    default_implementations.push(default_impl(main_struct));
 
     quote! {
         #from_args_implementations
@@ -160,7 +160,7 @@ fn update_arg(strct: &MyOwnStruct) -> proc_macro2::TokenStream {
 
         match (&f.1.name, f.0.ty.is_bool()) {
             (Some(option_name), false) => {
-                let parse_arg = parse_arg(&f);
+                let parse_arg = parse_arg(f);
                 let extract_arg_value = extract_arg_value();
                 option_parsers.push(quote! {
                     let option_name = #option_name;
@@ -190,7 +190,7 @@ fn update_arg(strct: &MyOwnStruct) -> proc_macro2::TokenStream {
                     panic!("multiple fields without option name are not supported");
                 }
 
-                let parse_arg = parse_arg(&f);
+                let parse_arg = parse_arg(f);
                 default_option_parser = Some(quote! {
                     let arg_value = arg;
                     self.#field_name = #parse_arg;
@@ -363,7 +363,7 @@ fn match_struct(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnStruct> {
         panic!("expected struct name");
     };
 
-    let lifetime = if let Some(_) = peek_punct_value(tokens, '<') {
+    let lifetime = if peek_punct_value(tokens, '<').is_some() {
         let open_lifetime = match_punct_value(tokens, '<');
         let apostrophe = match_punct_value(tokens, '\'');
         let ident = match_ident(tokens).expect("should have lifetime");
@@ -403,7 +403,7 @@ fn match_struct(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnStruct> {
 
         maybe_match_punct_value(&mut group_tokens, ',');
 
-        if let None = group_tokens.peek() {
+        if group_tokens.peek().is_none() {
             break;
         }
     }
@@ -501,9 +501,7 @@ fn match_struct_group(tokens: &mut Peekable<IntoIter>) -> Group {
 }
 
 fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
-    if let None = peek_punct_value(tokens, '#') {
-        return None;
-    }
+    peek_punct_value(tokens, '#')?;
     match_punct_value(tokens, '#');
 
     let group =
@@ -521,7 +519,7 @@ fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
                 .into_iter()
                 .peekable();
 
-        if let Some(_) = attributes_tokens.next() {
+        if attributes_tokens.next().is_some() {
             panic!("unexpected attribute, should only have one attribute per #[..] block");
         };
 
@@ -540,13 +538,13 @@ fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
                 None => break,
             }
 
-            if let None = peek_punct_value(&mut group_tokens, ',') {
+            if peek_punct_value(&mut group_tokens, ',').is_none() {
                 break;
             }
             match_punct_value(&mut group_tokens, ',');
         }
 
-        if let Some(_) = group_tokens.peek() {
+        if group_tokens.peek().is_some() {
             panic!("unexpected token in attribute, all attributes should be separated by `,`");
         }
 
@@ -567,7 +565,7 @@ fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
                 .into_iter()
                 .peekable();
 
-        if let Some(_) = attributes_tokens.next() {
+        if attributes_tokens.next().is_some() {
             panic!("unexpected attribute, should only have one attribute per #[..] block");
         };
 
@@ -581,7 +579,7 @@ fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
             None => panic!("expected `name` field in `suboptions` attribute"),
         };
 
-        if let Some(_) = group_tokens.peek() {
+        if group_tokens.peek().is_some() {
             panic!("unexpected token in attribute, `suboptions` should only have `name` field");
         }
 
@@ -594,7 +592,7 @@ fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
                 .into_iter()
                 .peekable();
 
-        if let Some(_) = attributes_tokens.next() {
+        if attributes_tokens.next().is_some() {
             panic!("unexpected attribute, should only have one attribute per #[..] block");
         };
 
@@ -609,7 +607,7 @@ fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
                     "name" => variant.name = Some(field.value_as_string()),
                     "variant" => variant.variant = Some(field.value),
                     "default" => {
-                        if let Some(_) = attribute.default {
+                        if attribute.default.is_some() {
                             panic!("`default` variant can only be set once");
                         }
 
@@ -624,7 +622,7 @@ fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
                 None => break,
             }
 
-            if let None = peek_punct_value(&mut group_tokens, ',') {
+            if peek_punct_value(&mut group_tokens, ',').is_none() {
                 break;
             }
             match_punct_value(&mut group_tokens, ',');
@@ -632,7 +630,7 @@ fn match_attribute(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnAttribute> {
 
         attribute.variants.push(variant);
 
-        if let Some(_) = group_tokens.peek() {
+        if group_tokens.peek().is_some() {
             panic!("unexpected token in attribute, all attributes should be separated by `,`");
         }
 
@@ -697,9 +695,9 @@ fn match_value(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnValue> {
                 Some(MyOwnValue::Bool(false))
             } else if literal_string
                 .chars()
-                .nth(0)
+                .next()
                 .expect("default should have a value")
-                .is_digit(10)
+                .is_ascii_digit()
             {
                 Some(MyOwnValue::Number(literal))
             } else {
@@ -727,7 +725,7 @@ fn match_value(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnValue> {
                         .expect("expected a char literal"),
                 );
                 loop {
-                    if let None = maybe_match_punct_value(&mut slice_group, ',') {
+                    if maybe_match_punct_value(&mut slice_group, ',').is_none() {
                         break;
                     }
                     let literal = match_literal(&mut slice_group)
@@ -743,7 +741,7 @@ fn match_value(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnValue> {
                             .expect("expected a char literal"),
                     );
                 }
-                if let Some(_) = slice_group.next() {
+                if slice_group.next().is_some() {
                     panic!("unexpected token in `&[]` group");
                 }
 
@@ -754,7 +752,7 @@ fn match_value(tokens: &mut Peekable<IntoIter>) -> Option<MyOwnValue> {
         }
         TokenTree::Ident(ident) => {
             let mut path = vec![ident];
-            while let Some(_) = peek_punct_value(tokens, ':') {
+            while peek_punct_value(tokens, ':').is_some() {
                 match_punct_value(tokens, ':');
                 match_punct_value(tokens, ':');
                 let ident = match_ident(tokens).expect("expected ident after `::`");
@@ -802,7 +800,7 @@ fn peek_punct_value(tokens: &mut Peekable<IntoIter>, expected_punct: char) -> Op
             if punct.as_char() != expected_punct {
                 None
             } else {
-                Some(&punct)
+                Some(punct)
             }
         }
         _ => None,
@@ -36,7 +36,7 @@ fn cut(options: CutOptions, input: impl Read, mut output: impl Write) -> Result<
             options
                 .fields
                 .iter()
-                .map(|f| all_fields.get(f - 1).map(|s| *s).unwrap_or(""))
+                .map(|f| all_fields.get(f - 1).copied().unwrap_or(""))
                 .collect::<Vec<&str>>()
                 .join(options.delimiter.to_string().as_str())
         )?;
@@ -20,7 +20,7 @@ impl Display for Bits {
             } else {
                 string = format!("{}0", string);
             }
-            mask = mask >> 1;
+            mask >>= 1;
         }
         write!(fmt, "{}", string)
     }
@@ -53,7 +53,7 @@ impl Bits {
             }
         } else {
             let mut mask = 1 << 31;
-            mask = mask >> self.amount_of_bits;
+            mask >>= self.amount_of_bits;
             Self {
                 data: mask | self.data,
                 amount_of_bits: self.amount_of_bits + 1,
@@ -85,9 +85,8 @@ impl<T: Write> BitsWriter<T> {
                 self.flush()?;
             }
 
-            self.current_byte =
-                ((((bits.data << i) >> 24) as u8 >> self.shift) & self.mask) | self.current_byte;
-            self.mask = self.mask >> 1;
+            self.current_byte |= (((bits.data << i) >> 24) as u8 >> self.shift) & self.mask;
+            self.mask >>= 1;
             self.shift += 1;
         }
 
@@ -95,8 +94,8 @@ impl<T: Write> BitsWriter<T> {
     }
 
     pub fn flush(&mut self) -> Result<(), MyOwnError> {
-        let mut buf = [self.current_byte];
-        self.writer.write(&mut buf)?;
+        let buf = [self.current_byte];
+        self.writer.write_all(&buf)?;
         self.current_byte = 0b00000000;
         self.mask = 0b10000000;
         self.shift = 0;
@@ -105,11 +104,11 @@ impl<T: Write> BitsWriter<T> {
     }
 
     pub fn final_flush_with_offset(&mut self) -> Result<(), MyOwnError> {
-        let mut buf = [self.mask];
+        let buf = [self.mask];
         if self.mask != 0b10000000 {
             self.flush()?;
         }
-        self.writer.write(&mut buf)?;
+        self.writer.write_all(&buf)?;
 
         Ok(())
     }
@@ -172,7 +171,7 @@ impl<T: Read> BitsReader<T> {
         }
 
         let result = self.current_byte & self.mask == self.mask;
-        self.mask = self.mask >> 1;
+        self.mask >>= 1;
         result
     }
 
@@ -190,7 +189,7 @@ impl<T: Read> BitsReader<T> {
         }
 
         let result = self.current_byte & self.mask == self.mask;
-        self.mask = self.mask >> 1;
+        self.mask >>= 1;
         Some(result)
     }
 }
@@ -222,15 +221,15 @@ mod tests {
 
         let input: &[u8] = &output;
         let mut reader = BitsReader::new(input).unwrap();
-        assert_eq!(reader.read(), true);
-        assert_eq!(reader.read(), true);
-        assert_eq!(reader.read(), false);
-        assert_eq!(reader.read(), true);
-        assert_eq!(reader.read(), false);
-        assert_eq!(reader.read(), true);
-        assert_eq!(reader.read(), false);
-        assert_eq!(reader.read(), true);
-        assert_eq!(reader.read(), false);
-        assert_eq!(reader.read(), true);
+        assert!(reader.read());
+        assert!(reader.read());
+        assert!(!reader.read());
+        assert!(reader.read());
+        assert!(!reader.read());
+        assert!(reader.read());
+        assert!(!reader.read());
+        assert!(reader.read());
+        assert!(!reader.read());
+        assert!(reader.read());
     }
 }
@@ -14,13 +14,13 @@ pub fn decode(input: HuffmanInput, output: &mut impl Write) -> Result<(), MyOwnE
         let bit = reader.read_safe();
 
         match bit {
-            Some(true) => current_node = &current_node.right.as_ref().unwrap(),
-            Some(false) => current_node = &current_node.left.as_ref().unwrap(),
+            Some(true) => current_node = current_node.right.as_ref().unwrap(),
+            Some(false) => current_node = current_node.left.as_ref().unwrap(),
             None => break,
         }
 
         if current_node.byte.is_some() {
-            output.write(&[current_node.byte.unwrap()])?;
+            output.write_all(&[current_node.byte.unwrap()])?;
             current_node = &root;
         }
     }
@@ -6,7 +6,7 @@ use std::cmp::{Ordering, Reverse};
 use std::collections::BinaryHeap;
 use std::fmt::Display;
 use std::fmt::Formatter;
-use std::io::{Read, Write};
+use std::io::{BufReader, Read, Write};
 
 pub fn encode(input: HuffmanInput, output: &mut impl Write) -> Result<(), MyOwnError> {
     let frequencies = huffman_frequencies(&mut input.take())?;
@@ -66,9 +66,7 @@ fn huffman_tree(frequencies: [usize; 256]) -> HuffmanNode {
 fn huffman_prefix_code_table(root: HuffmanNode) -> HuffmanPrefixCodeTable {
     let mut prefix_code_table: [Bits; 256] = [Bits::empty(); 256];
     let mut nodes_to_process: Vec<(HuffmanNode, Bits)> = vec![(root, Bits::empty())];
-    while nodes_to_process.len() > 0 {
-        let node_with_prefix = nodes_to_process.pop().unwrap();
-
+    while let Some(node_with_prefix) = nodes_to_process.pop() {
         if let Some(byte) = node_with_prefix.0.byte {
             prefix_code_table[byte as usize] = node_with_prefix.1;
         } else {
@@ -93,9 +91,7 @@ fn write_huffman_file(
     let mut nodes_to_process: Vec<HuffmanNode> = vec![root];
     let mut writer = BitsWriter::new(output);
 
-    while nodes_to_process.len() > 0 {
-        let node = nodes_to_process.pop().unwrap();
-
+    while let Some(node) = nodes_to_process.pop() {
         if let Some(byte) = node.byte {
             writer.write(&Bits::empty().add(true))?;
             writer.write(&Bits::byte(byte))?;
@@ -110,7 +106,7 @@ fn write_huffman_file(
         }
     }
 
-    for byte in input.bytes() {
+    for byte in BufReader::new(input).bytes() {
         let byte = byte.unwrap();
         let prefix_code = table.get(&byte);
         writer.write(prefix_code)?;
@@ -16,7 +16,7 @@ pub fn huffman_cli(args: &[&str]) -> Result<(), MyOwnError> {
     huffman_cli_impl(args, stdin(), stdout())
 }
 
-fn huffman_cli_impl<'a>(
+fn huffman_cli_impl(
     args: &[&str],
     input: impl Read,
     mut output: impl Write,
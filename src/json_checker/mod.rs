@@ -79,7 +79,7 @@ fn json_checker_cli_impl(mut reader: impl Read) -> Result<JsonCheckerResult, MyO
         }
     }
 
-    Ok(if let None = parse(tokens) {
+    Ok(if parse(tokens).is_none() {
         JsonCheckerResult::Fail
     } else {
         JsonCheckerResult::Pass
@@ -153,11 +153,7 @@ fn parse_array_values(tokens: &mut RefCell<Peekable<IntoIter<Token>>>) -> Option
         return Some(());
     }
 
-    loop {
-        let Some(Token::Comma) = tokens.get_mut().peek() else {
-            break;
-        };
-
+    while let Some(Token::Comma) = tokens.get_mut().peek() {
         tokens.get_mut().next();
 
         if let Some(Token::String) = tokens.get_mut().peek() {
@@ -178,11 +174,7 @@ fn parse_properties(tokens: &mut RefCell<Peekable<IntoIter<Token>>>) -> Option<(
         return Some(());
     }
 
-    loop {
-        let Some(Token::Comma) = tokens.get_mut().peek() else {
-            break;
-        };
-
+    while let Some(Token::Comma) = tokens.get_mut().peek() {
         tokens.get_mut().next();
 
         if let Some(Token::String) = tokens.get_mut().peek() {
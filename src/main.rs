@@ -4,6 +4,8 @@ use std::env;
 mod cut;
 mod huffman;
 mod json_checker;
+// Signals, unix sockets and the poller are only available on unix systems.
+#[cfg(unix)]
 mod redis;
 mod tools;
 mod wc;
@@ -37,10 +39,18 @@ tools! {
         Cut,
         #[tool(
             command = "redis",
-            description = "myown redis [-p]",
+            description = "myown redis [config_file] [-p] [--dir] [--dbfilename] [--save] [--appendonly] [--replicaof] [--maxmemory] [--maxmemory-policy] [--databases] [--timeout] [--requirepass] [--bind] [--unixsocket]",
             function = redis::redis_cli
         )]
+        #[cfg(unix)]
         Redis,
+        #[tool(
+            command = "redis-benchmark",
+            description = "myown redis-benchmark [-p] [-c] [-n] [-P] [-r] [-t]",
+            function = redis::redis_benchmark_cli
+        )]
+        #[cfg(unix)]
+        RedisBenchmark,
         #[tool(
             command = "xxd",
             description = "myown xxd [-e] [-l] [-g] [-c] [-s] [-r] [file]",
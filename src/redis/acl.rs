@@ -0,0 +1,498 @@
+use super::commands::table::{self, Command};
+use super::glob::glob_match;
+use super::resp::Reply;
+use super::sha256::sha256_hex;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// https://redis.io/docs/latest/operate/oss_and_stack/management/security/acl/
+
+/// The user new clients are authenticated as, and the one AUTH with a password only
+/// authenticates as. Its password is set by `requirepass`.
+pub const DEFAULT_USER: &[u8] = b"default";
+
+const UNKNOWN_COMMAND: &str = "Unknown command or category name in ACL";
+const PATTERN_AFTER_ALL_KEYS: &str = "Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns";
+const NO_SUCH_PASSWORD: &str = "The password you are trying to remove from the user does not exist";
+const INVALID_HASH: &str = "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters";
+
+/// The users clients authenticate as, with the commands and keys they are allowed.
+pub struct Acl {
+    users: RwLock<BTreeMap<Vec<u8>, User>>,
+}
+
+/// What a user can run, and how it authenticates.
+#[derive(Clone)]
+struct User {
+    /// Disabled users cannot authenticate, the clients already authenticated stay so.
+    enabled: bool,
+    /// Any password authenticates as the user.
+    nopass: bool,
+    /// The SHA256 digests of the passwords, in hexadecimal.
+    passwords: BTreeSet<String>,
+    commands: HashSet<&'static str>,
+    /// The rules the commands result from, as described by ACL LIST: `+@all` or `-@all`
+    /// then the rules applied since, the latest one for each command or category.
+    command_rules: Vec<String>,
+    /// The glob-style patterns of the keys the user can access.
+    key_patterns: Vec<Vec<u8>>,
+}
+
+impl Acl {
+    /// The default user only, allowed everything and needing `requirepass` when it is not
+    /// empty.
+    pub fn new(requirepass: &str) -> Self {
+        let mut default = User::default();
+        for rule in ["on", "allkeys", "allcommands"] {
+            default
+                .apply(rule.as_bytes())
+                .expect("the rules of the default user are valid");
+        }
+        let acl = Self {
+            users: RwLock::new(BTreeMap::from([(DEFAULT_USER.to_vec(), default)])),
+        };
+        acl.set_requirepass(requirepass);
+        acl
+    }
+
+    /// Replaces the passwords of the default user, none needed when `password` is empty.
+    pub fn set_requirepass(&self, password: &str) {
+        let mut users = self.write();
+        let default = users
+            .get_mut(DEFAULT_USER)
+            .expect("the default user cannot be deleted");
+        default.passwords.clear();
+        default.nopass = password.is_empty();
+        if !password.is_empty() {
+            default.passwords.insert(sha256_hex(password.as_bytes()));
+        }
+    }
+
+    /// Whether new clients are authenticated as the default user right away, which they
+    /// are when it is enabled and needs no password.
+    pub fn authenticates_new_clients(&self) -> bool {
+        let users = self.read();
+        let default = &users[DEFAULT_USER];
+        default.enabled && default.nopass
+    }
+
+    /// Checks the password of an enabled user.
+    pub fn authenticate(&self, username: &[u8], password: &[u8]) -> Result<(), Reply> {
+        match self.read().get(username) {
+            Some(user)
+                if user.enabled
+                    && (user.nopass || user.passwords.contains(&sha256_hex(password))) =>
+            {
+                Ok(())
+            }
+            _ => Err(Reply::error(
+                "WRONGPASS invalid username-password pair or user is disabled.",
+            )),
+        }
+    }
+
+    /// AUTH [username] password, returning the user authenticated as.
+    pub fn auth(&self, arguments: &[Vec<u8>]) -> Result<Vec<u8>, Reply> {
+        let (username, password) = match arguments {
+            [_, password] => {
+                if self.read()[DEFAULT_USER].nopass {
+                    return Err(Reply::error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"));
+                }
+                (DEFAULT_USER, password)
+            }
+            [_, username, password] => (username.as_slice(), password),
+            _ => return Err(Reply::syntax_error()),
+        };
+        self.authenticate(username, password)?;
+        Ok(username.to_vec())
+    }
+
+    /// Checks that `username` can run a command on the keys it accesses. The commands
+    /// authenticating clients are allowed to anyone.
+    pub fn check(
+        &self,
+        username: &[u8],
+        command: &Command,
+        arguments: &[Vec<u8>],
+    ) -> Result<(), Reply> {
+        if command.has(table::NO_AUTH) {
+            return Ok(());
+        }
+
+        let users = self.read();
+        let Some(user) = users
+            .get(username)
+            .filter(|user| user.commands.contains(command.name))
+        else {
+            return Err(Reply::error(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                String::from_utf8_lossy(username),
+                command.name.to_lowercase()
+            )));
+        };
+        // the commands accessing the whole keyspace have no key to check
+        let keys = command.keys(arguments).unwrap_or_default();
+        if !keys.iter().all(|key| {
+            user.key_patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, key))
+        }) {
+            return Err(Reply::error("NOPERM No permissions to access a key"));
+        }
+
+        Ok(())
+    }
+
+    /// ACL SETUSER username [rule ...]: applies the rules to the user, created when it does
+    /// not exist. No rule is applied when one of them is invalid.
+    pub fn set_user(&self, username: &[u8], rules: &[Vec<u8>]) -> Result<(), Reply> {
+        if username.iter().any(|byte| *byte == b' ' || *byte == 0) {
+            return Err(Reply::error(
+                "ERR Usernames can't contain spaces or null characters",
+            ));
+        }
+
+        let mut users = self.write();
+        let mut user = users.get(username).cloned().unwrap_or_default();
+        for rule in rules {
+            user.apply(rule).map_err(|message| {
+                Reply::error(format!(
+                    "ERR Error in ACL SETUSER modifier '{}': {}",
+                    String::from_utf8_lossy(rule),
+                    message
+                ))
+            })?;
+        }
+        users.insert(username.to_vec(), user);
+        Ok(())
+    }
+
+    /// ACL GETUSER username: the flags, password digests, commands and key patterns of a
+    /// user.
+    pub fn get_user(&self, username: &[u8]) -> Reply {
+        let users = self.read();
+        let Some(user) = users.get(username) else {
+            return Reply::Nil;
+        };
+
+        let mut flags = vec![if user.enabled { "on" } else { "off" }];
+        if user.nopass {
+            flags.push("nopass");
+        }
+        let keys = user
+            .key_patterns
+            .iter()
+            .map(|pattern| [&b"~"[..], pattern].concat())
+            .collect::<Vec<_>>()
+            .join(&b' ');
+        Reply::Map(vec![
+            (Reply::bulk("flags"), Reply::bulk_array(flags)),
+            (
+                Reply::bulk("passwords"),
+                Reply::bulk_array(user.passwords.iter().cloned()),
+            ),
+            (
+                Reply::bulk("commands"),
+                Reply::bulk(user.command_rules.join(" ")),
+            ),
+            (Reply::bulk("keys"), Reply::bulk(keys)),
+        ])
+    }
+
+    /// ACL DELUSER username [username ...], returning the users deleted.
+    pub fn delete_users(&self, usernames: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, Reply> {
+        if usernames.iter().any(|username| username == DEFAULT_USER) {
+            return Err(Reply::error("ERR The 'default' user cannot be removed"));
+        }
+
+        let mut users = self.write();
+        Ok(usernames
+            .iter()
+            .filter(|username| users.remove(username.as_slice()).is_some())
+            .cloned()
+            .collect())
+    }
+
+    /// ACL LIST: every user described by the rules giving its permissions.
+    pub fn list(&self) -> Reply {
+        Reply::Array(
+            self.read()
+                .iter()
+                .map(|(username, user)| {
+                    let mut line = b"user ".to_vec();
+                    line.extend_from_slice(username);
+                    for rule in user.describe() {
+                        line.push(b' ');
+                        line.extend(rule);
+                    }
+                    Reply::Bulk(line)
+                })
+                .collect(),
+        )
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, BTreeMap<Vec<u8>, User>> {
+        self.users.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, BTreeMap<Vec<u8>, User>> {
+        self.users.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A new user is disabled and allowed nothing.
+impl Default for User {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            commands: HashSet::new(),
+            command_rules: vec!["-@all".to_string()],
+            key_patterns: Vec::new(),
+        }
+    }
+}
+
+impl User {
+    /// Applies a rule of ACL SETUSER.
+    fn apply(&mut self, rule: &[u8]) -> Result<(), &'static str> {
+        match rule.to_ascii_lowercase().as_slice() {
+            b"on" => self.enabled = true,
+            b"off" => self.enabled = false,
+            b"nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            b"resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            b"allkeys" => self.key_patterns = vec![b"*".to_vec()],
+            b"resetkeys" => self.key_patterns.clear(),
+            b"allcommands" => self.apply(b"+@all")?,
+            b"nocommands" => self.apply(b"-@all")?,
+            b"reset" => *self = Self::default(),
+            _ => match rule.split_first() {
+                Some((b'>', password)) => {
+                    self.nopass = false;
+                    self.passwords.insert(sha256_hex(password));
+                }
+                Some((b'<', password)) => {
+                    if !self.passwords.remove(&sha256_hex(password)) {
+                        return Err(NO_SUCH_PASSWORD);
+                    }
+                }
+                Some((b'#', hash)) => {
+                    self.nopass = false;
+                    self.passwords.insert(password_hash(hash)?);
+                }
+                Some((b'!', hash)) => {
+                    if !self.passwords.remove(&password_hash(hash)?) {
+                        return Err(NO_SUCH_PASSWORD);
+                    }
+                }
+                Some((b'~', pattern)) => {
+                    if self.key_patterns.iter().any(|existing| existing == b"*") {
+                        return Err(PATTERN_AFTER_ALL_KEYS);
+                    }
+                    if !self.key_patterns.iter().any(|existing| existing == pattern) {
+                        self.key_patterns.push(pattern.to_vec());
+                    }
+                }
+                Some((sign @ (b'+' | b'-'), name)) => self.allow(*sign == b'+', name)?,
+                _ => return Err("Syntax error"),
+            },
+        }
+        Ok(())
+    }
+
+    /// Allows or disallows a command, or every command of a category when `name` starts
+    /// with `@`.
+    fn allow(&mut self, allowed: bool, name: &[u8]) -> Result<(), &'static str> {
+        let commands = match name.strip_prefix(b"@") {
+            Some(category) => table::category(category)
+                .ok_or(UNKNOWN_COMMAND)?
+                .collect::<Vec<_>>(),
+            None => vec![table::lookup(name).ok_or(UNKNOWN_COMMAND)?],
+        };
+        for command in commands {
+            if allowed {
+                self.commands.insert(command.name);
+            } else {
+                self.commands.remove(command.name);
+            }
+        }
+
+        let name = String::from_utf8_lossy(name).to_lowercase();
+        let rule = format!("{}{}", if allowed { '+' } else { '-' }, name);
+        if name == "@all" {
+            self.command_rules = vec![rule];
+        } else {
+            self.command_rules.retain(|existing| existing[1..] != name);
+            self.command_rules.push(rule);
+        }
+        Ok(())
+    }
+
+    /// The rules giving the user its permissions.
+    fn describe(&self) -> Vec<Vec<u8>> {
+        let mut rules = vec![if self.enabled { "on" } else { "off" }.into()];
+        if self.nopass {
+            rules.push("nopass".into());
+        }
+        rules.extend(
+            self.passwords
+                .iter()
+                .map(|password| format!("#{}", password).into_bytes()),
+        );
+        rules.extend(
+            self.key_patterns
+                .iter()
+                .map(|pattern| [&b"~"[..], pattern].concat()),
+        );
+        rules.extend(
+            self.command_rules
+                .iter()
+                .map(|rule| rule.clone().into_bytes()),
+        );
+        rules
+    }
+}
+
+/// Checks a password given by its SHA256 digest to `#` or `!`.
+fn password_hash(hash: &[u8]) -> Result<String, &'static str> {
+    if hash.len() != 64
+        || !hash
+            .iter()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(byte))
+    {
+        return Err(INVALID_HASH);
+    }
+    Ok(String::from_utf8_lossy(hash).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+
+    fn check(acl: &Acl, username: &str, command: &[&str]) -> Result<(), Reply> {
+        let arguments = arguments(command);
+        acl.check(
+            username.as_bytes(),
+            table::lookup(&arguments[0]).unwrap(),
+            &arguments,
+        )
+    }
+
+    #[test]
+    fn default_user_and_requirepass() {
+        let acl = Acl::new("");
+        assert!(acl.authenticates_new_clients());
+        assert!(check(&acl, "default", &["FLUSHALL"]).is_ok());
+        assert!(acl
+            .auth(&arguments(&["AUTH", "default", "anything"]))
+            .is_ok());
+        assert!(acl.auth(&arguments(&["AUTH", "secret"])).is_err());
+
+        acl.set_requirepass("secret");
+        assert!(!acl.authenticates_new_clients());
+        assert_eq!(
+            acl.auth(&arguments(&["AUTH", "secret"])),
+            Ok(b"default".to_vec())
+        );
+        assert_eq!(
+            acl.auth(&arguments(&["AUTH", "default", "wrong"])),
+            Err(Reply::error(
+                "WRONGPASS invalid username-password pair or user is disabled."
+            ))
+        );
+        assert_eq!(
+            acl.list(),
+            Reply::bulk_array([format!(
+                "user default on #{} ~* +@all",
+                sha256_hex(b"secret")
+            )])
+        );
+    }
+
+    #[test]
+    fn commands_and_keys() {
+        let acl = Acl::new("");
+        let set_user = |rules: &[&str]| acl.set_user(b"alice", &arguments(rules));
+
+        set_user(&["on", ">pw", "~cache:*", "+@read", "-keys", "+set"]).unwrap();
+        assert!(acl.authenticate(b"alice", b"pw").is_ok());
+        assert!(check(&acl, "alice", &["GET", "cache:1"]).is_ok());
+        assert!(check(&acl, "alice", &["MSET", "cache:1", "v", "cache:2", "v"]).is_err());
+        assert!(check(&acl, "alice", &["SET", "cache:1", "v"]).is_ok());
+        assert_eq!(
+            check(&acl, "alice", &["GET", "other"]),
+            Err(Reply::error("NOPERM No permissions to access a key"))
+        );
+        assert_eq!(
+            check(&acl, "alice", &["KEYS", "*"]),
+            Err(Reply::error(
+                "NOPERM User alice has no permissions to run the 'keys' command"
+            ))
+        );
+        // the commands of the whole keyspace have no key
+        assert!(check(&acl, "alice", &["DBSIZE"]).is_ok());
+        assert!(check(&acl, "alice", &["AUTH", "pw"]).is_ok());
+
+        // a rule replaces the previous one for the same command
+        set_user(&["-set", "+keys", "+set"]).unwrap();
+        let Reply::Map(fields) = acl.get_user(b"alice") else {
+            panic!("expected a map");
+        };
+        assert_eq!(
+            fields,
+            vec![
+                (Reply::bulk("flags"), Reply::bulk_array(["on"])),
+                (
+                    Reply::bulk("passwords"),
+                    Reply::bulk_array([sha256_hex(b"pw")])
+                ),
+                (
+                    Reply::bulk("commands"),
+                    Reply::bulk("-@all +@read +keys +set")
+                ),
+                (Reply::bulk("keys"), Reply::bulk("~cache:*")),
+            ]
+        );
+
+        set_user(&["off", "reset"]).unwrap();
+        assert!(acl.authenticate(b"alice", b"pw").is_err());
+        assert!(check(&acl, "alice", &["GET", "cache:1"]).is_err());
+        assert_eq!(acl.get_user(b"bob"), Reply::Nil);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let acl = Acl::new("");
+        let set_user = |rules: &[&str]| acl.set_user(b"alice", &arguments(rules));
+
+        assert_eq!(
+            set_user(&["on", "+nope"]),
+            Err(Reply::error(
+                "ERR Error in ACL SETUSER modifier '+nope': Unknown command or category name in ACL"
+            ))
+        );
+        // no rule is applied
+        assert_eq!(acl.get_user(b"alice"), Reply::Nil);
+        assert!(set_user(&["-@nope"]).is_err());
+        assert!(set_user(&["#abc"]).is_err());
+        assert!(set_user(&["<missing"]).is_err());
+        assert!(set_user(&["allkeys", "~a"]).is_err());
+        assert!(set_user(&["maybe"]).is_err());
+        assert!(acl.set_user(b"a b", &[]).is_err());
+
+        set_user(&[]).unwrap();
+        assert_eq!(
+            acl.delete_users(&arguments(&["alice", "bob"])),
+            Ok(vec![b"alice".to_vec()])
+        );
+        assert!(acl.delete_users(&arguments(&["default"])).is_err());
+    }
+}
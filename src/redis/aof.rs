@@ -0,0 +1,540 @@
+use super::commands::unix_millis;
+use super::keyspace::{Entry, Value};
+use super::resp::{parse_command, Protocol, Reply, RespError};
+use super::stream::{Stream, StreamId};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Maximum number of elements written by a single command when rewriting a collection.
+const REWRITE_ITEMS_PER_COMMAND: usize = 64;
+
+/// When the append only file is flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppendFsync {
+    /// After every write command, before replying to the client.
+    Always,
+    /// At most once per second, losing up to a second of writes on a crash.
+    EverySec,
+    /// Whenever the operating system decides to.
+    No,
+}
+
+impl FromStr for AppendFsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::EverySec),
+            "no" => Ok(AppendFsync::No),
+            other => Err(format!("invalid appendfsync policy: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AofError {
+    Io(io::Error),
+    Protocol(RespError),
+    /// The file ends in the middle of a command, at the given offset.
+    Truncated(usize),
+}
+
+impl Display for AofError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AofError::Io(e) => write!(f, "AOF I/O error: {}", e),
+            AofError::Protocol(e) => {
+                write!(f, "Bad file format reading the append only file: {}", e)
+            }
+            AofError::Truncated(offset) => write!(
+                f,
+                "Unexpected end of file reading the append only file at offset {}, \
+                 use --aof-load-truncated to discard the last incomplete command",
+                offset
+            ),
+        }
+    }
+}
+
+impl Error for AofError {}
+
+impl From<io::Error> for AofError {
+    fn from(e: io::Error) -> Self {
+        AofError::Io(e)
+    }
+}
+
+/// Executes every command stored in the append only file at `path`, returning how many
+/// there were. A file ending with an incomplete command, as left by a crash in the middle
+/// of a write, is truncated to the last complete one when `load_truncated` is set.
+pub fn replay(
+    path: &Path,
+    load_truncated: bool,
+    mut execute: impl FnMut(&mut [Vec<u8>]),
+) -> Result<usize, AofError> {
+    let content = fs::read(path)?;
+
+    let mut processed = 0;
+    let mut commands = 0;
+    while let Some((mut arguments, used)) =
+        parse_command(&content[processed..]).map_err(AofError::Protocol)?
+    {
+        processed += used;
+        if !arguments.is_empty() {
+            execute(&mut arguments);
+            commands += 1;
+        }
+    }
+
+    if processed < content.len() {
+        if !load_truncated {
+            return Err(AofError::Truncated(processed));
+        }
+        eprintln!(
+            "Truncating the append only file from {} to {} bytes, the last command was incomplete",
+            content.len(),
+            processed
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(processed as u64)?;
+    }
+
+    Ok(commands)
+}
+
+struct AofState {
+    file: File,
+    /// Whether something was written since the last fsync.
+    unsynced: bool,
+    last_fsync: SystemTime,
+    /// Commands received while a rewrite is in progress, appended to the rewritten file.
+    rewrite_buffer: Option<Vec<u8>>,
+    /// The logical database the last command written applies to, `None` when a SELECT
+    /// must come before the next one.
+    selected_db: Option<usize>,
+}
+
+/// The append only file, logging every write command in RESP form.
+pub struct AppendOnlyFile {
+    path: PathBuf,
+    appendfsync: AppendFsync,
+    state: Mutex<AofState>,
+    rewrite_in_progress: AtomicBool,
+}
+
+impl AppendOnlyFile {
+    /// Opens the file at `path` for appending. When it does not exist yet, it is created
+    /// with the commands rebuilding `entries`, the dataset possibly loaded from a snapshot.
+    pub fn open<'a>(
+        path: PathBuf,
+        appendfsync: AppendFsync,
+        entries: impl Iterator<Item = (usize, &'a [u8], &'a Entry)>,
+        now: SystemTime,
+    ) -> io::Result<Self> {
+        match fs::metadata(&path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => write_rewrite(&path, entries)?,
+            Err(e) => return Err(e),
+        }
+
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self {
+            path,
+            appendfsync,
+            state: Mutex::new(AofState {
+                file,
+                unsynced: false,
+                last_fsync: now,
+                rewrite_buffer: None,
+                selected_db: None,
+            }),
+            rewrite_in_progress: AtomicBool::new(false),
+        })
+    }
+
+    /// Logs a command executed in the logical database `db`, it has to be called in the
+    /// same order the commands are executed.
+    pub fn append(&self, db: usize, arguments: &[Vec<u8>]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut command = Vec::new();
+        if state.selected_db != Some(db) {
+            Reply::bulk_array([b"SELECT".to_vec(), db.to_string().into_bytes()])
+                .encode(Protocol::Resp2, &mut command);
+            state.selected_db = Some(db);
+        }
+        Reply::bulk_array(arguments.iter().cloned()).encode(Protocol::Resp2, &mut command);
+
+        if let Some(rewrite_buffer) = &mut state.rewrite_buffer {
+            rewrite_buffer.extend_from_slice(&command);
+        }
+        state.file.write_all(&command)?;
+
+        if self.appendfsync == AppendFsync::Always {
+            state.file.sync_data()
+        } else {
+            state.unsynced = true;
+            Ok(())
+        }
+    }
+
+    /// Flushes the file to disk when the `everysec` policy requires it.
+    pub fn fsync_if_needed(&self, now: SystemTime) -> io::Result<()> {
+        if self.appendfsync != AppendFsync::EverySec {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let elapsed = now.duration_since(state.last_fsync).unwrap_or_default();
+        if state.unsynced && elapsed >= Duration::from_secs(1) {
+            state.file.sync_data()?;
+            state.unsynced = false;
+            state.last_fsync = now;
+        }
+        Ok(())
+    }
+
+    /// Flushes what was written to disk whatever the policy, before the server exits.
+    pub fn fsync(&self, now: SystemTime) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.unsynced {
+            state.file.sync_data()?;
+            state.unsynced = false;
+            state.last_fsync = now;
+        }
+        Ok(())
+    }
+
+    /// Starts buffering the commands to add to the rewritten file, returning false when
+    /// a rewrite is already in progress. The dataset must be copied while holding the
+    /// keyspace lock, so no command falls in between.
+    pub fn start_rewrite(&self) -> bool {
+        if self.rewrite_in_progress.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.rewrite_buffer = Some(Vec::new());
+        // the rewritten file may end in another database than the current one
+        state.selected_db = None;
+        true
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.rewrite_in_progress.load(Ordering::Acquire)
+    }
+
+    /// Writes the shortest sequence of commands rebuilding `entries`, followed by the
+    /// commands received since the rewrite started, and replaces the current file with it.
+    pub fn finish_rewrite<'a>(
+        &self,
+        entries: impl Iterator<Item = (usize, &'a [u8], &'a Entry)>,
+    ) -> io::Result<()> {
+        let mut temporary_path = self.path.clone().into_os_string();
+        temporary_path.push(format!(".temp-rewrite-{}", std::process::id()));
+        let temporary_path = PathBuf::from(temporary_path);
+
+        // most of the file is written without blocking the commands being appended
+        let result = write_rewrite(&temporary_path, entries).and_then(|_| {
+            let mut state = self.state.lock().unwrap();
+            let rewrite_buffer = state.rewrite_buffer.take().unwrap_or_default();
+
+            let mut file = OpenOptions::new().append(true).open(&temporary_path)?;
+            file.write_all(&rewrite_buffer)?;
+            file.sync_data()?;
+            fs::rename(&temporary_path, &self.path)?;
+
+            state.file = file;
+            state.unsynced = false;
+            Ok(())
+        });
+
+        if result.is_err() {
+            self.state.lock().unwrap().rewrite_buffer = None;
+            let _ = fs::remove_file(&temporary_path);
+        }
+        self.rewrite_in_progress.store(false, Ordering::Release);
+
+        result
+    }
+}
+
+fn write_rewrite<'a>(
+    path: &Path,
+    entries: impl Iterator<Item = (usize, &'a [u8], &'a Entry)>,
+) -> io::Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    let mut command = Vec::new();
+    let mut selected_db = None;
+
+    for (db, key, entry) in entries {
+        let select = (selected_db != Some(db))
+            .then(|| vec![b"SELECT".to_vec(), db.to_string().into_bytes()]);
+        selected_db = Some(db);
+        for arguments in select.into_iter().chain(rewrite_commands(key, entry)) {
+            command.clear();
+            Reply::bulk_array(arguments).encode(Protocol::Resp2, &mut command);
+            output.write_all(&command)?;
+        }
+    }
+
+    output.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+/// Commands recreating `entry` at `key` from scratch.
+fn rewrite_commands(key: &[u8], entry: &Entry) -> Vec<Vec<Vec<u8>>> {
+    let command = |name: &str, items: &[Vec<u8>]| {
+        let mut arguments = vec![name.as_bytes().to_vec(), key.to_vec()];
+        arguments.extend_from_slice(items);
+        arguments
+    };
+    let batched = |name: &str, items: Vec<Vec<u8>>, per_item: usize| {
+        items
+            .chunks(REWRITE_ITEMS_PER_COMMAND * per_item)
+            .map(|items| command(name, items))
+            .collect::<Vec<_>>()
+    };
+
+    let mut commands = match &entry.value {
+        Value::String(value) => vec![command("SET", std::slice::from_ref(value))],
+        Value::List(list) => batched("RPUSH", list.iter().cloned().collect(), 1),
+        Value::Set(set) => batched("SADD", set.iter().cloned().collect(), 1),
+        Value::Hash(hash) => batched(
+            "HSET",
+            hash.iter()
+                .flat_map(|(field, value)| [field.clone(), value.clone()])
+                .collect(),
+            2,
+        ),
+        Value::SortedSet(sorted_set) => batched(
+            "ZADD",
+            sorted_set
+                .iter()
+                .flat_map(|(member, score)| [format_score(score).into_bytes(), member.to_vec()])
+                .collect(),
+            2,
+        ),
+        Value::Stream(stream) => rewrite_stream(key, stream),
+    };
+
+    if let Some(expires_at) = entry.expires_at() {
+        commands.push(command(
+            "PEXPIREAT",
+            &[unix_millis(expires_at).to_string().into_bytes()],
+        ));
+    }
+
+    commands
+}
+
+/// Commands recreating a stream with its consumer groups: its entries, its last ID, then
+/// each group with its consumers and the entries pending for them.
+fn rewrite_stream(key: &[u8], stream: &Stream) -> Vec<Vec<Vec<u8>>> {
+    let command = |arguments: &[&[u8]]| {
+        arguments
+            .iter()
+            .map(|argument| argument.to_vec())
+            .collect::<Vec<_>>()
+    };
+    let last_id = stream.last_id().to_string();
+
+    let mut commands = stream
+        .iter()
+        .map(|(id, fields)| {
+            let mut arguments = command(&[b"XADD", key, id.to_string().as_bytes()]);
+            for (field, value) in fields {
+                arguments.extend([field.clone(), value.clone()]);
+            }
+            arguments
+        })
+        .collect::<Vec<_>>();
+    if stream.is_empty() {
+        // an entry trimmed right away creates the empty stream
+        let id = stream.last_id().max(StreamId::new(0, 1)).to_string();
+        commands.push(command(&[
+            b"XADD",
+            key,
+            b"MAXLEN",
+            b"0",
+            id.as_bytes(),
+            b"x",
+            b"y",
+        ]));
+    }
+    commands.push(command(&[b"XSETID", key, last_id.as_bytes()]));
+
+    for (name, group) in stream.groups() {
+        let last_delivered = group.last_delivered.to_string();
+        commands.push(command(&[
+            b"XGROUP",
+            b"CREATE",
+            key,
+            name,
+            last_delivered.as_bytes(),
+        ]));
+        for consumer in group.consumers.keys() {
+            commands.push(command(&[
+                b"XGROUP",
+                b"CREATECONSUMER",
+                key,
+                name,
+                consumer,
+            ]));
+        }
+        for (id, pending) in &group.pending {
+            commands.push(command(&[
+                b"XCLAIM",
+                key,
+                name,
+                &pending.consumer,
+                b"0",
+                id.to_string().as_bytes(),
+                b"TIME",
+                unix_millis(pending.delivered_at).to_string().as_bytes(),
+                b"RETRYCOUNT",
+                pending.deliveries.to_string().as_bytes(),
+                b"FORCE",
+                b"JUSTID",
+            ]));
+        }
+    }
+
+    commands
+}
+
+/// Formats a score so that parsing it back gives exactly the same float.
+fn format_score(score: f64) -> String {
+    if score.is_infinite() {
+        if score > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format!("{:?}", score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::keyspace::Keyspace;
+    use crate::redis::sorted_set::SortedSet;
+    use std::collections::VecDeque;
+    use std::time::UNIX_EPOCH;
+
+    fn temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("redis-aof-{}-{}.aof", name, std::process::id()))
+    }
+
+    fn replayed(path: &Path, load_truncated: bool) -> Result<Vec<Vec<Vec<u8>>>, AofError> {
+        let mut commands = Vec::new();
+        replay(path, load_truncated, |arguments| {
+            commands.push(arguments.to_vec())
+        })?;
+        Ok(commands)
+    }
+
+    #[test]
+    fn append_and_replay() {
+        let path = temporary_path("append");
+        let _ = fs::remove_file(&path);
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let aof = AppendOnlyFile::open(path.clone(), AppendFsync::Always, std::iter::empty(), now)
+            .unwrap();
+        aof.append(0, &[b"SET".to_vec(), b"k".to_vec(), b"a\r\nb".to_vec()])
+            .unwrap();
+        aof.append(0, &[b"LPUSH".to_vec(), b"l".to_vec(), b"x".to_vec()])
+            .unwrap();
+
+        assert_eq!(
+            replayed(&path, false).unwrap(),
+            vec![
+                vec![b"SELECT".to_vec(), b"0".to_vec()],
+                vec![b"SET".to_vec(), b"k".to_vec(), b"a\r\nb".to_vec()],
+                vec![b"LPUSH".to_vec(), b"l".to_vec(), b"x".to_vec()],
+            ]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_file() {
+        let path = temporary_path("truncated");
+        fs::write(&path, b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$1").unwrap();
+
+        assert!(matches!(
+            replayed(&path, false),
+            Err(AofError::Truncated(14))
+        ));
+        assert_eq!(replayed(&path, true).unwrap().len(), 1);
+        assert_eq!(fs::read(&path).unwrap(), b"*1\r\n$4\r\nPING\r\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rewrite_keeps_commands_received_meanwhile() {
+        let path = temporary_path("rewrite");
+        let _ = fs::remove_file(&path);
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut keyspace = Keyspace::default();
+        let mut sorted_set = SortedSet::default();
+        sorted_set.insert(b"m".to_vec(), 0.1);
+        keyspace.set(b"z".to_vec(), Value::SortedSet(sorted_set), None, now);
+        let list = (0..100)
+            .map(|i| i.to_string().into_bytes())
+            .collect::<VecDeque<_>>();
+        keyspace.set(
+            b"l".to_vec(),
+            Value::List(list),
+            Some(now + Duration::from_secs(10)),
+            now,
+        );
+
+        let aof =
+            AppendOnlyFile::open(path.clone(), AppendFsync::No, std::iter::empty(), now).unwrap();
+        aof.append(0, &[b"SET".to_vec(), b"old".to_vec(), b"1".to_vec()])
+            .unwrap();
+
+        assert!(aof.start_rewrite());
+        assert!(!aof.start_rewrite());
+        aof.append(0, &[b"SET".to_vec(), b"new".to_vec(), b"2".to_vec()])
+            .unwrap();
+        let mut entries = keyspace
+            .iter(now)
+            .map(|(key, entry)| (0, key, entry))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, key, _)| *key);
+        aof.finish_rewrite(entries.into_iter()).unwrap();
+        assert!(!aof.rewrite_in_progress());
+        aof.append(1, &[b"SET".to_vec(), b"after".to_vec(), b"3".to_vec()])
+            .unwrap();
+
+        let names = replayed(&path, false)
+            .unwrap()
+            .into_iter()
+            .map(|arguments| String::from_utf8(arguments[0].clone()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "SELECT",
+                "RPUSH",
+                "RPUSH",
+                "PEXPIREAT",
+                "ZADD",
+                "SELECT",
+                "SET",
+                "SELECT",
+                "SET"
+            ]
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+use super::random::Random;
+use super::resp::{parse_value, Protocol, Reply};
+use build_your_own_macros::cli_options;
+use build_your_own_utils::my_own_error::MyOwnError;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// https://redis.io/docs/latest/operate/oss_and_stack/management/optimization/benchmarks/
+
+pub fn redis_benchmark_cli(args: &[&str]) -> Result<(), MyOwnError> {
+    let config = BenchmarkConfig::from_args(args)?;
+    let address = SocketAddr::from(([127, 0, 0, 1], config.port));
+
+    for test in config.tests.split(',') {
+        let test = test.parse::<Test>()?;
+        let benchmark = Benchmark {
+            test,
+            clients: config.clients.max(1),
+            requests: config.requests,
+            pipeline: config.pipeline.max(1),
+            keyspace_length: config.keyspace_length.max(1),
+        };
+
+        let elapsed = benchmark.run(address)?;
+        println!(
+            "{}: {:.2} requests per second, {} requests by {} clients in {:.3} seconds",
+            test.name(),
+            config.requests as f64 / elapsed.as_secs_f64(),
+            config.requests,
+            benchmark.clients,
+            elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+cli_options! {
+    struct BenchmarkConfig<'a> {
+        #[option(name = "-p", default = 6379)]
+        port: u16,
+        #[option(name = "-c", default = 50)]
+        clients: usize,
+        #[option(name = "-n", default = 100000)]
+        requests: usize,
+        #[option(name = "-P", default = 1)]
+        pipeline: usize,
+        #[option(name = "-r", default = 100000)]
+        keyspace_length: u64,
+        #[option(name = "-t", default = "ping,set,get")]
+        tests: &'a str
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Test {
+    Ping,
+    Set,
+    Get,
+}
+
+impl std::str::FromStr for Test {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ping" => Ok(Test::Ping),
+            "set" => Ok(Test::Set),
+            "get" => Ok(Test::Get),
+            other => Err(format!("unknown benchmark test: {}", other)),
+        }
+    }
+}
+
+impl Test {
+    fn name(self) -> &'static str {
+        match self {
+            Test::Ping => "PING",
+            Test::Set => "SET",
+            Test::Get => "GET",
+        }
+    }
+
+    /// Appends one request to `output`, on a random key out of `keyspace_length`.
+    fn encode(self, random: &mut Random, keyspace_length: u64, output: &mut Vec<u8>) {
+        let key = format!("key:{:012}", random.next_u64() % keyspace_length);
+        let arguments = match self {
+            Test::Ping => vec!["PING".to_string()],
+            Test::Set => vec!["SET".to_string(), key, "xxx".to_string()],
+            Test::Get => vec!["GET".to_string(), key],
+        };
+        Reply::bulk_array(arguments).encode(Protocol::Resp2, output);
+    }
+}
+
+/// Sends `requests` commands to the server from `clients` concurrent connections, each
+/// waiting for the replies of `pipeline` commands before sending the next ones.
+struct Benchmark {
+    test: Test,
+    clients: usize,
+    requests: usize,
+    pipeline: usize,
+    keyspace_length: u64,
+}
+
+impl Benchmark {
+    /// Returns the time it took for all the replies to be received.
+    fn run(&self, address: SocketAddr) -> io::Result<Duration> {
+        let streams = (0..self.clients)
+            .map(|_| TcpStream::connect(address))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let start = Instant::now();
+        thread::scope(|scope| {
+            let clients = streams
+                .into_iter()
+                .enumerate()
+                .map(|(client, stream)| {
+                    // the remainder of the division is sent by the first clients
+                    let requests = self.requests / self.clients
+                        + usize::from(client < self.requests % self.clients);
+                    scope.spawn(move || self.client(stream, requests, client as u64))
+                })
+                .collect::<Vec<_>>();
+
+            clients
+                .into_iter()
+                .try_for_each(|client| client.join().expect("Benchmark client panicked"))
+        })?;
+
+        Ok(start.elapsed())
+    }
+
+    fn client(&self, mut stream: TcpStream, requests: usize, seed: u64) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut random = Random::new(seed + 1);
+        let mut request = Vec::new();
+        let mut response = Vec::new();
+        let mut buffer = [0; 16 * 1024];
+
+        let mut sent = 0;
+        while sent < requests {
+            let batch = self.pipeline.min(requests - sent);
+            request.clear();
+            for _ in 0..batch {
+                self.test
+                    .encode(&mut random, self.keyspace_length, &mut request);
+            }
+            stream.write_all(&request)?;
+
+            let mut received = 0;
+            while received < batch {
+                match parse_value(&response).map_err(io::Error::other)? {
+                    Some((_, used)) => {
+                        response.drain(..used);
+                        received += 1;
+                    }
+                    None => {
+                        let bytes_read = stream.read(&mut buffer)?;
+                        if bytes_read == 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        response.extend_from_slice(&buffer[..bytes_read]);
+                    }
+                }
+            }
+            sent += batch;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::server::{Listener, Server};
+    use crate::redis::Redis;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn benchmark_against_event_loops() {
+        let redis = Redis::default();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let stopped = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                let mut server =
+                    Server::new(vec![Listener::Tcp(listener.try_clone().unwrap())]).unwrap();
+                let (redis, stopped) = (&redis, &stopped);
+                scope.spawn(move || server.run(redis, stopped).unwrap());
+            }
+
+            for test in [Test::Set, Test::Get] {
+                let benchmark = Benchmark {
+                    test,
+                    clients: 8,
+                    requests: 1003,
+                    pipeline: 4,
+                    keyspace_length: 100,
+                };
+                benchmark.run(address).unwrap();
+            }
+            stopped.store(true, Ordering::Release);
+        });
+
+        let keys = redis
+            .data
+            .lock_all(0)
+            .iter(std::time::SystemTime::now())
+            .count();
+        assert!(keys > 0 && keys <= 100);
+    }
+}
@@ -0,0 +1,306 @@
+use super::client::{Client, Outbox};
+use super::commands::{parse_float, streams};
+use super::resp::Reply;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// Clients blocked by BLPOP, BRPOP or BLMOVE until one of the lists they wait for gets
+/// elements, or by XREAD or XREADGROUP until one of the streams gets entries.
+#[derive(Default)]
+pub struct Blocking {
+    state: Mutex<BlockingState>,
+    /// Whether some clients are blocked, checked without locking on every write.
+    has_blocked: AtomicBool,
+    /// Whether keys were signaled and not served yet.
+    has_ready: AtomicBool,
+}
+
+#[derive(Default)]
+struct BlockingState {
+    /// The clients blocked on each key of each logical database, the one waiting the
+    /// longest first.
+    waiting: HashMap<(usize, Vec<u8>), VecDeque<Arc<Blocked>>>,
+    blocked: HashMap<u64, Arc<Blocked>>,
+    /// Keys that changed while clients were waiting for them, with their database.
+    ready: Vec<(usize, Vec<u8>)>,
+}
+
+/// A blocking command waiting for one of its keys.
+pub struct Blocked {
+    client_id: u64,
+    outbox: Arc<Outbox>,
+    arguments: Vec<Vec<u8>>,
+    /// The logical database of the keys.
+    db: usize,
+    keys: Vec<Vec<u8>>,
+    deadline: Option<SystemTime>,
+    /// Set once the reply is in the outbox, the client then executes its next commands.
+    done: AtomicBool,
+}
+
+impl Blocking {
+    /// Blocks a client until one of `keys` of its selected database has elements or
+    /// `deadline` is reached. Called with the shards of the keys locked, so that a push
+    /// right after is not missed.
+    pub fn block(
+        &self,
+        client: &mut Client,
+        arguments: &[Vec<u8>],
+        keys: &[Vec<u8>],
+        deadline: Option<SystemTime>,
+    ) {
+        let blocked = Arc::new(Blocked {
+            client_id: client.id,
+            outbox: Arc::clone(&client.outbox),
+            arguments: arguments.to_vec(),
+            db: client.db,
+            keys: keys.to_vec(),
+            deadline,
+            done: AtomicBool::new(false),
+        });
+
+        let mut state = self.lock();
+        for key in keys {
+            let waiting = state.waiting.entry((client.db, key.clone())).or_default();
+            // the same key given twice is waited for once
+            if !waiting.iter().any(|other| Arc::ptr_eq(other, &blocked)) {
+                waiting.push_back(Arc::clone(&blocked));
+            }
+        }
+        state.blocked.insert(client.id, Arc::clone(&blocked));
+        self.has_blocked.store(true, Ordering::Release);
+        client.blocked = Some(blocked);
+    }
+
+    /// Whether some clients wait for keys, to skip signaling them otherwise.
+    pub fn has_blocked(&self) -> bool {
+        self.has_blocked.load(Ordering::Acquire)
+    }
+
+    /// How many clients are blocked.
+    pub fn blocked_clients(&self) -> usize {
+        self.lock().blocked.len()
+    }
+
+    pub fn is_blocked(&self, client_id: u64) -> bool {
+        self.has_blocked() && self.lock().blocked.contains_key(&client_id)
+    }
+
+    /// Marks `key` of the logical database `db` as ready to be served if clients wait for
+    /// it. Called with the key locked after it changed.
+    pub fn signal(&self, db: usize, key: &[u8]) {
+        let mut state = self.lock();
+        let ready = (db, key.to_vec());
+        if state.waiting.contains_key(&ready) && !state.ready.contains(&ready) {
+            state.ready.push(ready);
+            self.has_ready.store(true, Ordering::Release);
+        }
+    }
+
+    /// Takes a key signaled since it was last served, with its database.
+    pub fn next_ready(&self) -> Option<(usize, Vec<u8>)> {
+        if !self.has_ready.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let mut state = self.lock();
+        let key = state.ready.pop();
+        self.has_ready
+            .store(!state.ready.is_empty(), Ordering::Release);
+        key
+    }
+
+    /// The clients waiting for `key` of the logical database `db`, the one waiting the
+    /// longest first.
+    pub fn waiting(&self, db: usize, key: &[u8]) -> Vec<Arc<Blocked>> {
+        self.lock()
+            .waiting
+            .get(&(db, key.to_vec()))
+            .map_or_else(Vec::new, |waiting| waiting.iter().cloned().collect())
+    }
+
+    /// Stops waiting for the keys of a blocked client, returning false when it was already
+    /// unblocked by someone else.
+    pub fn unblock(&self, blocked: &Blocked) -> bool {
+        let mut state = self.lock();
+        if state.blocked.remove(&blocked.client_id).is_none() {
+            return false;
+        }
+
+        for key in &blocked.keys {
+            let waited = (blocked.db, key.clone());
+            if let Some(waiting) = state.waiting.get_mut(&waited) {
+                waiting.retain(|other| other.client_id != blocked.client_id);
+                if waiting.is_empty() {
+                    state.waiting.remove(&waited);
+                }
+            }
+        }
+        self.has_blocked
+            .store(!state.blocked.is_empty(), Ordering::Release);
+        true
+    }
+
+    /// Unblocks the clients whose timeout expired at `now`, returning them to be replied.
+    pub fn timed_out(&self, now: SystemTime) -> Vec<Arc<Blocked>> {
+        if !self.has_blocked() {
+            return Vec::new();
+        }
+
+        let expired = self
+            .lock()
+            .blocked
+            .values()
+            .filter(|blocked| blocked.deadline.is_some_and(|deadline| deadline <= now))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter(|blocked| self.unblock(blocked))
+            .collect()
+    }
+
+    /// Forgets a client that disconnected while blocked.
+    pub fn disconnect(&self, client: &mut Client) {
+        if let Some(blocked) = client.blocked.take() {
+            self.unblock(&blocked);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BlockingState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Blocked {
+    pub fn arguments(&self) -> &[Vec<u8>] {
+        &self.arguments
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+
+    /// Sends the reply of the blocking command, the client can then go on with the
+    /// commands it sent meanwhile.
+    pub fn finish(&self, reply: Reply) {
+        reply.encode(self.outbox.protocol(), &mut self.outbox.lock());
+        self.done.store(true, Ordering::Release);
+        self.outbox.wake();
+    }
+}
+
+/// The command executed for a blocking command once `key` has elements: the same without
+/// the timeout, and with only `key` for BLPOP, BRPOP, XREAD and XREADGROUP.
+pub fn served_command(arguments: &[Vec<u8>], key: &[u8]) -> Vec<Vec<u8>> {
+    match arguments[0].as_slice() {
+        b"BLPOP" => vec![b"LPOP".to_vec(), key.to_vec()],
+        b"BRPOP" => vec![b"RPOP".to_vec(), key.to_vec()],
+        // blocked without their BLOCK option already
+        b"XREAD" | b"XREADGROUP" => streams::served_read(arguments, key),
+        _ => {
+            let mut command = arguments[..arguments.len() - 1].to_vec();
+            command[0] = b"LMOVE".to_vec();
+            command
+        }
+    }
+}
+
+/// The reply of a blocking command from the one of the command it executed: BLPOP and
+/// BRPOP also tell from which key the element was popped.
+pub fn served_reply(arguments: &[Vec<u8>], key: &[u8], reply: Reply) -> Reply {
+    match (arguments[0].as_slice(), reply) {
+        (b"BLPOP" | b"BRPOP", Reply::Bulk(element)) => {
+            Reply::Array(vec![Reply::bulk(key), Reply::Bulk(element)])
+        }
+        (_, reply) => reply,
+    }
+}
+
+/// The reply of a blocking command when no list had elements, or no stream entries,
+/// before the timeout.
+pub fn timeout_reply(arguments: &[Vec<u8>]) -> Reply {
+    match arguments[0].as_slice() {
+        b"BLMOVE" => Reply::Nil,
+        _ => Reply::NilArray,
+    }
+}
+
+/// Parses a timeout in seconds, where zero means waiting forever.
+pub fn parse_timeout(argument: &[u8]) -> Result<Option<Duration>, Reply> {
+    match parse_float(argument) {
+        Some(timeout) if timeout < 0.0 => Err(Reply::error("ERR timeout is negative")),
+        Some(0.0) => Ok(None),
+        Some(timeout) => Duration::try_from_secs_f64(timeout)
+            .map(Some)
+            .map_err(|_| Reply::error("ERR timeout is out of range")),
+        None => Err(Reply::error("ERR timeout is not a float or out of range")),
+    }
+}
+
+/// The time a client blocked for `timeout` gives up, `None` when it waits forever. A time
+/// the clock cannot represent is refused, as redis does.
+pub fn deadline(timeout: Option<Duration>, now: SystemTime) -> Result<Option<SystemTime>, Reply> {
+    match timeout {
+        Some(timeout) => now
+            .checked_add(timeout)
+            .map(Some)
+            .ok_or_else(|| Reply::error("ERR timeout is out of range")),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+
+    #[test]
+    fn longest_waiting_client_first() {
+        let blocking = Blocking::default();
+        let mut first = Client::default();
+        let mut second = Client::default();
+        let now = SystemTime::UNIX_EPOCH;
+
+        let command = arguments(&["BLPOP", "a", "b", "0"]);
+        blocking.block(&mut first, &command, &command[1..3], None);
+        let command = arguments(&["BLPOP", "b", "1"]);
+        let deadline = now + Duration::from_secs(1);
+        blocking.block(&mut second, &command, &command[1..2], Some(deadline));
+
+        blocking.signal(0, b"b");
+        blocking.signal(0, b"c");
+        blocking.signal(1, b"b");
+        assert_eq!(blocking.next_ready(), Some((0, b"b".to_vec())));
+        assert_eq!(blocking.next_ready(), None);
+
+        let waiting = blocking.waiting(0, b"b");
+        assert_eq!(waiting.len(), 2);
+        let served = Arc::clone(&waiting[0]);
+        assert_eq!(served.client_id, first.id);
+        assert!(blocking.is_blocked(first.id));
+        assert!(blocking.unblock(&served));
+        assert!(!blocking.unblock(&served));
+        assert!(!blocking.is_blocked(first.id));
+        assert!(blocking.waiting(0, b"a").is_empty());
+
+        assert!(blocking.timed_out(now).is_empty());
+        let timed_out = blocking.timed_out(deadline);
+        assert_eq!(timed_out.len(), 1);
+        timed_out[0].finish(timeout_reply(timed_out[0].arguments()));
+        assert!(!second.is_blocked());
+        assert_eq!(second.outbox.take(), b"*-1\r\n");
+        assert!(!blocking.has_blocked());
+    }
+
+    #[test]
+    fn timeouts() {
+        assert_eq!(parse_timeout(b"0"), Ok(None));
+        assert_eq!(parse_timeout(b"1.5"), Ok(Some(Duration::from_millis(1500))));
+        assert!(parse_timeout(b"-1").is_err());
+        assert!(parse_timeout(b"soon").is_err());
+    }
+}
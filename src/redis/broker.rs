@@ -0,0 +1,203 @@
+use super::client::{Client, Outbox};
+use super::glob::glob_match;
+use super::resp::Reply;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+type Subscribers = HashMap<u64, Arc<Outbox>>;
+
+/// Clients subscribed to each channel and pattern, to deliver the published messages.
+#[derive(Default)]
+pub struct PubSub {
+    state: RwLock<PubSubState>,
+}
+
+#[derive(Default)]
+struct PubSubState {
+    channels: HashMap<Vec<u8>, Subscribers>,
+    patterns: HashMap<Vec<u8>, Subscribers>,
+}
+
+impl PubSub {
+    /// Subscribes the client to `channel`, returning false when it already was.
+    pub fn subscribe(&self, client: &mut Client, channel: &[u8]) -> bool {
+        if !client.channels.insert(channel.to_vec()) {
+            return false;
+        }
+        let mut state = self.state.write().unwrap();
+        add(&mut state.channels, channel, client);
+        true
+    }
+
+    /// Unsubscribes the client from `channel`, returning false when it was not subscribed.
+    pub fn unsubscribe(&self, client: &mut Client, channel: &[u8]) -> bool {
+        if !client.channels.remove(channel) {
+            return false;
+        }
+        let mut state = self.state.write().unwrap();
+        remove(&mut state.channels, channel, client.id);
+        true
+    }
+
+    pub fn psubscribe(&self, client: &mut Client, pattern: &[u8]) -> bool {
+        if !client.patterns.insert(pattern.to_vec()) {
+            return false;
+        }
+        let mut state = self.state.write().unwrap();
+        add(&mut state.patterns, pattern, client);
+        true
+    }
+
+    pub fn punsubscribe(&self, client: &mut Client, pattern: &[u8]) -> bool {
+        if !client.patterns.remove(pattern) {
+            return false;
+        }
+        let mut state = self.state.write().unwrap();
+        remove(&mut state.patterns, pattern, client.id);
+        true
+    }
+
+    /// Removes every subscription of a client that disconnected.
+    pub fn disconnect(&self, client: &mut Client) {
+        if !client.is_subscribed() {
+            return;
+        }
+        let mut state = self.state.write().unwrap();
+        for channel in client.channels.drain() {
+            remove(&mut state.channels, &channel, client.id);
+        }
+        for pattern in client.patterns.drain() {
+            remove(&mut state.patterns, &pattern, client.id);
+        }
+    }
+
+    /// Sends `message` to the subscribers of `channel` and of the patterns matching it,
+    /// returning how many messages were sent.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let state = self.state.read().unwrap();
+        let mut receivers = 0;
+
+        if let Some(subscribers) = state.channels.get(channel) {
+            let reply = message_reply(&[b"message", channel, message]);
+            subscribers.values().for_each(|outbox| outbox.push(&reply));
+            receivers += subscribers.len();
+        }
+
+        for (pattern, subscribers) in &state.patterns {
+            if glob_match(pattern, channel) {
+                let reply = message_reply(&[b"pmessage", pattern, channel, message]);
+                subscribers.values().for_each(|outbox| outbox.push(&reply));
+                receivers += subscribers.len();
+            }
+        }
+
+        receivers
+    }
+
+    /// Channels with at least one subscriber, only those matching `pattern` when given.
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let state = self.state.read().unwrap();
+        state
+            .channels
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    /// Number of subscribers of `channel`, not counting the pattern subscribers.
+    pub fn numsub(&self, channel: &[u8]) -> usize {
+        let state = self.state.read().unwrap();
+        state.channels.get(channel).map_or(0, HashMap::len)
+    }
+
+    /// Number of distinct patterns clients are subscribed to.
+    pub fn numpat(&self) -> usize {
+        self.state.read().unwrap().patterns.len()
+    }
+}
+
+/// A message delivered to a subscriber, pushed outside of the replies to its commands.
+fn message_reply(parts: &[&[u8]]) -> Reply {
+    Reply::Push(parts.iter().map(|part| Reply::bulk(*part)).collect())
+}
+
+fn add(subscriptions: &mut HashMap<Vec<u8>, Subscribers>, name: &[u8], client: &Client) {
+    subscriptions
+        .entry(name.to_vec())
+        .or_default()
+        .insert(client.id, Arc::clone(&client.outbox));
+}
+
+fn remove(subscriptions: &mut HashMap<Vec<u8>, Subscribers>, name: &[u8], client_id: u64) {
+    if let Some(subscribers) = subscriptions.get_mut(name) {
+        subscribers.remove(&client_id);
+        if subscribers.is_empty() {
+            subscriptions.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::resp::Protocol;
+
+    fn message(parts: &[&str]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        Reply::bulk_array(parts.iter().map(|part| part.as_bytes()))
+            .encode(Protocol::Resp2, &mut encoded);
+        encoded
+    }
+
+    #[test]
+    fn publish_to_channels_and_patterns() {
+        let pubsub = PubSub::default();
+        let mut first = Client::default();
+        let mut second = Client::default();
+
+        assert!(pubsub.subscribe(&mut first, b"news.tech"));
+        assert!(!pubsub.subscribe(&mut first, b"news.tech"));
+        assert!(pubsub.psubscribe(&mut first, b"news.*"));
+        assert!(pubsub.subscribe(&mut second, b"news.tech"));
+
+        assert_eq!(pubsub.publish(b"news.tech", b"hello"), 3);
+        assert_eq!(pubsub.publish(b"news.art", b"hi"), 1);
+        assert_eq!(pubsub.publish(b"weather", b"sunny"), 0);
+
+        let mut expected = message(&["message", "news.tech", "hello"]);
+        expected.extend(message(&["pmessage", "news.*", "news.tech", "hello"]));
+        expected.extend(message(&["pmessage", "news.*", "news.art", "hi"]));
+        assert_eq!(first.outbox.take(), expected);
+        assert_eq!(
+            second.outbox.take(),
+            message(&["message", "news.tech", "hello"])
+        );
+    }
+
+    #[test]
+    fn introspection_and_disconnect() {
+        let pubsub = PubSub::default();
+        let mut first = Client::default();
+        let mut second = Client::default();
+        pubsub.subscribe(&mut first, b"a");
+        pubsub.subscribe(&mut first, b"b");
+        pubsub.subscribe(&mut second, b"a");
+        pubsub.psubscribe(&mut second, b"*");
+
+        let mut channels = pubsub.channels(None);
+        channels.sort();
+        assert_eq!(channels, vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(pubsub.channels(Some(b"b*")), vec![b"b".to_vec()]);
+        assert_eq!(pubsub.numsub(b"a"), 2);
+        assert_eq!(pubsub.numpat(), 1);
+
+        assert!(pubsub.unsubscribe(&mut first, b"b"));
+        assert!(!pubsub.unsubscribe(&mut first, b"b"));
+        pubsub.disconnect(&mut second);
+        assert!(!second.is_subscribed());
+        assert_eq!(pubsub.channels(None), vec![b"a".to_vec()]);
+        assert_eq!(pubsub.numsub(b"a"), 1);
+        assert_eq!(pubsub.numpat(), 0);
+    }
+}
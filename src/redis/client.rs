@@ -0,0 +1,236 @@
+use super::acl::DEFAULT_USER;
+use super::blocking::Blocked;
+use super::clients::{ClientState, Registered};
+use super::resp::{Protocol, Reply};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+/// Once the messages waiting to be sent to a client exceed this size, the client is
+/// disconnected instead of letting a slow subscriber exhaust the memory.
+const OUTBOX_LIMIT: usize = 32 * 1024 * 1024;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State of a client connection, kept across the commands it sends.
+pub struct Client {
+    pub id: u64,
+    /// Address of the peer, `None` for the clients not connected through the network.
+    pub address: Option<SocketAddr>,
+    /// Port a replica listens to, announced with REPLCONF listening-port.
+    pub listening_port: Option<u16>,
+    /// Set by CLIENT SETNAME.
+    pub name: Option<Vec<u8>>,
+    /// The logical database the commands access, changed by SELECT.
+    pub db: usize,
+    /// The user whose permissions apply to the commands, changed by AUTH and HELLO.
+    pub user: Vec<u8>,
+    /// Whether the client can send commands other than AUTH and HELLO, false until it
+    /// authenticates when the default user needs a password.
+    pub authenticated: bool,
+    pub outbox: Arc<Outbox>,
+    pub channels: HashSet<Vec<u8>>,
+    pub patterns: HashSet<Vec<u8>>,
+    /// Commands queued since MULTI, `None` outside of a transaction.
+    pub transaction: Option<Vec<Vec<Vec<u8>>>>,
+    /// Set when a command was refused while being queued, EXEC then discards the whole
+    /// transaction.
+    pub transaction_failed: bool,
+    /// Keys watched for the next transaction, with their database and their version when
+    /// they were watched.
+    pub watched: Vec<(usize, Vec<u8>, u64)>,
+    /// The blocking command the client waits for, if any.
+    pub blocked: Option<Arc<Blocked>>,
+    /// The entry of the client in the registry of the connected clients, `None` for the
+    /// clients not connected through the network.
+    pub registered: Option<Arc<Registered>>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new(Outbox::default())
+    }
+}
+
+impl Client {
+    pub fn new(outbox: Outbox) -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            address: None,
+            listening_port: None,
+            name: None,
+            db: 0,
+            user: DEFAULT_USER.to_vec(),
+            authenticated: true,
+            outbox: Arc::new(outbox),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            transaction: None,
+            transaction_failed: false,
+            watched: Vec::new(),
+            blocked: None,
+            registered: None,
+        }
+    }
+
+    /// Total number of channels and patterns the client is subscribed to.
+    pub fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// A blocked client executes its next commands only once its blocking command is done.
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+            .as_ref()
+            .is_some_and(|blocked| !blocked.is_done())
+    }
+
+    /// A subscribed client can only send the commands managing its subscriptions.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscriptions() > 0
+    }
+
+    /// The protocol chosen with HELLO.
+    pub fn protocol(&self) -> Protocol {
+        self.outbox.protocol()
+    }
+
+    /// The state of the client as shown by CLIENT LIST, `command` being the last one it
+    /// sent at `now`.
+    pub fn state(&self, command: &[u8], now: SystemTime) -> ClientState {
+        ClientState {
+            name: self.name.clone(),
+            db: self.db,
+            user: self.user.clone(),
+            subscriptions: self.channels.len(),
+            patterns: self.patterns.len(),
+            multi: self.transaction.as_ref().map(Vec::len),
+            protocol: self.protocol(),
+            last_command: command.to_vec(),
+            last_interaction: now,
+        }
+    }
+
+    /// Reports the state of the client to the registry after `command`.
+    pub fn report(&self, command: &[u8], now: SystemTime) {
+        if let Some(registered) = &self.registered {
+            registered.update(self.state(command, now));
+        }
+    }
+}
+
+/// Wakes up the thread serving a client when something is pushed to its outbox.
+pub trait Waker: Send + Sync {
+    fn wake(&self);
+}
+
+/// Bytes waiting to be sent to a client: the replies to its commands and the messages
+/// pushed by other clients, in the order they were produced.
+#[derive(Default)]
+pub struct Outbox {
+    buffer: Mutex<Vec<u8>>,
+    waker: Option<Box<dyn Waker>>,
+    overflowed: AtomicBool,
+    /// Set by CLIENT KILL and the idle timeout, the connection is closed once the
+    /// pending replies are sent.
+    closed: AtomicBool,
+    /// Whether the client speaks RESP3, shared with the clients pushing messages to it.
+    resp3: AtomicBool,
+}
+
+impl Outbox {
+    pub fn new(waker: impl Waker + 'static) -> Self {
+        Self {
+            buffer: Mutex::default(),
+            waker: Some(Box::new(waker)),
+            overflowed: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            resp3: AtomicBool::new(false),
+        }
+    }
+
+    /// Pushes a reply from another client, waking up the thread serving this one.
+    pub fn push(&self, reply: &Reply) {
+        self.push_with(|buffer| reply.encode(self.protocol(), buffer));
+    }
+
+    /// Pushes bytes already encoded, e.g. the commands propagated to a replica.
+    pub fn push_bytes(&self, bytes: &[u8]) {
+        self.push_with(|buffer| buffer.extend_from_slice(bytes));
+    }
+
+    fn push_with(&self, write: impl FnOnce(&mut Vec<u8>)) {
+        let mut buffer = self.lock();
+        let was_empty = buffer.is_empty();
+        write(&mut buffer);
+        if buffer.len() > OUTBOX_LIMIT {
+            buffer.clear();
+            self.overflowed.store(true, Ordering::Release);
+        }
+        drop(buffer);
+
+        if was_empty {
+            self.wake();
+        }
+    }
+
+    /// Wakes up the thread serving the client, to send what is in the outbox.
+    pub fn wake(&self) {
+        if let Some(waker) = &self.waker {
+            waker.wake();
+        }
+    }
+
+    /// The protocol the replies and messages are encoded in.
+    pub fn protocol(&self) -> Protocol {
+        if self.resp3.load(Ordering::Acquire) {
+            Protocol::Resp3
+        } else {
+            Protocol::Resp2
+        }
+    }
+
+    pub fn set_protocol(&self, protocol: Protocol) {
+        self.resp3
+            .store(protocol == Protocol::Resp3, Ordering::Release);
+    }
+
+    /// Whether messages were dropped because the client did not read them fast enough.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed.load(Ordering::Acquire)
+    }
+
+    /// Asks the thread serving the client to close the connection.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.wake();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(test)]
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.lock())
+    }
+}
+
+/// The replies to the commands of the client itself, written without waking anyone up.
+impl Write for &Outbox {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.lock().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
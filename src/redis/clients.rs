@@ -0,0 +1,160 @@
+use super::client::{Client, Outbox};
+use super::resp::Protocol;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+/// The clients connected to the server, as listed by CLIENT LIST.
+#[derive(Default)]
+pub struct Clients {
+    registered: Mutex<BTreeMap<u64, Arc<Registered>>>,
+}
+
+/// What other clients see of a connected client.
+pub struct Registered {
+    pub id: u64,
+    pub address: Option<SocketAddr>,
+    outbox: Arc<Outbox>,
+    created_at: SystemTime,
+    /// The state of the client after its last command.
+    state: Mutex<ClientState>,
+}
+
+/// The state of a client shown by CLIENT LIST, copied from the client after each of its
+/// commands.
+#[derive(Clone)]
+pub struct ClientState {
+    pub name: Option<Vec<u8>>,
+    pub db: usize,
+    pub user: Vec<u8>,
+    pub subscriptions: usize,
+    pub patterns: usize,
+    /// Number of commands queued since MULTI, `None` outside of a transaction.
+    pub multi: Option<usize>,
+    pub protocol: Protocol,
+    pub last_command: Vec<u8>,
+    pub last_interaction: SystemTime,
+}
+
+impl Clients {
+    /// Registers a client connected at `now`, which then reports its state to the registry.
+    pub fn register(&self, client: &mut Client, now: SystemTime) {
+        let registered = Arc::new(Registered {
+            id: client.id,
+            address: client.address,
+            outbox: Arc::clone(&client.outbox),
+            created_at: now,
+            state: Mutex::new(client.state(b"", now)),
+        });
+        self.lock().insert(client.id, Arc::clone(&registered));
+        client.registered = Some(registered);
+    }
+
+    pub fn unregister(&self, client_id: u64) {
+        self.lock().remove(&client_id);
+    }
+
+    /// Every registered client, by increasing id.
+    pub fn all(&self) -> Vec<Arc<Registered>> {
+        self.lock().values().cloned().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, Arc<Registered>>> {
+        self.registered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Registered {
+    pub fn state(&self) -> ClientState {
+        self.lock().clone()
+    }
+
+    pub fn update(&self, state: ClientState) {
+        *self.lock() = state;
+    }
+
+    /// The address as shown by CLIENT LIST and matched by CLIENT KILL.
+    pub fn address(&self) -> String {
+        self.address
+            .map_or_else(String::new, |address| address.to_string())
+    }
+
+    /// Closes the connection of the client once the replies already produced are sent.
+    pub fn kill(&self) {
+        self.outbox.close();
+    }
+
+    /// The line of CLIENT LIST describing the client, with `state` as its current state
+    /// and `flags` as computed by the caller.
+    pub fn describe(&self, state: &ClientState, flags: &str, now: SystemTime) -> String {
+        let seconds_since =
+            |time: SystemTime| now.duration_since(time).unwrap_or_default().as_secs();
+        format!(
+            "id={} addr={} name={} age={} idle={} flags={} db={} sub={} psub={} multi={} cmd={} user={} resp={}\n",
+            self.id,
+            self.address(),
+            String::from_utf8_lossy(state.name.as_deref().unwrap_or_default()),
+            seconds_since(self.created_at),
+            seconds_since(state.last_interaction),
+            flags,
+            state.db,
+            state.subscriptions,
+            state.patterns,
+            state.multi.map_or(-1, |queued| queued as i64),
+            String::from_utf8_lossy(&state.last_command).to_lowercase(),
+            String::from_utf8_lossy(&state.user),
+            state.protocol.version(),
+        )
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ClientState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Whether a name can be set with CLIENT SETNAME: printable characters without spaces.
+pub fn is_valid_name(name: &[u8]) -> bool {
+    name.iter().all(|byte| (b'!'..=b'~').contains(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn register_and_describe() {
+        let clients = Clients::default();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut client = Client {
+            address: Some("127.0.0.1:5000".parse().unwrap()),
+            name: Some(b"worker".to_vec()),
+            ..Client::default()
+        };
+        clients.register(&mut client, now);
+        client.db = 3;
+        client.transaction = Some(vec![vec![b"GET".to_vec()]]);
+        client.report(b"GET", now + Duration::from_secs(2));
+
+        let registered = clients.all();
+        assert_eq!(registered.len(), 1);
+        let state = registered[0].state();
+        assert_eq!(
+            registered[0].describe(&state, "x", now + Duration::from_secs(5)),
+            format!(
+                "id={} addr=127.0.0.1:5000 name=worker age=5 idle=3 flags=x db=3 sub=0 psub=0 multi=1 cmd=get user=default resp=2\n",
+                client.id
+            )
+        );
+
+        registered[0].kill();
+        assert!(client.outbox.is_closed());
+        clients.unregister(client.id);
+        assert!(clients.all().is_empty());
+        assert!(is_valid_name(b"a-b_c"));
+        assert!(!is_valid_name(b"a b") && !is_valid_name(b"a\nb"));
+    }
+}
@@ -0,0 +1,266 @@
+use super::{expire_time, parse_integer, unix_millis, TimeUnit};
+use crate::redis::keyspace::Keyspace;
+use crate::redis::resp::Reply;
+use std::time::SystemTime;
+
+/// EXPIRE/PEXPIRE/EXPIREAT/PEXPIREAT key time [NX|XX|GT|LT]
+pub fn expire(
+    keyspace: &mut Keyspace,
+    arguments: &[Vec<u8>],
+    now: SystemTime,
+    unit: TimeUnit,
+    relative: bool,
+) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for option in &arguments[3..] {
+        match option.to_ascii_uppercase().as_slice() {
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            b"GT" => gt = true,
+            b"LT" => lt = true,
+            _ => {
+                return Reply::error(format!(
+                    "ERR Unsupported option {}",
+                    String::from_utf8_lossy(option)
+                ))
+            }
+        }
+    }
+
+    if nx && (xx || gt || lt) {
+        return Reply::error("ERR NX and XX, GT or LT options at the same time are not compatible");
+    }
+    if gt && lt {
+        return Reply::error("ERR GT and LT options at the same time are not compatible");
+    }
+
+    let Some(value) = parse_integer(&arguments[2]) else {
+        return Reply::not_an_integer();
+    };
+    let Some(expires_at) = expire_time(value, unit, relative, now) else {
+        return Reply::error(format!(
+            "ERR invalid expire time in '{}' command",
+            String::from_utf8_lossy(&arguments[0]).to_lowercase()
+        ));
+    };
+
+    let Some(entry) = keyspace.get_mut(&arguments[1], now) else {
+        return Reply::Integer(0);
+    };
+
+    // a key without expiry behaves as if its time to live was infinite
+    let skip = match entry.expires_at() {
+        Some(_) if nx => true,
+        None if xx || gt => true,
+        Some(current) => (gt && expires_at <= current) || (lt && expires_at >= current),
+        None => false,
+    };
+    if skip {
+        return Reply::Integer(0);
+    }
+
+    if expires_at <= now {
+        keyspace.remove(&arguments[1], now);
+    } else {
+        keyspace.set_expiry(&arguments[1], Some(expires_at));
+    }
+
+    Reply::Integer(1)
+}
+
+/// TTL/PTTL key
+pub fn ttl(
+    keyspace: &mut Keyspace,
+    arguments: &[Vec<u8>],
+    now: SystemTime,
+    unit: TimeUnit,
+) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get(&arguments[1], now) {
+        None => Reply::Integer(-2),
+        Some(entry) => match entry.expires_at() {
+            None => Reply::Integer(-1),
+            Some(expires_at) => {
+                let remaining = unix_millis(expires_at) - unix_millis(now);
+                match unit {
+                    TimeUnit::Seconds => Reply::Integer((remaining + 500) / 1000),
+                    TimeUnit::Milliseconds => Reply::Integer(remaining),
+                }
+            }
+        },
+    }
+}
+
+/// PERSIST key
+pub fn persist(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    if keyspace.get(&arguments[1], now).is_none() {
+        return Reply::Integer(0);
+    }
+
+    Reply::Integer(keyspace.set_expiry(&arguments[1], None).is_some() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::{arguments, strings};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn keyspace_with_key(now: SystemTime) -> Keyspace {
+        let mut keyspace = Keyspace::default();
+        strings::set(&mut keyspace, &arguments(&["SET", "k", "v"]), now);
+        keyspace
+    }
+
+    #[test]
+    fn expire_and_ttl() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut keyspace = keyspace_with_key(now);
+
+        let ttl_of = |keyspace: &mut Keyspace, at: SystemTime| {
+            (
+                ttl(keyspace, &arguments(&["TTL", "k"]), at, TimeUnit::Seconds),
+                ttl(
+                    keyspace,
+                    &arguments(&["PTTL", "k"]),
+                    at,
+                    TimeUnit::Milliseconds,
+                ),
+            )
+        };
+        assert_eq!(
+            ttl_of(&mut keyspace, now),
+            (Reply::Integer(-1), Reply::Integer(-1))
+        );
+
+        let reply = expire(
+            &mut keyspace,
+            &arguments(&["EXPIRE", "k", "10"]),
+            now,
+            TimeUnit::Seconds,
+            true,
+        );
+        assert_eq!(reply, Reply::Integer(1));
+        let later = now + Duration::from_millis(2600);
+        assert_eq!(
+            ttl_of(&mut keyspace, later),
+            (Reply::Integer(7), Reply::Integer(7400))
+        );
+
+        let reply = expire(
+            &mut keyspace,
+            &arguments(&["PEXPIREAT", "k", "1700000001000"]),
+            now,
+            TimeUnit::Milliseconds,
+            false,
+        );
+        assert_eq!(reply, Reply::Integer(1));
+        assert_eq!(
+            ttl_of(&mut keyspace, now),
+            (Reply::Integer(1), Reply::Integer(1000))
+        );
+
+        let expired = now + Duration::from_secs(1);
+        assert_eq!(
+            ttl_of(&mut keyspace, expired),
+            (Reply::Integer(-2), Reply::Integer(-2))
+        );
+    }
+
+    #[test]
+    fn expire_options() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut keyspace = keyspace_with_key(now);
+        let mut expire_with = |options: &[&str]| {
+            let mut command = vec!["EXPIRE", "k"];
+            command.extend_from_slice(options);
+            expire(
+                &mut keyspace,
+                &arguments(&command),
+                now,
+                TimeUnit::Seconds,
+                true,
+            )
+        };
+
+        assert_eq!(expire_with(&["10", "XX"]), Reply::Integer(0));
+        assert_eq!(expire_with(&["10", "GT"]), Reply::Integer(0));
+        assert_eq!(expire_with(&["10", "NX"]), Reply::Integer(1));
+        assert_eq!(expire_with(&["20", "NX"]), Reply::Integer(0));
+        assert_eq!(expire_with(&["5", "GT"]), Reply::Integer(0));
+        assert_eq!(expire_with(&["20", "GT"]), Reply::Integer(1));
+        assert_eq!(expire_with(&["30", "LT"]), Reply::Integer(0));
+        assert_eq!(expire_with(&["15", "LT", "XX"]), Reply::Integer(1));
+        assert_eq!(
+            expire_with(&["15", "NX", "GT"]),
+            Reply::error("ERR NX and XX, GT or LT options at the same time are not compatible")
+        );
+        assert_eq!(expire_with(&["x"]), Reply::not_an_integer());
+    }
+
+    #[test]
+    fn expire_in_the_past_deletes() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut keyspace = keyspace_with_key(now);
+
+        let reply = expire(
+            &mut keyspace,
+            &arguments(&["EXPIRE", "k", "-1"]),
+            now,
+            TimeUnit::Seconds,
+            true,
+        );
+        assert_eq!(reply, Reply::Integer(1));
+        assert!(keyspace.get(b"k", now).is_none());
+        let reply = expire(
+            &mut keyspace,
+            &arguments(&["EXPIRE", "k", "10"]),
+            now,
+            TimeUnit::Seconds,
+            true,
+        );
+        assert_eq!(reply, Reply::Integer(0));
+    }
+
+    #[test]
+    fn persist_removes_expiry() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut keyspace = keyspace_with_key(now);
+
+        assert_eq!(
+            persist(&mut keyspace, &arguments(&["PERSIST", "k"]), now),
+            Reply::Integer(0)
+        );
+        expire(
+            &mut keyspace,
+            &arguments(&["EXPIRE", "k", "10"]),
+            now,
+            TimeUnit::Seconds,
+            true,
+        );
+        assert_eq!(
+            persist(&mut keyspace, &arguments(&["PERSIST", "k"]), now),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            ttl(
+                &mut keyspace,
+                &arguments(&["TTL", "k"]),
+                now,
+                TimeUnit::Seconds
+            ),
+            Reply::Integer(-1)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use crate::redis::keyspace::Keyspace;
+use crate::redis::resp::Reply;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+type Hash = HashMap<Vec<u8>, Vec<u8>>;
+
+/// HSET key field value [field value ...]
+pub fn hset(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 4 || !arguments.len().is_multiple_of(2) {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let Ok(hash) = keyspace.get_or_create::<Hash>(&arguments[1], now) else {
+        return Reply::wrong_type();
+    };
+
+    let added = arguments[2..]
+        .chunks(2)
+        .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+        .count();
+
+    Reply::Integer(added as i64)
+}
+
+/// HGET key field
+pub fn hget(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_typed::<Hash>(&arguments[1], now) {
+        Ok(hash) => hash
+            .and_then(|hash| hash.get(&arguments[2]))
+            .map(|value| Reply::bulk(value.clone()))
+            .unwrap_or(Reply::Nil),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// HDEL key field [field ...]
+pub fn hdel(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let hash = match keyspace.get_typed_mut::<Hash>(&arguments[1], now) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return Reply::Integer(0),
+        Err(_) => return Reply::wrong_type(),
+    };
+
+    let removed = arguments[2..]
+        .iter()
+        .filter(|field| hash.remove(*field).is_some())
+        .count();
+
+    keyspace.remove_if_empty(&arguments[1]);
+
+    Reply::Integer(removed as i64)
+}
+
+/// HGETALL key
+pub fn hgetall(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_typed::<Hash>(&arguments[1], now) {
+        Ok(hash) => Reply::Map(
+            hash.into_iter()
+                .flatten()
+                .map(|(field, value)| (Reply::bulk(field.clone()), Reply::bulk(value.clone())))
+                .collect(),
+        ),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+
+    #[test]
+    fn set_get_delete() {
+        let mut keyspace = Keyspace::default();
+        let now = SystemTime::now();
+
+        let reply = hset(
+            &mut keyspace,
+            &arguments(&["HSET", "h", "a", "1", "b", "2"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Integer(2));
+        let reply = hset(&mut keyspace, &arguments(&["HSET", "h", "a", "3"]), now);
+        assert_eq!(reply, Reply::Integer(0));
+
+        assert_eq!(
+            hget(&mut keyspace, &arguments(&["HGET", "h", "a"]), now),
+            Reply::bulk("3")
+        );
+        assert_eq!(
+            hget(&mut keyspace, &arguments(&["HGET", "h", "c"]), now),
+            Reply::Nil
+        );
+
+        let reply = hdel(
+            &mut keyspace,
+            &arguments(&["HDEL", "h", "a", "b", "c"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Integer(2));
+        assert_eq!(
+            hgetall(&mut keyspace, &arguments(&["HGETALL", "h"]), now),
+            Reply::Map(vec![])
+        );
+        assert!(keyspace.get(b"h", now).is_none());
+    }
+
+    #[test]
+    fn getall() {
+        let mut keyspace = Keyspace::default();
+        let now = SystemTime::now();
+        hset(&mut keyspace, &arguments(&["HSET", "h", "a", "1"]), now);
+
+        assert_eq!(
+            hgetall(&mut keyspace, &arguments(&["HGETALL", "h"]), now),
+            Reply::Map(vec![(Reply::bulk("a"), Reply::bulk("1"))])
+        );
+        assert_eq!(
+            hset(&mut keyspace, &arguments(&["HSET", "h", "a"]), now),
+            Reply::wrong_number_of_arguments(b"HSET")
+        );
+    }
+}
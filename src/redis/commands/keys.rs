@@ -0,0 +1,295 @@
+use super::parse_integer;
+use crate::redis::database::{Database, Shards};
+use crate::redis::glob::glob_match;
+use crate::redis::keyspace::Keyspace;
+use crate::redis::resp::Reply;
+use std::time::SystemTime;
+
+/// Keys examined by a step of SCAN without COUNT.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// EXISTS key [key ...], a key given several times is counted as many times.
+pub fn exists(shards: &mut Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let existing = arguments[1..]
+        .iter()
+        .filter(|key| shards.keyspace(key).get(key, now).is_some())
+        .count();
+    Reply::Integer(existing as i64)
+}
+
+/// DEL key [key ...]
+pub fn del(shards: &mut Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let deleted = arguments[1..]
+        .iter()
+        .filter(|key| shards.keyspace(key).remove(key, now).is_some())
+        .count();
+    Reply::Integer(deleted as i64)
+}
+
+/// TYPE key
+pub fn type_of(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let name = keyspace
+        .get(&arguments[1], now)
+        .map_or("none", |entry| entry.value.type_name());
+    Reply::Simple(name.to_string())
+}
+
+/// RENAME key newkey, keeping the time to live of the key.
+pub fn rename(shards: &mut Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let (source, destination) = (&arguments[1], &arguments[2]);
+    let Some(entry) = shards.keyspace(source).remove(source, now) else {
+        return Reply::error("ERR no such key");
+    };
+    shards
+        .keyspace(destination)
+        .insert(destination.clone(), entry);
+    Reply::ok()
+}
+
+/// KEYS pattern
+pub fn keys(shards: &Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    Reply::bulk_array(
+        shards
+            .iter(now)
+            .filter(|(key, _)| glob_match(&arguments[1], key))
+            .map(|(key, _)| key.to_vec()),
+    )
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type], over the keys of the logical
+/// database `db`.
+pub fn scan(database: &Database, db: usize, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 2 || !arguments.len().is_multiple_of(2) {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let Some(cursor) = std::str::from_utf8(&arguments[1])
+        .ok()
+        .and_then(|cursor| cursor.parse::<u64>().ok())
+    else {
+        return Reply::error("ERR invalid cursor");
+    };
+
+    let (mut pattern, mut count, mut type_name) = (None, DEFAULT_SCAN_COUNT, None);
+    for option in arguments[2..].chunks(2) {
+        match option[0].to_ascii_uppercase().as_slice() {
+            b"MATCH" => pattern = Some(option[1].as_slice()),
+            b"COUNT" => match parse_integer(&option[1]) {
+                Some(value) if value >= 1 => count = value as usize,
+                Some(_) => return Reply::syntax_error(),
+                None => return Reply::not_an_integer(),
+            },
+            b"TYPE" => type_name = Some(option[1].as_slice()),
+            _ => return Reply::syntax_error(),
+        }
+    }
+
+    let (next, keys) = database.scan(db, cursor, count, now, |key, entry| {
+        pattern.is_none_or(|pattern| glob_match(pattern, key))
+            && type_name
+                .is_none_or(|name| name.eq_ignore_ascii_case(entry.value.type_name().as_bytes()))
+    });
+    Reply::Array(vec![Reply::bulk(next.to_string()), Reply::bulk_array(keys)])
+}
+
+/// DBSIZE
+pub fn dbsize(shards: &Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 1 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    Reply::Integer(shards.iter(now).count() as i64)
+}
+
+/// FLUSHALL [ASYNC|SYNC] and FLUSHDB [ASYNC|SYNC], deleting the keys of every logical
+/// database or of the selected one, right away either way.
+pub fn flush(shards: &mut Shards, arguments: &[Vec<u8>], all: bool) -> Reply {
+    match arguments.get(1..) {
+        Some([]) => {}
+        Some([mode])
+            if mode.eq_ignore_ascii_case(b"ASYNC") || mode.eq_ignore_ascii_case(b"SYNC") => {}
+        _ => return Reply::syntax_error(),
+    }
+
+    if all {
+        shards.clear_all();
+    } else {
+        shards.clear();
+    }
+    Reply::ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+    use crate::redis::keyspace::Value;
+    use std::time::Duration;
+
+    fn database() -> Database {
+        let mut keyspace = Keyspace::default();
+        let now = SystemTime::now();
+        keyspace.set(b"one".to_vec(), Value::String(b"1".to_vec()), None, now);
+        keyspace.set(b"two".to_vec(), Value::String(b"2".to_vec()), None, now);
+        keyspace.set(
+            b"list".to_vec(),
+            Value::List([b"a".to_vec()].into()),
+            None,
+            now,
+        );
+        keyspace.set(
+            b"gone".to_vec(),
+            Value::String(b"v".to_vec()),
+            Some(now - Duration::from_secs(1)),
+            now,
+        );
+        Database::from(keyspace)
+    }
+
+    fn sorted(reply: Reply) -> Vec<Reply> {
+        let Reply::Array(mut keys) = reply else {
+            panic!("expected an array, got {:?}", reply);
+        };
+        keys.sort_by_key(|key| format!("{:?}", key));
+        keys
+    }
+
+    #[test]
+    fn exists_del_and_type() {
+        let database = database();
+        let now = SystemTime::now();
+        let command = arguments(&["EXISTS", "one", "one", "gone", "missing"]);
+        let mut shards = database.lock(0, command[1..].iter().map(Vec::as_slice));
+        assert_eq!(exists(&mut shards, &command, now), Reply::Integer(2));
+        assert_eq!(
+            type_of(shards.keyspace(b"one"), &arguments(&["TYPE", "one"]), now),
+            Reply::Simple("string".to_string())
+        );
+        assert_eq!(
+            type_of(shards.keyspace(b"gone"), &arguments(&["TYPE", "gone"]), now),
+            Reply::Simple("none".to_string())
+        );
+        drop(shards);
+
+        let command = arguments(&["DEL", "one", "one", "list", "missing"]);
+        let mut shards = database.lock(0, command[1..].iter().map(Vec::as_slice));
+        assert_eq!(del(&mut shards, &command, now), Reply::Integer(2));
+        drop(shards);
+        assert_eq!(
+            dbsize(&database.lock_all(0), &arguments(&["DBSIZE"]), now),
+            Reply::Integer(1)
+        );
+    }
+
+    #[test]
+    fn rename_keeps_the_time_to_live() {
+        let database = database();
+        let now = SystemTime::now();
+        let expires_at = now + Duration::from_secs(10);
+        let command = arguments(&["RENAME", "one", "two"]);
+        let mut shards = database.lock(0, command[1..].iter().map(Vec::as_slice));
+        shards.keyspace(b"one").set_expiry(b"one", Some(expires_at));
+
+        assert_eq!(rename(&mut shards, &command, now), Reply::ok());
+        assert!(shards.keyspace(b"one").get(b"one", now).is_none());
+        let entry = shards.keyspace(b"two").get(b"two", now).unwrap();
+        assert_eq!(entry.expires_at(), Some(expires_at));
+        assert!(matches!(entry.value, Value::String(ref value) if value == b"1"));
+        assert_eq!(
+            rename(&mut shards, &command, now),
+            Reply::error("ERR no such key")
+        );
+    }
+
+    #[test]
+    fn keys_and_flushall() {
+        let database = database();
+        let now = SystemTime::now();
+        let mut shards = database.lock_all(1);
+        shards
+            .keyspace(b"other")
+            .set(b"other".to_vec(), Value::String(b"v".to_vec()), None, now);
+        assert_eq!(
+            flush(&mut shards, &arguments(&["FLUSHDB"]), false),
+            Reply::ok()
+        );
+        assert_eq!(shards.iter_all(now).count(), 3);
+        shards.select(0);
+
+        assert_eq!(
+            sorted(keys(&shards, &arguments(&["KEYS", "*o*"]), now)),
+            vec![Reply::bulk("one"), Reply::bulk("two")]
+        );
+        assert_eq!(
+            flush(&mut shards, &arguments(&["FLUSHALL", "NOW"]), true),
+            Reply::syntax_error()
+        );
+        assert_eq!(
+            flush(&mut shards, &arguments(&["FLUSHALL", "async"]), true),
+            Reply::ok()
+        );
+        assert_eq!(
+            dbsize(&shards, &arguments(&["DBSIZE"]), now),
+            Reply::Integer(0)
+        );
+    }
+
+    #[test]
+    fn scan_with_options() {
+        let database = database();
+        let now = SystemTime::now();
+
+        let mut found = Vec::new();
+        let mut cursor = "0".to_string();
+        loop {
+            let command = arguments(&["SCAN", &cursor, "MATCH", "*o*", "COUNT", "1"]);
+            let Reply::Array(reply) = scan(&database, 0, &command, now) else {
+                panic!("expected a cursor and keys");
+            };
+            let Ok([Reply::Bulk(next), Reply::Array(keys)]) = <[Reply; 2]>::try_from(reply) else {
+                panic!("expected a cursor and keys");
+            };
+            found.extend(keys);
+            cursor = String::from_utf8(next).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        found.sort_by_key(|key| format!("{:?}", key));
+        assert_eq!(found, vec![Reply::bulk("one"), Reply::bulk("two")]);
+
+        let command = arguments(&["SCAN", "0", "TYPE", "list", "COUNT", "100"]);
+        assert_eq!(
+            scan(&database, 0, &command, now),
+            Reply::Array(vec![Reply::bulk("0"), Reply::bulk_array(["list"])])
+        );
+        assert_eq!(
+            scan(&database, 0, &arguments(&["SCAN", "x"]), now),
+            Reply::error("ERR invalid cursor")
+        );
+        assert_eq!(
+            scan(&database, 0, &arguments(&["SCAN", "0", "COUNT", "0"]), now),
+            Reply::syntax_error()
+        );
+    }
+}
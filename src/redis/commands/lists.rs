@@ -0,0 +1,302 @@
+use super::{normalize_range, parse_integer};
+use crate::redis::database::Shards;
+use crate::redis::keyspace::Keyspace;
+use crate::redis::resp::Reply;
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+pub type List = VecDeque<Vec<u8>>;
+
+#[derive(Clone, Copy)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    fn parse(argument: &[u8]) -> Option<End> {
+        if argument.eq_ignore_ascii_case(b"LEFT") {
+            Some(End::Left)
+        } else if argument.eq_ignore_ascii_case(b"RIGHT") {
+            Some(End::Right)
+        } else {
+            None
+        }
+    }
+}
+
+/// LPUSH/RPUSH key element [element ...]
+pub fn push(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime, end: End) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let Ok(list) = keyspace.get_or_create::<VecDeque<Vec<u8>>>(&arguments[1], now) else {
+        return Reply::wrong_type();
+    };
+
+    for element in &arguments[2..] {
+        match end {
+            End::Left => list.push_front(element.clone()),
+            End::Right => list.push_back(element.clone()),
+        }
+    }
+
+    Reply::Integer(list.len() as i64)
+}
+
+/// LPOP/RPOP key [count]
+pub fn pop(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime, end: End) -> Reply {
+    if arguments.len() != 2 && arguments.len() != 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let count = match arguments.get(2).map(|count| parse_integer(count)) {
+        None => None,
+        Some(Some(count)) if count >= 0 => Some(count as usize),
+        Some(_) => return Reply::error("ERR value is out of range, must be positive"),
+    };
+
+    let list = match keyspace.get_typed_mut::<VecDeque<Vec<u8>>>(&arguments[1], now) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return Reply::NilArray,
+        Ok(None) => return Reply::Nil,
+        Err(_) => return Reply::wrong_type(),
+    };
+
+    let mut popped = Vec::new();
+    for _ in 0..count.unwrap_or(1) {
+        let element = match end {
+            End::Left => list.pop_front(),
+            End::Right => list.pop_back(),
+        };
+        match element {
+            Some(element) => popped.push(element),
+            None => break,
+        }
+    }
+
+    keyspace.remove_if_empty(&arguments[1]);
+
+    match count {
+        Some(_) => Reply::bulk_array(popped),
+        None => popped.pop().map(Reply::bulk).unwrap_or(Reply::Nil),
+    }
+}
+
+/// LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+pub fn lmove(shards: &mut Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 5 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let (Some(from), Some(to)) = (End::parse(&arguments[3]), End::parse(&arguments[4])) else {
+        return Reply::syntax_error();
+    };
+    let (source, destination) = (&arguments[1], &arguments[2]);
+
+    // nothing is popped when the element cannot be pushed
+    if shards
+        .keyspace(destination)
+        .get_typed::<List>(destination, now)
+        .is_err()
+    {
+        return Reply::wrong_type();
+    }
+
+    let list = match shards.keyspace(source).get_typed_mut::<List>(source, now) {
+        Ok(Some(list)) => list,
+        Ok(None) => return Reply::Nil,
+        Err(_) => return Reply::wrong_type(),
+    };
+    let element = match from {
+        End::Left => list.pop_front(),
+        End::Right => list.pop_back(),
+    }
+    .expect("lists are never empty");
+    shards.keyspace(source).remove_if_empty(source);
+
+    let list = shards
+        .keyspace(destination)
+        .get_or_create::<List>(destination, now)
+        .expect("the destination has just been checked");
+    match to {
+        End::Left => list.push_front(element.clone()),
+        End::Right => list.push_back(element.clone()),
+    }
+
+    Reply::bulk(element)
+}
+
+/// LRANGE key start stop
+pub fn lrange(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let (Some(start), Some(stop)) = (parse_integer(&arguments[2]), parse_integer(&arguments[3]))
+    else {
+        return Reply::not_an_integer();
+    };
+
+    match keyspace.get_typed::<VecDeque<Vec<u8>>>(&arguments[1], now) {
+        Ok(Some(list)) => match normalize_range(start, stop, list.len()) {
+            Some((start, stop)) => Reply::bulk_array(list.range(start..=stop).cloned()),
+            None => Reply::Array(vec![]),
+        },
+        Ok(None) => Reply::Array(vec![]),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// LLEN key
+pub fn llen(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_typed::<VecDeque<Vec<u8>>>(&arguments[1], now) {
+        Ok(list) => Reply::Integer(list.map_or(0, |list| list.len()) as i64),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::{arguments, strings};
+    use crate::redis::database::Database;
+
+    #[test]
+    fn push_and_range() {
+        let mut keyspace = Keyspace::default();
+        let now = SystemTime::now();
+
+        let reply = push(
+            &mut keyspace,
+            &arguments(&["LPUSH", "l", "b", "a"]),
+            now,
+            End::Left,
+        );
+        assert_eq!(reply, Reply::Integer(2));
+        let reply = push(
+            &mut keyspace,
+            &arguments(&["RPUSH", "l", "c"]),
+            now,
+            End::Right,
+        );
+        assert_eq!(reply, Reply::Integer(3));
+
+        let reply = lrange(&mut keyspace, &arguments(&["LRANGE", "l", "0", "-1"]), now);
+        assert_eq!(reply, Reply::bulk_array(["a", "b", "c"]));
+        let reply = lrange(&mut keyspace, &arguments(&["LRANGE", "l", "-2", "10"]), now);
+        assert_eq!(reply, Reply::bulk_array(["b", "c"]));
+        assert_eq!(
+            llen(&mut keyspace, &arguments(&["LLEN", "l"]), now),
+            Reply::Integer(3)
+        );
+    }
+
+    #[test]
+    fn pop_removes_empty_list() {
+        let mut keyspace = Keyspace::default();
+        let now = SystemTime::now();
+        push(
+            &mut keyspace,
+            &arguments(&["RPUSH", "l", "a", "b", "c"]),
+            now,
+            End::Right,
+        );
+
+        let reply = pop(&mut keyspace, &arguments(&["RPOP", "l"]), now, End::Right);
+        assert_eq!(reply, Reply::bulk("c"));
+        let reply = pop(
+            &mut keyspace,
+            &arguments(&["LPOP", "l", "5"]),
+            now,
+            End::Left,
+        );
+        assert_eq!(reply, Reply::bulk_array(["a", "b"]));
+
+        assert!(keyspace.get(b"l", now).is_none());
+        let reply = pop(&mut keyspace, &arguments(&["LPOP", "l"]), now, End::Left);
+        assert_eq!(reply, Reply::Nil);
+        let reply = pop(
+            &mut keyspace,
+            &arguments(&["LPOP", "l", "1"]),
+            now,
+            End::Left,
+        );
+        assert_eq!(reply, Reply::NilArray);
+    }
+
+    #[test]
+    fn wrong_type() {
+        let mut keyspace = Keyspace::default();
+        let now = SystemTime::now();
+        strings::set(&mut keyspace, &arguments(&["SET", "s", "v"]), now);
+
+        let reply = push(
+            &mut keyspace,
+            &arguments(&["LPUSH", "s", "a"]),
+            now,
+            End::Left,
+        );
+        assert_eq!(reply, Reply::wrong_type());
+        let reply = push(&mut keyspace, &arguments(&["LPUSH", "s"]), now, End::Left);
+        assert_eq!(reply, Reply::wrong_number_of_arguments(b"LPUSH"));
+    }
+
+    #[test]
+    fn lmove_between_lists() {
+        let database = Database::default();
+        let now = SystemTime::now();
+        let mut shards = database.lock(0, [&b"a"[..], b"b", b"s"]);
+        push(
+            shards.keyspace(b"a"),
+            &arguments(&["RPUSH", "a", "1", "2"]),
+            now,
+            End::Right,
+        );
+        strings::set(shards.keyspace(b"s"), &arguments(&["SET", "s", "v"]), now);
+
+        let reply = lmove(
+            &mut shards,
+            &arguments(&["LMOVE", "a", "b", "LEFT", "RIGHT"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk("1"));
+        let reply = lmove(
+            &mut shards,
+            &arguments(&["LMOVE", "a", "b", "right", "left"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk("2"));
+        assert!(shards.keyspace(b"a").get(b"a", now).is_none());
+        let reply = lrange(
+            shards.keyspace(b"b"),
+            &arguments(&["LRANGE", "b", "0", "-1"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk_array(["2", "1"]));
+
+        let reply = lmove(
+            &mut shards,
+            &arguments(&["LMOVE", "a", "b", "LEFT", "LEFT"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Nil);
+        let reply = lmove(
+            &mut shards,
+            &arguments(&["LMOVE", "b", "s", "LEFT", "LEFT"]),
+            now,
+        );
+        assert_eq!(reply, Reply::wrong_type());
+        let reply = lmove(
+            &mut shards,
+            &arguments(&["LMOVE", "b", "a", "UP", "LEFT"]),
+            now,
+        );
+        assert_eq!(reply, Reply::syntax_error());
+    }
+}
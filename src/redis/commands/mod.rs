@@ -0,0 +1,223 @@
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub mod expire;
+pub mod hashes;
+pub mod keys;
+pub mod lists;
+pub mod pubsub;
+pub mod sets;
+pub mod sorted_sets;
+pub mod streams;
+pub mod strings;
+pub mod table;
+
+/// Whether the command modifies the keyspace, successful writes count as changes for the
+/// save rules. Blocking commands are not: they are logged as the command they execute
+/// once served.
+pub fn is_write_command(name: &[u8]) -> bool {
+    table::lookup(name)
+        .is_some_and(|command| command.has(table::WRITE) && !command.has(table::BLOCKING))
+}
+
+/// Whether the command can be sent by a client subscribed to channels or patterns.
+pub fn is_subscriber_command(name: &[u8]) -> bool {
+    matches!(
+        name,
+        b"SUBSCRIBE" | b"UNSUBSCRIBE" | b"PSUBSCRIBE" | b"PUNSUBSCRIBE" | b"PING"
+    )
+}
+
+/// Whether the command controls a transaction, executed right away instead of being
+/// queued after MULTI.
+pub fn is_transaction_command(name: &[u8]) -> bool {
+    matches!(name, b"MULTI" | b"EXEC" | b"DISCARD" | b"WATCH")
+}
+
+/// Rewrites relative expire times of a successfully executed command to absolute ones,
+/// so that replaying it later has the same effect.
+pub fn with_absolute_expiry(arguments: &[Vec<u8>], now: SystemTime) -> Cow<'_, [Vec<u8>]> {
+    let absolute = |value: &[u8], unit| {
+        parse_integer(value)
+            .and_then(|value| expire_time(value, unit, true, now))
+            .map(|at| unix_millis(at).to_string().into_bytes())
+    };
+
+    match arguments[0].to_ascii_uppercase().as_slice() {
+        b"EXPIRE" | b"PEXPIRE" if arguments.len() >= 3 => {
+            let unit = if arguments[0].eq_ignore_ascii_case(b"EXPIRE") {
+                TimeUnit::Seconds
+            } else {
+                TimeUnit::Milliseconds
+            };
+            let Some(at) = absolute(&arguments[2], unit) else {
+                return Cow::Borrowed(arguments);
+            };
+
+            let mut rewritten = arguments.to_vec();
+            rewritten[0] = b"PEXPIREAT".to_vec();
+            rewritten[2] = at;
+            Cow::Owned(rewritten)
+        }
+        name @ (b"SET" | b"GETEX") => {
+            let first_option = if name == b"SET" { 3 } else { 2 };
+            let mut rewritten = arguments.to_vec();
+            for i in first_option..rewritten.len().saturating_sub(1) {
+                let unit = match rewritten[i].to_ascii_uppercase().as_slice() {
+                    b"EX" => TimeUnit::Seconds,
+                    b"PX" => TimeUnit::Milliseconds,
+                    _ => continue,
+                };
+                if let Some(at) = absolute(&rewritten[i + 1], unit) {
+                    rewritten[i] = b"PXAT".to_vec();
+                    rewritten[i + 1] = at;
+                }
+            }
+            Cow::Owned(rewritten)
+        }
+        _ => Cow::Borrowed(arguments),
+    }
+}
+
+pub fn parse_integer(argument: &[u8]) -> Option<i64> {
+    std::str::from_utf8(argument).ok()?.parse().ok()
+}
+
+/// Parses a float as redis does, accepting `inf`, `+inf` and `-inf` but not `nan`.
+pub fn parse_float(argument: &[u8]) -> Option<f64> {
+    let argument = std::str::from_utf8(argument).ok()?;
+
+    match argument.to_ascii_lowercase().as_str() {
+        "inf" | "+inf" | "infinity" | "+infinity" => Some(f64::INFINITY),
+        "-inf" | "-infinity" => Some(f64::NEG_INFINITY),
+        _ => argument
+            .parse::<f64>()
+            .ok()
+            .filter(|f| !f.is_nan() && !argument.starts_with(char::is_whitespace)),
+    }
+}
+
+pub fn format_float(value: f64) -> String {
+    if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as i64,
+        Err(before) => -(before.duration().as_millis() as i64),
+    }
+}
+
+pub fn from_unix_millis(millis: i64) -> SystemTime {
+    if millis >= 0 {
+        UNIX_EPOCH + Duration::from_millis(millis as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_millis(millis.unsigned_abs())
+    }
+}
+
+/// Unit of the time argument of expire related commands and options.
+#[derive(Clone, Copy)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl TimeUnit {
+    fn to_millis(self, value: i64) -> Option<i64> {
+        match self {
+            TimeUnit::Seconds => value.checked_mul(1000),
+            TimeUnit::Milliseconds => Some(value),
+        }
+    }
+}
+
+/// Converts an expire time argument, either relative to `now` or an absolute unix time,
+/// to the point in time it refers to. `None` if it cannot be represented.
+pub fn expire_time(
+    value: i64,
+    unit: TimeUnit,
+    relative: bool,
+    now: SystemTime,
+) -> Option<SystemTime> {
+    let millis = unit.to_millis(value)?;
+    let base = if relative { unix_millis(now) } else { 0 };
+
+    base.checked_add(millis).map(from_unix_millis)
+}
+
+/// Converts an inclusive range of possibly negative indexes, counting from the end,
+/// to a range of valid positions for a sequence of `len` elements.
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+
+    if start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+#[cfg(test)]
+pub fn arguments(arguments: &[&str]) -> Vec<Vec<u8>> {
+    arguments.iter().map(|a| a.as_bytes().to_vec()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_range_test() {
+        assert_eq!(normalize_range(0, -1, 3), Some((0, 2)));
+        assert_eq!(normalize_range(-2, 10, 3), Some((1, 2)));
+        assert_eq!(normalize_range(-10, 0, 3), Some((0, 0)));
+        assert_eq!(normalize_range(2, 1, 3), None);
+        assert_eq!(normalize_range(3, 5, 3), None);
+        assert_eq!(normalize_range(0, -1, 0), None);
+    }
+
+    #[test]
+    fn absolute_expiry() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            with_absolute_expiry(&arguments(&["EXPIRE", "k", "10", "NX"]), now),
+            arguments(&["PEXPIREAT", "k", "1700000010000", "NX"])
+        );
+        assert_eq!(
+            with_absolute_expiry(&arguments(&["SET", "k", "v", "GET", "px", "500"]), now),
+            arguments(&["SET", "k", "v", "GET", "PXAT", "1700000000500"])
+        );
+        assert_eq!(
+            with_absolute_expiry(&arguments(&["GETEX", "k", "EX", "1"]), now),
+            arguments(&["GETEX", "k", "PXAT", "1700000001000"])
+        );
+        assert_eq!(
+            with_absolute_expiry(&arguments(&["PEXPIREAT", "k", "1"]), now),
+            arguments(&["PEXPIREAT", "k", "1"])
+        );
+    }
+
+    #[test]
+    fn parse_float_test() {
+        assert_eq!(parse_float(b"1.5"), Some(1.5));
+        assert_eq!(parse_float(b"-inf"), Some(f64::NEG_INFINITY));
+        assert_eq!(parse_float(b"nan"), None);
+        assert_eq!(parse_float(b"abc"), None);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::redis::broker::PubSub;
+use crate::redis::client::Client;
+use crate::redis::resp::Reply;
+
+/// Which subscriptions a (un)subscribe command changes.
+#[derive(Clone, Copy)]
+pub enum Target {
+    Channels,
+    Patterns,
+}
+
+/// SUBSCRIBE channel [channel ...] and PSUBSCRIBE pattern [pattern ...]
+pub fn subscribe(
+    broker: &PubSub,
+    client: &mut Client,
+    arguments: &[Vec<u8>],
+    target: Target,
+) -> Reply {
+    if arguments.len() < 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let replies = arguments[1..]
+        .iter()
+        .map(|name| {
+            match target {
+                Target::Channels => broker.subscribe(client, name),
+                Target::Patterns => broker.psubscribe(client, name),
+            };
+            confirmation(target, "subscribe", Some(name), client)
+        })
+        .collect();
+
+    Reply::Multiple(replies)
+}
+
+/// UNSUBSCRIBE [channel ...] and PUNSUBSCRIBE [pattern ...], from every channel or
+/// pattern when none is given.
+pub fn unsubscribe(
+    broker: &PubSub,
+    client: &mut Client,
+    arguments: &[Vec<u8>],
+    target: Target,
+) -> Reply {
+    let names = if arguments.len() > 1 {
+        arguments[1..].to_vec()
+    } else {
+        match target {
+            Target::Channels => client.channels.iter().cloned().collect(),
+            Target::Patterns => client.patterns.iter().cloned().collect(),
+        }
+    };
+
+    if names.is_empty() {
+        return confirmation(target, "unsubscribe", None, client);
+    }
+
+    let replies = names
+        .iter()
+        .map(|name| {
+            match target {
+                Target::Channels => broker.unsubscribe(client, name),
+                Target::Patterns => broker.punsubscribe(client, name),
+            };
+            confirmation(target, "unsubscribe", Some(name), client)
+        })
+        .collect();
+
+    Reply::Multiple(replies)
+}
+
+/// PUBLISH channel message
+pub fn publish(broker: &PubSub, arguments: &[Vec<u8>]) -> Reply {
+    if arguments.len() != 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    Reply::Integer(broker.publish(&arguments[1], &arguments[2]) as i64)
+}
+
+/// PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT
+pub fn pubsub(broker: &PubSub, arguments: &[Vec<u8>]) -> Reply {
+    let Some(subcommand) = arguments.get(1) else {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    };
+
+    match subcommand.to_ascii_uppercase().as_slice() {
+        b"CHANNELS" if arguments.len() <= 3 => {
+            Reply::bulk_array(broker.channels(arguments.get(2).map(Vec::as_slice)))
+        }
+        b"NUMSUB" => Reply::Array(
+            arguments[2..]
+                .iter()
+                .flat_map(|channel| {
+                    [
+                        Reply::bulk(channel.clone()),
+                        Reply::Integer(broker.numsub(channel) as i64),
+                    ]
+                })
+                .collect(),
+        ),
+        b"NUMPAT" if arguments.len() == 2 => Reply::Integer(broker.numpat() as i64),
+        b"CHANNELS" | b"NUMPAT" => Reply::error(format!(
+            "ERR wrong number of arguments for 'pubsub|{}' command",
+            String::from_utf8_lossy(subcommand).to_lowercase()
+        )),
+        _ => Reply::error(format!(
+            "ERR unknown subcommand '{}'. Try PUBSUB HELP.",
+            String::from_utf8_lossy(subcommand)
+        )),
+    }
+}
+
+/// The reply confirming a change of subscription, with the number of subscriptions left.
+fn confirmation(target: Target, action: &str, name: Option<&[u8]>, client: &Client) -> Reply {
+    let kind = match target {
+        Target::Channels => action.to_string(),
+        Target::Patterns => format!("p{}", action),
+    };
+
+    Reply::Push(vec![
+        Reply::bulk(kind),
+        name.map_or(Reply::Nil, Reply::bulk),
+        Reply::Integer(client.subscriptions() as i64),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+
+    fn confirmation_reply(kind: &str, name: &str, count: i64) -> Reply {
+        Reply::Push(vec![
+            Reply::bulk(kind),
+            Reply::bulk(name),
+            Reply::Integer(count),
+        ])
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe() {
+        let broker = PubSub::default();
+        let mut client = Client::default();
+
+        assert_eq!(
+            subscribe(
+                &broker,
+                &mut client,
+                &arguments(&["SUBSCRIBE", "a", "b"]),
+                Target::Channels
+            ),
+            Reply::Multiple(vec![
+                confirmation_reply("subscribe", "a", 1),
+                confirmation_reply("subscribe", "b", 2),
+            ])
+        );
+        assert_eq!(
+            subscribe(
+                &broker,
+                &mut client,
+                &arguments(&["PSUBSCRIBE", "a*"]),
+                Target::Patterns
+            ),
+            Reply::Multiple(vec![confirmation_reply("psubscribe", "a*", 3)])
+        );
+
+        let Reply::Multiple(replies) = unsubscribe(
+            &broker,
+            &mut client,
+            &arguments(&["UNSUBSCRIBE"]),
+            Target::Channels,
+        ) else {
+            panic!("expected one reply per channel");
+        };
+        assert_eq!(replies.len(), 2);
+        assert_eq!(
+            unsubscribe(
+                &broker,
+                &mut client,
+                &arguments(&["UNSUBSCRIBE"]),
+                Target::Channels
+            ),
+            Reply::Push(vec![
+                Reply::bulk("unsubscribe"),
+                Reply::Nil,
+                Reply::Integer(1)
+            ])
+        );
+        assert_eq!(
+            unsubscribe(
+                &broker,
+                &mut client,
+                &arguments(&["PUNSUBSCRIBE", "a*"]),
+                Target::Patterns
+            ),
+            Reply::Multiple(vec![confirmation_reply("punsubscribe", "a*", 0)])
+        );
+    }
+
+    #[test]
+    fn publish_and_introspection() {
+        let broker = PubSub::default();
+        let mut client = Client::default();
+        broker.subscribe(&mut client, b"news");
+
+        assert_eq!(
+            publish(&broker, &arguments(&["PUBLISH", "news", "hi"])),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            publish(&broker, &arguments(&["PUBLISH", "other", "hi"])),
+            Reply::Integer(0)
+        );
+        assert_eq!(
+            pubsub(&broker, &arguments(&["PUBSUB", "CHANNELS", "n*"])),
+            Reply::bulk_array(["news"])
+        );
+        assert_eq!(
+            pubsub(&broker, &arguments(&["PUBSUB", "NUMSUB", "news", "other"])),
+            Reply::Array(vec![
+                Reply::bulk("news"),
+                Reply::Integer(1),
+                Reply::bulk("other"),
+                Reply::Integer(0)
+            ])
+        );
+        assert_eq!(
+            pubsub(&broker, &arguments(&["PUBSUB", "NUMPAT"])),
+            Reply::Integer(0)
+        );
+        assert!(matches!(
+            pubsub(&broker, &arguments(&["PUBSUB", "NOPE"])),
+            Reply::Error(_)
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::redis::keyspace::Keyspace;
+use crate::redis::resp::Reply;
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+type Set = HashSet<Vec<u8>>;
+
+/// SADD key member [member ...]
+pub fn sadd(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let Ok(set) = keyspace.get_or_create::<Set>(&arguments[1], now) else {
+        return Reply::wrong_type();
+    };
+
+    let added = arguments[2..]
+        .iter()
+        .filter(|member| set.insert(member.to_vec()))
+        .count();
+
+    Reply::Integer(added as i64)
+}
+
+/// SREM key member [member ...]
+pub fn srem(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let set = match keyspace.get_typed_mut::<Set>(&arguments[1], now) {
+        Ok(Some(set)) => set,
+        Ok(None) => return Reply::Integer(0),
+        Err(_) => return Reply::wrong_type(),
+    };
+
+    let removed = arguments[2..]
+        .iter()
+        .filter(|member| set.remove(*member))
+        .count();
+
+    keyspace.remove_if_empty(&arguments[1]);
+
+    Reply::Integer(removed as i64)
+}
+
+/// SMEMBERS key
+pub fn smembers(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_typed::<Set>(&arguments[1], now) {
+        Ok(set) => Reply::Set(
+            set.into_iter()
+                .flatten()
+                .cloned()
+                .map(Reply::Bulk)
+                .collect(),
+        ),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// SISMEMBER key member
+pub fn sismember(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_typed::<Set>(&arguments[1], now) {
+        Ok(set) => Reply::Integer(set.is_some_and(|set| set.contains(&arguments[2])) as i64),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+
+    #[test]
+    fn add_and_remove_members() {
+        let mut keyspace = Keyspace::default();
+        let now = SystemTime::now();
+
+        let reply = sadd(
+            &mut keyspace,
+            &arguments(&["SADD", "s", "a", "b", "a"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Integer(2));
+        assert_eq!(
+            sismember(&mut keyspace, &arguments(&["SISMEMBER", "s", "a"]), now),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            sismember(&mut keyspace, &arguments(&["SISMEMBER", "s", "c"]), now),
+            Reply::Integer(0)
+        );
+
+        let reply = srem(&mut keyspace, &arguments(&["SREM", "s", "a", "c"]), now);
+        assert_eq!(reply, Reply::Integer(1));
+        assert_eq!(
+            smembers(&mut keyspace, &arguments(&["SMEMBERS", "s"]), now),
+            Reply::Set(vec![Reply::bulk("b")])
+        );
+
+        srem(&mut keyspace, &arguments(&["SREM", "s", "b"]), now);
+        assert!(keyspace.get(b"s", now).is_none());
+    }
+}
@@ -0,0 +1,496 @@
+use super::{format_float, normalize_range, parse_float, parse_integer};
+use crate::redis::keyspace::Keyspace;
+use crate::redis::resp::Reply;
+use crate::redis::sorted_set::SortedSet;
+use std::ops::Bound;
+use std::time::SystemTime;
+
+/// ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]
+pub fn zadd(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+        (false, false, false, false, false, false);
+    let mut position = 2;
+    while let Some(option) = arguments.get(position) {
+        match option.to_ascii_uppercase().as_slice() {
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            b"GT" => gt = true,
+            b"LT" => lt = true,
+            b"CH" => ch = true,
+            b"INCR" => incr = true,
+            _ => break,
+        }
+        position += 1;
+    }
+
+    let pairs = &arguments[position..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Reply::syntax_error();
+    }
+    if nx && xx {
+        return Reply::error("ERR XX and NX options at the same time are not compatible");
+    }
+    if (gt && lt) || (nx && (gt || lt)) {
+        return Reply::error("ERR GT, LT, and/or NX options at the same time are not compatible");
+    }
+    if incr && pairs.len() > 2 {
+        return Reply::error("ERR INCR option supports a single increment-element pair");
+    }
+
+    let Some(scores) = pairs
+        .chunks(2)
+        .map(|pair| parse_float(&pair[0]))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Reply::not_a_float();
+    };
+
+    let sorted_set = match keyspace.get_typed_mut::<SortedSet>(&arguments[1], now) {
+        Ok(Some(sorted_set)) => sorted_set,
+        Ok(None) if xx => return if incr { Reply::Nil } else { Reply::Integer(0) },
+        Ok(None) => keyspace
+            .get_or_create::<SortedSet>(&arguments[1], now)
+            .expect("key has just been checked"),
+        Err(_) => return Reply::wrong_type(),
+    };
+
+    let (mut added, mut changed) = (0, 0);
+    let mut incremented = None;
+    for (pair, score) in pairs.chunks(2).zip(scores) {
+        let member = &pair[1];
+        let current = sorted_set.score(member);
+
+        if (nx && current.is_some()) || (xx && current.is_none()) {
+            continue;
+        }
+
+        let score = match (incr, current) {
+            (true, Some(current)) => current + score,
+            _ => score,
+        };
+        if score.is_nan() {
+            return Reply::error("ERR resulting score is not a number (NaN)");
+        }
+
+        if let Some(current) = current {
+            if (gt && score <= current) || (lt && score >= current) {
+                continue;
+            }
+            if score != current {
+                changed += 1;
+            }
+        } else {
+            added += 1;
+        }
+
+        sorted_set.insert(member.clone(), score);
+        incremented = Some(score);
+    }
+
+    keyspace.remove_if_empty(&arguments[1]);
+
+    if incr {
+        incremented.map(Reply::Double).unwrap_or(Reply::Nil)
+    } else if ch {
+        Reply::Integer(added + changed)
+    } else {
+        Reply::Integer(added)
+    }
+}
+
+/// ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+pub fn zrange(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let mut range = RangeOptions::default();
+    let mut options = arguments[4..].iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"BYSCORE" => range.by = RangeBy::Score,
+            b"BYLEX" => range.by = RangeBy::Lex,
+            b"REV" => range.rev = true,
+            b"WITHSCORES" => range.with_scores = true,
+            b"LIMIT" => match parse_limit(options.next(), options.next()) {
+                Some(limit) => range.limit = Some(limit),
+                None => return Reply::not_an_integer(),
+            },
+            _ => return Reply::syntax_error(),
+        }
+    }
+
+    if range.limit.is_some() && range.by == RangeBy::Rank {
+        return Reply::error(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+        );
+    }
+    if range.with_scores && range.by == RangeBy::Lex {
+        return Reply::error(
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX",
+        );
+    }
+
+    range.reply(keyspace, arguments, now)
+}
+
+/// ZRANGEBYSCORE key min max [WITHSCORES] [LIMIT offset count]
+pub fn zrangebyscore(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let mut range = RangeOptions {
+        by: RangeBy::Score,
+        ..Default::default()
+    };
+    let mut options = arguments[4..].iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"WITHSCORES" => range.with_scores = true,
+            b"LIMIT" => match parse_limit(options.next(), options.next()) {
+                Some(limit) => range.limit = Some(limit),
+                None => return Reply::not_an_integer(),
+            },
+            _ => return Reply::syntax_error(),
+        }
+    }
+
+    range.reply(keyspace, arguments, now)
+}
+
+/// ZRANK key member [WITHSCORE]
+pub fn zrank(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 3 && arguments.len() != 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let with_score = match arguments.get(3) {
+        None => false,
+        Some(option) if option.eq_ignore_ascii_case(b"WITHSCORE") => true,
+        Some(_) => return Reply::syntax_error(),
+    };
+
+    let sorted_set = match keyspace.get_typed::<SortedSet>(&arguments[1], now) {
+        Ok(sorted_set) => sorted_set,
+        Err(_) => return Reply::wrong_type(),
+    };
+
+    let rank = sorted_set.and_then(|sorted_set| {
+        Some((
+            sorted_set.rank(&arguments[2])?,
+            sorted_set.score(&arguments[2])?,
+        ))
+    });
+
+    match (rank, with_score) {
+        (Some((rank, _)), false) => Reply::Integer(rank as i64),
+        (Some((rank, score)), true) => {
+            Reply::Array(vec![Reply::Integer(rank as i64), Reply::Double(score)])
+        }
+        (None, false) => Reply::Nil,
+        (None, true) => Reply::NilArray,
+    }
+}
+
+/// ZREM key member [member ...]
+pub fn zrem(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let sorted_set = match keyspace.get_typed_mut::<SortedSet>(&arguments[1], now) {
+        Ok(Some(sorted_set)) => sorted_set,
+        Ok(None) => return Reply::Integer(0),
+        Err(_) => return Reply::wrong_type(),
+    };
+
+    let removed = arguments[2..]
+        .iter()
+        .filter(|member| sorted_set.remove(member).is_some())
+        .count();
+
+    keyspace.remove_if_empty(&arguments[1]);
+
+    Reply::Integer(removed as i64)
+}
+
+#[derive(Default, PartialEq)]
+enum RangeBy {
+    #[default]
+    Rank,
+    Score,
+    Lex,
+}
+
+#[derive(Default)]
+struct RangeOptions {
+    by: RangeBy,
+    rev: bool,
+    limit: Option<(usize, Option<usize>)>,
+    with_scores: bool,
+}
+
+impl RangeOptions {
+    fn reply(&self, keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+        let sorted_set = match keyspace.get_typed::<SortedSet>(&arguments[1], now) {
+            Ok(Some(sorted_set)) => sorted_set,
+            Ok(None) => &SortedSet::default(),
+            Err(_) => return Reply::wrong_type(),
+        };
+
+        let (start, stop) = if self.rev {
+            (&arguments[3], &arguments[2])
+        } else {
+            (&arguments[2], &arguments[3])
+        };
+
+        let members: Box<dyn DoubleEndedIterator<Item = (&[u8], f64)>> = match self.by {
+            RangeBy::Rank => {
+                let (Some(start), Some(stop)) = (parse_integer(start), parse_integer(stop)) else {
+                    return Reply::not_an_integer();
+                };
+                // with REV the indexes count from the highest score
+                let (start, stop) = if self.rev {
+                    (stop, start)
+                } else {
+                    (start, stop)
+                };
+                let ordered: Box<dyn Iterator<Item = (&[u8], f64)>> = if self.rev {
+                    Box::new(sorted_set.iter().rev())
+                } else {
+                    Box::new(sorted_set.iter())
+                };
+                return match normalize_range(start, stop, sorted_set.len()) {
+                    Some((start, stop)) => {
+                        self.to_reply(ordered.skip(start).take(stop - start + 1))
+                    }
+                    None => Reply::Array(vec![]),
+                };
+            }
+            RangeBy::Score => {
+                let (Some(min), Some(max)) = (parse_score_bound(start), parse_score_bound(stop))
+                else {
+                    return Reply::error("ERR min or max is not a float");
+                };
+                Box::new(sorted_set.range_by_score(min, max))
+            }
+            RangeBy::Lex => {
+                let (Some(min), Some(max)) = (parse_lex_bound(start), parse_lex_bound(stop)) else {
+                    return Reply::error("ERR min or max not valid string range item");
+                };
+                Box::new(sorted_set.range_by_lex(min, max))
+            }
+        };
+
+        let members: Box<dyn Iterator<Item = (&[u8], f64)>> = if self.rev {
+            Box::new(members.rev())
+        } else {
+            members
+        };
+
+        match self.limit {
+            Some((offset, Some(count))) => self.to_reply(members.skip(offset).take(count)),
+            Some((offset, None)) => self.to_reply(members.skip(offset)),
+            None => self.to_reply(members),
+        }
+    }
+
+    fn to_reply<'a>(&self, members: impl Iterator<Item = (&'a [u8], f64)>) -> Reply {
+        let mut reply = Vec::new();
+        for (member, score) in members {
+            reply.push(Reply::bulk(member));
+            if self.with_scores {
+                reply.push(Reply::bulk(format_float(score)));
+            }
+        }
+
+        Reply::Array(reply)
+    }
+}
+
+/// Parses `LIMIT offset count`, a negative count meaning all the remaining elements.
+fn parse_limit(
+    offset: Option<&Vec<u8>>,
+    count: Option<&Vec<u8>>,
+) -> Option<(usize, Option<usize>)> {
+    let offset = parse_integer(offset?)?;
+    let count = parse_integer(count?)?;
+
+    Some((
+        offset.max(0) as usize,
+        (count >= 0).then_some(count as usize),
+    ))
+}
+
+/// Parses a score interval bound such as `1.5`, `(1.5`, `-inf` or `+inf`.
+fn parse_score_bound(argument: &[u8]) -> Option<Bound<f64>> {
+    match argument.strip_prefix(b"(") {
+        Some(exclusive) => parse_float(exclusive).map(Bound::Excluded),
+        None => parse_float(argument).map(Bound::Included),
+    }
+}
+
+/// Parses a lexicographical interval bound such as `[a`, `(a`, `-` or `+`.
+fn parse_lex_bound(argument: &[u8]) -> Option<Bound<&[u8]>> {
+    match argument.split_first() {
+        Some((b'[', member)) => Some(Bound::Included(member)),
+        Some((b'(', member)) => Some(Bound::Excluded(member)),
+        Some((b'-', [])) | Some((b'+', [])) => Some(Bound::Unbounded),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+
+    fn keyspace_with_scores(now: SystemTime) -> Keyspace {
+        let mut keyspace = Keyspace::default();
+        let reply = zadd(
+            &mut keyspace,
+            &arguments(&["ZADD", "z", "1", "a", "2", "b", "3", "c"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Integer(3));
+        keyspace
+    }
+
+    #[test]
+    fn zadd_options() {
+        let now = SystemTime::now();
+        let mut keyspace = keyspace_with_scores(now);
+
+        let reply = zadd(
+            &mut keyspace,
+            &arguments(&["ZADD", "z", "NX", "5", "a", "4", "d"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Integer(1));
+        let reply = zadd(
+            &mut keyspace,
+            &arguments(&["ZADD", "z", "XX", "CH", "5", "a", "6", "e"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Integer(1));
+        let reply = zadd(
+            &mut keyspace,
+            &arguments(&["ZADD", "z", "GT", "CH", "1", "a"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Integer(0));
+        let reply = zadd(
+            &mut keyspace,
+            &arguments(&["ZADD", "z", "INCR", "2.5", "a"]),
+            now,
+        );
+        assert_eq!(reply, Reply::Double(7.5));
+        let reply = zadd(
+            &mut keyspace,
+            &arguments(&["ZADD", "z", "NX", "XX", "1", "a"]),
+            now,
+        );
+        assert_eq!(
+            reply,
+            Reply::error("ERR XX and NX options at the same time are not compatible")
+        );
+        let reply = zadd(&mut keyspace, &arguments(&["ZADD", "z", "x", "a"]), now);
+        assert_eq!(reply, Reply::not_a_float());
+    }
+
+    #[test]
+    fn zrange_by_rank() {
+        let now = SystemTime::now();
+        let mut keyspace = keyspace_with_scores(now);
+
+        let reply = zrange(&mut keyspace, &arguments(&["ZRANGE", "z", "0", "-1"]), now);
+        assert_eq!(reply, Reply::bulk_array(["a", "b", "c"]));
+        let reply = zrange(
+            &mut keyspace,
+            &arguments(&["ZRANGE", "z", "0", "1", "REV", "WITHSCORES"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk_array(["c", "3", "b", "2"]));
+    }
+
+    #[test]
+    fn zrange_by_score() {
+        let now = SystemTime::now();
+        let mut keyspace = keyspace_with_scores(now);
+
+        let reply = zrange(
+            &mut keyspace,
+            &arguments(&["ZRANGE", "z", "(1", "+inf", "BYSCORE"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk_array(["b", "c"]));
+        let reply = zrange(
+            &mut keyspace,
+            &arguments(&[
+                "ZRANGE", "z", "+inf", "-inf", "BYSCORE", "REV", "LIMIT", "1", "1",
+            ]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk_array(["b"]));
+        let reply = zrangebyscore(
+            &mut keyspace,
+            &arguments(&["ZRANGEBYSCORE", "z", "2", "3", "WITHSCORES"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk_array(["b", "2", "c", "3"]));
+        let reply = zrangebyscore(
+            &mut keyspace,
+            &arguments(&["ZRANGEBYSCORE", "z", "x", "3"]),
+            now,
+        );
+        assert_eq!(reply, Reply::error("ERR min or max is not a float"));
+    }
+
+    #[test]
+    fn zrange_by_lex() {
+        let now = SystemTime::now();
+        let mut keyspace = keyspace_with_scores(now);
+
+        let reply = zrange(
+            &mut keyspace,
+            &arguments(&["ZRANGE", "z", "[b", "+", "BYLEX"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk_array(["b", "c"]));
+    }
+
+    #[test]
+    fn zrank_and_zrem() {
+        let now = SystemTime::now();
+        let mut keyspace = keyspace_with_scores(now);
+
+        assert_eq!(
+            zrank(&mut keyspace, &arguments(&["ZRANK", "z", "c"]), now),
+            Reply::Integer(2)
+        );
+        assert_eq!(
+            zrank(
+                &mut keyspace,
+                &arguments(&["ZRANK", "z", "b", "WITHSCORE"]),
+                now
+            ),
+            Reply::Array(vec![Reply::Integer(1), Reply::Double(2.0)])
+        );
+        assert_eq!(
+            zrank(&mut keyspace, &arguments(&["ZRANK", "z", "x"]), now),
+            Reply::Nil
+        );
+
+        let reply = zrem(&mut keyspace, &arguments(&["ZREM", "z", "a", "x"]), now);
+        assert_eq!(reply, Reply::Integer(1));
+        assert_eq!(
+            zrank(&mut keyspace, &arguments(&["ZRANK", "z", "c"]), now),
+            Reply::Integer(1)
+        );
+    }
+}
@@ -0,0 +1,1495 @@
+use super::{from_unix_millis, parse_integer, unix_millis};
+use crate::redis::database::Shards;
+use crate::redis::keyspace::Keyspace;
+use crate::redis::resp::Reply;
+use crate::redis::stream::{Fields, PendingEntry, Stream, StreamId};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+/// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] *|id field value
+/// [field value ...]
+pub fn xadd(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 5 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let options = match parse_add(arguments) {
+        Ok(options) => options,
+        Err(reply) => return reply,
+    };
+    let new_id = match parse_new_id(&arguments[options.id_position]) {
+        Ok(new_id) => new_id,
+        Err(reply) => return reply,
+    };
+    if new_id == NewId::Explicit(StreamId::MIN) {
+        return Reply::error("ERR The ID specified in XADD must be greater than 0-0");
+    }
+
+    let stream = match keyspace.get_typed_mut::<Stream>(&arguments[1], now) {
+        Ok(Some(stream)) => stream,
+        Ok(None) if options.nomkstream => return Reply::Nil,
+        Ok(None) => keyspace
+            .get_or_create::<Stream>(&arguments[1], now)
+            .expect("key has just been checked"),
+        Err(_) => return Reply::wrong_type(),
+    };
+
+    let too_small = || {
+        Reply::error(
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+        )
+    };
+    let last_id = stream.last_id();
+    let id =
+        match new_id {
+            NewId::Auto => match stream.next_id(unix_millis(now).max(0) as u64) {
+                Some(id) => id,
+                None => return Reply::error(
+                    "ERR The stream has exhausted the last possible ID, unable to add more items",
+                ),
+            },
+            NewId::Sequence(ms) if ms == last_id.ms => match last_id.next() {
+                Some(id) if id.ms == ms => id,
+                _ => return too_small(),
+            },
+            NewId::Sequence(ms) if ms > last_id.ms => StreamId::new(ms, 0),
+            NewId::Explicit(id) if id > last_id => id,
+            _ => return too_small(),
+        };
+
+    let fields = arguments[options.id_position + 1..]
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    stream.add(id, fields);
+    if let Some(trim) = options.trim {
+        trim.apply(stream);
+    }
+
+    Reply::bulk(id.to_string())
+}
+
+/// Replaces the ID generated by a successful XADD in its arguments, so that replaying it
+/// adds the same entry.
+pub fn with_added_id<'a>(arguments: &'a [Vec<u8>], reply: &Reply) -> Cow<'a, [Vec<u8>]> {
+    if !arguments[0].eq_ignore_ascii_case(b"XADD") {
+        return Cow::Borrowed(arguments);
+    }
+
+    match (reply, parse_add(arguments)) {
+        (Reply::Bulk(id), Ok(options)) => {
+            let mut rewritten = arguments.to_vec();
+            rewritten[options.id_position] = id.clone();
+            Cow::Owned(rewritten)
+        }
+        _ => Cow::Borrowed(arguments),
+    }
+}
+
+/// XLEN key
+pub fn xlen(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_typed::<Stream>(&arguments[1], now) {
+        Ok(Some(stream)) => Reply::Integer(stream.len() as i64),
+        Ok(None) => Reply::Integer(0),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// XRANGE key start end [COUNT count], and XREVRANGE key end start [COUNT count] which
+/// replies the newest entries first.
+pub fn xrange(
+    keyspace: &mut Keyspace,
+    arguments: &[Vec<u8>],
+    now: SystemTime,
+    reverse: bool,
+) -> Reply {
+    if arguments.len() < 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let (start, end) = if reverse {
+        (&arguments[3], &arguments[2])
+    } else {
+        (&arguments[2], &arguments[3])
+    };
+    let (first, last) = match (parse_bound(start, true), parse_bound(end, false)) {
+        (Ok(first), Ok(last)) => (first, last),
+        (Err(reply), _) | (_, Err(reply)) => return reply,
+    };
+    let count = match &arguments[4..] {
+        [] => usize::MAX,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => match parse_integer(count) {
+            Some(count) => count.max(0) as usize,
+            None => return Reply::not_an_integer(),
+        },
+        _ => return Reply::syntax_error(),
+    };
+
+    let stream = match keyspace.get_typed::<Stream>(&arguments[1], now) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return Reply::Array(Vec::new()),
+        Err(_) => return Reply::wrong_type(),
+    };
+    let entries = stream.range(first, last);
+    Reply::Array(if reverse {
+        entries
+            .rev()
+            .take(count)
+            .map(|(id, fields)| entry_reply(id, fields))
+            .collect()
+    } else {
+        entries
+            .take(count)
+            .map(|(id, fields)| entry_reply(id, fields))
+            .collect()
+    })
+}
+
+/// XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+pub fn xtrim(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let is_strategy = |argument: &[u8]| {
+        argument.eq_ignore_ascii_case(b"MAXLEN") || argument.eq_ignore_ascii_case(b"MINID")
+    };
+    if !is_strategy(&arguments[2]) {
+        return Reply::syntax_error();
+    }
+    let trim = match parse_trim(arguments, 2) {
+        Ok((trim, position)) if position == arguments.len() => trim,
+        Ok(_) => return Reply::syntax_error(),
+        Err(reply) => return reply,
+    };
+
+    match keyspace.get_typed_mut::<Stream>(&arguments[1], now) {
+        Ok(Some(stream)) => Reply::Integer(trim.apply(stream) as i64),
+        Ok(None) => Reply::Integer(0),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+///
+/// Replies the entries after the given IDs, `$` standing for the last ID of the stream.
+/// BLOCK is handled by the server, this never blocks.
+pub fn xread(shards: &mut Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    let read = match parse_read(arguments) {
+        Ok(read) => read,
+        Err(reply) => return reply,
+    };
+    let positions = match read.positions() {
+        Ok(positions) => positions,
+        Err(reply) => return reply,
+    };
+
+    let mut replies = Vec::new();
+    for (key, position) in read.keys.iter().zip(positions) {
+        let stream = match shards.keyspace(key).get_typed::<Stream>(key, now) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => continue,
+            Err(_) => return Reply::wrong_type(),
+        };
+        let after = match position {
+            Position::After(id) => id,
+            Position::Last | Position::New => stream.last_id(),
+        };
+        let entries = stream
+            .after(after)
+            .take(read.count)
+            .map(|(id, fields)| entry_reply(id, fields))
+            .collect::<Vec<_>>();
+        if !entries.is_empty() {
+            replies.push(Reply::Array(vec![
+                Reply::bulk(key.clone()),
+                Reply::Array(entries),
+            ]));
+        }
+    }
+
+    if replies.is_empty() {
+        Reply::NilArray
+    } else {
+        Reply::Array(replies)
+    }
+}
+
+/// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key
+/// [key ...] id [id ...]
+///
+/// With the `>` ID, delivers to the consumer the entries no one in its group got yet and
+/// adds them to the pending entries unless NOACK is given. With another ID, replies the
+/// history of the entries pending for the consumer after it. BLOCK is handled by the
+/// server, this never blocks.
+pub fn xreadgroup(shards: &mut Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    let read = match parse_read(arguments) {
+        Ok(read) => read,
+        Err(reply) => return reply,
+    };
+    let positions = match read.positions() {
+        Ok(positions) => positions,
+        Err(reply) => return reply,
+    };
+    let (group, consumer) = read.group.expect("XREADGROUP is parsed with its group");
+
+    // nothing is delivered unless every group exists
+    for key in read.keys {
+        match shards.keyspace(key).get_typed::<Stream>(key, now) {
+            Ok(Some(stream)) if stream.group(group).is_some() => {}
+            Ok(_) => {
+                return Reply::error(format!(
+                "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(group)
+            ))
+            }
+            Err(_) => return Reply::wrong_type(),
+        }
+    }
+
+    let mut replies = Vec::new();
+    for (key, position) in read.keys.iter().zip(positions) {
+        let stream = match shards.keyspace(key).get_typed_mut::<Stream>(key, now) {
+            Ok(Some(stream)) => stream,
+            _ => unreachable!("stream has just been checked"),
+        };
+        stream
+            .group_mut(group)
+            .expect("group has just been checked")
+            .see(consumer, now);
+
+        let entries = match position {
+            Position::New => {
+                let after = stream.group(group).expect("group exists").last_delivered;
+                let delivered = stream
+                    .after(after)
+                    .take(read.count)
+                    .map(|(id, fields)| (id, entry_reply(id, fields)))
+                    .collect::<Vec<_>>();
+                if delivered.is_empty() {
+                    continue;
+                }
+
+                let group = stream.group_mut(group).expect("group exists");
+                delivered
+                    .into_iter()
+                    .map(|(id, entry)| {
+                        group.last_delivered = id;
+                        if !read.noack {
+                            group.deliver(id, consumer, now);
+                        }
+                        entry
+                    })
+                    .collect()
+            }
+            Position::After(after) => {
+                let group = stream.group(group).expect("group exists");
+                group
+                    .pending_of(consumer)
+                    .filter(|(id, _)| *id > after)
+                    .take(read.count)
+                    .map(|(id, _)| match stream.get(id) {
+                        Some(fields) => entry_reply(id, fields),
+                        // deleted since it was delivered
+                        None => Reply::Array(vec![Reply::bulk(id.to_string()), Reply::NilArray]),
+                    })
+                    .collect()
+            }
+            Position::Last => unreachable!("$ is refused by XREADGROUP"),
+        };
+        replies.push(Reply::Array(vec![
+            Reply::bulk(key.clone()),
+            Reply::Array(entries),
+        ]));
+    }
+
+    if replies.is_empty() {
+        Reply::NilArray
+    } else {
+        Reply::Array(replies)
+    }
+}
+
+/// The reply of XGROUP HELP.
+const XGROUP_HELP: &[&str] = &[
+    "XGROUP <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "CREATE <key> <groupname> <id|$> [option]",
+    "    Create a new consumer group. Options are:",
+    "    * MKSTREAM",
+    "      Create the empty stream if it does not exist.",
+    "CREATECONSUMER <key> <groupname> <consumer>",
+    "    Create a new consumer in the specified group.",
+    "DELCONSUMER <key> <groupname> <consumer>",
+    "    Remove the specified consumer.",
+    "DESTROY <key> <groupname>",
+    "    Remove the specified group.",
+    "SETID <key> <groupname> <id|$>",
+    "    Set the current group ID.",
+    "HELP",
+    "    Print this help.",
+];
+
+/// XGROUP CREATE key group id|$ [MKSTREAM], XGROUP SETID key group id|$, XGROUP DESTROY
+/// key group, XGROUP CREATECONSUMER key group consumer, XGROUP DELCONSUMER key group
+/// consumer and XGROUP HELP. The key follows the subcommand, it is only locked when the
+/// arguments are complete.
+pub fn xgroup(shards: &mut Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let subcommand = arguments[1].to_ascii_uppercase();
+    let arity_matches = match subcommand.as_slice() {
+        b"HELP" if arguments.len() == 2 => {
+            return Reply::Array(
+                XGROUP_HELP
+                    .iter()
+                    .map(|line| Reply::Simple(line.to_string()))
+                    .collect(),
+            )
+        }
+        b"HELP" => false,
+        b"CREATE" => (5..=6).contains(&arguments.len()),
+        b"SETID" => arguments.len() == 5,
+        b"DESTROY" => arguments.len() == 4,
+        b"CREATECONSUMER" | b"DELCONSUMER" => arguments.len() == 5,
+        _ => {
+            return Reply::error(format!(
+                "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                String::from_utf8_lossy(&arguments[1])
+            ))
+        }
+    };
+    if !arity_matches {
+        return Reply::error(format!(
+            "ERR wrong number of arguments for 'xgroup|{}' command",
+            String::from_utf8_lossy(&subcommand).to_lowercase()
+        ));
+    }
+
+    let mkstream = match arguments.get(5) {
+        Some(option) if option.eq_ignore_ascii_case(b"MKSTREAM") => true,
+        Some(_) => return Reply::syntax_error(),
+        None => false,
+    };
+    // the ID of CREATE and SETID, `None` for the last ID of the stream
+    let id = match subcommand.as_slice() {
+        b"CREATE" | b"SETID" if arguments[4] != b"$" => match parse_id(&arguments[4], 0) {
+            Ok(id) => Some(id),
+            Err(reply) => return reply,
+        },
+        _ => None,
+    };
+
+    let (key, name) = (&arguments[2], &arguments[3]);
+    let keyspace = shards.keyspace(key);
+    let stream = match keyspace.get_typed_mut::<Stream>(key, now) {
+        Ok(Some(stream)) => stream,
+        Ok(None) if mkstream => keyspace
+            .get_or_create::<Stream>(key, now)
+            .expect("key has just been checked"),
+        Ok(None) => return Reply::error(
+            "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+        ),
+        Err(_) => return Reply::wrong_type(),
+    };
+    let id = id.unwrap_or(stream.last_id());
+
+    if subcommand == b"CREATE" {
+        return if stream.create_group(name, id) {
+            Reply::ok()
+        } else {
+            Reply::error("BUSYGROUP Consumer Group name already exists")
+        };
+    }
+    if subcommand == b"DESTROY" {
+        return Reply::Integer(stream.destroy_group(name) as i64);
+    }
+
+    let Some(group) = stream.group_mut(name) else {
+        return Reply::error(format!(
+            "NOGROUP No such consumer group '{}' for key name '{}'",
+            String::from_utf8_lossy(name),
+            String::from_utf8_lossy(key)
+        ));
+    };
+    match subcommand.as_slice() {
+        b"SETID" => {
+            group.last_delivered = id;
+            Reply::ok()
+        }
+        b"CREATECONSUMER" if group.consumers.contains_key(&arguments[4]) => Reply::Integer(0),
+        b"CREATECONSUMER" => Reply::Integer(group.see(&arguments[4], now) as i64),
+        _ => Reply::Integer(group.remove_consumer(&arguments[4]).unwrap_or(0) as i64),
+    }
+}
+
+/// XACK key group id [id ...]
+pub fn xack(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let ids = match arguments[3..]
+        .iter()
+        .map(|id| parse_id(id, 0))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(ids) => ids,
+        Err(reply) => return reply,
+    };
+
+    match keyspace.get_typed_mut::<Stream>(&arguments[1], now) {
+        Ok(Some(stream)) => match stream.group_mut(&arguments[2]) {
+            Some(group) => Reply::Integer(
+                ids.iter()
+                    .filter(|id| group.pending.remove(id).is_some())
+                    .count() as i64,
+            ),
+            None => Reply::Integer(0),
+        },
+        Ok(None) => Reply::Integer(0),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+///
+/// Without a range, replies the number of pending entries, the smallest and the greatest
+/// of their IDs and how many each consumer has.
+pub fn xpending(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let mut position = 3;
+    let mut min_idle = 0;
+    if arguments
+        .get(position)
+        .is_some_and(|option| option.eq_ignore_ascii_case(b"IDLE"))
+    {
+        match arguments
+            .get(position + 1)
+            .and_then(|idle| parse_integer(idle))
+        {
+            Some(idle) => min_idle = idle,
+            None => return Reply::not_an_integer(),
+        }
+        position += 2;
+    }
+    let range = match &arguments[position..] {
+        [] if position == 3 => None,
+        [start, end, count] | [start, end, count, _] => {
+            let (first, last) = match (parse_bound(start, true), parse_bound(end, false)) {
+                (Ok(first), Ok(last)) => (first, last),
+                (Err(reply), _) | (_, Err(reply)) => return reply,
+            };
+            match parse_integer(count) {
+                Some(count) => Some((first, last, count.max(0) as usize)),
+                None => return Reply::not_an_integer(),
+            }
+        }
+        _ => return Reply::syntax_error(),
+    };
+    let consumer = arguments.get(position + 3);
+
+    let group = match keyspace.get_typed::<Stream>(&arguments[1], now) {
+        Ok(stream) => stream.and_then(|stream| stream.group(&arguments[2])),
+        Err(_) => return Reply::wrong_type(),
+    };
+    let Some(group) = group else {
+        return no_such_key_or_group(&arguments[1], &arguments[2]);
+    };
+
+    let Some((first, last, count)) = range else {
+        let (Some(smallest), Some(greatest)) = (
+            group.pending.keys().next(),
+            group.pending.keys().next_back(),
+        ) else {
+            return Reply::Array(vec![
+                Reply::Integer(0),
+                Reply::Nil,
+                Reply::Nil,
+                Reply::NilArray,
+            ]);
+        };
+        let mut counts = BTreeMap::<&[u8], usize>::new();
+        for pending in group.pending.values() {
+            *counts.entry(&pending.consumer).or_default() += 1;
+        }
+        return Reply::Array(vec![
+            Reply::Integer(group.pending.len() as i64),
+            Reply::bulk(smallest.to_string()),
+            Reply::bulk(greatest.to_string()),
+            Reply::Array(
+                counts
+                    .into_iter()
+                    .map(|(consumer, count)| {
+                        Reply::Array(vec![Reply::bulk(consumer), Reply::bulk(count.to_string())])
+                    })
+                    .collect(),
+            ),
+        ]);
+    };
+
+    if first > last {
+        return Reply::Array(Vec::new());
+    }
+    Reply::Array(
+        group
+            .pending
+            .range(first..=last)
+            .filter(|(_, pending)| consumer.is_none_or(|consumer| pending.consumer == *consumer))
+            .map(|(id, pending)| (id, pending, idle_millis(pending, now)))
+            .filter(|(_, _, idle)| *idle >= min_idle)
+            .take(count)
+            .map(|(id, pending, idle)| {
+                Reply::Array(vec![
+                    Reply::bulk(id.to_string()),
+                    Reply::bulk(pending.consumer.clone()),
+                    Reply::Integer(idle),
+                    Reply::Integer(pending.deliveries as i64),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+/// [RETRYCOUNT count] [FORCE] [JUSTID]
+///
+/// Gives to the consumer the pending entries idle for at least `min-idle-time`
+/// milliseconds. With FORCE, the entries not pending are claimed as well.
+pub fn xclaim(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 6 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let Some(min_idle) = parse_integer(&arguments[4]) else {
+        return Reply::error("ERR Invalid min-idle-time argument for XCLAIM");
+    };
+    let mut position = 5;
+    let mut ids = Vec::new();
+    while let Some(id) = arguments
+        .get(position)
+        .and_then(|id| StreamId::parse(id, 0))
+    {
+        ids.push(id);
+        position += 1;
+    }
+
+    let (mut delivered_at, mut retry_count, mut force, mut just_id) = (now, None, false, false);
+    while let Some(option) = arguments.get(position) {
+        let value = arguments
+            .get(position + 1)
+            .and_then(|value| parse_integer(value));
+        match option.to_ascii_uppercase().as_slice() {
+            b"FORCE" => force = true,
+            b"JUSTID" => just_id = true,
+            b"IDLE" | b"TIME" | b"RETRYCOUNT" => {
+                let Some(value) = value else {
+                    return Reply::not_an_integer();
+                };
+                match option.to_ascii_uppercase().as_slice() {
+                    b"IDLE" => delivered_at = from_unix_millis(unix_millis(now) - value.max(0)),
+                    b"TIME" => delivered_at = from_unix_millis(value).min(now),
+                    _ => retry_count = Some(value.max(0) as u64),
+                }
+                position += 1;
+            }
+            _ => {
+                return Reply::error(format!(
+                    "ERR Unrecognized XCLAIM option '{}'",
+                    String::from_utf8_lossy(option)
+                ))
+            }
+        }
+        position += 1;
+    }
+
+    let (key, name, consumer) = (&arguments[1], &arguments[2], &arguments[3]);
+    let stream = match keyspace.get_typed_mut::<Stream>(key, now) {
+        Ok(Some(stream)) if stream.group(name).is_some() => stream,
+        Ok(_) => return no_such_key_or_group(key, name),
+        Err(_) => return Reply::wrong_type(),
+    };
+    let in_stream = ids
+        .iter()
+        .map(|id| stream.get(*id).is_some())
+        .collect::<Vec<_>>();
+
+    let group = stream.group_mut(name).expect("group has just been checked");
+    group.see(consumer, now);
+    let mut claimed = Vec::new();
+    for (id, in_stream) in ids.into_iter().zip(in_stream) {
+        let deliveries = match group.pending.get(&id) {
+            None if force && in_stream => 0,
+            None => continue,
+            Some(_) if !in_stream => {
+                // deleted since it was delivered, it cannot be processed anymore
+                group.pending.remove(&id);
+                continue;
+            }
+            Some(pending) if idle_millis(pending, now) < min_idle => continue,
+            Some(pending) => pending.deliveries,
+        };
+        let deliveries = retry_count.unwrap_or(if just_id { deliveries } else { deliveries + 1 });
+        group.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.clone(),
+                delivered_at,
+                deliveries,
+            },
+        );
+        claimed.push(id);
+    }
+
+    Reply::Array(
+        claimed
+            .into_iter()
+            .map(|id| match (just_id, stream.get(id)) {
+                (false, Some(fields)) => entry_reply(id, fields),
+                _ => Reply::bulk(id.to_string()),
+            })
+            .collect(),
+    )
+}
+
+/// XSETID key last-id
+pub fn xsetid(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+    if arguments.len() > 3 {
+        return Reply::syntax_error();
+    }
+
+    let id = match parse_id(&arguments[2], 0) {
+        Ok(id) => id,
+        Err(reply) => return reply,
+    };
+    let stream = match keyspace.get_typed_mut::<Stream>(&arguments[1], now) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return Reply::error("ERR no such key"),
+        Err(_) => return Reply::wrong_type(),
+    };
+    if stream.last_entry_id().is_some_and(|last| id < last) {
+        return Reply::error(
+            "ERR The ID specified in XSETID is smaller than the target stream top item",
+        );
+    }
+
+    stream.set_last_id(id);
+    Reply::ok()
+}
+
+/// The options of XREAD and XREADGROUP.
+pub struct Read<'a> {
+    /// The group and the consumer reading for XREADGROUP.
+    group: Option<(&'a [u8], &'a [u8])>,
+    count: usize,
+    /// How long to wait when no stream has entries to read, `Some(None)` for ever.
+    pub block: Option<Option<Duration>>,
+    noack: bool,
+    pub keys: &'a [Vec<u8>],
+    ids: &'a [Vec<u8>],
+}
+
+/// Where a stream is read from.
+#[derive(Clone, Copy)]
+enum Position {
+    /// After an ID.
+    After(StreamId),
+    /// After the last ID, `$`.
+    Last,
+    /// After the entries delivered to the group, `>`.
+    New,
+}
+
+pub fn parse_read(arguments: &[Vec<u8>]) -> Result<Read<'_>, Reply> {
+    let grouped = arguments[0].eq_ignore_ascii_case(b"XREADGROUP");
+    let mut read = Read {
+        group: None,
+        count: usize::MAX,
+        block: None,
+        noack: false,
+        keys: &[],
+        ids: &[],
+    };
+
+    let mut position = 1;
+    let streams = loop {
+        let Some(option) = arguments.get(position) else {
+            return Err(Reply::syntax_error());
+        };
+        let value = arguments.get(position + 1);
+        match option.to_ascii_uppercase().as_slice() {
+            b"COUNT" => {
+                let Some(count) = value.and_then(|count| parse_integer(count)) else {
+                    return Err(Reply::not_an_integer());
+                };
+                // zero counts as no limit
+                read.count = if count > 0 {
+                    count as usize
+                } else {
+                    usize::MAX
+                };
+                position += 1;
+            }
+            b"BLOCK" => {
+                read.block = match value.and_then(|timeout| parse_integer(timeout)) {
+                    Some(timeout) if timeout < 0 => {
+                        return Err(Reply::error("ERR timeout is negative"))
+                    }
+                    Some(0) => Some(None),
+                    Some(timeout) => Some(Some(Duration::from_millis(timeout as u64))),
+                    None => {
+                        return Err(Reply::error(
+                            "ERR timeout is not an integer or out of range",
+                        ))
+                    }
+                };
+                position += 1;
+            }
+            b"GROUP" if grouped => {
+                let (Some(group), Some(consumer)) = (value, arguments.get(position + 2)) else {
+                    return Err(Reply::syntax_error());
+                };
+                read.group = Some((group, consumer));
+                position += 2;
+            }
+            b"GROUP" => return Err(Reply::error(
+                "ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead.",
+            )),
+            b"NOACK" if grouped => read.noack = true,
+            b"STREAMS" => break &arguments[position + 1..],
+            _ => return Err(Reply::syntax_error()),
+        }
+        position += 1;
+    };
+
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Err(Reply::error(format!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            if grouped { "xreadgroup" } else { "xread" }
+        )));
+    }
+    if grouped && read.group.is_none() {
+        return Err(Reply::error("ERR Missing GROUP option for XREADGROUP"));
+    }
+    (read.keys, read.ids) = streams.split_at(streams.len() / 2);
+    Ok(read)
+}
+
+impl Read<'_> {
+    fn positions(&self) -> Result<Vec<Position>, Reply> {
+        self.ids
+            .iter()
+            .map(|id| match (id.as_slice(), self.group) {
+                (b"$", None) => Ok(Position::Last),
+                (b"$", Some(_)) => Err(Reply::error(
+                    "ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.",
+                )),
+                (b">", Some(_)) => Ok(Position::New),
+                (b">", None) => Err(Reply::error(
+                    "ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.",
+                )),
+                (id, _) => parse_id(id, 0).map(Position::After),
+            })
+            .collect()
+    }
+
+    /// The arguments reading `keys` from `ids`, with the same options but BLOCK.
+    fn command(&self, keys: &[Vec<u8>], ids: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let mut command = Vec::new();
+        match self.group {
+            Some((group, consumer)) => command.extend([
+                b"XREADGROUP".to_vec(),
+                b"GROUP".to_vec(),
+                group.to_vec(),
+                consumer.to_vec(),
+            ]),
+            None => command.push(b"XREAD".to_vec()),
+        }
+        if self.count != usize::MAX {
+            command.extend([b"COUNT".to_vec(), self.count.to_string().into_bytes()]);
+        }
+        if self.noack {
+            command.push(b"NOACK".to_vec());
+        }
+        command.push(b"STREAMS".to_vec());
+        command.extend_from_slice(keys);
+        command.extend_from_slice(ids);
+        command
+    }
+}
+
+/// The command executed for a blocking XREAD or XREADGROUP, right away and once blocked:
+/// without BLOCK, and with `$` replaced by the last ID of its stream so that only the
+/// entries added after the call are read.
+pub fn non_blocking_read(shards: &mut Shards, read: &Read, now: SystemTime) -> Vec<Vec<u8>> {
+    let ids = read
+        .keys
+        .iter()
+        .zip(read.ids)
+        .map(|(key, id)| {
+            if id != b"$" {
+                return id.clone();
+            }
+            match shards.keyspace(key).get_typed::<Stream>(key, now) {
+                Ok(Some(stream)) => stream.last_id().to_string().into_bytes(),
+                Ok(None) => StreamId::MIN.to_string().into_bytes(),
+                Err(_) => id.clone(),
+            }
+        })
+        .collect::<Vec<_>>();
+    read.command(read.keys, &ids)
+}
+
+/// The command serving a blocked XREAD or XREADGROUP once `key` got entries: the same
+/// reading only `key`.
+pub fn served_read(arguments: &[Vec<u8>], key: &[u8]) -> Vec<Vec<u8>> {
+    let Ok(read) = parse_read(arguments) else {
+        return arguments.to_vec();
+    };
+    let position = read
+        .keys
+        .iter()
+        .position(|other| other == key)
+        .unwrap_or_default();
+    read.command(
+        &read.keys[position..=position],
+        &read.ids[position..=position],
+    )
+}
+
+/// Whether the XREAD or XREADGROUP of a single stream served to a blocked client has
+/// something to reply: entries after its ID, or an error once the stream or the group
+/// are gone.
+pub fn can_serve(keyspace: &mut Keyspace, command: &[Vec<u8>], now: SystemTime) -> bool {
+    let Ok(read) = parse_read(command) else {
+        return true;
+    };
+    let Ok(positions) = read.positions() else {
+        return true;
+    };
+    let (Some(key), Some(position)) = (read.keys.first(), positions.first()) else {
+        return true;
+    };
+
+    let stream = match keyspace.get_typed::<Stream>(key, now) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return read.group.is_some(),
+        Err(_) => return true,
+    };
+    let after = match (read.group, position) {
+        (Some((group, _)), _) => match stream.group(group) {
+            Some(group) => group.last_delivered,
+            None => return true,
+        },
+        (None, Position::After(id)) => *id,
+        (None, _) => stream.last_id(),
+    };
+    stream.after(after).next().is_some()
+}
+
+/// How a stream is trimmed by XADD and XTRIM.
+enum Trim {
+    /// Keeps this many entries at most.
+    MaxLen(usize),
+    /// Deletes the entries with a smaller ID.
+    MinId(StreamId),
+}
+
+impl Trim {
+    fn apply(&self, stream: &mut Stream) -> usize {
+        match *self {
+            Trim::MaxLen(max_len) => stream.trim_to_len(max_len),
+            Trim::MinId(min_id) => stream.trim_before(min_id),
+        }
+    }
+}
+
+/// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]` starting at `position`, returning
+/// the position following it. Entries are not grouped in nodes that approximate trimming
+/// would keep whole, so it trims exactly and LIMIT has nothing to bound.
+fn parse_trim(arguments: &[Vec<u8>], mut position: usize) -> Result<(Trim, usize), Reply> {
+    let max_len = arguments[position].eq_ignore_ascii_case(b"MAXLEN");
+    position += 1;
+    let approximate = arguments.get(position).is_some_and(|mode| mode == b"~");
+    if approximate || arguments.get(position).is_some_and(|mode| mode == b"=") {
+        position += 1;
+    }
+
+    let Some(threshold) = arguments.get(position) else {
+        return Err(Reply::syntax_error());
+    };
+    let trim = if max_len {
+        match parse_integer(threshold) {
+            Some(max_len) if max_len < 0 => {
+                return Err(Reply::error("ERR The MAXLEN argument must be >= 0."))
+            }
+            Some(max_len) => Trim::MaxLen(max_len as usize),
+            None => return Err(Reply::not_an_integer()),
+        }
+    } else {
+        Trim::MinId(parse_id(threshold, 0)?)
+    };
+    position += 1;
+
+    if arguments
+        .get(position)
+        .is_some_and(|option| option.eq_ignore_ascii_case(b"LIMIT"))
+    {
+        match arguments
+            .get(position + 1)
+            .and_then(|limit| parse_integer(limit))
+        {
+            Some(limit) if limit < 0 => {
+                return Err(Reply::error("ERR The LIMIT argument must be >= 0."))
+            }
+            Some(_) => {}
+            None => return Err(Reply::not_an_integer()),
+        }
+        if !approximate {
+            return Err(Reply::error(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option",
+            ));
+        }
+        position += 2;
+    }
+
+    Ok((trim, position))
+}
+
+struct AddOptions {
+    nomkstream: bool,
+    trim: Option<Trim>,
+    /// The position of the ID, followed by the fields and their values.
+    id_position: usize,
+}
+
+fn parse_add(arguments: &[Vec<u8>]) -> Result<AddOptions, Reply> {
+    let mut options = AddOptions {
+        nomkstream: false,
+        trim: None,
+        id_position: 2,
+    };
+
+    while let Some(option) = arguments.get(options.id_position) {
+        match option.to_ascii_uppercase().as_slice() {
+            b"NOMKSTREAM" => {
+                options.nomkstream = true;
+                options.id_position += 1;
+            }
+            b"MAXLEN" | b"MINID" => {
+                let (trim, position) = parse_trim(arguments, options.id_position)?;
+                options.trim = Some(trim);
+                options.id_position = position;
+            }
+            _ => break,
+        }
+    }
+
+    let fields = arguments.len().saturating_sub(options.id_position + 1);
+    if fields == 0 || !fields.is_multiple_of(2) {
+        return Err(Reply::wrong_number_of_arguments(&arguments[0]));
+    }
+    Ok(options)
+}
+
+/// The ID given to XADD.
+#[derive(PartialEq)]
+enum NewId {
+    /// `*`, generated from the time.
+    Auto,
+    /// `ms-*`, with a generated sequence number.
+    Sequence(u64),
+    Explicit(StreamId),
+}
+
+fn parse_new_id(argument: &[u8]) -> Result<NewId, Reply> {
+    if argument == b"*" {
+        return Ok(NewId::Auto);
+    }
+    match argument.strip_suffix(b"-*") {
+        Some(ms) => match StreamId::parse(ms, 0) {
+            Some(id) if !ms.contains(&b'-') => Ok(NewId::Sequence(id.ms)),
+            _ => Err(invalid_id()),
+        },
+        None => parse_id(argument, 0).map(NewId::Explicit),
+    }
+}
+
+fn parse_id(argument: &[u8], missing_seq: u64) -> Result<StreamId, Reply> {
+    StreamId::parse(argument, missing_seq).ok_or_else(invalid_id)
+}
+
+/// Parses a bound of the interval of XRANGE, XREVRANGE or XPENDING: `-`, `+`, or an ID
+/// standing for the first or the last entry of its millisecond when it has no sequence
+/// number, which is excluded when prefixed by `(`.
+fn parse_bound(argument: &[u8], start: bool) -> Result<StreamId, Reply> {
+    let missing_seq = if start { 0 } else { u64::MAX };
+    let Some(excluded) = argument.strip_prefix(b"(") else {
+        return match argument {
+            b"-" => Ok(StreamId::MIN),
+            b"+" => Ok(StreamId::MAX),
+            _ => parse_id(argument, missing_seq),
+        };
+    };
+
+    let id = parse_id(excluded, missing_seq)?;
+    let bound = if start { id.next() } else { id.previous() };
+    bound.ok_or_else(|| {
+        Reply::error(if start {
+            "ERR invalid start ID for the interval"
+        } else {
+            "ERR invalid end ID for the interval"
+        })
+    })
+}
+
+fn invalid_id() -> Reply {
+    Reply::error("ERR Invalid stream ID specified as stream command argument")
+}
+
+fn no_such_key_or_group(key: &[u8], group: &[u8]) -> Reply {
+    Reply::error(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    ))
+}
+
+/// An entry as replied by the commands reading streams: its ID and its fields followed
+/// by their values.
+fn entry_reply(id: StreamId, fields: &Fields) -> Reply {
+    Reply::Array(vec![
+        Reply::bulk(id.to_string()),
+        Reply::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| [Reply::bulk(field.clone()), Reply::bulk(value.clone())])
+                .collect(),
+        ),
+    ])
+}
+
+fn idle_millis(pending: &PendingEntry, now: SystemTime) -> i64 {
+    now.duration_since(pending.delivered_at)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::{arguments, strings};
+    use crate::redis::database::Database;
+    use std::time::UNIX_EPOCH;
+
+    fn entry(id: &str, fields: &[&str]) -> Reply {
+        Reply::Array(vec![
+            Reply::bulk(id),
+            Reply::bulk_array(fields.iter().copied()),
+        ])
+    }
+
+    #[test]
+    fn add_range_and_trim() {
+        let mut keyspace = Keyspace::default();
+        let now = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+        let mut call = |command: &[&str]| {
+            let arguments = arguments(command);
+            match command[0] {
+                "XADD" => xadd(&mut keyspace, &arguments, now),
+                "XLEN" => xlen(&mut keyspace, &arguments, now),
+                "XRANGE" => xrange(&mut keyspace, &arguments, now, false),
+                "XREVRANGE" => xrange(&mut keyspace, &arguments, now, true),
+                _ => xtrim(&mut keyspace, &arguments, now),
+            }
+        };
+
+        assert_eq!(call(&["XADD", "s", "1-1", "a", "1"]), Reply::bulk("1-1"));
+        assert_eq!(
+            call(&["XADD", "s", "1-*", "a", "2", "b", "3"]),
+            Reply::bulk("1-2")
+        );
+        assert_eq!(call(&["XADD", "s", "5-*", "a", "4"]), Reply::bulk("5-0"));
+        assert_eq!(
+            call(&["XADD", "s", "*", "a", "5"]),
+            Reply::bulk("1700000000000-0")
+        );
+        assert_eq!(
+            call(&["XADD", "s", "*", "a", "6"]),
+            Reply::bulk("1700000000000-1")
+        );
+        assert_eq!(
+            call(&["XADD", "s", "5-1", "a", "7"]),
+            Reply::error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            )
+        );
+        assert_eq!(
+            call(&["XADD", "t", "0-0", "a", "1"]),
+            Reply::error("ERR The ID specified in XADD must be greater than 0-0")
+        );
+        assert_eq!(
+            call(&["XADD", "s", "1-x", "a", "1"]),
+            Reply::error("ERR Invalid stream ID specified as stream command argument")
+        );
+        assert_eq!(
+            call(&["XADD", "s", "*", "a"]),
+            Reply::wrong_number_of_arguments(b"XADD")
+        );
+        assert_eq!(
+            call(&["XADD", "t", "NOMKSTREAM", "*", "a", "1"]),
+            Reply::Nil
+        );
+        assert_eq!(call(&["XLEN", "s"]), Reply::Integer(5));
+        assert_eq!(call(&["XLEN", "t"]), Reply::Integer(0));
+
+        assert_eq!(
+            call(&["XRANGE", "s", "-", "1"]),
+            Reply::Array(vec![
+                entry("1-1", &["a", "1"]),
+                entry("1-2", &["a", "2", "b", "3"])
+            ])
+        );
+        assert_eq!(
+            call(&["XRANGE", "s", "(1-1", "+", "COUNT", "1"]),
+            Reply::Array(vec![entry("1-2", &["a", "2", "b", "3"])])
+        );
+        assert_eq!(
+            call(&["XREVRANGE", "s", "+", "(5", "COUNT", "2"]),
+            Reply::Array(vec![
+                entry("1700000000000-1", &["a", "6"]),
+                entry("1700000000000-0", &["a", "5"])
+            ])
+        );
+        assert_eq!(call(&["XRANGE", "s", "+", "-"]), Reply::Array(vec![]));
+        assert_eq!(
+            call(&["XRANGE", "s", "-", "+", "LIMIT", "1"]),
+            Reply::syntax_error()
+        );
+
+        assert_eq!(
+            call(&["XADD", "s", "MAXLEN", "~", "4", "*", "a", "8"]),
+            Reply::bulk("1700000000000-2")
+        );
+        assert_eq!(call(&["XLEN", "s"]), Reply::Integer(4));
+        assert_eq!(
+            call(&["XTRIM", "s", "MINID", "1700000000000"]),
+            Reply::Integer(1)
+        );
+        assert_eq!(call(&["XTRIM", "s", "MAXLEN", "=", "1"]), Reply::Integer(2));
+        assert_eq!(
+            call(&["XTRIM", "s", "MAXLEN", "1", "LIMIT", "10"]),
+            Reply::error("ERR syntax error, LIMIT cannot be used without the special ~ option")
+        );
+        assert_eq!(
+            call(&["XTRIM", "s", "MAXLEN", "~", "0", "LIMIT", "10"]),
+            Reply::Integer(1)
+        );
+        // an emptied stream still exists and keeps its last ID
+        assert_eq!(call(&["XLEN", "s"]), Reply::Integer(0));
+        assert_eq!(
+            call(&["XADD", "s", "1700000000000-2", "a", "9"]),
+            Reply::error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            )
+        );
+    }
+
+    #[test]
+    fn read_several_streams() {
+        let database = Database::default();
+        let now = UNIX_EPOCH;
+        let mut shards = database.lock(0, [&b"a"[..], b"b", b"s"]);
+        for (key, id) in [("a", "1-0"), ("a", "2-0"), ("b", "3-0")] {
+            xadd(
+                shards.keyspace(key.as_bytes()),
+                &arguments(&["XADD", key, id, "f", id]),
+                now,
+            );
+        }
+        strings::set(shards.keyspace(b"s"), &arguments(&["SET", "s", "v"]), now);
+
+        assert_eq!(
+            xread(
+                &mut shards,
+                &arguments(&["XREAD", "COUNT", "1", "STREAMS", "a", "b", "0", "0"]),
+                now
+            ),
+            Reply::Array(vec![
+                Reply::Array(vec![
+                    Reply::bulk("a"),
+                    Reply::Array(vec![entry("1-0", &["f", "1-0"])])
+                ]),
+                Reply::Array(vec![
+                    Reply::bulk("b"),
+                    Reply::Array(vec![entry("3-0", &["f", "3-0"])])
+                ]),
+            ])
+        );
+        assert_eq!(
+            xread(
+                &mut shards,
+                &arguments(&["XREAD", "STREAMS", "a", "b", "1", "$"]),
+                now
+            ),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::bulk("a"),
+                Reply::Array(vec![entry("2-0", &["f", "2-0"])])
+            ])])
+        );
+        assert_eq!(
+            xread(
+                &mut shards,
+                &arguments(&["XREAD", "STREAMS", "b", "3"]),
+                now
+            ),
+            Reply::NilArray
+        );
+        assert_eq!(
+            xread(
+                &mut shards,
+                &arguments(&["XREAD", "STREAMS", "s", "0"]),
+                now
+            ),
+            Reply::wrong_type()
+        );
+        assert_eq!(
+            xread(&mut shards, &arguments(&["XREAD", "STREAMS", "a", "b", "0"]), now),
+            Reply::error(
+                "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+            )
+        );
+        assert_eq!(
+            xread(
+                &mut shards,
+                &arguments(&["XREAD", "BLOCK", "-1", "STREAMS", "a", "0"]),
+                now
+            ),
+            Reply::error("ERR timeout is negative")
+        );
+
+        let blocking = arguments(&["XREAD", "BLOCK", "0", "STREAMS", "a", "b", "$", "$"]);
+        let read = parse_read(&blocking).unwrap();
+        assert_eq!(read.block, Some(None));
+        let command = non_blocking_read(&mut shards, &read, now);
+        assert_eq!(
+            command,
+            arguments(&["XREAD", "STREAMS", "a", "b", "2-0", "3-0"])
+        );
+        let served = served_read(&command, b"b");
+        assert_eq!(served, arguments(&["XREAD", "STREAMS", "b", "3-0"]));
+        assert!(!can_serve(shards.keyspace(b"b"), &served, now));
+        xadd(
+            shards.keyspace(b"b"),
+            &arguments(&["XADD", "b", "4-0", "f", "v"]),
+            now,
+        );
+        assert!(can_serve(shards.keyspace(b"b"), &served, now));
+    }
+
+    #[test]
+    fn consumer_groups() {
+        let database = Database::default();
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let later = now + Duration::from_millis(500);
+        let mut shards = database.lock(0, [&b"s"[..]]);
+        let mut call = |command: &[&str], now: SystemTime| {
+            let arguments = arguments(command);
+            match command[0] {
+                "XADD" => xadd(shards.keyspace(b"s"), &arguments, now),
+                "XGROUP" => xgroup(&mut shards, &arguments, now),
+                "XACK" => xack(shards.keyspace(b"s"), &arguments, now),
+                "XPENDING" => xpending(shards.keyspace(b"s"), &arguments, now),
+                "XCLAIM" => xclaim(shards.keyspace(b"s"), &arguments, now),
+                _ => xreadgroup(&mut shards, &arguments, now),
+            }
+        };
+
+        assert_eq!(
+            call(&["XGROUP", "CREATE", "s", "g", "$"], now),
+            Reply::error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")
+        );
+        assert_eq!(
+            call(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"], now),
+            Reply::ok()
+        );
+        assert_eq!(
+            call(&["XGROUP", "CREATE", "s", "g", "0"], now),
+            Reply::error("BUSYGROUP Consumer Group name already exists")
+        );
+        for id in ["1-0", "2-0", "3-0"] {
+            call(&["XADD", "s", id, "f", id], now);
+        }
+
+        let read = |consumer: &'static str, count: &'static str, id: &'static str| {
+            vec![
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                consumer,
+                "COUNT",
+                count,
+                "STREAMS",
+                "s",
+                id,
+            ]
+        };
+        assert_eq!(
+            call(&read("alice", "2", ">"), now),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::bulk("s"),
+                Reply::Array(vec![
+                    entry("1-0", &["f", "1-0"]),
+                    entry("2-0", &["f", "2-0"])
+                ])
+            ])])
+        );
+        assert_eq!(
+            call(&read("bob", "0", ">"), now),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::bulk("s"),
+                Reply::Array(vec![entry("3-0", &["f", "3-0"])])
+            ])])
+        );
+        assert_eq!(call(&read("bob", "0", ">"), now), Reply::NilArray);
+        // the history of a consumer after an ID
+        assert_eq!(
+            call(&read("alice", "0", "1-0"), now),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::bulk("s"),
+                Reply::Array(vec![entry("2-0", &["f", "2-0"])])
+            ])])
+        );
+        assert_eq!(
+            call(
+                &["XREADGROUP", "GROUP", "nope", "c", "STREAMS", "s", ">"],
+                now
+            ),
+            Reply::error(
+                "NOGROUP No such key 's' or consumer group 'nope' in XREADGROUP with GROUP option"
+            )
+        );
+
+        assert_eq!(
+            call(&["XPENDING", "s", "g"], later),
+            Reply::Array(vec![
+                Reply::Integer(3),
+                Reply::bulk("1-0"),
+                Reply::bulk("3-0"),
+                Reply::Array(vec![
+                    Reply::bulk_array(["alice", "2"]),
+                    Reply::bulk_array(["bob", "1"])
+                ])
+            ])
+        );
+        assert_eq!(
+            call(
+                &["XPENDING", "s", "g", "IDLE", "100", "-", "+", "10", "bob"],
+                later
+            ),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::bulk("3-0"),
+                Reply::bulk("bob"),
+                Reply::Integer(500),
+                Reply::Integer(1)
+            ])])
+        );
+        assert_eq!(
+            call(&["XACK", "s", "g", "1-0", "1-0", "9-0"], now),
+            Reply::Integer(1)
+        );
+
+        assert_eq!(
+            call(&["XCLAIM", "s", "g", "bob", "1000", "2-0"], later),
+            Reply::Array(vec![])
+        );
+        assert_eq!(
+            call(&["XCLAIM", "s", "g", "bob", "100", "2-0", "JUSTID"], later),
+            Reply::bulk_array(["2-0"])
+        );
+        assert_eq!(
+            call(&["XPENDING", "s", "g", "-", "+", "10"], later),
+            Reply::Array(vec![
+                Reply::Array(vec![
+                    Reply::bulk("2-0"),
+                    Reply::bulk("bob"),
+                    Reply::Integer(0),
+                    Reply::Integer(1)
+                ]),
+                Reply::Array(vec![
+                    Reply::bulk("3-0"),
+                    Reply::bulk("bob"),
+                    Reply::Integer(500),
+                    Reply::Integer(1)
+                ])
+            ])
+        );
+
+        assert_eq!(
+            call(&["XGROUP", "CREATECONSUMER", "s", "g", "carol"], now),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            call(&["XGROUP", "DELCONSUMER", "s", "g", "bob"], now),
+            Reply::Integer(2)
+        );
+        assert_eq!(
+            call(&["XPENDING", "s", "g"], now),
+            Reply::Array(vec![
+                Reply::Integer(0),
+                Reply::Nil,
+                Reply::Nil,
+                Reply::NilArray
+            ])
+        );
+        assert_eq!(call(&["XGROUP", "SETID", "s", "g", "0"], now), Reply::ok());
+        assert_eq!(
+            call(&read("carol", "0", ">"), now),
+            Reply::Array(vec![Reply::Array(vec![
+                Reply::bulk("s"),
+                Reply::Array(vec![
+                    entry("1-0", &["f", "1-0"]),
+                    entry("2-0", &["f", "2-0"]),
+                    entry("3-0", &["f", "3-0"])
+                ])
+            ])])
+        );
+        assert_eq!(
+            call(&["XGROUP", "DESTROY", "s", "g"], now),
+            Reply::Integer(1)
+        );
+        assert_eq!(
+            call(&["XGROUP", "SETID", "s", "g", "0"], now),
+            Reply::error("NOGROUP No such consumer group 'g' for key name 's'")
+        );
+        assert_eq!(
+            call(&["XGROUP", "DESTROY", "s"], now),
+            Reply::error("ERR wrong number of arguments for 'xgroup|destroy' command")
+        );
+    }
+
+    #[test]
+    fn generated_ids_are_logged() {
+        let command = arguments(&["XADD", "s", "MAXLEN", "~", "10", "*", "f", "v"]);
+        assert_eq!(
+            with_added_id(&command, &Reply::bulk("5-1")),
+            arguments(&["XADD", "s", "MAXLEN", "~", "10", "5-1", "f", "v"])
+        );
+        assert_eq!(with_added_id(&command, &Reply::Nil), command);
+        let command = arguments(&["SET", "s", "*"]);
+        assert_eq!(with_added_id(&command, &Reply::bulk("5-1")), command);
+    }
+}
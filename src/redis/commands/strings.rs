@@ -0,0 +1,701 @@
+use super::{expire_time, format_float, parse_float, parse_integer, TimeUnit};
+use crate::redis::database::Shards;
+use crate::redis::keyspace::{Keyspace, Value};
+use crate::redis::resp::Reply;
+use std::time::SystemTime;
+
+/// Largest string SETRANGE can produce, as the largest bulk string of the protocol.
+const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+enum Condition {
+    IfNotExists,
+    IfExists,
+}
+
+enum Expiry {
+    At(SystemTime),
+    KeepTtl,
+}
+
+/// SET key value [NX|XX] [GET] [EX seconds|PX milliseconds|EXAT unix-time-seconds|PXAT unix-time-milliseconds|KEEPTTL]
+pub fn set(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let mut condition = None;
+    let mut get = false;
+    let mut expiry = None;
+
+    let mut options = arguments[3..].iter();
+    while let Some(option) = options.next() {
+        let option = option.to_ascii_uppercase();
+        match option.as_slice() {
+            b"NX" if condition.is_none() => condition = Some(Condition::IfNotExists),
+            b"XX" if condition.is_none() => condition = Some(Condition::IfExists),
+            b"GET" => get = true,
+            b"KEEPTTL" if expiry.is_none() => expiry = Some(Expiry::KeepTtl),
+            b"EX" | b"PX" | b"EXAT" | b"PXAT" if expiry.is_none() => {
+                match expiry_option(&arguments[0], &option, options.next(), now) {
+                    Ok(at) => expiry = Some(Expiry::At(at)),
+                    Err(reply) => return reply,
+                }
+            }
+            _ => return Reply::syntax_error(),
+        }
+    }
+
+    let current = keyspace.get(&arguments[1], now);
+
+    let previous = match current.map(|entry| &entry.value) {
+        _ if !get => None,
+        None => Some(Reply::Nil),
+        Some(Value::String(previous)) => Some(Reply::bulk(previous.clone())),
+        Some(_) => return Reply::wrong_type(),
+    };
+
+    let skip = match condition {
+        Some(Condition::IfNotExists) => current.is_some(),
+        Some(Condition::IfExists) => current.is_none(),
+        None => false,
+    };
+    if skip {
+        return previous.unwrap_or(Reply::Nil);
+    }
+
+    let expires_at = match expiry {
+        Some(Expiry::At(at)) => Some(at),
+        Some(Expiry::KeepTtl) => current.and_then(|entry| entry.expires_at()),
+        None => None,
+    };
+
+    keyspace.set(
+        arguments[1].clone(),
+        Value::String(arguments[2].clone()),
+        expires_at,
+        now,
+    );
+
+    previous.unwrap_or_else(Reply::ok)
+}
+
+/// Parses the time of an EX, PX, EXAT or PXAT option of `command`.
+fn expiry_option(
+    command: &[u8],
+    option: &[u8],
+    value: Option<&Vec<u8>>,
+    now: SystemTime,
+) -> Result<SystemTime, Reply> {
+    let Some(value) = value else {
+        return Err(Reply::syntax_error());
+    };
+    let Some(value) = parse_integer(value) else {
+        return Err(Reply::not_an_integer());
+    };
+    let unit = if option.starts_with(b"E") {
+        TimeUnit::Seconds
+    } else {
+        TimeUnit::Milliseconds
+    };
+    let relative = !option.ends_with(b"AT");
+
+    match expire_time(value, unit, relative, now) {
+        Some(at) if value > 0 => Ok(at),
+        _ => Err(Reply::error(format!(
+            "ERR invalid expire time in '{}' command",
+            String::from_utf8_lossy(command).to_lowercase()
+        ))),
+    }
+}
+
+pub fn get(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    match keyspace.get_typed::<Vec<u8>>(&arguments[1], now) {
+        Ok(Some(value)) => Reply::bulk(value.clone()),
+        Ok(None) => Reply::Nil,
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// GETDEL key
+pub fn getdel(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_typed::<Vec<u8>>(&arguments[1], now) {
+        Ok(Some(_)) => {}
+        Ok(None) => return Reply::Nil,
+        Err(_) => return Reply::wrong_type(),
+    }
+    match keyspace.remove(&arguments[1], now).map(|entry| entry.value) {
+        Some(Value::String(value)) => Reply::Bulk(value),
+        _ => unreachable!("the key holds a string"),
+    }
+}
+
+/// GETEX key [EX seconds|PX milliseconds|EXAT unix-time-seconds|PXAT unix-time-milliseconds|PERSIST]
+pub fn getex(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    // `None` keeps the current time to live, `Some(None)` removes it
+    let expiry = match arguments.get(2).map(|option| option.to_ascii_uppercase()) {
+        None => None,
+        Some(option) if option == b"PERSIST" && arguments.len() == 3 => Some(None),
+        Some(option)
+            if matches!(option.as_slice(), b"EX" | b"PX" | b"EXAT" | b"PXAT")
+                && arguments.len() <= 4 =>
+        {
+            match expiry_option(&arguments[0], &option, arguments.get(3), now) {
+                Ok(at) => Some(Some(at)),
+                Err(reply) => return reply,
+            }
+        }
+        Some(_) => return Reply::syntax_error(),
+    };
+
+    let value = match keyspace.get_typed::<Vec<u8>>(&arguments[1], now) {
+        Ok(Some(value)) => value.clone(),
+        Ok(None) => return Reply::Nil,
+        Err(_) => return Reply::wrong_type(),
+    };
+    if let Some(expires_at) = expiry {
+        keyspace.set_expiry(&arguments[1], expires_at);
+    }
+    Reply::Bulk(value)
+}
+
+/// MGET key [key ...], replying nil for the keys not holding a string.
+pub fn mget(shards: &mut Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() < 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    Reply::Array(
+        arguments[1..]
+            .iter()
+            .map(
+                |key| match shards.keyspace(key).get_typed::<Vec<u8>>(key, now) {
+                    Ok(Some(value)) => Reply::bulk(value.clone()),
+                    _ => Reply::Nil,
+                },
+            )
+            .collect(),
+    )
+}
+
+/// MSET key value [key value ...] and MSETNX, which sets nothing when one of the keys
+/// exists.
+pub fn mset(
+    shards: &mut Shards,
+    arguments: &[Vec<u8>],
+    now: SystemTime,
+    if_none_exists: bool,
+) -> Reply {
+    if arguments.len() < 3 || arguments.len().is_multiple_of(2) {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let pairs = arguments[1..].chunks(2);
+    if if_none_exists
+        && pairs
+            .clone()
+            .any(|pair| shards.keyspace(&pair[0]).get(&pair[0], now).is_some())
+    {
+        return Reply::Integer(0);
+    }
+
+    for pair in pairs {
+        shards
+            .keyspace(&pair[0])
+            .set(pair[0].clone(), Value::String(pair[1].clone()), None, now);
+    }
+
+    if if_none_exists {
+        Reply::Integer(1)
+    } else {
+        Reply::ok()
+    }
+}
+
+/// INCR/DECR key and INCRBY/DECRBY key increment, `sign` being -1 for the decrements.
+pub fn incr_by(
+    keyspace: &mut Keyspace,
+    arguments: &[Vec<u8>],
+    now: SystemTime,
+    sign: i64,
+) -> Reply {
+    let with_increment = arguments[0].ends_with(b"BY");
+    if arguments.len() != if with_increment { 3 } else { 2 } {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let increment = if with_increment {
+        match parse_stored_integer(&arguments[2]) {
+            Some(increment) => increment,
+            None => return Reply::not_an_integer(),
+        }
+    } else {
+        1
+    };
+    let Some(increment) = increment.checked_mul(sign) else {
+        return Reply::error("ERR decrement would overflow");
+    };
+
+    let current = match keyspace.get_typed::<Vec<u8>>(&arguments[1], now) {
+        Ok(Some(value)) => match parse_stored_integer(value) {
+            Some(current) => current,
+            None => return Reply::not_an_integer(),
+        },
+        Ok(None) => 0,
+        Err(_) => return Reply::wrong_type(),
+    };
+    let Some(value) = current.checked_add(increment) else {
+        return Reply::error("ERR increment or decrement would overflow");
+    };
+
+    set_keeping_ttl(keyspace, &arguments[1], value.to_string().into_bytes(), now);
+    Reply::Integer(value)
+}
+
+/// INCRBYFLOAT key increment
+pub fn incr_by_float(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let Some(increment) = parse_float(&arguments[2]) else {
+        return Reply::not_a_float();
+    };
+    let current = match keyspace.get_typed::<Vec<u8>>(&arguments[1], now) {
+        Ok(Some(value)) => match parse_float(value) {
+            Some(current) => current,
+            None => return Reply::not_a_float(),
+        },
+        Ok(None) => 0.0,
+        Err(_) => return Reply::wrong_type(),
+    };
+    let value = current + increment;
+    if !value.is_finite() {
+        return Reply::error("ERR increment would produce NaN or Infinity");
+    }
+
+    let value = format_float(value).into_bytes();
+    set_keeping_ttl(keyspace, &arguments[1], value.clone(), now);
+    Reply::Bulk(value)
+}
+
+/// APPEND key value
+pub fn append(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 3 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_or_create::<Vec<u8>>(&arguments[1], now) {
+        Ok(value) => {
+            value.extend_from_slice(&arguments[2]);
+            Reply::Integer(value.len() as i64)
+        }
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// STRLEN key
+pub fn strlen(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 2 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    match keyspace.get_typed::<Vec<u8>>(&arguments[1], now) {
+        Ok(value) => Reply::Integer(value.map_or(0, Vec::len) as i64),
+        Err(_) => Reply::wrong_type(),
+    }
+}
+
+/// GETRANGE key start end, both inclusive and counting from the end when negative.
+pub fn getrange(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let (Some(start), Some(end)) = (parse_integer(&arguments[2]), parse_integer(&arguments[3]))
+    else {
+        return Reply::not_an_integer();
+    };
+    let value = match keyspace.get_typed::<Vec<u8>>(&arguments[1], now) {
+        Ok(Some(value)) => value,
+        Ok(None) => return Reply::bulk(""),
+        Err(_) => return Reply::wrong_type(),
+    };
+
+    // unlike LRANGE, an end before the start of the string is clamped to the first byte
+    let len = value.len() as i64;
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.clamp(0, len - 1);
+    if start > end || len == 0 {
+        return Reply::bulk("");
+    }
+    Reply::bulk(&value[start as usize..=end as usize])
+}
+
+/// SETRANGE key offset value, padding the string with zero bytes up to `offset`.
+pub fn setrange(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    if arguments.len() != 4 {
+        return Reply::wrong_number_of_arguments(&arguments[0]);
+    }
+
+    let offset = match parse_integer(&arguments[2]) {
+        Some(offset) if offset >= 0 => offset as usize,
+        Some(_) => return Reply::error("ERR offset is out of range"),
+        None => return Reply::not_an_integer(),
+    };
+    let patch = &arguments[3];
+    if offset.saturating_add(patch.len()) > MAX_STRING_LENGTH {
+        return Reply::error("ERR string exceeds maximum allowed size (proto-max-bulk-len)");
+    }
+
+    // an empty patch neither creates nor extends the string
+    let value = match keyspace.get_typed::<Vec<u8>>(&arguments[1], now) {
+        Ok(value) if patch.is_empty() => return Reply::Integer(value.map_or(0, Vec::len) as i64),
+        Ok(_) => keyspace
+            .get_or_create::<Vec<u8>>(&arguments[1], now)
+            .expect("the key holds a string"),
+        Err(_) => return Reply::wrong_type(),
+    };
+    if value.len() < offset + patch.len() {
+        value.resize(offset + patch.len(), 0);
+    }
+    value[offset..offset + patch.len()].copy_from_slice(patch);
+    Reply::Integer(value.len() as i64)
+}
+
+/// Replaces the string of `key`, keeping its time to live as the commands modifying a
+/// value in place do.
+fn set_keeping_ttl(keyspace: &mut Keyspace, key: &[u8], value: Vec<u8>, now: SystemTime) {
+    match keyspace.get_typed_mut::<Vec<u8>>(key, now) {
+        Ok(Some(current)) => *current = value,
+        _ => keyspace.set(key.to_vec(), Value::String(value), None, now),
+    }
+}
+
+/// Parses an integer the way redis does for counters: an optional minus sign then digits
+/// without leading zeros, so that "01" or "+1" are not integers.
+fn parse_stored_integer(value: &[u8]) -> Option<i64> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    let canonical = match digits {
+        [] => false,
+        [b'0'] => value.len() == 1,
+        [first, ..] => *first != b'0' && digits.iter().all(u8::is_ascii_digit),
+    };
+    if canonical {
+        parse_integer(value)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+    use crate::redis::database::Database;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn fake_now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    #[test]
+    fn set_conditions() {
+        let mut keyspace = Keyspace::default();
+        let now = fake_now();
+
+        let reply = set(&mut keyspace, &arguments(&["SET", "k", "1", "XX"]), now);
+        assert_eq!(reply, Reply::Nil);
+        let reply = set(&mut keyspace, &arguments(&["SET", "k", "1", "NX"]), now);
+        assert_eq!(reply, Reply::ok());
+        let reply = set(&mut keyspace, &arguments(&["SET", "k", "2", "nx"]), now);
+        assert_eq!(reply, Reply::Nil);
+        let reply = set(
+            &mut keyspace,
+            &arguments(&["SET", "k", "3", "XX", "GET"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk("1"));
+        let reply = set(
+            &mut keyspace,
+            &arguments(&["SET", "k", "4", "NX", "GET"]),
+            now,
+        );
+        assert_eq!(reply, Reply::bulk("3"));
+        let reply = set(
+            &mut keyspace,
+            &arguments(&["SET", "k", "5", "NX", "XX"]),
+            now,
+        );
+        assert_eq!(reply, Reply::syntax_error());
+    }
+
+    #[test]
+    fn set_expiry_options() {
+        let mut keyspace = Keyspace::default();
+        let now = fake_now();
+
+        set(
+            &mut keyspace,
+            &arguments(&["SET", "a", "v", "PX", "1500"]),
+            now,
+        );
+        set(
+            &mut keyspace,
+            &arguments(&["SET", "b", "v", "EXAT", "1700000010"]),
+            now,
+        );
+        set(
+            &mut keyspace,
+            &arguments(&["SET", "c", "v", "PXAT", "1700000000250"]),
+            now,
+        );
+
+        let mut expires_at = |key: &[u8]| keyspace.get(key, now).unwrap().expires_at();
+        assert_eq!(expires_at(b"a"), Some(now + Duration::from_millis(1500)));
+        assert_eq!(expires_at(b"b"), Some(now + Duration::from_secs(10)));
+        assert_eq!(expires_at(b"c"), Some(now + Duration::from_millis(250)));
+
+        set(
+            &mut keyspace,
+            &arguments(&["SET", "a", "w", "KEEPTTL"]),
+            now,
+        );
+        assert_eq!(
+            keyspace.get(b"a", now).unwrap().expires_at(),
+            Some(now + Duration::from_millis(1500))
+        );
+        set(&mut keyspace, &arguments(&["SET", "a", "x"]), now);
+        assert_eq!(keyspace.get(b"a", now).unwrap().expires_at(), None);
+    }
+
+    #[test]
+    fn set_invalid_expiry() {
+        let mut keyspace = Keyspace::default();
+        let now = fake_now();
+
+        let reply = set(
+            &mut keyspace,
+            &arguments(&["SET", "k", "v", "EX", "abc"]),
+            now,
+        );
+        assert_eq!(reply, Reply::not_an_integer());
+        let reply = set(
+            &mut keyspace,
+            &arguments(&["SET", "k", "v", "EX", "0"]),
+            now,
+        );
+        assert_eq!(
+            reply,
+            Reply::error("ERR invalid expire time in 'set' command")
+        );
+        let reply = set(&mut keyspace, &arguments(&["SET", "k", "v", "EX"]), now);
+        assert_eq!(reply, Reply::syntax_error());
+        let reply = set(
+            &mut keyspace,
+            &arguments(&["SET", "k", "v", "EX", "1", "PX", "1"]),
+            now,
+        );
+        assert_eq!(reply, Reply::syntax_error());
+        assert!(keyspace.get(b"k", now).is_none());
+    }
+
+    #[test]
+    fn counters() {
+        let mut keyspace = Keyspace::default();
+        let now = fake_now();
+        let expires_at = now + Duration::from_secs(10);
+        keyspace.set(
+            b"k".to_vec(),
+            Value::String(b"10".to_vec()),
+            Some(expires_at),
+            now,
+        );
+
+        let command = arguments(&["INCR", "k"]);
+        assert_eq!(incr_by(&mut keyspace, &command, now, 1), Reply::Integer(11));
+        let command = arguments(&["DECRBY", "k", "20"]);
+        assert_eq!(
+            incr_by(&mut keyspace, &command, now, -1),
+            Reply::Integer(-9)
+        );
+        assert_eq!(
+            keyspace.get(b"k", now).unwrap().expires_at(),
+            Some(expires_at)
+        );
+        let command = arguments(&["INCR", "new"]);
+        assert_eq!(incr_by(&mut keyspace, &command, now, 1), Reply::Integer(1));
+
+        for value in ["01", "+1", " 1", "1.0", "", "-0"] {
+            keyspace.set(b"k".to_vec(), Value::String(value.into()), None, now);
+            let command = arguments(&["INCR", "k"]);
+            assert_eq!(
+                incr_by(&mut keyspace, &command, now, 1),
+                Reply::not_an_integer()
+            );
+        }
+        keyspace.set(
+            b"k".to_vec(),
+            Value::String(b"9223372036854775807".to_vec()),
+            None,
+            now,
+        );
+        assert_eq!(
+            incr_by(&mut keyspace, &arguments(&["INCR", "k"]), now, 1),
+            Reply::error("ERR increment or decrement would overflow")
+        );
+        let command = arguments(&["DECRBY", "k", "-9223372036854775808"]);
+        assert_eq!(
+            incr_by(&mut keyspace, &command, now, -1),
+            Reply::error("ERR decrement would overflow")
+        );
+        let command = arguments(&["INCRBY", "k", "x"]);
+        assert_eq!(
+            incr_by(&mut keyspace, &command, now, 1),
+            Reply::not_an_integer()
+        );
+    }
+
+    #[test]
+    fn float_counters() {
+        let mut keyspace = Keyspace::default();
+        let now = fake_now();
+        keyspace.set(b"k".to_vec(), Value::String(b"10.5".to_vec()), None, now);
+
+        let command = arguments(&["INCRBYFLOAT", "k", "0.1"]);
+        assert_eq!(
+            incr_by_float(&mut keyspace, &command, now),
+            Reply::bulk("10.6")
+        );
+        let command = arguments(&["INCRBYFLOAT", "k", "-5.6"]);
+        assert_eq!(
+            incr_by_float(&mut keyspace, &command, now),
+            Reply::bulk("5")
+        );
+        let command = arguments(&["INCRBYFLOAT", "k", "inf"]);
+        assert_eq!(
+            incr_by_float(&mut keyspace, &command, now),
+            Reply::error("ERR increment would produce NaN or Infinity")
+        );
+        let command = arguments(&["INCRBYFLOAT", "k", "x"]);
+        assert_eq!(
+            incr_by_float(&mut keyspace, &command, now),
+            Reply::not_a_float()
+        );
+    }
+
+    #[test]
+    fn ranges_and_append() {
+        let mut keyspace = Keyspace::default();
+        let now = fake_now();
+
+        let command = arguments(&["APPEND", "k", "Hello"]);
+        assert_eq!(append(&mut keyspace, &command, now), Reply::Integer(5));
+        let command = arguments(&["APPEND", "k", " World"]);
+        assert_eq!(append(&mut keyspace, &command, now), Reply::Integer(11));
+        assert_eq!(
+            strlen(&mut keyspace, &arguments(&["STRLEN", "k"]), now),
+            Reply::Integer(11)
+        );
+
+        let range = |keyspace: &mut Keyspace, start: &str, end: &str| {
+            getrange(keyspace, &arguments(&["GETRANGE", "k", start, end]), now)
+        };
+        assert_eq!(range(&mut keyspace, "0", "4"), Reply::bulk("Hello"));
+        assert_eq!(range(&mut keyspace, "-5", "-1"), Reply::bulk("World"));
+        assert_eq!(range(&mut keyspace, "-100", "-50"), Reply::bulk("H"));
+        assert_eq!(range(&mut keyspace, "6", "100"), Reply::bulk("World"));
+        assert_eq!(range(&mut keyspace, "5", "3"), Reply::bulk(""));
+
+        let command = arguments(&["SETRANGE", "k", "6", "Redis"]);
+        assert_eq!(setrange(&mut keyspace, &command, now), Reply::Integer(11));
+        assert_eq!(
+            get(&mut keyspace, &arguments(&["GET", "k"]), now),
+            Reply::bulk("Hello Redis")
+        );
+        let command = arguments(&["SETRANGE", "padded", "3", "x"]);
+        assert_eq!(setrange(&mut keyspace, &command, now), Reply::Integer(4));
+        assert_eq!(
+            get(&mut keyspace, &arguments(&["GET", "padded"]), now),
+            Reply::bulk(&b"\0\0\0x"[..])
+        );
+        let command = arguments(&["SETRANGE", "missing", "3", ""]);
+        assert_eq!(setrange(&mut keyspace, &command, now), Reply::Integer(0));
+        assert!(keyspace.get(b"missing", now).is_none());
+        let command = arguments(&["SETRANGE", "k", "-1", "x"]);
+        assert_eq!(
+            setrange(&mut keyspace, &command, now),
+            Reply::error("ERR offset is out of range")
+        );
+    }
+
+    #[test]
+    fn getdel_and_getex() {
+        let mut keyspace = Keyspace::default();
+        let now = fake_now();
+        keyspace.set(b"k".to_vec(), Value::String(b"v".to_vec()), None, now);
+
+        let command = arguments(&["GETEX", "k", "PX", "1500"]);
+        assert_eq!(getex(&mut keyspace, &command, now), Reply::bulk("v"));
+        assert_eq!(
+            keyspace.get(b"k", now).unwrap().expires_at(),
+            Some(now + Duration::from_millis(1500))
+        );
+        let command = arguments(&["GETEX", "k", "PERSIST"]);
+        assert_eq!(getex(&mut keyspace, &command, now), Reply::bulk("v"));
+        assert_eq!(keyspace.get(b"k", now).unwrap().expires_at(), None);
+        let command = arguments(&["GETEX", "k", "EX", "0"]);
+        assert_eq!(
+            getex(&mut keyspace, &command, now),
+            Reply::error("ERR invalid expire time in 'getex' command")
+        );
+
+        let command = arguments(&["GETDEL", "k"]);
+        assert_eq!(getdel(&mut keyspace, &command, now), Reply::bulk("v"));
+        assert_eq!(getdel(&mut keyspace, &command, now), Reply::Nil);
+    }
+
+    #[test]
+    fn multiple_keys() {
+        let database = Database::default();
+        let now = fake_now();
+        let command = arguments(&["MSETNX", "a", "1", "b", "2", "c", "3"]);
+        let mut shards = database.lock(0, ["a", "b", "c"].map(str::as_bytes));
+        shards
+            .keyspace(b"c")
+            .set(b"c".to_vec(), Value::List(Default::default()), None, now);
+
+        assert_eq!(mset(&mut shards, &command, now, true), Reply::Integer(0));
+        let command = arguments(&["MSET", "a", "1", "b", "2"]);
+        assert_eq!(mset(&mut shards, &command, now, false), Reply::ok());
+        let command = arguments(&["MGET", "a", "b", "c"]);
+        assert_eq!(
+            mget(&mut shards, &command, now),
+            Reply::Array(vec![Reply::bulk("1"), Reply::bulk("2"), Reply::Nil])
+        );
+        let command = arguments(&["MSET", "a", "1", "b"]);
+        assert_eq!(
+            mset(&mut shards, &command, now, false),
+            Reply::wrong_number_of_arguments(b"MSET")
+        );
+    }
+
+    #[test]
+    fn set_get_wrong_type() {
+        let mut keyspace = Keyspace::default();
+        let now = fake_now();
+        keyspace.set(b"k".to_vec(), Value::List(Default::default()), None, now);
+
+        let reply = set(&mut keyspace, &arguments(&["SET", "k", "v", "GET"]), now);
+        assert_eq!(reply, Reply::wrong_type());
+        let reply = set(&mut keyspace, &arguments(&["SET", "k", "v"]), now);
+        assert_eq!(reply, Reply::ok());
+    }
+}
@@ -0,0 +1,521 @@
+use super::streams::parse_read;
+use crate::redis::resp::Reply;
+
+/// The command changes the keyspace.
+pub const WRITE: u16 = 1 << 0;
+/// The command only reads keys.
+pub const READONLY: u16 = 1 << 1;
+/// The command runs in constant or logarithmic time.
+pub const FAST: u16 = 1 << 2;
+/// The command administers the server.
+pub const ADMIN: u16 = 1 << 3;
+/// The command is part of publish/subscribe.
+pub const PUBSUB: u16 = 1 << 4;
+/// The command may block the client.
+pub const BLOCKING: u16 = 1 << 5;
+/// The command may use more memory, it is refused when over the memory limit.
+pub const DENYOOM: u16 = 1 << 6;
+/// The command cannot be called by scripts.
+pub const NOSCRIPT: u16 = 1 << 7;
+/// The command can be sent by clients not authenticated yet, whatever their permissions.
+pub const NO_AUTH: u16 = 1 << 8;
+
+const FLAG_NAMES: [(u16, &str); 9] = [
+    (WRITE, "write"),
+    (READONLY, "readonly"),
+    (FAST, "fast"),
+    (ADMIN, "admin"),
+    (PUBSUB, "pubsub"),
+    (BLOCKING, "blocking"),
+    (DENYOOM, "denyoom"),
+    (NOSCRIPT, "noscript"),
+    (NO_AUTH, "no_auth"),
+];
+
+// the ACL categories of the commands, besides the ones following from their flags
+
+/// The command accesses keys whatever their type.
+const KEYSPACE: u32 = 1 << 0;
+const STRING: u32 = 1 << 1;
+const LIST: u32 = 1 << 2;
+const HASH: u32 = 1 << 3;
+const SET: u32 = 1 << 4;
+const SORTED_SET: u32 = 1 << 5;
+const STREAM: u32 = 1 << 6;
+/// The command is about the connection of the client.
+const CONNECTION: u32 = 1 << 7;
+const TRANSACTION: u32 = 1 << 8;
+const SCRIPTING: u32 = 1 << 9;
+/// The command may disrupt the server, administrative ones are too.
+const DANGEROUS: u32 = 1 << 10;
+
+/// Whether a command belongs to an ACL category.
+type Membership = fn(&Command) -> bool;
+
+/// The ACL categories by name.
+const CATEGORIES: &[(&str, Membership)] = &[
+    ("keyspace", |command| command.categories & KEYSPACE != 0),
+    ("read", |command| command.has(READONLY)),
+    ("write", |command| command.has(WRITE)),
+    ("set", |command| command.categories & SET != 0),
+    ("sortedset", |command| command.categories & SORTED_SET != 0),
+    ("list", |command| command.categories & LIST != 0),
+    ("hash", |command| command.categories & HASH != 0),
+    ("string", |command| command.categories & STRING != 0),
+    ("pubsub", |command| command.has(PUBSUB)),
+    ("admin", |command| command.has(ADMIN)),
+    ("fast", |command| command.has(FAST)),
+    ("slow", |command| !command.has(FAST)),
+    ("blocking", |command| command.has(BLOCKING)),
+    ("dangerous", |command| {
+        command.has(ADMIN) || command.categories & DANGEROUS != 0
+    }),
+    ("connection", |command| command.categories & CONNECTION != 0),
+    ("transaction", |command| {
+        command.categories & TRANSACTION != 0
+    }),
+    ("scripting", |command| command.categories & SCRIPTING != 0),
+    ("stream", |command| command.categories & STREAM != 0),
+    ("all", |_| true),
+];
+
+/// The keys a command accesses, deciding which shards are locked to execute it.
+#[derive(Clone, Copy)]
+pub enum Keys {
+    None,
+    /// The whole keyspace, every shard is locked.
+    All,
+    /// The arguments from `first` to `last` included, every `step` arguments. `last`
+    /// counts from the end when negative.
+    Range {
+        first: usize,
+        last: isize,
+        step: usize,
+    },
+    /// The first half of the arguments after the `STREAMS` option, the other half being
+    /// their IDs. None when the options are invalid, the command failing before accessing
+    /// any key.
+    Streams,
+}
+
+const fn range(first: usize, last: isize, step: usize) -> Keys {
+    Keys::Range { first, last, step }
+}
+
+/// The single key in the first argument.
+const FIRST: Keys = range(1, 1, 1);
+/// Every argument is a key.
+const EVERY: Keys = range(1, -1, 1);
+
+pub struct Command {
+    pub name: &'static str,
+    /// The number of arguments including the name, or its opposite when it is the minimum.
+    pub arity: i64,
+    pub flags: u16,
+    pub keys: Keys,
+    /// The ACL categories not following from the flags.
+    categories: u32,
+}
+
+impl Command {
+    const fn new(name: &'static str, arity: i64, flags: u16, keys: Keys, categories: u32) -> Self {
+        Self {
+            name,
+            arity,
+            flags,
+            keys,
+            categories,
+        }
+    }
+
+    pub fn has(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// The keys of a call of the command, `None` when it accesses the whole keyspace.
+    pub fn keys<'a>(&self, arguments: &'a [Vec<u8>]) -> Option<Vec<&'a [u8]>> {
+        match self.keys {
+            Keys::None => Some(Vec::new()),
+            Keys::All => None,
+            Keys::Range { first, last, step } => {
+                let last = if last < 0 {
+                    arguments.len() as isize + last
+                } else {
+                    last
+                };
+                let keys = usize::try_from(last)
+                    .ok()
+                    .and_then(|last| arguments.get(first..=last))
+                    .unwrap_or_default();
+                Some(keys.iter().step_by(step).map(Vec::as_slice).collect())
+            }
+            // a group or a consumer may be named STREAMS, the options are parsed as the
+            // command does
+            Keys::Streams => Some(parse_read(arguments).map_or_else(
+                |_| Vec::new(),
+                |read| read.keys.iter().map(Vec::as_slice).collect(),
+            )),
+        }
+    }
+
+    /// The description of the command replied by COMMAND: name, arity, flags, then the
+    /// position of the first key, of the last key and the step between keys.
+    fn describe(&self) -> Reply {
+        let flags = FLAG_NAMES
+            .iter()
+            .filter(|(flag, _)| self.has(*flag))
+            .map(|(_, name)| Reply::Simple(name.to_string()))
+            .collect();
+        let (first, last, step) = match self.keys {
+            Keys::None | Keys::All | Keys::Streams => (0, 0, 0),
+            Keys::Range { first, last, step } => (first as i64, last as i64, step as i64),
+        };
+
+        Reply::Array(vec![
+            Reply::bulk(self.name.to_lowercase()),
+            Reply::Integer(self.arity),
+            Reply::Array(flags),
+            Reply::Integer(first),
+            Reply::Integer(last),
+            Reply::Integer(step),
+        ])
+    }
+}
+
+/// Every command the server knows.
+pub static COMMANDS: &[Command] = &[
+    Command::new("PING", -1, FAST, Keys::None, CONNECTION),
+    Command::new("ECHO", 2, FAST, Keys::None, CONNECTION),
+    Command::new("COMMAND", -1, 0, Keys::None, CONNECTION),
+    // the keyspace section counts the keys of every shard
+    Command::new("INFO", -1, 0, Keys::All, DANGEROUS),
+    Command::new("CONFIG", -2, ADMIN | NOSCRIPT, Keys::None, 0),
+    // connection
+    Command::new("SELECT", 2, FAST, Keys::None, CONNECTION),
+    Command::new("CLIENT", -2, NOSCRIPT, Keys::None, CONNECTION),
+    Command::new(
+        "HELLO",
+        -1,
+        FAST | NOSCRIPT | NO_AUTH,
+        Keys::None,
+        CONNECTION,
+    ),
+    Command::new(
+        "AUTH",
+        -2,
+        FAST | NOSCRIPT | NO_AUTH,
+        Keys::None,
+        CONNECTION,
+    ),
+    // subcommands are not told apart, ACL WHOAMI needs the permission to run ACL as well
+    Command::new("ACL", -2, ADMIN | NOSCRIPT, Keys::None, 0),
+    // keys
+    Command::new("EXISTS", -2, READONLY | FAST, EVERY, KEYSPACE),
+    Command::new("DEL", -2, WRITE, EVERY, KEYSPACE),
+    Command::new("TYPE", 2, READONLY | FAST, FIRST, KEYSPACE),
+    Command::new("RENAME", 3, WRITE, range(1, 2, 1), KEYSPACE),
+    Command::new("KEYS", 2, READONLY, Keys::All, KEYSPACE | DANGEROUS),
+    // locks the shards one at a time itself
+    Command::new("SCAN", -2, READONLY, Keys::None, KEYSPACE),
+    Command::new("DBSIZE", 1, READONLY | FAST, Keys::All, KEYSPACE),
+    Command::new("FLUSHALL", -1, WRITE, Keys::All, KEYSPACE | DANGEROUS),
+    Command::new("FLUSHDB", -1, WRITE, Keys::All, KEYSPACE | DANGEROUS),
+    Command::new("EXPIRE", -3, WRITE | FAST, FIRST, KEYSPACE),
+    Command::new("PEXPIRE", -3, WRITE | FAST, FIRST, KEYSPACE),
+    Command::new("EXPIREAT", -3, WRITE | FAST, FIRST, KEYSPACE),
+    Command::new("PEXPIREAT", -3, WRITE | FAST, FIRST, KEYSPACE),
+    Command::new("TTL", 2, READONLY | FAST, FIRST, KEYSPACE),
+    Command::new("PTTL", 2, READONLY | FAST, FIRST, KEYSPACE),
+    Command::new("PERSIST", 2, WRITE | FAST, FIRST, KEYSPACE),
+    // strings
+    Command::new("SET", -3, WRITE | DENYOOM, FIRST, STRING),
+    Command::new("GET", 2, READONLY | FAST, FIRST, STRING),
+    Command::new("GETDEL", 2, WRITE | FAST, FIRST, STRING),
+    Command::new("GETEX", -2, WRITE | FAST, FIRST, STRING),
+    Command::new("MGET", -2, READONLY | FAST, EVERY, STRING),
+    Command::new("MSET", -3, WRITE | DENYOOM, range(1, -1, 2), STRING),
+    Command::new("MSETNX", -3, WRITE | DENYOOM, range(1, -1, 2), STRING),
+    Command::new("INCR", 2, WRITE | DENYOOM | FAST, FIRST, STRING),
+    Command::new("DECR", 2, WRITE | DENYOOM | FAST, FIRST, STRING),
+    Command::new("INCRBY", 3, WRITE | DENYOOM | FAST, FIRST, STRING),
+    Command::new("DECRBY", 3, WRITE | DENYOOM | FAST, FIRST, STRING),
+    Command::new("INCRBYFLOAT", 3, WRITE | DENYOOM | FAST, FIRST, STRING),
+    Command::new("APPEND", 3, WRITE | DENYOOM | FAST, FIRST, STRING),
+    Command::new("STRLEN", 2, READONLY | FAST, FIRST, STRING),
+    Command::new("GETRANGE", 4, READONLY, FIRST, STRING),
+    Command::new("SETRANGE", 4, WRITE | DENYOOM, FIRST, STRING),
+    // lists
+    Command::new("LPUSH", -3, WRITE | DENYOOM | FAST, FIRST, LIST),
+    Command::new("RPUSH", -3, WRITE | DENYOOM | FAST, FIRST, LIST),
+    Command::new("LPOP", -2, WRITE | FAST, FIRST, LIST),
+    Command::new("RPOP", -2, WRITE | FAST, FIRST, LIST),
+    Command::new("LRANGE", 4, READONLY, FIRST, LIST),
+    Command::new("LLEN", 2, READONLY | FAST, FIRST, LIST),
+    Command::new("LMOVE", 5, WRITE | DENYOOM, range(1, 2, 1), LIST),
+    Command::new(
+        "BLPOP",
+        -3,
+        WRITE | BLOCKING | NOSCRIPT,
+        range(1, -2, 1),
+        LIST,
+    ),
+    Command::new(
+        "BRPOP",
+        -3,
+        WRITE | BLOCKING | NOSCRIPT,
+        range(1, -2, 1),
+        LIST,
+    ),
+    Command::new(
+        "BLMOVE",
+        6,
+        WRITE | DENYOOM | BLOCKING | NOSCRIPT,
+        range(1, 2, 1),
+        LIST,
+    ),
+    // hashes
+    Command::new("HSET", -4, WRITE | DENYOOM | FAST, FIRST, HASH),
+    Command::new("HGET", 3, READONLY | FAST, FIRST, HASH),
+    Command::new("HDEL", -3, WRITE | FAST, FIRST, HASH),
+    Command::new("HGETALL", 2, READONLY, FIRST, HASH),
+    // sets
+    Command::new("SADD", -3, WRITE | DENYOOM | FAST, FIRST, SET),
+    Command::new("SREM", -3, WRITE | FAST, FIRST, SET),
+    Command::new("SMEMBERS", 2, READONLY, FIRST, SET),
+    Command::new("SISMEMBER", 3, READONLY | FAST, FIRST, SET),
+    // sorted sets
+    Command::new("ZADD", -4, WRITE | DENYOOM | FAST, FIRST, SORTED_SET),
+    Command::new("ZRANGE", -4, READONLY, FIRST, SORTED_SET),
+    Command::new("ZRANGEBYSCORE", -4, READONLY, FIRST, SORTED_SET),
+    Command::new("ZRANK", -3, READONLY | FAST, FIRST, SORTED_SET),
+    Command::new("ZREM", -3, WRITE | FAST, FIRST, SORTED_SET),
+    // streams
+    Command::new("XADD", -5, WRITE | DENYOOM | FAST, FIRST, STREAM),
+    Command::new("XLEN", 2, READONLY | FAST, FIRST, STREAM),
+    Command::new("XRANGE", -4, READONLY, FIRST, STREAM),
+    Command::new("XREVRANGE", -4, READONLY, FIRST, STREAM),
+    Command::new("XTRIM", -4, WRITE, FIRST, STREAM),
+    Command::new("XREAD", -4, READONLY | BLOCKING, Keys::Streams, STREAM),
+    // not flagged as blocking, so that the entries it delivers are logged
+    Command::new("XREADGROUP", -7, WRITE, Keys::Streams, STREAM),
+    Command::new("XGROUP", -2, WRITE, range(2, 2, 1), STREAM),
+    Command::new("XACK", -4, WRITE | FAST, FIRST, STREAM),
+    Command::new("XPENDING", -3, READONLY, FIRST, STREAM),
+    Command::new("XCLAIM", -6, WRITE | FAST, FIRST, STREAM),
+    Command::new("XSETID", -3, WRITE | DENYOOM, FIRST, STREAM),
+    // publish/subscribe
+    Command::new("SUBSCRIBE", -2, PUBSUB | NOSCRIPT, Keys::None, 0),
+    Command::new("UNSUBSCRIBE", -1, PUBSUB | NOSCRIPT, Keys::None, 0),
+    Command::new("PSUBSCRIBE", -2, PUBSUB | NOSCRIPT, Keys::None, 0),
+    Command::new("PUNSUBSCRIBE", -1, PUBSUB | NOSCRIPT, Keys::None, 0),
+    Command::new("PUBLISH", 3, PUBSUB | FAST, Keys::None, 0),
+    Command::new("PUBSUB", -2, PUBSUB, Keys::None, 0),
+    // transactions
+    Command::new("MULTI", 1, FAST | NOSCRIPT, Keys::None, TRANSACTION),
+    Command::new("EXEC", 1, NOSCRIPT, Keys::None, TRANSACTION),
+    Command::new("DISCARD", 1, FAST | NOSCRIPT, Keys::None, TRANSACTION),
+    Command::new("WATCH", -2, FAST | NOSCRIPT, EVERY, TRANSACTION),
+    Command::new("UNWATCH", 1, FAST | NOSCRIPT, Keys::None, TRANSACTION),
+    // persistence
+    Command::new("SAVE", 1, ADMIN | NOSCRIPT, Keys::All, 0),
+    Command::new("BGSAVE", -1, ADMIN | NOSCRIPT, Keys::All, 0),
+    Command::new("BGREWRITEAOF", 1, ADMIN | NOSCRIPT, Keys::All, 0),
+    Command::new("LASTSAVE", 1, FAST, Keys::None, 0),
+    // the final snapshot is taken with every shard locked
+    Command::new("SHUTDOWN", -1, ADMIN | NOSCRIPT, Keys::All, 0),
+    // replication
+    Command::new("REPLICAOF", 3, ADMIN | NOSCRIPT, Keys::None, 0),
+    Command::new("SLAVEOF", 3, ADMIN | NOSCRIPT, Keys::None, 0),
+    Command::new("REPLCONF", -1, ADMIN | NOSCRIPT, Keys::None, 0),
+    Command::new("PSYNC", -3, ADMIN | NOSCRIPT, Keys::All, 0),
+    Command::new("SYNC", 1, ADMIN | NOSCRIPT, Keys::All, 0),
+    // scripting, a script can access any key
+    Command::new("EVAL", -3, NOSCRIPT, Keys::All, SCRIPTING),
+    Command::new("EVALSHA", -3, NOSCRIPT, Keys::All, SCRIPTING),
+    Command::new("SCRIPT", -2, NOSCRIPT, Keys::None, SCRIPTING),
+];
+
+/// Uppercases the name of a command, the case in which commands are dispatched.
+pub fn normalize(arguments: &mut [Vec<u8>]) {
+    if let Some(name) = arguments.first_mut() {
+        name.make_ascii_uppercase();
+    }
+}
+
+/// Finds a command by name, ignoring case.
+pub fn lookup(name: &[u8]) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name.as_bytes().eq_ignore_ascii_case(name))
+}
+
+/// The commands of an ACL category, `None` when there is no such category.
+pub fn category(name: &[u8]) -> Option<impl Iterator<Item = &'static Command>> {
+    let (_, belongs) = CATEGORIES
+        .iter()
+        .find(|(category, _)| category.as_bytes().eq_ignore_ascii_case(name))?;
+    Some(COMMANDS.iter().filter(move |command| belongs(command)))
+}
+
+/// The names of the ACL categories, as listed by ACL CAT.
+pub fn category_names() -> impl Iterator<Item = &'static str> {
+    CATEGORIES.iter().map(|(name, _)| *name)
+}
+
+/// Finds the command called by `arguments` and checks its number of arguments, before it
+/// is queued or executed.
+pub fn check(arguments: &[Vec<u8>]) -> Result<&'static Command, Reply> {
+    let Some(command) = lookup(&arguments[0]) else {
+        return Err(unknown_command(arguments));
+    };
+
+    let count = arguments.len() as i64;
+    if (command.arity >= 0 && count != command.arity) || count < -command.arity {
+        return Err(Reply::wrong_number_of_arguments(&arguments[0]));
+    }
+    Ok(command)
+}
+
+pub fn unknown_command(arguments: &[Vec<u8>]) -> Reply {
+    let beginning = arguments[1..]
+        .iter()
+        .map(|argument| format!("'{}' ", String::from_utf8_lossy(argument)))
+        .collect::<String>();
+    Reply::error(format!(
+        "ERR unknown command '{}', with args beginning with: {}",
+        String::from_utf8_lossy(&arguments[0]),
+        beginning
+    ))
+}
+
+/// COMMAND, COMMAND COUNT and COMMAND INFO [command ...]
+pub fn command(arguments: &[Vec<u8>]) -> Reply {
+    let Some(subcommand) = arguments.get(1) else {
+        return Reply::Array(COMMANDS.iter().map(Command::describe).collect());
+    };
+
+    match subcommand.to_ascii_uppercase().as_slice() {
+        b"COUNT" if arguments.len() == 2 => Reply::Integer(COMMANDS.len() as i64),
+        b"INFO" if arguments.len() == 2 => {
+            Reply::Array(COMMANDS.iter().map(Command::describe).collect())
+        }
+        b"INFO" => Reply::Array(
+            arguments[2..]
+                .iter()
+                .map(|name| lookup(name).map_or(Reply::NilArray, Command::describe))
+                .collect(),
+        ),
+        b"COUNT" => Reply::error("ERR wrong number of arguments for 'command|count' command"),
+        _ => Reply::error(format!(
+            "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+            String::from_utf8_lossy(subcommand)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::arguments;
+
+    #[test]
+    fn arity_and_case() {
+        assert_eq!(check(&arguments(&["get", "k"])).unwrap().name, "GET");
+        assert_eq!(
+            check(&arguments(&["GET"])).err(),
+            Some(Reply::error(
+                "ERR wrong number of arguments for 'get' command"
+            ))
+        );
+        assert!(check(&arguments(&["SET", "k", "v", "EX", "1"])).is_ok());
+        assert!(check(&arguments(&["SET", "k"])).is_err());
+        assert_eq!(
+            check(&arguments(&["NOPE", "a", "b"])).err(),
+            Some(Reply::error(
+                "ERR unknown command 'NOPE', with args beginning with: 'a' 'b' "
+            ))
+        );
+    }
+
+    #[test]
+    fn key_positions() {
+        let keys = |command: &[&str]| {
+            let arguments = arguments(command);
+            lookup(&arguments[0])
+                .unwrap()
+                .keys(&arguments)
+                .map(|keys| keys.iter().map(|key| key.to_vec()).collect::<Vec<_>>())
+        };
+
+        assert_eq!(keys(&["GET", "k"]), Some(vec![b"k".to_vec()]));
+        assert_eq!(
+            keys(&["MSET", "a", "1", "b", "2"]),
+            Some(vec![b"a".to_vec(), b"b".to_vec()])
+        );
+        assert_eq!(
+            keys(&["BLPOP", "a", "b", "0"]),
+            Some(vec![b"a".to_vec(), b"b".to_vec()])
+        );
+        assert_eq!(
+            keys(&["XREAD", "COUNT", "2", "STREAMS", "a", "b", "0", "$"]),
+            Some(vec![b"a".to_vec(), b"b".to_vec()])
+        );
+        assert_eq!(keys(&["XREAD", "COUNT", "2"]), Some(vec![]));
+        assert_eq!(
+            keys(&["XREADGROUP", "GROUP", "streams", "c", "STREAMS", "k", ">"]),
+            Some(vec![b"k".to_vec()])
+        );
+        assert_eq!(
+            keys(&["XREADGROUP", "GROUP", "g", "STREAMS", "STREAMS", "k", ">"]),
+            Some(vec![b"k".to_vec()])
+        );
+        assert_eq!(keys(&["PING"]), Some(vec![]));
+        assert_eq!(keys(&["FLUSHALL"]), None);
+    }
+
+    #[test]
+    fn categories() {
+        let names = |name: &str| {
+            category(name.as_bytes())
+                .map(|commands| commands.map(|command| command.name).collect::<Vec<_>>())
+        };
+
+        assert_eq!(names("HASH"), Some(vec!["HSET", "HGET", "HDEL", "HGETALL"]));
+        assert!(names("dangerous").unwrap().contains(&"FLUSHALL"));
+        assert!(names("dangerous").unwrap().contains(&"CONFIG"));
+        let slow = names("slow").unwrap();
+        assert!(slow.contains(&"KEYS") && !slow.contains(&"GET"));
+        assert_eq!(names("all").unwrap().len(), COMMANDS.len());
+        assert_eq!(names("nope"), None);
+        assert!(category_names().any(|name| name == "stream"));
+    }
+
+    #[test]
+    fn introspection() {
+        assert_eq!(
+            command(&arguments(&["COMMAND", "COUNT"])),
+            Reply::Integer(COMMANDS.len() as i64)
+        );
+        assert_eq!(
+            command(&arguments(&["COMMAND", "INFO", "mset", "nope"])),
+            Reply::Array(vec![
+                Reply::Array(vec![
+                    Reply::bulk("mset"),
+                    Reply::Integer(-3),
+                    Reply::Array(vec![
+                        Reply::Simple("write".to_string()),
+                        Reply::Simple("denyoom".to_string())
+                    ]),
+                    Reply::Integer(1),
+                    Reply::Integer(-1),
+                    Reply::Integer(2),
+                ]),
+                Reply::NilArray,
+            ])
+        );
+        let Reply::Array(all) = command(&arguments(&["COMMAND"])) else {
+            panic!("expected every command");
+        };
+        assert_eq!(all.len(), COMMANDS.len());
+    }
+}
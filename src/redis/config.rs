@@ -0,0 +1,289 @@
+use super::glob::glob_match;
+use super::RedisConfig;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+/// A configuration parameter, named as in the config file and in CONFIG GET/SET.
+struct Parameter {
+    name: &'static str,
+    /// The command line option setting it.
+    option: &'static str,
+    /// Set by the presence of its option, `yes` or `no` in the config file.
+    is_flag: bool,
+    /// Whether CONFIG SET can change it while the server runs.
+    is_mutable: bool,
+}
+
+impl Parameter {
+    const fn new(
+        name: &'static str,
+        option: &'static str,
+        is_flag: bool,
+        is_mutable: bool,
+    ) -> Self {
+        Self {
+            name,
+            option,
+            is_flag,
+            is_mutable,
+        }
+    }
+}
+
+/// Every parameter, in the order of the values of `Config`.
+const PARAMETERS: &[Parameter] = &[
+    Parameter::new("port", "-p", false, false),
+    Parameter::new("dir", "--dir", false, false),
+    Parameter::new("dbfilename", "--dbfilename", false, false),
+    Parameter::new("save", "--save", false, true),
+    Parameter::new("appendonly", "--appendonly", true, false),
+    Parameter::new("appendfilename", "--appendfilename", false, false),
+    Parameter::new("appendfsync", "--appendfsync", false, false),
+    Parameter::new("aof-load-truncated", "--aof-load-truncated", true, false),
+    Parameter::new("replicaof", "--replicaof", false, false),
+    Parameter::new("maxmemory", "--maxmemory", false, true),
+    Parameter::new("maxmemory-policy", "--maxmemory-policy", false, true),
+    Parameter::new("databases", "--databases", false, false),
+    Parameter::new("timeout", "--timeout", false, true),
+    Parameter::new("requirepass", "--requirepass", false, true),
+    Parameter::new("bind", "--bind", false, false),
+    Parameter::new("unixsocket", "--unixsocket", false, false),
+];
+
+/// Translates the directives of a config file, one `name value` per line, to the command
+/// line options setting them. Options given on the command line after them take precedence.
+pub fn file_options(contents: &str) -> Result<Vec<&str>, String> {
+    let mut options = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        let Some(parameter) = PARAMETERS
+            .iter()
+            .find(|parameter| parameter.name.eq_ignore_ascii_case(name))
+        else {
+            return Err(format!(
+                "Bad directive in config file line {}: {}",
+                number + 1,
+                line
+            ));
+        };
+
+        match (parameter.is_flag, value) {
+            (true, "yes") => options.push(parameter.option),
+            (true, "no") => {}
+            (true, _) => {
+                return Err(format!(
+                    "argument must be 'yes' or 'no' in config file line {}: {}",
+                    number + 1,
+                    line
+                ))
+            }
+            (false, _) => options.extend([parameter.option, value]),
+        }
+    }
+
+    Ok(options)
+}
+
+/// The configuration of the running server, as reported by CONFIG GET.
+pub struct Config {
+    /// The config file the server was started with, written by CONFIG REWRITE.
+    file: Option<PathBuf>,
+    values: Vec<String>,
+    defaults: Vec<String>,
+}
+
+impl Config {
+    pub fn new(redis_config: &RedisConfig, file: Option<PathBuf>) -> Self {
+        Self {
+            file,
+            values: values(redis_config),
+            defaults: values(&RedisConfig::default()),
+        }
+    }
+
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
+    /// The parameters matching a glob-style pattern, with their value.
+    pub fn get(&self, pattern: &[u8]) -> Vec<(&'static str, &str)> {
+        let pattern = pattern.to_ascii_lowercase();
+        PARAMETERS
+            .iter()
+            .zip(&self.values)
+            .filter(|(parameter, _)| glob_match(&pattern, parameter.name.as_bytes()))
+            .map(|(parameter, value)| (parameter.name, value.as_str()))
+            .collect()
+    }
+
+    /// The canonical name of a parameter that can be changed at runtime.
+    pub fn mutable_name(&self, name: &[u8]) -> Result<&'static str, String> {
+        match PARAMETERS
+            .iter()
+            .find(|parameter| parameter.name.as_bytes().eq_ignore_ascii_case(name))
+        {
+            Some(parameter) if parameter.is_mutable => Ok(parameter.name),
+            Some(_) => Err("can't set immutable config".to_string()),
+            None => Err("unknown option or number of arguments".to_string()),
+        }
+    }
+
+    /// Records the new value of a parameter, once it was applied.
+    pub fn set(&mut self, name: &str, value: String) {
+        if let Some(position) = PARAMETERS
+            .iter()
+            .position(|parameter| parameter.name == name)
+        {
+            self.values[position] = value;
+        }
+    }
+
+    /// Writes the current configuration to the config file. The lines of the parameters are
+    /// updated in place, keeping the comments, and the parameters missing from it are
+    /// appended unless they have their default value.
+    pub fn rewrite(&self) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Err(io::Error::other(
+                "The server is running without a config file",
+            ));
+        };
+        let contents = match fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut written = vec![false; PARAMETERS.len()];
+        let mut lines = Vec::new();
+        for line in contents.lines() {
+            let name = line.split_whitespace().next().unwrap_or_default();
+            let position = PARAMETERS
+                .iter()
+                .position(|parameter| parameter.name.eq_ignore_ascii_case(name));
+            match position {
+                // a parameter set several times keeps the last value only
+                Some(position) if written[position] => {}
+                Some(position) => {
+                    written[position] = true;
+                    lines.push(self.directive(position));
+                }
+                None => lines.push(line.to_string()),
+            }
+        }
+        for (position, written) in written.into_iter().enumerate() {
+            if !written && self.values[position] != self.defaults[position] {
+                lines.push(self.directive(position));
+            }
+        }
+
+        let mut temporary_path = file.clone().into_os_string();
+        temporary_path.push(format!(".temp-{}", std::process::id()));
+        fs::write(&temporary_path, lines.join("\n") + "\n")?;
+        fs::rename(&temporary_path, file)
+    }
+
+    fn directive(&self, position: usize) -> String {
+        let value = &self.values[position];
+        if value.is_empty() {
+            format!("{} \"\"", PARAMETERS[position].name)
+        } else {
+            format!("{} {}", PARAMETERS[position].name, value)
+        }
+    }
+}
+
+/// The values of the parameters set by the command line options, in their order.
+fn values(redis_config: &RedisConfig) -> Vec<String> {
+    let flag = |set: bool| if set { "yes" } else { "no" }.to_string();
+    vec![
+        redis_config.port.to_string(),
+        redis_config.dir.to_string(),
+        redis_config.dbfilename.to_string(),
+        redis_config.save.to_string(),
+        flag(redis_config.appendonly),
+        redis_config.appendfilename.to_string(),
+        redis_config.appendfsync.to_string(),
+        flag(redis_config.aof_load_truncated),
+        redis_config.replicaof.to_string(),
+        redis_config.maxmemory.to_string(),
+        redis_config.maxmemory_policy.to_string(),
+        redis_config.databases.to_string(),
+        redis_config.timeout.to_string(),
+        redis_config.requirepass.to_string(),
+        redis_config.bind.to_string(),
+        redis_config.unixsocket.to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_directives() {
+        let contents = "# comment\n\nport 6380\nsave \"\"\nappendonly yes\naof-load-truncated no\nreplicaof 127.0.0.1 6379\nbind 127.0.0.1 ::1\n";
+        assert_eq!(
+            file_options(contents),
+            Ok(vec![
+                "-p",
+                "6380",
+                "--save",
+                "",
+                "--appendonly",
+                "--replicaof",
+                "127.0.0.1 6379",
+                "--bind",
+                "127.0.0.1 ::1"
+            ])
+        );
+        assert!(file_options("appendonly maybe").is_err());
+        assert_eq!(
+            file_options("port 1\nprotected-mode no"),
+            Err("Bad directive in config file line 2: protected-mode no".to_string())
+        );
+    }
+
+    #[test]
+    fn get_set_and_rewrite() {
+        let path = std::env::temp_dir().join(format!("redis-config-{}.conf", std::process::id()));
+        fs::write(&path, "# memory\nmaxmemory 1mb\nmaxmemory 2mb\nport 7000\n").unwrap();
+        let options = ["-p", "7000", "--maxmemory", "2mb"];
+        let mut config = Config::new(
+            &RedisConfig::from_args(&options).unwrap(),
+            Some(path.clone()),
+        );
+
+        assert_eq!(
+            config.get(b"MAXMEMORY*"),
+            vec![("maxmemory", "2mb"), ("maxmemory-policy", "noeviction")]
+        );
+        assert_eq!(config.mutable_name(b"MaxMemory"), Ok("maxmemory"));
+        assert!(config.mutable_name(b"port").is_err());
+        assert!(config.mutable_name(b"nope").is_err());
+
+        config.set("maxmemory", "3mb".to_string());
+        config.set("save", "60 1".to_string());
+        config.rewrite().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# memory\nmaxmemory 3mb\nport 7000\nsave 60 1\n"
+        );
+        fs::remove_file(path).unwrap();
+
+        assert!(Config::new(&RedisConfig::default(), None)
+            .rewrite()
+            .is_err());
+    }
+}
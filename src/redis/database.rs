@@ -0,0 +1,568 @@
+use super::eviction::Policy;
+use super::keyspace::{key_hash, Entry, Keyspace};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+/// Number of independently locked parts of the keyspace, commands on keys of different
+/// shards run in parallel.
+const SHARDS: usize = 64;
+/// Number of logical databases clients can SELECT, as in redis.
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// The keyspaces of the logical databases, each split in shards by the hash of the keys
+/// behind their own lock.
+pub struct Database {
+    /// The shards of every logical database, the ones of database `db` starting at
+    /// `db * SHARDS`.
+    shards: Box<[Mutex<Keyspace>]>,
+    /// The approximate memory used by all the shards, updated when shards are unlocked.
+    used_memory: AtomicUsize,
+    /// The keys deleted because they expired, updated when shards are unlocked.
+    expired_keys: AtomicU64,
+    /// The shard to evict a key from next, going round all of them.
+    next_eviction: AtomicUsize,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new(DEFAULT_DATABASES)
+    }
+}
+
+impl From<Keyspace> for Database {
+    /// A database with `keyspace` as its first logical database.
+    fn from(keyspace: Keyspace) -> Self {
+        let database = Database::default();
+        database.replace(vec![keyspace]);
+        database
+    }
+}
+
+impl Database {
+    pub fn new(databases: usize) -> Self {
+        Self {
+            shards: (0..databases * SHARDS).map(|_| Mutex::default()).collect(),
+            used_memory: AtomicUsize::new(0),
+            expired_keys: AtomicU64::new(0),
+            next_eviction: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of logical databases.
+    pub fn databases(&self) -> usize {
+        self.shards.len() / SHARDS
+    }
+
+    /// Locks the shards holding `keys` in the logical database `db`, which the commands
+    /// executed with the shards then access.
+    pub fn lock<'a>(&self, db: usize, keys: impl IntoIterator<Item = &'a [u8]>) -> Shards<'_> {
+        self.lock_in(db, keys.into_iter().map(|key| (db, key)))
+    }
+
+    /// Locks the shards holding keys of several logical databases, always in the same
+    /// order so that commands locking several shards never deadlock.
+    pub fn lock_in<'a>(
+        &self,
+        db: usize,
+        keys: impl IntoIterator<Item = (usize, &'a [u8])>,
+    ) -> Shards<'_> {
+        let mut indexes = keys
+            .into_iter()
+            .map(|(db, key)| db * SHARDS + shard_of(key))
+            .collect::<Vec<_>>();
+        indexes.sort_unstable();
+        indexes.dedup();
+        self.lock_indexes(db, indexes)
+    }
+
+    /// Locks every shard of every logical database, for commands that need a consistent
+    /// view of the whole dataset.
+    pub fn lock_all(&self, db: usize) -> Shards<'_> {
+        self.lock_indexes(db, 0..self.shards.len())
+    }
+
+    /// Locks the shards at `indexes`, which must be sorted.
+    fn lock_indexes(&self, db: usize, indexes: impl IntoIterator<Item = usize>) -> Shards<'_> {
+        let locked = indexes
+            .into_iter()
+            .map(|index| (index, lock(&self.shards[index])))
+            .collect::<Vec<_>>();
+        Shards {
+            used_memory: locked
+                .iter()
+                .map(|(_, keyspace)| keyspace.used_memory())
+                .sum(),
+            expired_keys: locked
+                .iter()
+                .map(|(_, keyspace)| keyspace.expired_keys())
+                .sum(),
+            locked,
+            db,
+            database: self,
+        }
+    }
+
+    /// The approximate memory used by the keys and their values.
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// How many keys were deleted because they expired, since the start or the last reset.
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_expired_keys(&self) {
+        self.expired_keys.store(0, Ordering::Relaxed);
+    }
+
+    /// Evicts keys chosen by `policy` until at most `maxmemory` bytes are used, calling
+    /// `evicted` with the logical database and each key while its shard is still locked.
+    /// Returns false when the memory is still over the limit because no key can be evicted.
+    pub fn evict(
+        &self,
+        maxmemory: usize,
+        policy: Policy,
+        now: SystemTime,
+        mut evicted: impl FnMut(usize, &[u8]),
+    ) -> bool {
+        if policy == Policy::NoEviction {
+            return self.used_memory() <= maxmemory;
+        }
+
+        while self.used_memory() > maxmemory {
+            // keys are evenly spread across shards, each eviction samples the keys of one
+            let has_evicted = (0..self.shards.len()).any(|_| {
+                let index = self.next_eviction.fetch_add(1, Ordering::Relaxed) % self.shards.len();
+                let db = index / SHARDS;
+                let mut shards = self.lock_indexes(db, [index]);
+                let keyspace = &mut shards.locked[0].1;
+                let Some(key) = keyspace.eviction_candidate(policy, now) else {
+                    return false;
+                };
+                keyspace.remove(&key, now);
+                evicted(db, &key);
+                true
+            });
+            if !has_evicted {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Replaces every key with the ones of `keyspaces`, one per logical database, at once
+    /// for the other clients.
+    pub fn replace(&self, keyspaces: Vec<Keyspace>) {
+        let mut shards = self.lock_all(0);
+        shards.clear_all();
+        for (db, keyspace) in keyspaces.into_iter().enumerate() {
+            shards.select(db);
+            for (key, entry) in keyspace.into_entries() {
+                shards.keyspace(&key).insert(key, entry);
+            }
+        }
+    }
+
+    /// One step of SCAN from `cursor`: examines about `count` keys, locking one shard at a
+    /// time, and returns the ones accepted by `filter` along with the cursor of the next
+    /// step, 0 once every key was examined.
+    ///
+    /// Shards are scanned in order and the keys of a shard by hash, the cursor being the
+    /// hash to resume from: as keys are sharded by hash, its remainder is the shard index.
+    /// Keys added or deleted meanwhile do not move the others, so a key present during the
+    /// whole scan is returned at least once.
+    pub fn scan(
+        &self,
+        db: usize,
+        cursor: u64,
+        count: usize,
+        now: SystemTime,
+        mut filter: impl FnMut(&[u8], &Entry) -> bool,
+    ) -> (u64, Vec<Vec<u8>>) {
+        let mut shard = (cursor % SHARDS as u64) as usize;
+        let mut from = cursor;
+        let mut examined = 0;
+        let mut keys = Vec::new();
+
+        loop {
+            let shards = self.lock_indexes(db, [db * SHARDS + shard]);
+            let (found, next) = shards.locked[0].1.scan(from, count - examined, now);
+            examined += found.len();
+            keys.extend(
+                found
+                    .into_iter()
+                    .filter(|(key, entry)| filter(key, entry))
+                    .map(|(key, _)| key.to_vec()),
+            );
+            if let Some(next) = next {
+                return (next, keys);
+            }
+
+            shard += 1;
+            if shard == SHARDS {
+                return (0, keys);
+            }
+            // the lowest hash a key of the next shard can have
+            from = shard as u64;
+            if examined >= count {
+                return (from, keys);
+            }
+        }
+    }
+
+    /// Runs the active expire cycle on every shard, one at a time, returning how many keys
+    /// were deleted.
+    pub fn active_expire_cycle(&self, now: SystemTime) -> usize {
+        (0..self.shards.len())
+            .map(|index| {
+                self.lock_indexes(index / SHARDS, [index]).locked[0]
+                    .1
+                    .active_expire_cycle(now)
+            })
+            .sum()
+    }
+}
+
+/// Locks a shard even if a command panicked while holding it: keys are only ever
+/// changed with simple insertions and removals, and the server must keep running.
+fn lock(shard: &Mutex<Keyspace>) -> MutexGuard<'_, Keyspace> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn shard_of(key: &[u8]) -> usize {
+    (key_hash(key) % SHARDS as u64) as usize
+}
+
+/// Locked shards of the database, sorted by index.
+pub struct Shards<'a> {
+    locked: Vec<(usize, MutexGuard<'a, Keyspace>)>,
+    /// The logical database the keys are looked up in, changed by SELECT.
+    db: usize,
+    /// The memory used by the shards when they were locked.
+    used_memory: usize,
+    /// The keys of the shards that had expired when they were locked.
+    expired_keys: u64,
+    database: &'a Database,
+}
+
+impl Drop for Shards<'_> {
+    /// Accounts for the memory the keys changed while locked now use, and for the keys
+    /// that expired meanwhile.
+    fn drop(&mut self) {
+        let (used_memory, expired_keys) =
+            self.locked
+                .iter()
+                .fold((0, 0), |(used_memory, expired_keys), (_, keyspace)| {
+                    (
+                        used_memory + keyspace.used_memory(),
+                        expired_keys + keyspace.expired_keys(),
+                    )
+                });
+        if used_memory > self.used_memory {
+            self.database
+                .used_memory
+                .fetch_add(used_memory - self.used_memory, Ordering::Relaxed);
+        } else {
+            self.database
+                .used_memory
+                .fetch_sub(self.used_memory - used_memory, Ordering::Relaxed);
+        }
+        self.database
+            .expired_keys
+            .fetch_add(expired_keys - self.expired_keys, Ordering::Relaxed);
+    }
+}
+
+impl Shards<'_> {
+    /// The logical database the keys are looked up in.
+    pub fn db(&self) -> usize {
+        self.db
+    }
+
+    pub fn select(&mut self, db: usize) {
+        self.db = db;
+    }
+
+    /// The shard holding `key` in the selected database, which must be one of the keys the
+    /// shards were locked for.
+    pub fn keyspace(&mut self, key: &[u8]) -> &mut Keyspace {
+        let index = self.db * SHARDS + shard_of(key);
+        let position = self
+            .locked
+            .binary_search_by_key(&index, |(index, _)| *index)
+            .expect("the shard of the key is locked");
+        &mut self.locked[position].1
+    }
+
+    /// Iterates over the keys that are not expired at `now` in the locked shards of the
+    /// selected database.
+    pub fn iter(&self, now: SystemTime) -> impl Iterator<Item = (&[u8], &Entry)> {
+        self.in_database(self.db)
+            .flat_map(move |keyspace| keyspace.iter(now))
+    }
+
+    /// Iterates over the keys that are not expired at `now` in all the locked shards, with
+    /// their logical database.
+    pub fn iter_all(&self, now: SystemTime) -> impl Iterator<Item = (usize, &[u8], &Entry)> {
+        self.locked.iter().flat_map(move |(index, keyspace)| {
+            keyspace
+                .iter(now)
+                .map(move |(key, entry)| (index / SHARDS, key, entry))
+        })
+    }
+
+    /// Copies the keys that are not expired at `now` in all the locked shards, with their
+    /// logical database, so they can be saved without holding a lock.
+    pub fn snapshot(&self, now: SystemTime) -> Vec<(usize, Vec<u8>, Entry)> {
+        self.locked
+            .iter()
+            .flat_map(|(index, keyspace)| {
+                keyspace
+                    .snapshot(now)
+                    .into_iter()
+                    .map(move |(key, entry)| (index / SHARDS, key, entry))
+            })
+            .collect()
+    }
+
+    /// The number of keys and of keys with an expire time in the locked shards of `db`.
+    pub fn counts(&self, db: usize) -> (usize, usize) {
+        self.in_database(db)
+            .map(|keyspace| keyspace.counts())
+            .fold((0, 0), |(keys, expires), (more_keys, more_expires)| {
+                (keys + more_keys, expires + more_expires)
+            })
+    }
+
+    /// Deletes every key of the locked shards of the selected database.
+    pub fn clear(&mut self) {
+        let db = self.db;
+        for (index, keyspace) in &mut self.locked {
+            if *index / SHARDS == db {
+                keyspace.clear();
+            }
+        }
+    }
+
+    /// Deletes every key of the locked shards.
+    pub fn clear_all(&mut self) {
+        for (_, keyspace) in &mut self.locked {
+            keyspace.clear();
+        }
+    }
+
+    fn in_database(&self, db: usize) -> impl Iterator<Item = &Keyspace> {
+        self.locked
+            .iter()
+            .filter(move |(index, _)| index / SHARDS == db)
+            .map(|(_, keyspace)| &**keyspace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::keyspace::Value;
+    use std::thread;
+    use std::time::Duration;
+
+    fn string(value: &str) -> Value {
+        Value::String(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn keys_are_spread_across_shards() {
+        let mut keyspace = Keyspace::default();
+        for i in 0..1000 {
+            keyspace.set(
+                format!("key:{}", i).into_bytes(),
+                string("v"),
+                None,
+                SystemTime::now(),
+            );
+        }
+        let database = Database::from(keyspace);
+
+        let used_shards = database
+            .shards
+            .iter()
+            .filter(|shard| shard.lock().unwrap().iter(SystemTime::now()).count() > 0)
+            .count();
+        assert_eq!(used_shards, SHARDS);
+        assert_eq!(database.lock_all(0).iter(SystemTime::now()).count(), 1000);
+    }
+
+    #[test]
+    fn lock_several_keys() {
+        let database = Database::default();
+        let now = SystemTime::now();
+
+        let mut shards = database.lock(0, [&b"a"[..], b"b", b"a"]);
+        shards
+            .keyspace(b"a")
+            .set(b"a".to_vec(), string("1"), None, now);
+        shards
+            .keyspace(b"b")
+            .set(b"b".to_vec(), string("2"), None, now);
+        drop(shards);
+
+        let mut shards = database.lock(0, [&b"b"[..]]);
+        assert!(shards.keyspace(b"b").get(b"b", now).is_some());
+        drop(shards);
+        assert_eq!(database.lock_all(0).snapshot(now).len(), 2);
+    }
+
+    #[test]
+    fn logical_databases_are_separate() {
+        let database = Database::new(2);
+        let now = SystemTime::now();
+
+        let mut shards = database.lock_in(0, [(0, &b"a"[..]), (1, b"a"), (1, b"b")]);
+        shards
+            .keyspace(b"a")
+            .set(b"a".to_vec(), string("0"), None, now);
+        shards.select(1);
+        shards
+            .keyspace(b"a")
+            .set(b"a".to_vec(), string("1"), None, now);
+        shards
+            .keyspace(b"b")
+            .set(b"b".to_vec(), string("1"), None, now);
+        drop(shards);
+
+        let mut shards = database.lock_all(1);
+        assert_eq!(shards.iter(now).count(), 2);
+        assert_eq!((shards.counts(0), shards.counts(1)), ((1, 0), (2, 0)));
+        assert_eq!(shards.snapshot(now).len(), 3);
+        shards.clear();
+        assert_eq!(shards.iter_all(now).collect::<Vec<_>>().len(), 1);
+        shards.clear_all();
+        assert_eq!(shards.iter_all(now).count(), 0);
+    }
+
+    #[test]
+    fn concurrent_locks_in_any_order() {
+        let database = Database::default();
+        let keys = (0..100)
+            .map(|i| format!("key:{}", i).into_bytes())
+            .collect::<Vec<_>>();
+
+        thread::scope(|scope| {
+            for reversed in [false, true] {
+                let (database, keys) = (&database, &keys);
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        let mut keys = keys.iter().map(Vec::as_slice).collect::<Vec<_>>();
+                        if reversed {
+                            keys.reverse();
+                        }
+                        let mut shards = database.lock(0, keys.iter().copied());
+                        for key in keys {
+                            shards.keyspace(key).set(
+                                key.to_vec(),
+                                string("v"),
+                                None,
+                                SystemTime::now(),
+                            );
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(database.lock_all(0).iter(SystemTime::now()).count(), 100);
+    }
+
+    #[test]
+    fn scan_returns_every_key_despite_concurrent_changes() {
+        let database = Database::default();
+        let now = SystemTime::now();
+        let key = |i: usize| format!("key:{}", i).into_bytes();
+        for i in 0..1000 {
+            let mut shards = database.lock(0, [key(i).as_slice()]);
+            shards.keyspace(&key(i)).set(key(i), string("v"), None, now);
+        }
+
+        let mut scanned = Vec::new();
+        let mut cursor = 0;
+        let mut steps = 0;
+        loop {
+            let (next, keys) = database.scan(0, cursor, 10, now, |_, _| true);
+            scanned.extend(keys);
+            // between steps, delete a key already returned and add a new one
+            let added = key(1000 + steps);
+            let deleted = scanned.last().unwrap();
+            let mut shards = database.lock(0, [added.as_slice(), deleted.as_slice()]);
+            shards
+                .keyspace(&added)
+                .set(added.clone(), string("v"), None, now);
+            shards.keyspace(deleted).remove(deleted, now);
+            drop(shards);
+
+            steps += 1;
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        for i in 0..1000 {
+            assert!(scanned.contains(&key(i)));
+        }
+    }
+
+    #[test]
+    fn evict_until_under_the_memory_limit() {
+        let database = Database::default();
+        let now = SystemTime::now();
+        let key = |i: usize| format!("key:{}", i).into_bytes();
+        for i in 0..100 {
+            let expires_at = (i % 2 == 0).then(|| now + Duration::from_secs(1000 + i as u64));
+            let mut shards = database.lock(0, [key(i).as_slice()]);
+            shards
+                .keyspace(&key(i))
+                .set(key(i), string("v"), expires_at, now);
+        }
+        let full = database.used_memory();
+        assert!(database.evict(full, Policy::NoEviction, now, |_, _| {}));
+        assert!(!database.evict(full / 2, Policy::NoEviction, now, |_, _| {}));
+
+        let mut evicted = Vec::new();
+        assert!(
+            database.evict(full / 2, Policy::VolatileTtl, now, |_, key| {
+                evicted.push(key.to_vec())
+            })
+        );
+        assert!(database.used_memory() <= full / 2);
+        assert_eq!(evicted.len(), 50);
+        assert_eq!(database.lock_all(0).iter(now).count() + evicted.len(), 100);
+        // only keys with an expire time can be evicted, and none is left
+        assert!(!database.evict(0, Policy::VolatileLru, now, |_, _| {}));
+        assert!(database.evict(0, Policy::AllKeysLru, now, |_, _| {}));
+        assert_eq!(database.used_memory(), 0);
+    }
+
+    #[test]
+    fn shard_usable_after_a_panic() {
+        let database = Database::default();
+
+        let panicked = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _shards = database.lock(0, [&b"k"[..]]);
+                    panic!("command failed");
+                })
+                .join()
+        });
+        assert!(panicked.is_err());
+
+        let mut shards = database.lock(0, [&b"k"[..]]);
+        let now = SystemTime::now();
+        shards
+            .keyspace(b"k")
+            .set(b"k".to_vec(), string("v"), None, now);
+        assert!(shards.keyspace(b"k").get(b"k", now).is_some());
+    }
+}
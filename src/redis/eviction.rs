@@ -0,0 +1,199 @@
+use super::commands::unix_millis;
+use super::random::Random;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Keys sampled to pick the one to evict, as the default `maxmemory-samples` of redis.
+pub const EVICTION_SAMPLES: usize = 5;
+
+/// Access frequency given to new keys, so that they are not evicted right away.
+const LFU_INIT: u8 = 5;
+/// The higher, the more accesses it takes to increment the frequency of a key.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// The frequency of a key decreases by one for each period it is not accessed.
+const LFU_DECAY_PERIOD: Duration = Duration::from_secs(60);
+
+/// Which keys are evicted when the memory limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Nothing is evicted, commands that would use more memory fail instead.
+    NoEviction,
+    /// The least recently used keys.
+    AllKeysLru,
+    /// The least frequently used keys.
+    AllKeysLfu,
+    AllKeysRandom,
+    /// The least recently used keys among the ones with an expire time.
+    VolatileLru,
+    /// The keys expiring the soonest.
+    VolatileTtl,
+}
+
+impl Policy {
+    /// Whether only the keys with an expire time can be evicted.
+    pub fn is_volatile(self) -> bool {
+        matches!(self, Policy::VolatileLru | Policy::VolatileTtl)
+    }
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Policy::NoEviction => "noeviction",
+            Policy::AllKeysLru => "allkeys-lru",
+            Policy::AllKeysLfu => "allkeys-lfu",
+            Policy::AllKeysRandom => "allkeys-random",
+            Policy::VolatileLru => "volatile-lru",
+            Policy::VolatileTtl => "volatile-ttl",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "noeviction" => Ok(Policy::NoEviction),
+            "allkeys-lru" => Ok(Policy::AllKeysLru),
+            "allkeys-lfu" => Ok(Policy::AllKeysLfu),
+            "allkeys-random" => Ok(Policy::AllKeysRandom),
+            "volatile-lru" => Ok(Policy::VolatileLru),
+            "volatile-ttl" => Ok(Policy::VolatileTtl),
+            other => Err(format!("invalid maxmemory policy: {}", other)),
+        }
+    }
+}
+
+/// Parses an amount of memory in bytes, with an optional unit as in the redis
+/// configuration: `k`, `kb`, `m`, `mb`, `g` or `gb`.
+pub fn parse_memory(s: &str) -> Result<usize, String> {
+    let lowercase = s.to_ascii_lowercase();
+    let digits = lowercase.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &lowercase[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid memory amount: {}", s)),
+    };
+
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid memory amount: {}", s))
+}
+
+/// How a key is used, to approximate the least recently and the least frequently used
+/// keys.
+#[derive(Clone, Copy)]
+pub struct Usage {
+    accessed_at: SystemTime,
+    /// A logarithmic counter of the accesses, decaying over time.
+    frequency: u8,
+}
+
+impl Usage {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            accessed_at: now,
+            frequency: LFU_INIT,
+        }
+    }
+
+    /// Records an access. The frequency is incremented with a probability decreasing as it
+    /// grows, so that 255 stands for about a million accesses.
+    pub fn access(&mut self, now: SystemTime, random: &mut Random) {
+        let frequency = self.decayed_frequency(now);
+        let base = frequency.saturating_sub(LFU_INIT) as f64;
+        let roll = (random.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        self.frequency = if frequency < u8::MAX && roll < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            frequency + 1
+        } else {
+            frequency
+        };
+        self.accessed_at = now;
+    }
+
+    /// How good a candidate for eviction the key is under `policy`, the higher the better.
+    pub fn eviction_score(
+        &self,
+        policy: Policy,
+        expires_at: Option<SystemTime>,
+        now: SystemTime,
+    ) -> u64 {
+        match policy {
+            Policy::AllKeysLru | Policy::VolatileLru => now
+                .duration_since(self.accessed_at)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            Policy::AllKeysLfu => (u8::MAX - self.decayed_frequency(now)) as u64,
+            Policy::VolatileTtl => {
+                expires_at.map_or(0, |at| u64::MAX - unix_millis(at).max(0) as u64)
+            }
+            Policy::AllKeysRandom | Policy::NoEviction => 0,
+        }
+    }
+
+    fn decayed_frequency(&self, now: SystemTime) -> u8 {
+        let periods = now
+            .duration_since(self.accessed_at)
+            .unwrap_or_default()
+            .as_secs()
+            / LFU_DECAY_PERIOD.as_secs();
+        self.frequency
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_and_amounts() {
+        assert_eq!("allkeys-LRU".parse(), Ok(Policy::AllKeysLru));
+        assert_eq!(Policy::VolatileTtl.to_string(), "volatile-ttl");
+        assert!("volatile-lfu".parse::<Policy>().is_err());
+        assert_eq!(parse_memory("100"), Ok(100));
+        assert_eq!(parse_memory("1kb"), Ok(1024));
+        assert_eq!(parse_memory("2MB"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_memory("1g"), Ok(1_000_000_000));
+        assert!(parse_memory("1tb").is_err());
+        assert!(parse_memory("mb").is_err());
+    }
+
+    #[test]
+    fn frequency_grows_logarithmically_and_decays() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut random = Random::new(42);
+        let mut usage = Usage::new(now);
+
+        for _ in 0..100 {
+            usage.access(now, &mut random);
+        }
+        let frequent = usage.frequency;
+        assert!(frequent > LFU_INIT + 2 && frequent < 20);
+        for _ in 0..10_000 {
+            usage.access(now, &mut random);
+        }
+        assert!(usage.frequency > frequent);
+
+        let later = now + LFU_DECAY_PERIOD * 3;
+        assert_eq!(usage.decayed_frequency(later), usage.frequency - 3);
+        assert!(
+            usage.eviction_score(Policy::AllKeysLfu, None, later)
+                > usage.eviction_score(Policy::AllKeysLfu, None, now)
+        );
+        assert_eq!(
+            usage.eviction_score(Policy::AllKeysLru, None, later),
+            180_000
+        );
+    }
+}
@@ -0,0 +1,764 @@
+use super::eviction::{Policy, Usage, EVICTION_SAMPLES};
+use super::random::Random;
+use super::sorted_set::SortedSet;
+use super::stream::Stream;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::SystemTime;
+
+#[derive(Clone)]
+pub enum Value {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Set(HashSet<Vec<u8>>),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// The name of the type, as replied by TYPE.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Approximates the memory used by the value. Only the first elements of a collection
+    /// are measured, so that large ones are estimated in constant time.
+    fn estimated_size(&self) -> usize {
+        fn estimate<T>(
+            len: usize,
+            elements: impl Iterator<Item = T>,
+            size: fn(T) -> usize,
+        ) -> usize {
+            let sampled = elements.take(SIZE_SAMPLES).map(size).collect::<Vec<_>>();
+            if sampled.is_empty() {
+                return 0;
+            }
+            len * (ELEMENT_OVERHEAD + sampled.iter().sum::<usize>() / sampled.len())
+        }
+
+        match self {
+            Value::String(string) => string.len(),
+            Value::List(list) => estimate(list.len(), list.iter(), Vec::len),
+            Value::Hash(hash) => estimate(hash.len(), hash.iter(), |(field, value)| {
+                field.len() + value.len()
+            }),
+            Value::Set(set) => estimate(set.len(), set.iter(), Vec::len),
+            // members are stored both by name and by score
+            Value::SortedSet(sorted_set) => {
+                estimate(sorted_set.len(), sorted_set.iter(), |(member, _)| {
+                    2 * member.len() + 8
+                })
+            }
+            Value::Stream(stream) => estimate(stream.len(), stream.iter(), |(_, fields)| {
+                16 + fields
+                    .iter()
+                    .map(|(field, value)| field.len() + value.len())
+                    .sum::<usize>()
+            }),
+        }
+    }
+
+    fn is_empty_collection(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::SortedSet(sorted_set) => sorted_set.is_empty(),
+            // consumer groups can wait for the entries of an empty stream
+            Value::Stream(_) => false,
+        }
+    }
+}
+
+/// Conversion between a `Value` and the concrete type stored in one of its variants,
+/// used to access a key expecting a specific type.
+pub trait TypedValue: Default {
+    fn from_value(value: &Value) -> Option<&Self>;
+    fn from_value_mut(value: &mut Value) -> Option<&mut Self>;
+    fn into_value(self) -> Value;
+}
+
+macro_rules! TypedValue {
+    ($variant:ident, $ty:ty) => {
+        impl TypedValue for $ty {
+            fn from_value(value: &Value) -> Option<&Self> {
+                match value {
+                    Value::$variant(inner) => Some(inner),
+                    _ => None,
+                }
+            }
+
+            fn from_value_mut(value: &mut Value) -> Option<&mut Self> {
+                match value {
+                    Value::$variant(inner) => Some(inner),
+                    _ => None,
+                }
+            }
+
+            fn into_value(self) -> Value {
+                Value::$variant(self)
+            }
+        }
+    };
+}
+
+TypedValue!(String, Vec<u8>);
+TypedValue!(List, VecDeque<Vec<u8>>);
+TypedValue!(Hash, HashMap<Vec<u8>, Vec<u8>>);
+TypedValue!(Set, HashSet<Vec<u8>>);
+TypedValue!(SortedSet, SortedSet);
+TypedValue!(Stream, Stream);
+
+/// Returned when a command hits a key holding a value of another type.
+#[derive(Debug, PartialEq)]
+pub struct WrongType;
+
+#[derive(Clone)]
+pub struct Entry {
+    pub value: Value,
+    expires_at: Option<SystemTime>,
+    usage: Usage,
+    /// The memory used by the key and its value when last measured.
+    size: usize,
+}
+
+impl Entry {
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Keys sampled on each round of the active expire cycle.
+const ACTIVE_EXPIRE_SAMPLES: usize = 20;
+/// The active expire cycle keeps sampling while more than this percentage of the
+/// sampled keys turned out to be expired.
+const ACTIVE_EXPIRE_STALE_PERCENTAGE: usize = 25;
+/// Upper bound of sampling rounds, to keep the keyspace lock for a short time.
+const ACTIVE_EXPIRE_MAX_ROUNDS: usize = 16;
+
+/// Approximate memory used by a key beside its name and value: its entries in the hash
+/// table and in the SCAN order.
+const ENTRY_OVERHEAD: usize = 96;
+/// Approximate memory used by each element of a collection beside its content.
+const ELEMENT_OVERHEAD: usize = 32;
+/// Elements measured to estimate the average size of the elements of a collection.
+const SIZE_SAMPLES: usize = 5;
+
+#[derive(Default)]
+pub struct Keyspace {
+    entries: HashMap<Vec<u8>, Entry>,
+    volatile: KeySet,
+    /// Keys by hash, the order of SCAN: resuming from a hash, keys added or deleted
+    /// meanwhile do not move the others.
+    by_hash: BTreeMap<u64, Vec<Vec<u8>>>,
+    random: Random,
+    /// Keys watched by some client, with the number of times they changed since.
+    watched: HashMap<Vec<u8>, Watch>,
+    /// The approximate memory used by the keys and their values.
+    used_memory: usize,
+    /// How many keys were deleted because they expired.
+    expired_keys: u64,
+}
+
+#[derive(Default)]
+struct Watch {
+    watchers: usize,
+    version: u64,
+}
+
+impl Keyspace {
+    /// Returns the entry of `key`, deleting it first if it has expired.
+    pub fn get(&mut self, key: &[u8], now: SystemTime) -> Option<&Entry> {
+        self.expire_if_needed(key, now);
+        let entry = self.entries.get_mut(key)?;
+        entry.usage.access(now, &mut self.random);
+        Some(entry)
+    }
+
+    /// Returns the entry of `key` to be changed, counting it as modified for the clients
+    /// watching it.
+    pub fn get_mut(&mut self, key: &[u8], now: SystemTime) -> Option<&mut Entry> {
+        self.expire_if_needed(key, now);
+        if !self.entries.contains_key(key) {
+            return None;
+        }
+        self.touch(key);
+        let entry = self.entries.get_mut(key)?;
+        entry.usage.access(now, &mut self.random);
+        Some(entry)
+    }
+
+    /// Iterates over the keys that are not expired at `now`, without deleting the expired ones.
+    pub fn iter(&self, now: SystemTime) -> impl Iterator<Item = (&[u8], &Entry)> {
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key.as_slice(), entry))
+    }
+
+    /// Copies the keys that are not expired at `now`, so they can be saved without holding a lock.
+    pub fn snapshot(&self, now: SystemTime) -> Vec<(Vec<u8>, Entry)> {
+        self.iter(now)
+            .map(|(key, entry)| (key.to_vec(), entry.clone()))
+            .collect()
+    }
+
+    /// Returns the keys that are not expired at `now` in hash order from the hash `from`
+    /// on, stopping once `count` keys were examined, along with the hash to continue from
+    /// when keys remain. Keys sharing a hash are returned together.
+    pub fn scan(
+        &self,
+        from: u64,
+        count: usize,
+        now: SystemTime,
+    ) -> (Vec<(&[u8], &Entry)>, Option<u64>) {
+        let mut keys = Vec::new();
+        let mut examined = 0;
+
+        for (&hash, bucket) in self.by_hash.range(from..) {
+            if examined >= count {
+                return (keys, Some(hash));
+            }
+            examined += bucket.len();
+            keys.extend(bucket.iter().filter_map(|key| {
+                let entry = &self.entries[key];
+                (!entry.is_expired(now)).then_some((key.as_slice(), entry))
+            }));
+        }
+
+        (keys, None)
+    }
+
+    /// Consumes the keyspace, including the expired keys not deleted yet.
+    pub fn into_entries(self) -> impl Iterator<Item = (Vec<u8>, Entry)> {
+        self.entries.into_iter()
+    }
+
+    pub fn set(
+        &mut self,
+        key: Vec<u8>,
+        value: Value,
+        expires_at: Option<SystemTime>,
+        now: SystemTime,
+    ) {
+        // a new key counts as accessed when it is created
+        let usage = self
+            .entries
+            .get(&key)
+            .map_or_else(|| Usage::new(now), |previous| previous.usage);
+        self.insert(
+            key,
+            Entry {
+                value,
+                expires_at,
+                usage,
+                size: 0,
+            },
+        );
+    }
+
+    /// Adds an entry moved from another keyspace as is, replacing the one of `key`.
+    pub fn insert(&mut self, key: Vec<u8>, mut entry: Entry) {
+        match entry.expires_at {
+            Some(_) => self.volatile.insert(&key),
+            None => self.volatile.remove(&key),
+        }
+        self.touch(&key);
+        match self.entries.get(&key) {
+            Some(previous) => self.used_memory -= previous.size,
+            None => self
+                .by_hash
+                .entry(key_hash(&key))
+                .or_default()
+                .push(key.clone()),
+        }
+
+        entry.size = ENTRY_OVERHEAD + key.len() + entry.value.estimated_size();
+        self.used_memory += entry.size;
+        self.entries.insert(key, entry);
+    }
+
+    /// Measures again the memory used by `key` after its value was changed in place.
+    pub fn update_size(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            let size = ENTRY_OVERHEAD + key.len() + entry.value.estimated_size();
+            self.used_memory = self.used_memory - entry.size + size;
+            entry.size = size;
+        }
+    }
+
+    /// The approximate memory used by the keys and their values.
+    pub fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys
+    }
+
+    /// The number of keys and of keys with an expire time, including the expired ones not
+    /// deleted yet.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.entries.len(), self.volatile.len())
+    }
+
+    /// Samples keys that can be evicted under `policy` and returns the best candidate.
+    pub fn eviction_candidate(&mut self, policy: Policy, now: SystemTime) -> Option<Vec<u8>> {
+        let mut best: Option<(&[u8], u64)> = None;
+        for _ in 0..EVICTION_SAMPLES {
+            let key = if policy.is_volatile() {
+                self.volatile.random(&mut self.random)
+            } else {
+                // the key following a random hash in the SCAN order
+                let hash = self.random.next_u64();
+                self.by_hash
+                    .range(hash..)
+                    .next()
+                    .or_else(|| self.by_hash.iter().next())
+                    .map(|(_, bucket)| bucket[0].as_slice())
+            };
+            let Some(key) = key else {
+                break;
+            };
+
+            let entry = &self.entries[key];
+            let score = entry.usage.eviction_score(policy, entry.expires_at, now);
+            if best.is_none_or(|(_, best)| score > best) {
+                best = Some((key, score));
+            }
+        }
+        best.map(|(key, _)| key.to_vec())
+    }
+
+    /// Sets or clears the expiration time of an existing key, returning the previous one.
+    pub fn set_expiry(&mut self, key: &[u8], expires_at: Option<SystemTime>) -> Option<SystemTime> {
+        let entry = self.entries.get_mut(key)?;
+        match expires_at {
+            Some(_) => self.volatile.insert(key),
+            None => self.volatile.remove(key),
+        }
+        let previous = std::mem::replace(&mut entry.expires_at, expires_at);
+        self.touch(key);
+        previous
+    }
+
+    pub fn remove(&mut self, key: &[u8], now: SystemTime) -> Option<Entry> {
+        self.expire_if_needed(key, now);
+        self.delete(key)
+    }
+
+    pub fn get_typed<T: TypedValue>(
+        &mut self,
+        key: &[u8],
+        now: SystemTime,
+    ) -> Result<Option<&T>, WrongType> {
+        match self.get(key, now) {
+            None => Ok(None),
+            Some(entry) => T::from_value(&entry.value).map(Some).ok_or(WrongType),
+        }
+    }
+
+    pub fn get_typed_mut<T: TypedValue>(
+        &mut self,
+        key: &[u8],
+        now: SystemTime,
+    ) -> Result<Option<&mut T>, WrongType> {
+        match self.get_mut(key, now) {
+            None => Ok(None),
+            Some(entry) => T::from_value_mut(&mut entry.value)
+                .map(Some)
+                .ok_or(WrongType),
+        }
+    }
+
+    /// Returns the value of `key`, creating an empty one when the key does not exist.
+    pub fn get_or_create<T: TypedValue>(
+        &mut self,
+        key: &[u8],
+        now: SystemTime,
+    ) -> Result<&mut T, WrongType> {
+        if self.get(key, now).is_none() {
+            self.set(key.to_vec(), T::default().into_value(), None, now);
+        }
+
+        let entry = self.get_mut(key, now).expect("entry has just been checked");
+        T::from_value_mut(&mut entry.value).ok_or(WrongType)
+    }
+
+    /// Deletes `key` if it holds an empty collection, aggregate types never exist empty.
+    pub fn remove_if_empty(&mut self, key: &[u8]) {
+        if self
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.value.is_empty_collection())
+        {
+            self.delete(key);
+        }
+    }
+
+    /// Samples keys with an expiration time and deletes the expired ones, repeating
+    /// while a large share of the sample was expired. Returns how many keys were deleted.
+    pub fn active_expire_cycle(&mut self, now: SystemTime) -> usize {
+        let mut expired = 0;
+
+        for _ in 0..ACTIVE_EXPIRE_MAX_ROUNDS {
+            let sampled = self.volatile.len().min(ACTIVE_EXPIRE_SAMPLES);
+            if sampled == 0 {
+                break;
+            }
+
+            // with few volatile keys every one of them is checked, instead of a sample
+            let keys = if self.volatile.len() <= ACTIVE_EXPIRE_SAMPLES {
+                self.volatile.keys.clone()
+            } else {
+                (0..sampled)
+                    .filter_map(|_| self.volatile.random(&mut self.random).map(|k| k.to_vec()))
+                    .collect()
+            };
+
+            let mut expired_in_round = 0;
+            for key in keys {
+                if self.expire_if_needed(&key, now) {
+                    expired_in_round += 1;
+                }
+            }
+
+            expired += expired_in_round;
+            if expired_in_round * 100 <= sampled * ACTIVE_EXPIRE_STALE_PERCENTAGE {
+                break;
+            }
+        }
+
+        expired
+    }
+
+    fn expire_if_needed(&mut self, key: &[u8], now: SystemTime) -> bool {
+        if self
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(now))
+        {
+            self.delete(key);
+            self.expired_keys += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.used_memory -= entry.size;
+        if entry.expires_at.is_some() {
+            self.volatile.remove(key);
+        }
+        let hash = key_hash(key);
+        if let Some(bucket) = self.by_hash.get_mut(&hash) {
+            bucket.retain(|other| other != key);
+            if bucket.is_empty() {
+                self.by_hash.remove(&hash);
+            }
+        }
+        self.touch(key);
+        Some(entry)
+    }
+
+    /// Deletes every key.
+    pub fn clear(&mut self) {
+        for (key, watch) in &mut self.watched {
+            if self.entries.contains_key(key) {
+                watch.version += 1;
+            }
+        }
+        self.entries.clear();
+        self.volatile = KeySet::default();
+        self.by_hash.clear();
+        self.used_memory = 0;
+    }
+
+    /// Starts tracking the changes of `key` for one more client, returning its version.
+    pub fn watch(&mut self, key: &[u8], now: SystemTime) -> u64 {
+        // a key already expired must not count as changed when it is deleted later
+        self.expire_if_needed(key, now);
+        let watch = self.watched.entry(key.to_vec()).or_default();
+        watch.watchers += 1;
+        watch.version
+    }
+
+    pub fn unwatch(&mut self, key: &[u8]) {
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.watchers -= 1;
+            if watch.watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// The version of a watched key, increasing each time the key changes.
+    pub fn version(&self, key: &[u8]) -> Option<u64> {
+        self.watched.get(key).map(|watch| watch.version)
+    }
+
+    fn touch(&mut self, key: &[u8]) {
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.version += 1;
+        }
+    }
+}
+
+/// The hash of a key, deciding its shard and its position in SCAN.
+pub fn key_hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A set of keys supporting the selection of a random element in constant time.
+#[derive(Default)]
+struct KeySet {
+    keys: Vec<Vec<u8>>,
+    positions: HashMap<Vec<u8>, usize>,
+}
+
+impl KeySet {
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn insert(&mut self, key: &[u8]) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_vec(), self.keys.len());
+            self.keys.push(key.to_vec());
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(position) = self.positions.remove(key) {
+            self.keys.swap_remove(position);
+            if let Some(moved) = self.keys.get(position) {
+                self.positions.insert(moved.clone(), position);
+            }
+        }
+    }
+
+    fn random(&self, random: &mut Random) -> Option<&[u8]> {
+        if self.keys.is_empty() {
+            None
+        } else {
+            Some(&self.keys[random.below(self.keys.len())])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn string(value: &str) -> Value {
+        Value::String(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn lazy_expiry_deletes_on_access() {
+        let mut keyspace = Keyspace::default();
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        keyspace.set(
+            b"k".to_vec(),
+            string("v"),
+            Some(now + Duration::from_secs(1)),
+            now,
+        );
+
+        assert!(keyspace.get(b"k", now).is_some());
+        assert!(keyspace.get(b"k", now + Duration::from_secs(1)).is_none());
+        assert!(keyspace.entries.is_empty());
+        assert_eq!(keyspace.volatile.len(), 0);
+        assert_eq!(keyspace.expired_keys(), 1);
+    }
+
+    #[test]
+    fn active_expire_cycle_deletes_expired_keys() {
+        let mut keyspace = Keyspace::default();
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+
+        for i in 0..200 {
+            let key = format!("expired:{}", i).into_bytes();
+            keyspace.set(key, string("v"), Some(now - Duration::from_secs(1)), now);
+        }
+        for i in 0..10 {
+            let key = format!("volatile:{}", i).into_bytes();
+            keyspace.set(key, string("v"), Some(now + Duration::from_secs(10)), now);
+        }
+        keyspace.set(b"persistent".to_vec(), string("v"), None, now);
+
+        // keys are sampled at random, a cycle may miss the last few expired ones
+        let mut expired = 0;
+        let mut cycles = 0;
+        while keyspace.volatile.len() > 10 {
+            expired += keyspace.active_expire_cycle(now);
+            cycles += 1;
+            assert!(cycles < 1000);
+        }
+
+        assert_eq!(expired, 200);
+        assert_eq!(keyspace.entries.len(), 11);
+        assert_eq!(keyspace.active_expire_cycle(now), 0);
+    }
+
+    #[test]
+    fn set_expiry_tracks_volatile_keys() {
+        let mut keyspace = Keyspace::default();
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        keyspace.set(b"k".to_vec(), string("v"), None, now);
+
+        keyspace.set_expiry(b"k", Some(now));
+        assert_eq!(keyspace.volatile.len(), 1);
+        assert_eq!(keyspace.set_expiry(b"k", None), Some(now));
+        assert_eq!(keyspace.volatile.len(), 0);
+    }
+
+    #[test]
+    fn watched_keys_count_changes() {
+        let mut keyspace = Keyspace::default();
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        keyspace.set(
+            b"k".to_vec(),
+            string("v"),
+            Some(now + Duration::from_secs(1)),
+            now,
+        );
+
+        let version = keyspace.watch(b"k", now);
+        assert_eq!(keyspace.version(b"k"), Some(version));
+        keyspace.get(b"k", now);
+        assert_eq!(keyspace.version(b"k"), Some(version));
+        keyspace.get_mut(b"k", now);
+        assert_eq!(keyspace.version(b"k"), Some(version + 1));
+        // expiring counts as a change too
+        keyspace.get(b"k", now + Duration::from_secs(1));
+        assert_eq!(keyspace.version(b"k"), Some(version + 2));
+
+        keyspace.watch(b"k", now);
+        keyspace.unwatch(b"k");
+        assert!(keyspace.version(b"k").is_some());
+        keyspace.unwatch(b"k");
+        assert!(keyspace.version(b"k").is_none());
+    }
+
+    #[test]
+    fn scan_resumes_in_hash_order() {
+        let mut keyspace = Keyspace::default();
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        for i in 0..10 {
+            keyspace.set(format!("key:{}", i).into_bytes(), string("v"), None, now);
+        }
+
+        let (first, Some(next)) = keyspace.scan(0, 4, now) else {
+            panic!("expected more keys");
+        };
+        assert_eq!(first.len(), 4);
+        let first = first
+            .into_iter()
+            .map(|(key, _)| key.to_vec())
+            .collect::<Vec<_>>();
+
+        keyspace.remove(&first[0], now);
+        keyspace.set(b"added".to_vec(), string("v"), None, now);
+        let (rest, next) = keyspace.scan(next, 100, now);
+        assert_eq!(next, None);
+        let rest = rest
+            .into_iter()
+            .map(|(key, _)| key.to_vec())
+            .collect::<Vec<_>>();
+        // the key added may come before or after the cursor, the others exactly once
+        for i in 0..10 {
+            let key = format!("key:{}", i).into_bytes();
+            assert!(first.contains(&key) != rest.contains(&key));
+        }
+    }
+
+    #[test]
+    fn used_memory_follows_the_changes() {
+        let mut keyspace = Keyspace::default();
+        let now = SystemTime::now();
+        keyspace.set(b"k".to_vec(), string("value"), None, now);
+        let small = keyspace.used_memory();
+        assert_eq!(small, ENTRY_OVERHEAD + 1 + 5);
+
+        let list = (0..100).map(|i| format!("{:04}", i).into_bytes()).collect();
+        keyspace.set(b"list".to_vec(), Value::List(list), Some(now), now);
+        let with_list = keyspace.used_memory();
+        assert_eq!(
+            with_list,
+            small + ENTRY_OVERHEAD + 4 + 100 * (ELEMENT_OVERHEAD + 4)
+        );
+
+        if let Some(Entry {
+            value: Value::List(list),
+            ..
+        }) = keyspace.get_mut(b"list", now - Duration::from_secs(1))
+        {
+            list.truncate(10);
+        }
+        keyspace.update_size(b"list");
+        assert_eq!(
+            keyspace.used_memory(),
+            small + ENTRY_OVERHEAD + 4 + 10 * (ELEMENT_OVERHEAD + 4)
+        );
+
+        // only the key with an expire time can be evicted by a volatile policy
+        assert_eq!(
+            keyspace.eviction_candidate(Policy::VolatileLru, now),
+            Some(b"list".to_vec())
+        );
+        keyspace.remove(b"list", now + Duration::from_secs(1));
+        assert_eq!(keyspace.used_memory(), small);
+        assert_eq!(keyspace.eviction_candidate(Policy::VolatileTtl, now), None);
+        keyspace.clear();
+        assert_eq!(keyspace.used_memory(), 0);
+    }
+
+    #[test]
+    fn usage_follows_the_given_time() {
+        let mut keyspace = Keyspace::default();
+        let created = UNIX_EPOCH + Duration::from_secs(1000);
+        let idle = |keyspace: &Keyspace, key: &[u8], now| {
+            keyspace.entries[key]
+                .usage
+                .eviction_score(Policy::AllKeysLru, None, now)
+        };
+        keyspace.set(b"k".to_vec(), string("v"), None, created);
+        assert_eq!(
+            idle(&keyspace, b"k", created + Duration::from_secs(5)),
+            5000
+        );
+
+        // replacing the value is not an access, moving the entry keeps its usage
+        keyspace.set(
+            b"k".to_vec(),
+            string("w"),
+            None,
+            created + Duration::from_secs(3),
+        );
+        let entry = keyspace.remove(b"k", created).unwrap();
+        keyspace.insert(b"moved".to_vec(), entry);
+        assert_eq!(
+            idle(&keyspace, b"moved", created + Duration::from_secs(5)),
+            5000
+        );
+    }
+}
@@ -0,0 +1,1840 @@
+use super::lexer::parse_number;
+use super::parser::{Block, Expression, Field, Function, Operator, Statement};
+use super::{from_reply, Host};
+use crate::redis::resp::Reply;
+use crate::redis::sha1::sha1_hex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// How much of the stack of the thread a script can use before failing with a stack
+/// overflow, the threads having 2 MiB.
+const STACK_LIMIT: usize = 1024 * 1024;
+
+/// How many steps run between two checks of the time limit.
+const STEPS_BETWEEN_CHECKS: u64 = 1024;
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<[u8]>),
+    Table(Rc<RefCell<Table>>),
+    Function(Callable),
+}
+
+#[derive(Clone, Debug)]
+pub enum Callable {
+    Closure(Rc<Closure>),
+    Builtin(Builtin),
+}
+
+/// A function of the script with the variables it captured.
+#[derive(Debug)]
+pub struct Closure {
+    function: Arc<Function>,
+    scope: Scope,
+}
+
+/// The local variables visible at some point of a script, the innermost first. Every
+/// declaration adds a variable in front, which closures share with the enclosing code.
+type Scope = Option<Rc<Variable>>;
+
+#[derive(Debug)]
+struct Variable {
+    name: String,
+    value: RefCell<Value>,
+    parent: Scope,
+}
+
+impl Value {
+    pub fn string(value: impl Into<Vec<u8>>) -> Self {
+        Value::String(Rc::from(value.into()))
+    }
+
+    pub fn table(table: Table) -> Self {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+
+    /// Equality without conversions: tables and functions are equal only to themselves.
+    pub fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(Callable::Closure(a)), Value::Function(Callable::Closure(b))) => {
+                Rc::ptr_eq(a, b)
+            }
+            (Value::Function(Callable::Builtin(a)), Value::Function(Callable::Builtin(b))) => {
+                a == b
+            }
+            _ => false,
+        }
+    }
+
+    /// The number of a number or of a string holding one, as converted by arithmetic.
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            Value::String(value) => parse_number(std::str::from_utf8(value).ok()?),
+            _ => None,
+        }
+    }
+
+    /// The bytes of a string or of a number, as converted by concatenation.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Value::String(value) => Some(value.to_vec()),
+            Value::Number(value) => Some(format_number(*value).into_bytes()),
+            _ => None,
+        }
+    }
+
+    /// The string `tostring` returns.
+    fn to_display(&self) -> Vec<u8> {
+        match self {
+            Value::Nil => b"nil".to_vec(),
+            Value::Boolean(value) => value.to_string().into_bytes(),
+            Value::Number(_) | Value::String(_) => self.to_bytes().unwrap_or_default(),
+            Value::Table(table) => format!("table: {:p}", Rc::as_ptr(table)).into_bytes(),
+            Value::Function(Callable::Closure(closure)) => {
+                format!("function: {:p}", Rc::as_ptr(closure)).into_bytes()
+            }
+            Value::Function(Callable::Builtin(builtin)) => {
+                format!("function: builtin: {}", builtin.name()).into_bytes()
+            }
+        }
+    }
+}
+
+// Tables and closures can be nested as deep as the memory allows, e.g. by
+// `for i = 1, 1000000 do t = {t} end`: they are taken apart in a loop rather than dropped
+// recursively, which would overflow the stack.
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        let mut values = std::mem::take(&mut self.array);
+        self.positions.clear();
+        for (Key(key), value) in self.entries.drain(..) {
+            values.extend([key, value]);
+        }
+        dismantle(values, None);
+    }
+}
+
+impl Drop for Closure {
+    fn drop(&mut self) {
+        dismantle(Vec::new(), self.scope.take());
+    }
+}
+
+impl Drop for Variable {
+    fn drop(&mut self) {
+        dismantle(vec![self.value.replace(Value::Nil)], self.parent.take());
+    }
+}
+
+/// Drops values and variables, taking apart the tables and closures only they reference
+/// so that they are dropped empty.
+fn dismantle(mut values: Vec<Value>, mut scope: Scope) {
+    loop {
+        while let Some(variable) = scope.take() {
+            if let Ok(mut variable) = Rc::try_unwrap(variable) {
+                values.push(variable.value.replace(Value::Nil));
+                scope = variable.parent.take();
+            }
+        }
+        match values.pop() {
+            Some(Value::Table(table)) => {
+                if let Ok(table) = Rc::try_unwrap(table) {
+                    let mut table = table.into_inner();
+                    values.append(&mut table.array);
+                    table.positions.clear();
+                    for (Key(key), value) in table.entries.drain(..) {
+                        values.extend([key, value]);
+                    }
+                }
+            }
+            Some(Value::Function(Callable::Closure(closure))) => {
+                if let Ok(mut closure) = Rc::try_unwrap(closure) {
+                    scope = closure.scope.take();
+                }
+            }
+            Some(_) => {}
+            None => return,
+        }
+    }
+}
+
+/// A value used as the key of a table, hashed by its contents for numbers and strings and
+/// by its identity for tables and functions.
+#[derive(Clone, Debug)]
+struct Key(Value);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.raw_equals(&other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Value::Nil => 0.hash(state),
+            Value::Boolean(value) => value.hash(state),
+            // -0 and 0 are the same key
+            Value::Number(value) => (value + 0.0).to_bits().hash(state),
+            Value::String(value) => value.hash(state),
+            Value::Table(table) => Rc::as_ptr(table).hash(state),
+            Value::Function(Callable::Closure(closure)) => Rc::as_ptr(closure).hash(state),
+            Value::Function(Callable::Builtin(builtin)) => builtin.name().hash(state),
+        }
+    }
+}
+
+/// A Lua table: the values at the keys 1 to n in an array, the other ones in a map
+/// keeping the order of insertion, for `next` to go through it.
+#[derive(Debug, Default)]
+pub struct Table {
+    array: Vec<Value>,
+    /// Removed entries keep their position as nil, so that a traversal removing them
+    /// can go on.
+    entries: Vec<(Key, Value)>,
+    positions: HashMap<Key, usize>,
+    removed: usize,
+}
+
+impl Table {
+    /// A table holding `values` at the keys 1 to n.
+    pub fn sequence(values: impl IntoIterator<Item = Value>) -> Self {
+        let mut table = Table::default();
+        values.into_iter().for_each(|value| table.push(value));
+        table
+    }
+
+    /// The position in the array of a key, if it is a positive integer.
+    fn array_index(key: &Value) -> Option<usize> {
+        match key {
+            Value::Number(value) if value.fract() == 0.0 && *value >= 1.0 => {
+                Some(*value as usize - 1)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(value) = Table::array_index(key).and_then(|index| self.array.get(index)) {
+            return value.clone();
+        }
+        match self.positions.get(&Key(key.clone())) {
+            Some(position) => self.entries[*position].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn get_field(&self, name: &str) -> Value {
+        self.get(&Value::string(name))
+    }
+
+    /// Sets the value at a key, failing for the keys a table cannot have.
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        match &key {
+            Value::Nil => return Err("table index is nil"),
+            Value::Number(number) if number.is_nan() => return Err("table index is NaN"),
+            _ => {}
+        }
+
+        match Table::array_index(&key) {
+            Some(index) if index < self.array.len() => {
+                self.array[index] = value;
+                while self
+                    .array
+                    .last()
+                    .is_some_and(|last| matches!(last, Value::Nil))
+                {
+                    self.array.pop();
+                }
+            }
+            Some(index) if index == self.array.len() && !matches!(value, Value::Nil) => {
+                self.remove_entry(&key);
+                self.push(value);
+            }
+            _ => {
+                let key = Key(key);
+                match self.positions.get(&key) {
+                    Some(position) => {
+                        let entry = &mut self.entries[*position].1;
+                        if matches!(value, Value::Nil) && !matches!(entry, Value::Nil) {
+                            self.removed += 1;
+                        }
+                        *entry = value;
+                    }
+                    None if matches!(value, Value::Nil) => {}
+                    None => {
+                        self.compact();
+                        self.positions.insert(key.clone(), self.entries.len());
+                        self.entries.push((key, value));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_field(&mut self, name: &str, value: Value) {
+        let _ = self.set(Value::string(name), value);
+    }
+
+    /// Appends a value after the last one of the array, moving the following keys from
+    /// the map to the array.
+    pub fn push(&mut self, value: Value) {
+        self.array.push(value);
+        loop {
+            let next = Value::Number(self.array.len() as f64 + 1.0);
+            match self.remove_entry(&next) {
+                Some(value) => self.array.push(value),
+                None => return,
+            }
+        }
+    }
+
+    fn remove_entry(&mut self, key: &Value) -> Option<Value> {
+        let position = *self.positions.get(&Key(key.clone()))?;
+        let value = std::mem::replace(&mut self.entries[position].1, Value::Nil);
+        if matches!(value, Value::Nil) {
+            return None;
+        }
+        self.removed += 1;
+        Some(value)
+    }
+
+    /// Forgets the removed entries once they are the majority, before adding a new key.
+    fn compact(&mut self) {
+        if self.removed < 16 || self.removed * 2 < self.entries.len() {
+            return;
+        }
+        self.entries
+            .retain(|(_, value)| !matches!(value, Value::Nil));
+        self.positions = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(position, (key, _))| (key.clone(), position))
+            .collect();
+        self.removed = 0;
+    }
+
+    /// The length given by `#`: the number of values in the array.
+    pub fn length(&self) -> usize {
+        self.array.len()
+    }
+
+    /// The values at the keys 1 to n, stopping at the first nil.
+    pub fn sequence_values(&self) -> impl Iterator<Item = &Value> {
+        self.array
+            .iter()
+            .take_while(|value| !matches!(value, Value::Nil))
+    }
+
+    /// The key and value following `key`, the first ones for nil, as `next` returns them.
+    fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, &'static str> {
+        let (array_start, entries_start) = match key {
+            Value::Nil => (0, 0),
+            _ => match Table::array_index(key).filter(|index| *index < self.array.len()) {
+                Some(index) => (index + 1, 0),
+                None => match self.positions.get(&Key(key.clone())) {
+                    Some(position) => (self.array.len(), position + 1),
+                    None => return Err("invalid key to 'next'"),
+                },
+            },
+        };
+
+        let in_array = self
+            .array
+            .iter()
+            .enumerate()
+            .skip(array_start)
+            .find(|(_, value)| !matches!(value, Value::Nil))
+            .map(|(index, value)| (Value::Number(index as f64 + 1.0), value.clone()));
+        let in_entries = || {
+            self.entries
+                .iter()
+                .skip(entries_start)
+                .find(|(_, value)| !matches!(value, Value::Nil))
+                .map(|(key, value)| (key.0.clone(), value.clone()))
+        };
+        Ok(in_array.or_else(in_entries))
+    }
+}
+
+/// The functions implemented by the interpreter itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Builtin {
+    Assert,
+    Error,
+    Ipairs,
+    IpairsIterator,
+    Next,
+    Pairs,
+    Pcall,
+    Rawequal,
+    Rawget,
+    Rawset,
+    Select,
+    Tonumber,
+    Tostring,
+    Type,
+    Unpack,
+    RedisCall,
+    RedisPcall,
+    RedisErrorReply,
+    RedisStatusReply,
+    RedisSha1hex,
+    RedisLog,
+    StringByte,
+    StringChar,
+    StringFind,
+    StringFormat,
+    StringLen,
+    StringLower,
+    StringRep,
+    StringReverse,
+    StringSub,
+    StringUpper,
+    TableConcat,
+    TableGetn,
+    TableInsert,
+    TableRemove,
+    TableSort,
+    MathAbs,
+    MathCeil,
+    MathFloor,
+    MathFmod,
+    MathMax,
+    MathMin,
+    MathPow,
+    MathSqrt,
+}
+
+const GLOBALS: &[(&str, Builtin)] = &[
+    ("assert", Builtin::Assert),
+    ("error", Builtin::Error),
+    ("ipairs", Builtin::Ipairs),
+    ("next", Builtin::Next),
+    ("pairs", Builtin::Pairs),
+    ("pcall", Builtin::Pcall),
+    ("rawequal", Builtin::Rawequal),
+    ("rawget", Builtin::Rawget),
+    ("rawset", Builtin::Rawset),
+    ("select", Builtin::Select),
+    ("tonumber", Builtin::Tonumber),
+    ("tostring", Builtin::Tostring),
+    ("type", Builtin::Type),
+    ("unpack", Builtin::Unpack),
+];
+
+const LIBRARIES: &[(&str, &[(&str, Builtin)])] = &[
+    (
+        "redis",
+        &[
+            ("call", Builtin::RedisCall),
+            ("pcall", Builtin::RedisPcall),
+            ("error_reply", Builtin::RedisErrorReply),
+            ("status_reply", Builtin::RedisStatusReply),
+            ("sha1hex", Builtin::RedisSha1hex),
+            ("log", Builtin::RedisLog),
+        ],
+    ),
+    (
+        "string",
+        &[
+            ("byte", Builtin::StringByte),
+            ("char", Builtin::StringChar),
+            ("find", Builtin::StringFind),
+            ("format", Builtin::StringFormat),
+            ("len", Builtin::StringLen),
+            ("lower", Builtin::StringLower),
+            ("rep", Builtin::StringRep),
+            ("reverse", Builtin::StringReverse),
+            ("sub", Builtin::StringSub),
+            ("upper", Builtin::StringUpper),
+        ],
+    ),
+    (
+        "table",
+        &[
+            ("concat", Builtin::TableConcat),
+            ("getn", Builtin::TableGetn),
+            ("insert", Builtin::TableInsert),
+            ("remove", Builtin::TableRemove),
+            ("sort", Builtin::TableSort),
+            ("unpack", Builtin::Unpack),
+        ],
+    ),
+    (
+        "math",
+        &[
+            ("abs", Builtin::MathAbs),
+            ("ceil", Builtin::MathCeil),
+            ("floor", Builtin::MathFloor),
+            ("fmod", Builtin::MathFmod),
+            ("max", Builtin::MathMax),
+            ("min", Builtin::MathMin),
+            ("pow", Builtin::MathPow),
+            ("sqrt", Builtin::MathSqrt),
+        ],
+    ),
+];
+
+impl Builtin {
+    /// The name the function is called by in error messages.
+    fn name(self) -> &'static str {
+        GLOBALS
+            .iter()
+            .chain(LIBRARIES.iter().flat_map(|(_, functions)| functions.iter()))
+            .find(|(_, builtin)| *builtin == self)
+            .map_or("?", |(name, _)| name)
+    }
+}
+
+/// How a script stopped before its end.
+#[derive(Debug)]
+pub enum Error {
+    /// An error raised by `error`, `redis.call` or a failed operation, which `pcall` catches.
+    Raised(Value),
+    /// The script ran for too long, it cannot be caught.
+    Timeout,
+}
+
+/// What the statements of a block ended with.
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+pub struct Interpreter<'a> {
+    globals: HashMap<String, Value>,
+    host: &'a mut dyn Host,
+    /// The line of the statement being executed, for the error messages.
+    line: usize,
+    /// Where the stack was when the script started.
+    stack_start: usize,
+    steps: u64,
+    deadline: Instant,
+}
+
+impl<'a> Interpreter<'a> {
+    /// An interpreter with the standard libraries and `globals`, calling the commands of
+    /// the scripts with `host` and stopping them at `deadline`.
+    pub fn new(host: &'a mut dyn Host, globals: Vec<(&str, Value)>, deadline: Instant) -> Self {
+        let mut interpreter = Self {
+            globals: HashMap::new(),
+            host,
+            line: 0,
+            stack_start: 0,
+            steps: 0,
+            deadline,
+        };
+
+        for (name, builtin) in GLOBALS {
+            interpreter.set_global(name, Value::Function(Callable::Builtin(*builtin)));
+        }
+        for (library, functions) in LIBRARIES {
+            let mut table = Table::default();
+            for (name, builtin) in *functions {
+                table.set_field(name, Value::Function(Callable::Builtin(*builtin)));
+            }
+            match *library {
+                "math" => {
+                    table.set_field("huge", Value::Number(f64::INFINITY));
+                    table.set_field("pi", Value::Number(std::f64::consts::PI));
+                }
+                "redis" => {
+                    let levels = ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"];
+                    for (level, name) in levels.iter().enumerate() {
+                        table.set_field(name, Value::Number(level as f64));
+                    }
+                }
+                _ => {}
+            }
+            interpreter.set_global(library, Value::table(table));
+        }
+        for (name, value) in globals {
+            interpreter.set_global(name, value);
+        }
+
+        interpreter
+    }
+
+    fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    /// Runs the main function of a script, returning its first result.
+    pub fn run(&mut self, main: &Block) -> Result<Value, Error> {
+        self.stack_start = stack_position();
+        match self.block(main, &None, &[])? {
+            Flow::Return(values) => Ok(values.into_iter().next().unwrap_or(Value::Nil)),
+            Flow::Normal | Flow::Break => Ok(Value::Nil),
+        }
+    }
+
+    /// An error at the current line, as reported for the failed operations.
+    fn error(&self, message: impl AsRef<str>) -> Error {
+        Error::Raised(Value::string(format!(
+            "user_script:{}: {}",
+            self.line,
+            message.as_ref()
+        )))
+    }
+
+    /// Fails once the script used too much of the stack, with calls nested too deep.
+    fn check_stack(&self) -> Result<(), Error> {
+        if self.stack_start.abs_diff(stack_position()) > STACK_LIMIT {
+            return Err(self.error("stack overflow"));
+        }
+        Ok(())
+    }
+
+    /// Fails once the script ran past its deadline, checked every few steps.
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.steps.is_multiple_of(STEPS_BETWEEN_CHECKS) && Instant::now() >= self.deadline {
+            return Err(Error::Timeout);
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: &Block, scope: &Scope, varargs: &[Value]) -> Result<Flow, Error> {
+        let mut scope = scope.clone();
+        for (statement, line) in &block.0 {
+            match self.statement(statement, *line, &mut scope, varargs)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Executes a statement, adding the locals it declares to `scope`.
+    fn statement(
+        &mut self,
+        statement: &Statement,
+        line: usize,
+        scope: &mut Scope,
+        varargs: &[Value],
+    ) -> Result<Flow, Error> {
+        self.line = line;
+        self.step()?;
+
+        match statement {
+            Statement::Local(names, expressions) => {
+                let mut values = self.expressions(expressions, scope, varargs)?.into_iter();
+                for name in names {
+                    *scope = declare(scope, name, values.next().unwrap_or(Value::Nil));
+                }
+            }
+            Statement::LocalFunction(name, function) => {
+                // the function can call itself
+                *scope = declare(scope, name, Value::Nil);
+                let closure = self.closure(function, scope);
+                if let Some(variable) = scope {
+                    *variable.value.borrow_mut() = closure;
+                }
+            }
+            Statement::Assign(targets, expressions) => {
+                self.assign(targets, expressions, scope, varargs)?;
+            }
+            Statement::Call(call) => {
+                self.multiple(call, scope, varargs)?;
+            }
+            Statement::Do(body) => return self.block(body, scope, varargs),
+            Statement::While(condition, body) => loop {
+                // an empty body executes no statement, every iteration counts as a step
+                self.step()?;
+                if !self.expression(condition, scope, varargs)?.is_truthy() {
+                    break;
+                }
+                match self.block(body, scope, varargs)? {
+                    Flow::Normal => {}
+                    Flow::Break => break,
+                    flow => return Ok(flow),
+                }
+            },
+            Statement::Repeat(body, condition) => loop {
+                self.step()?;
+                // the condition sees the locals of the body
+                let mut inner = scope.clone();
+                for (statement, line) in &body.0 {
+                    match self.statement(statement, *line, &mut inner, varargs)? {
+                        Flow::Normal => {}
+                        Flow::Break => return Ok(Flow::Normal),
+                        flow => return Ok(flow),
+                    }
+                }
+                if self.expression(condition, &inner, varargs)?.is_truthy() {
+                    break;
+                }
+            },
+            Statement::If(branches, otherwise) => {
+                for (condition, body) in branches {
+                    if self.expression(condition, scope, varargs)?.is_truthy() {
+                        return self.block(body, scope, varargs);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.block(body, scope, varargs);
+                }
+            }
+            Statement::NumericFor {
+                variable,
+                start,
+                limit,
+                step,
+                body,
+            } => {
+                let mut number = |expression: &Expression, what: &str| {
+                    let value = self.expression(expression, scope, varargs)?;
+                    value
+                        .to_number()
+                        .ok_or_else(|| self.error(format!("'for' {} must be a number", what)))
+                };
+                let start = number(start, "initial value")?;
+                let limit = number(limit, "limit")?;
+                let step = match step {
+                    Some(step) => number(step, "step")?,
+                    None => 1.0,
+                };
+
+                let mut value = start;
+                while (step > 0.0 && value <= limit) || (step <= 0.0 && value >= limit) {
+                    self.step()?;
+                    let inner = declare(scope, variable, Value::Number(value));
+                    match self.block(body, &inner, varargs)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                    value += step;
+                }
+            }
+            Statement::GenericFor {
+                variables,
+                expressions,
+                body,
+            } => {
+                let mut values = self.expressions(expressions, scope, varargs)?.into_iter();
+                let function = values.next().unwrap_or(Value::Nil);
+                let state = values.next().unwrap_or(Value::Nil);
+                let mut control = values.next().unwrap_or(Value::Nil);
+
+                loop {
+                    let mut results = self
+                        .call(&function, vec![state.clone(), control.clone()])?
+                        .into_iter();
+                    let first = results.next().unwrap_or(Value::Nil);
+                    if matches!(first, Value::Nil) {
+                        break;
+                    }
+                    control = first.clone();
+
+                    let mut inner = declare(scope, &variables[0], first);
+                    for variable in &variables[1..] {
+                        inner = declare(&inner, variable, results.next().unwrap_or(Value::Nil));
+                    }
+                    match self.block(body, &inner, varargs)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Statement::Return(expressions) => {
+                return Ok(Flow::Return(self.expressions(
+                    expressions,
+                    scope,
+                    varargs,
+                )?));
+            }
+            Statement::Break => return Ok(Flow::Break),
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn assign(
+        &mut self,
+        targets: &[Expression],
+        expressions: &[Expression],
+        scope: &Scope,
+        varargs: &[Value],
+    ) -> Result<(), Error> {
+        // the tables and keys are evaluated before the values are assigned
+        let mut places = Vec::with_capacity(targets.len());
+        for target in targets {
+            places.push(match target {
+                Expression::Index(table, key) => Some((
+                    self.expression(table, scope, varargs)?,
+                    self.expression(key, scope, varargs)?,
+                )),
+                _ => None,
+            });
+        }
+        let mut values = self.expressions(expressions, scope, varargs)?.into_iter();
+
+        for (target, place) in targets.iter().zip(places) {
+            let value = values.next().unwrap_or(Value::Nil);
+            match (target, place) {
+                (_, Some((table, key))) => self.set_index(&table, key, value)?,
+                (Expression::Name(name), None) => match lookup(scope, name) {
+                    Some(variable) => *variable.value.borrow_mut() = value,
+                    None if self.globals.contains_key(name) => {
+                        return Err(self.error("Attempt to modify a readonly table"));
+                    }
+                    None => {
+                        return Err(self.error(format!(
+                            "Script attempted to create global variable '{}'",
+                            name
+                        )));
+                    }
+                },
+                _ => unreachable!("the parser only accepts names and indexes as targets"),
+            }
+        }
+        Ok(())
+    }
+
+    fn set_index(&mut self, table: &Value, key: Value, value: Value) -> Result<(), Error> {
+        match table {
+            Value::Table(table) => table
+                .borrow_mut()
+                .set(key, value)
+                .map_err(|message| self.error(message)),
+            other => Err(self.error(format!("attempt to index a {} value", other.type_name()))),
+        }
+    }
+
+    fn index(&mut self, value: &Value, key: &Value) -> Result<Value, Error> {
+        match value {
+            Value::Table(table) => Ok(table.borrow().get(key)),
+            // strings have the functions of the string library as methods
+            Value::String(_) => match self.globals.get("string") {
+                Some(Value::Table(library)) => Ok(library.borrow().get(key)),
+                _ => Ok(Value::Nil),
+            },
+            other => Err(self.error(format!("attempt to index a {} value", other.type_name()))),
+        }
+    }
+
+    /// The values of a list of expressions, all the values of the last one if it is a call
+    /// or `...`.
+    fn expressions(
+        &mut self,
+        expressions: &[Expression],
+        scope: &Scope,
+        varargs: &[Value],
+    ) -> Result<Vec<Value>, Error> {
+        let mut values = Vec::with_capacity(expressions.len());
+        for (position, expression) in expressions.iter().enumerate() {
+            if position + 1 == expressions.len() {
+                values.extend(self.multiple(expression, scope, varargs)?);
+            } else {
+                values.push(self.expression(expression, scope, varargs)?);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Every value of an expression: the results of a call, the extra arguments for `...`
+    /// and a single value otherwise.
+    fn multiple(
+        &mut self,
+        expression: &Expression,
+        scope: &Scope,
+        varargs: &[Value],
+    ) -> Result<Vec<Value>, Error> {
+        match expression {
+            Expression::Vararg => Ok(varargs.to_vec()),
+            Expression::Call(function, arguments) => {
+                let function = self.expression(function, scope, varargs)?;
+                let arguments = self.expressions(arguments, scope, varargs)?;
+                self.call(&function, arguments)
+            }
+            Expression::Method(object, name, arguments) => {
+                let object = self.expression(object, scope, varargs)?;
+                let method = self.index(&object, &Value::string(name.as_str()))?;
+                let mut values = vec![object];
+                values.extend(self.expressions(arguments, scope, varargs)?);
+                self.call(&method, values)
+            }
+            expression => Ok(vec![self.expression(expression, scope, varargs)?]),
+        }
+    }
+
+    fn expression(
+        &mut self,
+        expression: &Expression,
+        scope: &Scope,
+        varargs: &[Value],
+    ) -> Result<Value, Error> {
+        self.check_stack()?;
+        Ok(match expression {
+            Expression::Nil => Value::Nil,
+            Expression::Boolean(value) => Value::Boolean(*value),
+            Expression::Number(value) => Value::Number(*value),
+            Expression::String(value) => Value::string(value.as_slice()),
+            Expression::Vararg => varargs.first().cloned().unwrap_or(Value::Nil),
+            Expression::Function(function) => self.closure(function, scope),
+            Expression::Name(name) => match lookup(scope, name) {
+                Some(variable) => variable.value.borrow().clone(),
+                None => match self.globals.get(name) {
+                    Some(value) => value.clone(),
+                    None => {
+                        return Err(self.error(format!(
+                            "Script attempted to access nonexistent global variable '{}'",
+                            name
+                        )))
+                    }
+                },
+            },
+            Expression::Index(table, key) => {
+                let table = self.expression(table, scope, varargs)?;
+                let key = self.expression(key, scope, varargs)?;
+                self.index(&table, &key)?
+            }
+            Expression::Call(..) | Expression::Method(..) => self
+                .multiple(expression, scope, varargs)?
+                .into_iter()
+                .next()
+                .unwrap_or(Value::Nil),
+            Expression::Parenthesized(inner) => self.expression(inner, scope, varargs)?,
+            Expression::Table(fields) => {
+                let mut table = Table::default();
+                // the positional fields are at the keys 1 to n, even when they are nil
+                let mut positional = 0.0;
+                for (position, field) in fields.iter().enumerate() {
+                    match field {
+                        Field::Positional(value) => {
+                            let values = match position + 1 == fields.len() {
+                                true => self.multiple(value, scope, varargs)?,
+                                false => vec![self.expression(value, scope, varargs)?],
+                            };
+                            for value in values {
+                                positional += 1.0;
+                                let key = Value::Number(positional);
+                                table
+                                    .set(key, value)
+                                    .map_err(|message| self.error(message))?;
+                            }
+                        }
+                        Field::Keyed(key, value) => {
+                            let key = self.expression(key, scope, varargs)?;
+                            let value = self.expression(value, scope, varargs)?;
+                            table
+                                .set(key, value)
+                                .map_err(|message| self.error(message))?;
+                        }
+                    }
+                }
+                Value::table(table)
+            }
+            Expression::Binary(Operator::And, left, right) => {
+                let left = self.expression(left, scope, varargs)?;
+                if !left.is_truthy() {
+                    return Ok(left);
+                }
+                self.expression(right, scope, varargs)?
+            }
+            Expression::Binary(Operator::Or, left, right) => {
+                let left = self.expression(left, scope, varargs)?;
+                if left.is_truthy() {
+                    return Ok(left);
+                }
+                self.expression(right, scope, varargs)?
+            }
+            Expression::Binary(operator, left, right) => {
+                let left = self.expression(left, scope, varargs)?;
+                let right = self.expression(right, scope, varargs)?;
+                self.binary(*operator, &left, &right)?
+            }
+            Expression::Unary(operator, operand) => {
+                let operand = self.expression(operand, scope, varargs)?;
+                match operator {
+                    Operator::Not => Value::Boolean(!operand.is_truthy()),
+                    Operator::Length => match &operand {
+                        Value::String(value) => Value::Number(value.len() as f64),
+                        Value::Table(table) => Value::Number(table.borrow().length() as f64),
+                        other => {
+                            return Err(self.error(format!(
+                                "attempt to get length of a {} value",
+                                other.type_name()
+                            )))
+                        }
+                    },
+                    _ => Value::Number(-self.arithmetic_operand(&operand)?),
+                }
+            }
+        })
+    }
+
+    fn arithmetic_operand(&self, value: &Value) -> Result<f64, Error> {
+        value.to_number().ok_or_else(|| {
+            self.error(format!(
+                "attempt to perform arithmetic on a {} value",
+                value.type_name()
+            ))
+        })
+    }
+
+    fn binary(&self, operator: Operator, left: &Value, right: &Value) -> Result<Value, Error> {
+        let compare = || match (left, right) {
+            (Value::Number(a), Value::Number(b)) => Ok(a.partial_cmp(b)),
+            (Value::String(a), Value::String(b)) => Ok(Some(a.cmp(b))),
+            _ => Err(self.error(format!(
+                "attempt to compare {} with {}",
+                left.type_name(),
+                right.type_name()
+            ))),
+        };
+
+        Ok(match operator {
+            Operator::Equal => Value::Boolean(left.raw_equals(right)),
+            Operator::NotEqual => Value::Boolean(!left.raw_equals(right)),
+            Operator::Less => Value::Boolean(compare()? == Some(Ordering::Less)),
+            Operator::LessOrEqual => {
+                Value::Boolean(matches!(compare()?, Some(Ordering::Less | Ordering::Equal)))
+            }
+            Operator::Greater => Value::Boolean(compare()? == Some(Ordering::Greater)),
+            Operator::GreaterOrEqual => Value::Boolean(matches!(
+                compare()?,
+                Some(Ordering::Greater | Ordering::Equal)
+            )),
+            Operator::Concat => match (left.to_bytes(), right.to_bytes()) {
+                (Some(mut left), Some(right)) => {
+                    left.extend(right);
+                    Value::string(left)
+                }
+                (None, _) => return Err(self.concat_error(left)),
+                (_, None) => return Err(self.concat_error(right)),
+            },
+            operator => {
+                let a = self.arithmetic_operand(left)?;
+                let b = self.arithmetic_operand(right)?;
+                Value::Number(match operator {
+                    Operator::Add => a + b,
+                    Operator::Subtract => a - b,
+                    Operator::Multiply => a * b,
+                    Operator::Divide => a / b,
+                    Operator::Modulo => a - (a / b).floor() * b,
+                    _ => a.powf(b),
+                })
+            }
+        })
+    }
+
+    fn concat_error(&self, value: &Value) -> Error {
+        self.error(format!(
+            "attempt to concatenate a {} value",
+            value.type_name()
+        ))
+    }
+
+    fn closure(&self, function: &Arc<Function>, scope: &Scope) -> Value {
+        Value::Function(Callable::Closure(Rc::new(Closure {
+            function: Arc::clone(function),
+            scope: scope.clone(),
+        })))
+    }
+
+    /// Calls a function, returning all its results.
+    pub fn call(&mut self, function: &Value, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+        let closure = match function {
+            Value::Function(Callable::Closure(closure)) => Rc::clone(closure),
+            Value::Function(Callable::Builtin(builtin)) => {
+                return self.builtin(*builtin, arguments)
+            }
+            other => {
+                return Err(self.error(format!("attempt to call a {} value", other.type_name())))
+            }
+        };
+        self.check_stack()?;
+
+        let mut arguments = arguments.into_iter();
+        let mut scope = closure.scope.clone();
+        for parameter in &closure.function.parameters {
+            scope = declare(&scope, parameter, arguments.next().unwrap_or(Value::Nil));
+        }
+        let varargs = match closure.function.is_vararg {
+            true => arguments.collect(),
+            false => Vec::new(),
+        };
+
+        // errors after the call are reported on the line of the caller
+        let line = self.line;
+        let flow = self.block(&closure.function.body, &scope, &varargs);
+        self.line = line;
+
+        match flow? {
+            Flow::Return(values) => Ok(values),
+            Flow::Normal | Flow::Break => Ok(Vec::new()),
+        }
+    }
+}
+
+/// The standard functions.
+impl Interpreter<'_> {
+    fn builtin(&mut self, builtin: Builtin, arguments: Vec<Value>) -> Result<Vec<Value>, Error> {
+        let name = builtin.name();
+        let argument = |position: usize| arguments.get(position - 1).cloned().unwrap_or(Value::Nil);
+        let one = |value: Value| Ok(vec![value]);
+
+        match builtin {
+            Builtin::Assert => match arguments.first() {
+                Some(value) if value.is_truthy() => Ok(arguments),
+                _ => match arguments.get(1) {
+                    Some(message) => Err(Error::Raised(message.clone())),
+                    None => Err(self.error("assertion failed!")),
+                },
+            },
+            Builtin::Error => {
+                let level = self.optional_number(&arguments, 2, name, 1.0)?;
+                match argument(1) {
+                    Value::String(message) if level > 0.0 => {
+                        Err(self.error(String::from_utf8_lossy(&message)))
+                    }
+                    value => Err(Error::Raised(value)),
+                }
+            }
+            Builtin::Ipairs => {
+                let table = self.check_table(&arguments, 1, name)?;
+                Ok(vec![
+                    Value::Function(Callable::Builtin(Builtin::IpairsIterator)),
+                    Value::Table(table),
+                    Value::Number(0.0),
+                ])
+            }
+            Builtin::IpairsIterator => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let index = self.check_number(&arguments, 2, name)? + 1.0;
+                let value = table.borrow().get(&Value::Number(index));
+                match value {
+                    Value::Nil => one(Value::Nil),
+                    value => Ok(vec![Value::Number(index), value]),
+                }
+            }
+            Builtin::Next => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let next = table.borrow().next(&argument(2));
+                match next.map_err(|message| self.error(message))? {
+                    Some((key, value)) => Ok(vec![key, value]),
+                    None => one(Value::Nil),
+                }
+            }
+            Builtin::Pairs => {
+                let table = self.check_table(&arguments, 1, name)?;
+                Ok(vec![
+                    Value::Function(Callable::Builtin(Builtin::Next)),
+                    Value::Table(table),
+                    Value::Nil,
+                ])
+            }
+            Builtin::Pcall => {
+                let mut arguments = arguments.into_iter();
+                let function = arguments.next().unwrap_or(Value::Nil);
+                match self.call(&function, arguments.collect()) {
+                    Ok(mut values) => {
+                        values.insert(0, Value::Boolean(true));
+                        Ok(values)
+                    }
+                    Err(Error::Raised(value)) => Ok(vec![Value::Boolean(false), value]),
+                    Err(Error::Timeout) => Err(Error::Timeout),
+                }
+            }
+            Builtin::Rawequal => one(Value::Boolean(argument(1).raw_equals(&argument(2)))),
+            Builtin::Rawget => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let value = table.borrow().get(&argument(2));
+                one(value)
+            }
+            Builtin::Rawset => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let set = table.borrow_mut().set(argument(2), argument(3));
+                set.map_err(|message| self.error(message))?;
+                one(Value::Table(table))
+            }
+            Builtin::Select => match argument(1) {
+                Value::String(value) if &*value == b"#" => {
+                    one(Value::Number(arguments.len() as f64 - 1.0))
+                }
+                _ => {
+                    let index = self.check_number(&arguments, 1, name)?;
+                    let count = arguments.len() as f64 - 1.0;
+                    let start = match index {
+                        index if index < 0.0 && -index <= count => count + index,
+                        index if index >= 1.0 => index - 1.0,
+                        _ => return Err(self.argument_error(1, name, "index out of range")),
+                    };
+                    Ok(arguments.into_iter().skip(start as usize + 1).collect())
+                }
+            },
+            Builtin::Tonumber => match arguments.get(1) {
+                None | Some(Value::Nil) => {
+                    one(argument(1).to_number().map_or(Value::Nil, Value::Number))
+                }
+                Some(_) => {
+                    let base = self.check_number(&arguments, 2, name)?;
+                    if !(2.0..=36.0).contains(&base) {
+                        return Err(self.argument_error(2, name, "base out of range"));
+                    }
+                    let text = self.check_string(&arguments, 1, name)?;
+                    let parsed = std::str::from_utf8(&text)
+                        .ok()
+                        .and_then(|text| i64::from_str_radix(text.trim(), base as u32).ok());
+                    one(parsed.map_or(Value::Nil, |value| Value::Number(value as f64)))
+                }
+            },
+            Builtin::Tostring => one(Value::string(argument(1).to_display())),
+            Builtin::Type => match arguments.first() {
+                Some(value) => one(Value::string(value.type_name())),
+                None => Err(self.argument_error(1, name, "value expected")),
+            },
+            Builtin::Unpack => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let table = table.borrow();
+                let first = self.optional_number(&arguments, 2, name, 1.0)?;
+                let last = self.optional_number(&arguments, 3, name, table.length() as f64)?;
+                if last - first >= 8000.0 {
+                    return Err(self.error("too many results to unpack"));
+                }
+                let mut values = Vec::new();
+                let mut index = first;
+                while index <= last {
+                    values.push(table.get(&Value::Number(index)));
+                    index += 1.0;
+                }
+                Ok(values)
+            }
+            Builtin::RedisCall | Builtin::RedisPcall => {
+                if arguments.is_empty() {
+                    return Err(
+                        self.error("Please specify at least one argument for this redis lib call")
+                    );
+                }
+                let command = arguments
+                    .iter()
+                    .map(|argument| match argument {
+                        Value::String(_) | Value::Number(_) => argument.to_bytes(),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| {
+                        self.error("Lua redis lib command arguments must be strings or integers")
+                    })?;
+                let reply = self.host.call(&command);
+                call_result(reply, builtin == Builtin::RedisCall)
+            }
+            Builtin::RedisErrorReply | Builtin::RedisStatusReply => {
+                let message = self.check_string(&arguments, 1, name)?;
+                let mut table = Table::default();
+                let field = match builtin {
+                    Builtin::RedisErrorReply => "err",
+                    _ => "ok",
+                };
+                table.set_field(field, Value::string(message));
+                one(Value::table(table))
+            }
+            Builtin::RedisSha1hex => {
+                let value = self.check_string(&arguments, 1, name)?;
+                one(Value::string(sha1_hex(&value)))
+            }
+            Builtin::RedisLog => {
+                let level = match self.check_number(&arguments, 1, name)? as i64 {
+                    0 => "debug",
+                    1 => "verbose",
+                    2 => "notice",
+                    3 => "warning",
+                    _ => return Err(self.error("Invalid debug level.")),
+                };
+                let message = arguments[1..]
+                    .iter()
+                    .map(|argument| String::from_utf8_lossy(&argument.to_display()).into_owned())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                // stdout belongs to the server, scripts only get to add to its diagnostics
+                eprintln!("[{}] {}", level, message);
+                Ok(Vec::new())
+            }
+            Builtin::StringByte => {
+                let value = self.check_string(&arguments, 1, name)?;
+                let first = self.optional_number(&arguments, 2, name, 1.0)?;
+                let last = self.optional_number(&arguments, 3, name, first)?;
+                let (start, end) = substring_range(value.len(), first, last);
+                Ok(value[start..end]
+                    .iter()
+                    .map(|byte| Value::Number(f64::from(*byte)))
+                    .collect())
+            }
+            Builtin::StringChar => {
+                let mut value = Vec::with_capacity(arguments.len());
+                for position in 1..=arguments.len() {
+                    let code = self.check_number(&arguments, position, name)?;
+                    match u8::try_from(code as i64) {
+                        Ok(byte) => value.push(byte),
+                        Err(_) => return Err(self.argument_error(position, name, "invalid value")),
+                    }
+                }
+                one(Value::string(value))
+            }
+            Builtin::StringFind => {
+                let value = self.check_string(&arguments, 1, name)?;
+                let pattern = self.check_string(&arguments, 2, name)?;
+                let init = self.optional_number(&arguments, 3, name, 1.0)?;
+                let plain = argument(4).is_truthy()
+                    || !pattern.iter().any(|byte| b"^$*+?.([%-".contains(byte));
+                if !plain {
+                    return Err(self.error("string.find only supports plain patterns"));
+                }
+                let (start, _) = substring_range(value.len(), init, -1.0);
+                let found = if pattern.is_empty() {
+                    Some(0)
+                } else {
+                    value[start..]
+                        .windows(pattern.len())
+                        .position(|window| window == pattern.as_slice())
+                };
+                match found {
+                    Some(offset) if start + offset <= value.len() => Ok(vec![
+                        Value::Number((start + offset + 1) as f64),
+                        Value::Number((start + offset + pattern.len()) as f64),
+                    ]),
+                    _ => one(Value::Nil),
+                }
+            }
+            Builtin::StringFormat => one(Value::string(self.format(&arguments)?)),
+            Builtin::StringLen => {
+                let value = self.check_string(&arguments, 1, name)?;
+                one(Value::Number(value.len() as f64))
+            }
+            Builtin::StringLower | Builtin::StringUpper | Builtin::StringReverse => {
+                let mut value = self.check_string(&arguments, 1, name)?;
+                match builtin {
+                    Builtin::StringLower => value.make_ascii_lowercase(),
+                    Builtin::StringUpper => value.make_ascii_uppercase(),
+                    _ => value.reverse(),
+                }
+                one(Value::string(value))
+            }
+            Builtin::StringRep => {
+                let value = self.check_string(&arguments, 1, name)?;
+                let count = self.check_number(&arguments, 2, name)?.max(0.0) as usize;
+                if value.len().saturating_mul(count) > 512 * 1024 * 1024 {
+                    return Err(self.error("resulting string too large"));
+                }
+                one(Value::string(value.repeat(count)))
+            }
+            Builtin::StringSub => {
+                let value = self.check_string(&arguments, 1, name)?;
+                let first = self.check_number(&arguments, 2, name)?;
+                let last = self.optional_number(&arguments, 3, name, -1.0)?;
+                let (start, end) = substring_range(value.len(), first, last);
+                one(Value::string(&value[start..end]))
+            }
+            Builtin::TableConcat => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let table = table.borrow();
+                let separator = match arguments.get(1) {
+                    None | Some(Value::Nil) => Vec::new(),
+                    Some(_) => self.check_string(&arguments, 2, name)?,
+                };
+                let first = self.optional_number(&arguments, 3, name, 1.0)?;
+                let last = self.optional_number(&arguments, 4, name, table.length() as f64)?;
+                let mut value = Vec::new();
+                let mut index = first;
+                while index <= last {
+                    let Some(bytes) = table.get(&Value::Number(index)).to_bytes() else {
+                        return Err(self.error(format!(
+                            "invalid value (at index {}) in table for 'concat'",
+                            format_number(index)
+                        )));
+                    };
+                    if index > first {
+                        value.extend_from_slice(&separator);
+                    }
+                    value.extend(bytes);
+                    index += 1.0;
+                }
+                one(Value::string(value))
+            }
+            Builtin::TableGetn => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let length = table.borrow().length();
+                one(Value::Number(length as f64))
+            }
+            Builtin::TableInsert => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let mut table = table.borrow_mut();
+                let length = table.length();
+                let (position, value) = match arguments.len() {
+                    2 => (length + 1, argument(2)),
+                    3 => {
+                        let position = self.check_number(&arguments, 2, name)? as usize;
+                        if position == 0 || position > length + 1 {
+                            return Err(self.argument_error(2, name, "position out of bounds"));
+                        }
+                        (position, argument(3))
+                    }
+                    _ => return Err(self.error("wrong number of arguments to 'insert'")),
+                };
+                for index in (position..=length).rev() {
+                    let moved = table.get(&Value::Number(index as f64));
+                    let _ = table.set(Value::Number(index as f64 + 1.0), moved);
+                }
+                let _ = table.set(Value::Number(position as f64), value);
+                Ok(Vec::new())
+            }
+            Builtin::TableRemove => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let mut table = table.borrow_mut();
+                let length = table.length();
+                if length == 0 {
+                    return one(Value::Nil);
+                }
+                let position = self.optional_number(&arguments, 2, name, length as f64)? as usize;
+                if position == 0 || position > length {
+                    return one(Value::Nil);
+                }
+                let removed = table.get(&Value::Number(position as f64));
+                for index in position..length {
+                    let moved = table.get(&Value::Number(index as f64 + 1.0));
+                    let _ = table.set(Value::Number(index as f64), moved);
+                }
+                let _ = table.set(Value::Number(length as f64), Value::Nil);
+                one(removed)
+            }
+            Builtin::TableSort => {
+                let table = self.check_table(&arguments, 1, name)?;
+                let comparator = argument(2);
+                let mut values = table
+                    .borrow()
+                    .sequence_values()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let mut failure = None;
+                values.sort_by(|a, b| {
+                    if failure.is_some() {
+                        return Ordering::Equal;
+                    }
+                    let ordering = self
+                        .less_than(&comparator, a, b)
+                        .and_then(|less| match less {
+                            true => Ok(Ordering::Less),
+                            false => match self.less_than(&comparator, b, a)? {
+                                true => Ok(Ordering::Greater),
+                                false => Ok(Ordering::Equal),
+                            },
+                        });
+                    ordering.unwrap_or_else(|error| {
+                        failure = Some(error);
+                        Ordering::Equal
+                    })
+                });
+                if let Some(error) = failure {
+                    return Err(error);
+                }
+                let mut table = table.borrow_mut();
+                for (index, value) in values.into_iter().enumerate() {
+                    let _ = table.set(Value::Number(index as f64 + 1.0), value);
+                }
+                Ok(Vec::new())
+            }
+            Builtin::MathAbs | Builtin::MathCeil | Builtin::MathFloor | Builtin::MathSqrt => {
+                let value = self.check_number(&arguments, 1, name)?;
+                one(Value::Number(match builtin {
+                    Builtin::MathAbs => value.abs(),
+                    Builtin::MathCeil => value.ceil(),
+                    Builtin::MathFloor => value.floor(),
+                    _ => value.sqrt(),
+                }))
+            }
+            Builtin::MathFmod | Builtin::MathPow => {
+                let a = self.check_number(&arguments, 1, name)?;
+                let b = self.check_number(&arguments, 2, name)?;
+                one(Value::Number(match builtin {
+                    Builtin::MathFmod => a % b,
+                    _ => a.powf(b),
+                }))
+            }
+            Builtin::MathMax | Builtin::MathMin => {
+                let mut result = self.check_number(&arguments, 1, name)?;
+                for position in 2..=arguments.len() {
+                    let value = self.check_number(&arguments, position, name)?;
+                    if (builtin == Builtin::MathMax && value > result)
+                        || (builtin == Builtin::MathMin && value < result)
+                    {
+                        result = value;
+                    }
+                }
+                one(Value::Number(result))
+            }
+        }
+    }
+
+    /// Whether `a` sorts before `b`, with `comparator` or with `<` when it is nil.
+    fn less_than(&mut self, comparator: &Value, a: &Value, b: &Value) -> Result<bool, Error> {
+        match comparator {
+            Value::Nil => Ok(self.binary(Operator::Less, a, b)?.is_truthy()),
+            comparator => Ok(self
+                .call(comparator, vec![a.clone(), b.clone()])?
+                .first()
+                .is_some_and(Value::is_truthy)),
+        }
+    }
+
+    /// `string.format`, supporting the `%d %i %u %c %x %X %o %e %E %f %g %G %q %s %%`
+    /// conversions with their flags, width and precision.
+    fn format(&self, arguments: &[Value]) -> Result<Vec<u8>, Error> {
+        let name = "format";
+        let template = self.check_string(arguments, 1, name)?;
+        let mut output = Vec::new();
+        let mut next_argument = 1;
+        let mut bytes = template.iter().copied().peekable();
+
+        while let Some(byte) = bytes.next() {
+            if byte != b'%' {
+                output.push(byte);
+                continue;
+            }
+            if bytes.next_if_eq(&b'%').is_some() {
+                output.push(b'%');
+                continue;
+            }
+
+            let mut flags = Vec::new();
+            while let Some(flag) = bytes.next_if(|byte| b"-+ #0".contains(byte)) {
+                flags.push(flag);
+            }
+            let mut width = 0;
+            while let Some(digit) = bytes.next_if(u8::is_ascii_digit) {
+                width = width * 10 + usize::from(digit - b'0');
+            }
+            let mut precision = None;
+            if bytes.next_if_eq(&b'.').is_some() {
+                let mut value = 0;
+                while let Some(digit) = bytes.next_if(u8::is_ascii_digit) {
+                    value = value * 10 + usize::from(digit - b'0');
+                }
+                precision = Some(value);
+            }
+            let Some(conversion) = bytes.next() else {
+                return Err(self.error("invalid option '%' to 'format'"));
+            };
+
+            next_argument += 1;
+            let position = next_argument;
+            let number = || self.check_number(arguments, position, name);
+            let sign = |value: f64, text: String| {
+                if value.is_sign_negative() || text.starts_with('-') {
+                    text
+                } else if flags.contains(&b'+') {
+                    format!("+{}", text)
+                } else if flags.contains(&b' ') {
+                    format!(" {}", text)
+                } else {
+                    text
+                }
+            };
+            let formatted = match conversion {
+                b'd' | b'i' => {
+                    let value = number()?;
+                    let mut digits = format!("{}", value.trunc().abs() as i64);
+                    if let Some(precision) = precision {
+                        digits = format!("{:0>width$}", digits, width = precision);
+                    }
+                    let text = if value < 0.0 && value.trunc() != 0.0 {
+                        format!("-{}", digits)
+                    } else {
+                        digits
+                    };
+                    sign(value, text).into_bytes()
+                }
+                b'u' => format!("{}", number()?.trunc() as u64).into_bytes(),
+                b'c' => vec![number()? as u8],
+                b'x' => format!("{:x}", number()? as i64).into_bytes(),
+                b'X' => format!("{:X}", number()? as i64).into_bytes(),
+                b'o' => format!("{:o}", number()? as i64).into_bytes(),
+                b'e' | b'E' => {
+                    let value = number()?;
+                    let text = format!("{:.*e}", precision.unwrap_or(6), value);
+                    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+                    let exponent = exponent.parse::<i32>().unwrap_or_default();
+                    let text = format!(
+                        "{}e{}{:02}",
+                        mantissa,
+                        if exponent < 0 { '-' } else { '+' },
+                        exponent.abs()
+                    );
+                    let text = sign(value, text);
+                    match conversion {
+                        b'E' => text.to_uppercase().into_bytes(),
+                        _ => text.into_bytes(),
+                    }
+                }
+                b'f' | b'F' => {
+                    let value = number()?;
+                    sign(value, format!("{:.*}", precision.unwrap_or(6), value)).into_bytes()
+                }
+                b'g' | b'G' => {
+                    let value = number()?;
+                    let text = format_general(value, precision.unwrap_or(6), flags.contains(&b'#'));
+                    let text = sign(value, text);
+                    match conversion {
+                        b'G' => text.to_uppercase().into_bytes(),
+                        _ => text.into_bytes(),
+                    }
+                }
+                b'q' => {
+                    let value = self.check_string(arguments, position, name)?;
+                    let mut quoted = vec![b'"'];
+                    for byte in value {
+                        match byte {
+                            b'"' | b'\\' | b'\n' => quoted.extend_from_slice(&[b'\\', byte]),
+                            b'\r' => quoted.extend_from_slice(b"\\r"),
+                            0 => quoted.extend_from_slice(b"\\000"),
+                            byte => quoted.push(byte),
+                        }
+                    }
+                    quoted.push(b'"');
+                    quoted
+                }
+                b's' => {
+                    let value = arguments
+                        .get(position - 1)
+                        .ok_or_else(|| self.argument_error(position, name, "no value"))?;
+                    let mut text = value.to_display();
+                    if let Some(precision) = precision {
+                        text.truncate(precision);
+                    }
+                    text
+                }
+                other => {
+                    return Err(
+                        self.error(format!("invalid option '%{}' to 'format'", other as char))
+                    )
+                }
+            };
+
+            let padding = width.saturating_sub(formatted.len());
+            if flags.contains(&b'-') {
+                output.extend(formatted);
+                output.extend(std::iter::repeat_n(b' ', padding));
+            } else if flags.contains(&b'0') && !matches!(conversion, b's' | b'q' | b'c') {
+                // the zeros go after the sign
+                let signed = formatted.first().is_some_and(|byte| b"+- ".contains(byte));
+                let (sign, digits) = formatted.split_at(usize::from(signed));
+                output.extend_from_slice(sign);
+                output.extend(std::iter::repeat_n(b'0', padding));
+                output.extend_from_slice(digits);
+            } else {
+                output.extend(std::iter::repeat_n(b' ', padding));
+                output.extend(formatted);
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn argument_error(&self, position: usize, name: &str, message: &str) -> Error {
+        self.error(format!(
+            "bad argument #{} to '{}' ({})",
+            position, name, message
+        ))
+    }
+
+    fn type_error(
+        &self,
+        arguments: &[Value],
+        position: usize,
+        name: &str,
+        expected: &str,
+    ) -> Error {
+        let found = arguments
+            .get(position - 1)
+            .map_or("no value", Value::type_name);
+        self.argument_error(
+            position,
+            name,
+            &format!("{} expected, got {}", expected, found),
+        )
+    }
+
+    fn check_number(&self, arguments: &[Value], position: usize, name: &str) -> Result<f64, Error> {
+        arguments
+            .get(position - 1)
+            .and_then(Value::to_number)
+            .ok_or_else(|| self.type_error(arguments, position, name, "number"))
+    }
+
+    fn optional_number(
+        &self,
+        arguments: &[Value],
+        position: usize,
+        name: &str,
+        default: f64,
+    ) -> Result<f64, Error> {
+        match arguments.get(position - 1) {
+            None | Some(Value::Nil) => Ok(default),
+            Some(_) => self.check_number(arguments, position, name),
+        }
+    }
+
+    fn check_string(
+        &self,
+        arguments: &[Value],
+        position: usize,
+        name: &str,
+    ) -> Result<Vec<u8>, Error> {
+        arguments
+            .get(position - 1)
+            .and_then(Value::to_bytes)
+            .ok_or_else(|| self.type_error(arguments, position, name, "string"))
+    }
+
+    fn check_table(
+        &self,
+        arguments: &[Value],
+        position: usize,
+        name: &str,
+    ) -> Result<Rc<RefCell<Table>>, Error> {
+        match arguments.get(position - 1) {
+            Some(Value::Table(table)) => Ok(Rc::clone(table)),
+            _ => Err(self.type_error(arguments, position, name, "table")),
+        }
+    }
+}
+
+/// The byte range of `string.sub(value, first, last)`, negative positions counting from
+/// the end.
+fn substring_range(length: usize, first: f64, last: f64) -> (usize, usize) {
+    let position = |index: f64| {
+        if index < 0.0 {
+            (length as f64 + index + 1.0).max(0.0)
+        } else {
+            index
+        }
+    };
+    let start = position(first).max(1.0) as usize;
+    let end = (position(last) as usize).min(length);
+    if start > end {
+        (0, 0)
+    } else {
+        (start - 1, end)
+    }
+}
+
+/// The address of a local variable, telling how deep the stack is.
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+fn declare(scope: &Scope, name: &str, value: Value) -> Scope {
+    Some(Rc::new(Variable {
+        name: name.to_string(),
+        value: RefCell::new(value),
+        parent: scope.clone(),
+    }))
+}
+
+fn lookup<'s>(scope: &'s Scope, name: &str) -> Option<&'s Variable> {
+    let mut current = scope.as_deref();
+    while let Some(variable) = current {
+        if variable.name == name {
+            return Some(variable);
+        }
+        current = variable.parent.as_deref();
+    }
+    None
+}
+
+/// Formats a number as `tostring` does, with up to 14 significant digits.
+pub fn format_number(value: f64) -> String {
+    format_general(value, 14, false)
+}
+
+/// Formats a number as `%g` does with `precision` significant digits, keeping the
+/// trailing zeros with `alternate`.
+fn format_general(value: f64, precision: usize, alternate: bool) -> String {
+    if value.is_nan() {
+        return if value.is_sign_negative() {
+            "-nan"
+        } else {
+            "nan"
+        }
+        .to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    let precision = precision.max(1);
+    let trim = |text: String| {
+        if alternate || !text.contains('.') {
+            text
+        } else {
+            text.trim_end_matches('0').trim_end_matches('.').to_string()
+        }
+    };
+
+    let scientific = format!("{:.*e}", precision - 1, value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent = exponent.parse::<i32>().unwrap_or_default();
+    if exponent < -4 || exponent >= precision as i32 {
+        format!(
+            "{}e{}{:02}",
+            trim(mantissa.to_string()),
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
+    } else {
+        let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+        trim(format!("{:.*}", decimals, value))
+    }
+}
+
+/// Converts the reply of a command to the values `redis.call` returns, raising the
+/// errors.
+fn call_result(reply: Reply, raise: bool) -> Result<Vec<Value>, Error> {
+    match reply {
+        Reply::Error(message) if raise => {
+            let mut table = Table::default();
+            table.set_field("err", Value::string(message));
+            Err(Error::Raised(Value::table(table)))
+        }
+        reply => Ok(vec![from_reply(reply)]),
+    }
+}
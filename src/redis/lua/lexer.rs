@@ -0,0 +1,335 @@
+use std::fmt::{Display, Formatter};
+
+const KEYWORDS: [&str; 21] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// The symbols, longest first so that `..` is not read as two `.`.
+const SYMBOLS: [&str; 26] = [
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(",
+    ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Name(String),
+    String(Vec<u8>),
+    Number(f64),
+    /// A keyword or a symbol.
+    Symbol(&'static str),
+    Eof,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::String(value) => write!(f, "'{}'", String::from_utf8_lossy(value)),
+            Token::Number(value) => write!(f, "'{}'", value),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+            Token::Eof => write!(f, "<eof>"),
+        }
+    }
+}
+
+/// Splits a script into tokens, each with the line it starts on.
+pub fn tokenize(source: &[u8]) -> Result<Vec<(Token, usize)>, (String, usize)> {
+    let mut lexer = Lexer {
+        source,
+        position: 0,
+        line: 1,
+    };
+    let mut tokens = Vec::new();
+
+    loop {
+        lexer.skip_whitespace_and_comments()?;
+        let line = lexer.line;
+        let token = lexer.next_token()?;
+        let is_eof = token == Token::Eof;
+        tokens.push((token, line));
+        if is_eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+struct Lexer<'a> {
+    source: &'a [u8],
+    position: usize,
+    line: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.source.get(self.position + offset).copied()
+    }
+
+    fn advance(&mut self) -> Option<u8> {
+        let byte = self.peek(0)?;
+        self.position += 1;
+        if byte == b'\n' {
+            self.line += 1;
+        }
+        Some(byte)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, (String, usize)> {
+        Err((message.into(), self.line))
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), (String, usize)> {
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some(byte), _) if byte.is_ascii_whitespace() => {
+                    self.advance();
+                }
+                (Some(b'-'), Some(b'-')) => {
+                    self.position += 2;
+                    if self.long_bracket_level().is_some() {
+                        self.long_string()?;
+                    } else {
+                        while self.peek(0).is_some_and(|byte| byte != b'\n') {
+                            self.advance();
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, (String, usize)> {
+        let Some(byte) = self.peek(0) else {
+            return Ok(Token::Eof);
+        };
+
+        if byte.is_ascii_alphabetic() || byte == b'_' {
+            let start = self.position;
+            while self
+                .peek(0)
+                .is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+            {
+                self.advance();
+            }
+            let name = String::from_utf8_lossy(&self.source[start..self.position]).into_owned();
+            return Ok(match KEYWORDS.iter().find(|keyword| **keyword == name) {
+                Some(keyword) => Token::Symbol(keyword),
+                None => Token::Name(name),
+            });
+        }
+        if byte.is_ascii_digit()
+            || (byte == b'.' && self.peek(1).is_some_and(|b| b.is_ascii_digit()))
+        {
+            return self.number();
+        }
+        if byte == b'"' || byte == b'\'' {
+            return self.quoted_string(byte);
+        }
+        if byte == b'[' && self.long_bracket_level().is_some() {
+            return self.long_string().map(Token::String);
+        }
+
+        let rest = &self.source[self.position..];
+        match SYMBOLS
+            .iter()
+            .find(|symbol| rest.starts_with(symbol.as_bytes()))
+        {
+            Some(symbol) => {
+                self.position += symbol.len();
+                Ok(Token::Symbol(symbol))
+            }
+            None => self.error(format!("unexpected symbol near '{}'", byte as char)),
+        }
+    }
+
+    fn number(&mut self) -> Result<Token, (String, usize)> {
+        let start = self.position;
+        while self
+            .peek(0)
+            .is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'.')
+            || (matches!(self.peek(0), Some(b'+' | b'-'))
+                && matches!(self.source[self.position - 1], b'e' | b'E')
+                && !self.source[start..].starts_with(b"0x"))
+        {
+            self.advance();
+        }
+
+        let text = String::from_utf8_lossy(&self.source[start..self.position]);
+        match parse_number(&text) {
+            Some(value) => Ok(Token::Number(value)),
+            None => self.error(format!("malformed number near '{}'", text)),
+        }
+    }
+
+    fn quoted_string(&mut self, quote: u8) -> Result<Token, (String, usize)> {
+        self.advance();
+        let mut value = Vec::new();
+
+        loop {
+            // the error is on the line of the string, before the newline
+            let byte = match self.peek(0) {
+                None | Some(b'\n') => return self.error("unfinished string"),
+                Some(byte) => byte,
+            };
+            self.advance();
+            match byte {
+                byte if byte == quote => return Ok(Token::String(value)),
+                b'\\' => match self.advance() {
+                    Some(b'n') => value.push(b'\n'),
+                    Some(b't') => value.push(b'\t'),
+                    Some(b'r') => value.push(b'\r'),
+                    Some(b'a') => value.push(0x07),
+                    Some(b'b') => value.push(0x08),
+                    Some(b'f') => value.push(0x0c),
+                    Some(b'v') => value.push(0x0b),
+                    Some(digit) if digit.is_ascii_digit() => {
+                        let mut code = u32::from(digit - b'0');
+                        for _ in 0..2 {
+                            match self.peek(0) {
+                                Some(digit) if digit.is_ascii_digit() => {
+                                    code = code * 10 + u32::from(digit - b'0');
+                                    self.advance();
+                                }
+                                _ => break,
+                            }
+                        }
+                        match u8::try_from(code) {
+                            Ok(byte) => value.push(byte),
+                            Err(_) => return self.error("escape sequence too large"),
+                        }
+                    }
+                    Some(b'\n') => value.push(b'\n'),
+                    Some(other @ (b'\\' | b'"' | b'\'')) => value.push(other),
+                    _ => return self.error("invalid escape sequence"),
+                },
+                byte => value.push(byte),
+            }
+        }
+    }
+
+    /// The level of the long bracket starting at the current position, `[[` being level 0
+    /// and `[==[` level 2.
+    fn long_bracket_level(&self) -> Option<usize> {
+        if self.peek(0) != Some(b'[') {
+            return None;
+        }
+        let level = self.source[self.position + 1..]
+            .iter()
+            .take_while(|byte| **byte == b'=')
+            .count();
+        (self.peek(level + 1) == Some(b'[')).then_some(level)
+    }
+
+    /// Reads a `[[long string]]`, also used by block comments.
+    fn long_string(&mut self) -> Result<Vec<u8>, (String, usize)> {
+        let level = self.long_bracket_level().unwrap_or_default();
+        self.position += level + 2;
+        // a newline right after the opening bracket is skipped
+        if self.peek(0) == Some(b'\n') {
+            self.advance();
+        }
+
+        let mut closing = vec![b']'];
+        closing.extend(std::iter::repeat_n(b'=', level));
+        closing.push(b']');
+
+        let start = self.position;
+        while !self.source[self.position..].starts_with(&closing) {
+            if self.advance().is_none() {
+                return self.error("unfinished long string");
+            }
+        }
+        let value = self.source[start..self.position].to_vec();
+        self.position += closing.len();
+        Ok(value)
+    }
+}
+
+/// Parses a number as written in a script or converted by `tonumber`: decimal, with an
+/// optional fraction and exponent, or hexadecimal.
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text),
+    };
+
+    let value = match unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    {
+        Some(hexadecimal) => u64::from_str_radix(hexadecimal, 16).ok()? as f64,
+        None if unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.') => unsigned
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())?,
+        None => return None,
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_statements() {
+        assert_eq!(
+            tokens("local x = t.y .. 'a\\n' -- comment\nreturn #x ~= 0x10"),
+            vec![
+                Token::Symbol("local"),
+                Token::Name("x".to_string()),
+                Token::Symbol("="),
+                Token::Name("t".to_string()),
+                Token::Symbol("."),
+                Token::Name("y".to_string()),
+                Token::Symbol(".."),
+                Token::String(b"a\n".to_vec()),
+                Token::Symbol("return"),
+                Token::Symbol("#"),
+                Token::Name("x".to_string()),
+                Token::Symbol("~="),
+                Token::Number(16.0),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            tokens("--[[ long\ncomment ]] x = [==[\nsome ]] text]==] 1.5e3"),
+            vec![
+                Token::Name("x".to_string()),
+                Token::Symbol("="),
+                Token::String(b"some ]] text".to_vec()),
+                Token::Number(1500.0),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_and_errors() {
+        let tokens = tokenize(b"a\n\nb").unwrap();
+        assert_eq!(tokens[1], (Token::Name("b".to_string()), 3));
+        assert_eq!(
+            tokenize(b"x = 'open\n"),
+            Err(("unfinished string".to_string(), 1))
+        );
+        assert_eq!(
+            tokenize(b"\nx = 1 @ 2"),
+            Err(("unexpected symbol near '@'".to_string(), 2))
+        );
+        assert_eq!(parse_number(" -12.5 "), Some(-12.5));
+        assert_eq!(parse_number("0xff"), Some(255.0));
+        assert_eq!(parse_number("1e"), None);
+        assert_eq!(parse_number("abc"), None);
+    }
+}
@@ -0,0 +1,339 @@
+//! The subset of Lua 5.1 that scripts run by EVAL are written in: the whole syntax, with
+//! the base, string, table and math libraries and the `redis` one, but without metatables,
+//! coroutines or string patterns.
+
+use crate::redis::commands::format_float;
+use crate::redis::resp::Reply;
+use interpreter::{Interpreter, Table, Value};
+use std::time::{Duration, Instant};
+
+mod interpreter;
+mod lexer;
+mod parser;
+
+// https://www.lua.org/manual/5.1/manual.html
+
+/// How long a script can run before it is stopped.
+const TIME_LIMIT: Duration = Duration::from_secs(5);
+
+/// How deep the tables returned by a script can be nested.
+const MAX_REPLY_DEPTH: usize = 100;
+
+/// What runs the commands of `redis.call` and `redis.pcall`.
+pub trait Host {
+    fn call(&mut self, arguments: &[Vec<u8>]) -> Reply;
+}
+
+/// A parsed script, cached by its SHA1 and shared between the clients.
+pub struct Script {
+    main: parser::Block,
+}
+
+/// Parses a script, the error telling the line where it is invalid.
+pub fn compile(source: &[u8]) -> Result<Script, String> {
+    parser::parse(source)
+        .map(|main| Script { main })
+        .map_err(|(message, line)| format!("user_script:{}: {}", line, message))
+}
+
+impl Script {
+    /// Runs the script with the `KEYS` and `ARGV` globals, its result converted to a reply.
+    pub fn run(&self, keys: &[Vec<u8>], argv: &[Vec<u8>], host: &mut dyn Host) -> Reply {
+        self.run_until(keys, argv, host, Instant::now() + TIME_LIMIT)
+    }
+
+    fn run_until(
+        &self,
+        keys: &[Vec<u8>],
+        argv: &[Vec<u8>],
+        host: &mut dyn Host,
+        deadline: Instant,
+    ) -> Reply {
+        let sequence = |values: &[Vec<u8>]| {
+            Value::table(Table::sequence(
+                values.iter().map(|value| Value::string(value.as_slice())),
+            ))
+        };
+        let globals = vec![("KEYS", sequence(keys)), ("ARGV", sequence(argv))];
+        let mut interpreter = Interpreter::new(host, globals, deadline);
+
+        match interpreter.run(&self.main) {
+            Ok(value) => to_reply(value, 0),
+            Err(interpreter::Error::Timeout) => {
+                Reply::Error("ERR Script killed by timeout".to_string())
+            }
+            Err(interpreter::Error::Raised(value)) => match to_reply(value, 0) {
+                Reply::Error(message) => Reply::Error(message),
+                Reply::Bulk(message) => {
+                    Reply::Error(format!("ERR {}", String::from_utf8_lossy(&message)))
+                }
+                _ => Reply::Error("ERR Error running script".to_string()),
+            },
+        }
+    }
+}
+
+/// Converts the reply of a command to a value, as redis does for `redis.call`.
+pub fn from_reply(reply: Reply) -> Value {
+    let sequence =
+        |replies: Vec<Reply>| Value::table(Table::sequence(replies.into_iter().map(from_reply)));
+    let field = |name: &str, value: String| {
+        let mut table = Table::default();
+        table.set_field(name, Value::string(value));
+        Value::table(table)
+    };
+
+    match reply {
+        Reply::Simple(status) => field("ok", status),
+        Reply::Error(message) => field("err", message),
+        Reply::Integer(value) => Value::Number(value as f64),
+        Reply::Bulk(value) => Value::string(value),
+        Reply::Nil | Reply::NilArray => Value::Boolean(false),
+        Reply::Array(replies)
+        | Reply::Set(replies)
+        | Reply::Push(replies)
+        | Reply::Multiple(replies) => sequence(replies),
+        Reply::Map(pairs) => sequence(
+            pairs
+                .into_iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+        ),
+        Reply::Double(value) => Value::string(format_float(value)),
+        Reply::Boolean(value) => Value::Number(if value { 1.0 } else { 0.0 }),
+    }
+}
+
+/// Converts the result of a script to a reply: numbers are truncated to integers and
+/// tables are arrays of their values up to the first nil, unless they have an `err` or
+/// `ok` field.
+fn to_reply(value: Value, depth: usize) -> Reply {
+    match value {
+        Value::Nil | Value::Boolean(false) | Value::Function(_) => Reply::Nil,
+        Value::Boolean(true) => Reply::Integer(1),
+        Value::Number(value) => Reply::Integer(value as i64),
+        Value::String(value) => Reply::Bulk(value.to_vec()),
+        Value::Table(_) if depth == MAX_REPLY_DEPTH => {
+            Reply::Error("ERR reached lua stack limit".to_string())
+        }
+        Value::Table(table) => {
+            let table = table.borrow();
+            if let Some(message) = table.get_field("err").to_bytes() {
+                return Reply::Error(String::from_utf8_lossy(&message).into_owned());
+            }
+            if let Some(status) = table.get_field("ok").to_bytes() {
+                return Reply::Simple(String::from_utf8_lossy(&status).into_owned());
+            }
+            Reply::Array(
+                table
+                    .sequence_values()
+                    .map(|value| to_reply(value.clone(), depth + 1))
+                    .collect(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replies to every command with its arguments, or an error for `fail`.
+    struct Echo;
+
+    impl Host for Echo {
+        fn call(&mut self, arguments: &[Vec<u8>]) -> Reply {
+            match arguments[0].as_slice() {
+                b"fail" => Reply::Error("ERR failed".to_string()),
+                b"ok" => Reply::ok(),
+                _ => Reply::Array(arguments.iter().cloned().map(Reply::Bulk).collect()),
+            }
+        }
+    }
+
+    fn eval(source: &str, keys: &[&str], argv: &[&str]) -> Reply {
+        let owned = |values: &[&str]| {
+            values
+                .iter()
+                .map(|value| value.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+        match compile(source.as_bytes()) {
+            Ok(script) => script.run(&owned(keys), &owned(argv), &mut Echo),
+            Err(message) => Reply::Error(message),
+        }
+    }
+
+    fn bulk(value: &str) -> Reply {
+        Reply::Bulk(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(eval("return 3.7", &[], &[]), Reply::Integer(3));
+        assert_eq!(eval("return true", &[], &[]), Reply::Integer(1));
+        assert_eq!(eval("return false", &[], &[]), Reply::Nil);
+        assert_eq!(
+            eval("return {1, 'a', {KEYS[1]}, nil, 5}", &["k"], &[]),
+            Reply::Array(vec![
+                Reply::Integer(1),
+                bulk("a"),
+                Reply::Array(vec![bulk("k")]),
+            ])
+        );
+        assert_eq!(
+            eval("return redis.status_reply('FINE')", &[], &[]),
+            Reply::Simple("FINE".to_string())
+        );
+        assert_eq!(
+            eval("return {err='MY error'}", &[], &[]),
+            Reply::Error("MY error".to_string())
+        );
+        assert_eq!(eval("return redis.call('ok')['ok']", &[], &[]), bulk("OK"));
+    }
+
+    #[test]
+    fn redis_calls() {
+        assert_eq!(
+            eval(
+                "return redis.call('get', KEYS[1], ARGV[1], 10)",
+                &["k"],
+                &["v"]
+            ),
+            Reply::Array(vec![bulk("get"), bulk("k"), bulk("v"), bulk("10")])
+        );
+        assert_eq!(
+            eval("redis.call('fail') return 1", &[], &[]),
+            Reply::Error("ERR failed".to_string())
+        );
+        assert_eq!(
+            eval("return redis.pcall('fail')['err']", &[], &[]),
+            bulk("ERR failed")
+        );
+        assert_eq!(
+            eval("return redis.call('set', {})", &[], &[]),
+            Reply::Error(
+                "ERR user_script:1: Lua redis lib command arguments must be strings or integers"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn language() {
+        let script = "
+            local function fib(n)
+                if n < 2 then return n end
+                return fib(n - 1) + fib(n - 2)
+            end
+            local t = {}
+            for i = 1, 10 do t[#t + 1] = fib(i) end
+            local sum = 0
+            for _, v in ipairs(t) do sum = sum + v end
+            local words = {}
+            for k, v in pairs({a = 1}) do words[#words + 1] = k .. '=' .. v end
+            table.sort(t, function(a, b) return a > b end)
+            return {sum, t[1], table.concat(words, ','), string.format('%05.1f|%-3s|%x', 3.14159, 'a', 255)}
+        ";
+        assert_eq!(
+            eval(script, &[], &[]),
+            Reply::Array(vec![
+                Reply::Integer(143),
+                Reply::Integer(55),
+                bulk("a=1"),
+                bulk("003.1|a  |ff"),
+            ])
+        );
+        assert_eq!(
+            eval(
+                "local ok, e = pcall(error, 'boom') return {tostring(ok), e}",
+                &[],
+                &[]
+            ),
+            Reply::Array(vec![bulk("false"), bulk("user_script:1: boom")])
+        );
+        assert_eq!(
+            eval(
+                "return {string.sub('hello', -3), ('x'):rep(3), select('#', 1, 2, 3), tonumber('ff', 16)}",
+                &[],
+                &[]
+            ),
+            Reply::Array(vec![
+                bulk("llo"),
+                bulk("xxx"),
+                Reply::Integer(3),
+                Reply::Integer(255),
+            ])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            eval("return 1 +", &[], &[]),
+            Reply::Error("user_script:1: unexpected symbol near <eof>".to_string())
+        );
+        assert_eq!(
+            eval("x = 1", &[], &[]),
+            Reply::Error(
+                "ERR user_script:1: Script attempted to create global variable 'x'".to_string()
+            )
+        );
+        assert_eq!(
+            eval("return y", &[], &[]),
+            Reply::Error(
+                "ERR user_script:1: Script attempted to access nonexistent global variable 'y'"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            eval("local t = nil\nreturn t.x", &[], &[]),
+            Reply::Error("ERR user_script:2: attempt to index a nil value".to_string())
+        );
+        assert_eq!(
+            eval("local function f() return f() + 1 end return f()", &[], &[]),
+            Reply::Error("ERR user_script:1: stack overflow".to_string())
+        );
+        assert_eq!(
+            eval("redis.log(4, 'message')", &[], &[]),
+            Reply::Error("ERR user_script:1: Invalid debug level.".to_string())
+        );
+        assert_eq!(
+            eval(
+                "local ok, e = pcall(function() local function f() return f() + 1 end return f() end)
+                 local t = {}
+                 for i = 1, 100000 do t = {t, function() return t end} end
+                 return {tostring(ok), e}",
+                &[],
+                &[]
+            ),
+            Reply::Array(vec![bulk("false"), bulk("user_script:1: stack overflow")])
+        );
+        let mut reply = eval(
+            "local t = {} for i = 1, 200 do t = {t} end return t",
+            &[],
+            &[],
+        );
+        for _ in 0..MAX_REPLY_DEPTH {
+            match reply {
+                Reply::Array(mut replies) => reply = replies.remove(0),
+                _ => panic!("Not an array: {:?}", reply),
+            }
+        }
+        assert_eq!(
+            reply,
+            Reply::Error("ERR reached lua stack limit".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_loops_time_out() {
+        for source in ["while true do end", "repeat until false"] {
+            let script = compile(source.as_bytes()).unwrap();
+            assert_eq!(
+                script.run_until(&[], &[], &mut Echo, Instant::now()),
+                Reply::Error("ERR Script killed by timeout".to_string())
+            );
+        }
+    }
+}
@@ -0,0 +1,659 @@
+use super::lexer::{tokenize, Token};
+use std::sync::Arc;
+
+// https://www.lua.org/manual/5.1/manual.html#8
+
+/// How deep blocks and expressions can be nested, as in Lua, so that neither parsing nor
+/// running a script overflows the stack.
+const MAX_LEVELS: usize = 200;
+
+/// A sequence of statements, each with the line it starts on.
+#[derive(Debug, Default)]
+pub struct Block(pub Vec<(Statement, usize)>);
+
+#[derive(Debug)]
+pub enum Statement {
+    Local(Vec<String>, Vec<Expression>),
+    LocalFunction(String, Arc<Function>),
+    Assign(Vec<Expression>, Vec<Expression>),
+    Call(Expression),
+    Do(Block),
+    While(Expression, Block),
+    Repeat(Block, Expression),
+    /// The conditions with their blocks, then the `else` block.
+    If(Vec<(Expression, Block)>, Option<Block>),
+    NumericFor {
+        variable: String,
+        start: Expression,
+        limit: Expression,
+        step: Option<Expression>,
+        body: Block,
+    },
+    GenericFor {
+        variables: Vec<String>,
+        expressions: Vec<Expression>,
+        body: Block,
+    },
+    Return(Vec<Expression>),
+    Break,
+}
+
+#[derive(Debug)]
+pub enum Expression {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Vec<u8>),
+    Vararg,
+    Function(Arc<Function>),
+    Name(String),
+    Index(Box<Expression>, Box<Expression>),
+    Call(Box<Expression>, Vec<Expression>),
+    /// `object:method(arguments)`
+    Method(Box<Expression>, String, Vec<Expression>),
+    Table(Vec<Field>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    Unary(Operator, Box<Expression>),
+    /// An expression in parentheses, which keeps only the first value of a call.
+    Parenthesized(Box<Expression>),
+}
+
+#[derive(Debug)]
+pub enum Field {
+    Positional(Expression),
+    Keyed(Expression, Expression),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Or,
+    And,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    NotEqual,
+    Equal,
+    Concat,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+    Not,
+    Length,
+    Negate,
+}
+
+/// The priority of the unary operators, between the one of `*` and the one of `^`.
+const UNARY_PRIORITY: u8 = 8;
+
+impl Operator {
+    /// The binary operator of a symbol, with its left and right priorities.
+    fn binary(token: &Token) -> Option<(Operator, u8, u8)> {
+        let Token::Symbol(symbol) = token else {
+            return None;
+        };
+        Some(match *symbol {
+            "or" => (Operator::Or, 1, 1),
+            "and" => (Operator::And, 2, 2),
+            "<" => (Operator::Less, 3, 3),
+            ">" => (Operator::Greater, 3, 3),
+            "<=" => (Operator::LessOrEqual, 3, 3),
+            ">=" => (Operator::GreaterOrEqual, 3, 3),
+            "~=" => (Operator::NotEqual, 3, 3),
+            "==" => (Operator::Equal, 3, 3),
+            // right associative
+            ".." => (Operator::Concat, 5, 4),
+            "+" => (Operator::Add, 6, 6),
+            "-" => (Operator::Subtract, 6, 6),
+            "*" => (Operator::Multiply, 7, 7),
+            "/" => (Operator::Divide, 7, 7),
+            "%" => (Operator::Modulo, 7, 7),
+            "^" => (Operator::Power, 10, 9),
+            _ => return None,
+        })
+    }
+
+    fn unary(token: &Token) -> Option<Operator> {
+        match token {
+            Token::Symbol("not") => Some(Operator::Not),
+            Token::Symbol("#") => Some(Operator::Length),
+            Token::Symbol("-") => Some(Operator::Negate),
+            _ => None,
+        }
+    }
+}
+
+/// The parameters and body of a function, shared by the closures created from it.
+#[derive(Debug)]
+pub struct Function {
+    pub parameters: Vec<String>,
+    pub is_vararg: bool,
+    pub body: Block,
+}
+
+/// Parses a script into the body of its main function, or returns the error with the
+/// line it happened on.
+pub fn parse(source: &[u8]) -> Result<Block, (String, usize)> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+        levels: 0,
+    };
+    let block = parser.block()?;
+    match parser.peek() {
+        Token::Eof => Ok(block),
+        token => parser.error(format!("'<eof>' expected near {}", token)),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// How deep the syntax tree being parsed is.
+    levels: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.position].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+        // the last token is always Eof
+        if self.position + 1 < self.tokens.len() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, (String, usize)> {
+        Err((message.into(), self.line()))
+    }
+
+    /// Skips `symbol` if it is next.
+    fn accept(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Token::Symbol(next) if *next == symbol);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    /// Goes one level deeper in the syntax tree, the callers restoring `levels` once done.
+    fn enter_level(&mut self) -> Result<(), (String, usize)> {
+        self.levels += 1;
+        if self.levels > MAX_LEVELS {
+            return self.error("chunk has too many syntax levels");
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), (String, usize)> {
+        if self.accept(symbol) {
+            Ok(())
+        } else {
+            self.error(format!("'{}' expected near {}", symbol, self.peek()))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, (String, usize)> {
+        match self.peek() {
+            Token::Name(name) => {
+                let name = name.clone();
+                self.next();
+                Ok(name)
+            }
+            token => self.error(format!("<name> expected near {}", token)),
+        }
+    }
+
+    fn block(&mut self) -> Result<Block, (String, usize)> {
+        let levels = self.levels;
+        self.enter_level()?;
+        let mut statements = Vec::new();
+
+        loop {
+            while self.accept(";") {}
+            let line = self.line();
+            match self.peek() {
+                Token::Eof | Token::Symbol("end" | "else" | "elseif" | "until") => break,
+                Token::Symbol("return") => {
+                    self.next();
+                    let values = match self.peek() {
+                        Token::Eof | Token::Symbol("end" | "else" | "elseif" | "until" | ";") => {
+                            Vec::new()
+                        }
+                        _ => self.expression_list()?,
+                    };
+                    self.accept(";");
+                    statements.push((Statement::Return(values), line));
+                    break;
+                }
+                Token::Symbol("break") => {
+                    self.next();
+                    statements.push((Statement::Break, line));
+                    break;
+                }
+                _ => statements.push((self.statement()?, line)),
+            }
+        }
+
+        self.levels = levels;
+        Ok(Block(statements))
+    }
+
+    fn statement(&mut self) -> Result<Statement, (String, usize)> {
+        match self.peek() {
+            Token::Symbol("local") => {
+                self.next();
+                if self.accept("function") {
+                    let name = self.name()?;
+                    return Ok(Statement::LocalFunction(name, self.function_body(false)?));
+                }
+                let mut names = vec![self.name()?];
+                while self.accept(",") {
+                    names.push(self.name()?);
+                }
+                let values = if self.accept("=") {
+                    self.expression_list()?
+                } else {
+                    Vec::new()
+                };
+                Ok(Statement::Local(names, values))
+            }
+            Token::Symbol("function") => {
+                self.next();
+                let mut target = Expression::Name(self.name()?);
+                let mut is_method = false;
+                while matches!(self.peek(), Token::Symbol("." | ":")) && !is_method {
+                    is_method = self.next() == Token::Symbol(":");
+                    let key = Expression::String(self.name()?.into_bytes());
+                    target = Expression::Index(Box::new(target), Box::new(key));
+                }
+                Ok(Statement::Assign(
+                    vec![target],
+                    vec![Expression::Function(self.function_body(is_method)?)],
+                ))
+            }
+            Token::Symbol("do") => {
+                self.next();
+                let body = self.block()?;
+                self.expect("end")?;
+                Ok(Statement::Do(body))
+            }
+            Token::Symbol("while") => {
+                self.next();
+                let condition = self.expression()?;
+                self.expect("do")?;
+                let body = self.block()?;
+                self.expect("end")?;
+                Ok(Statement::While(condition, body))
+            }
+            Token::Symbol("repeat") => {
+                self.next();
+                let body = self.block()?;
+                self.expect("until")?;
+                Ok(Statement::Repeat(body, self.expression()?))
+            }
+            Token::Symbol("if") => {
+                self.next();
+                let mut branches = Vec::new();
+                let mut otherwise = None;
+                loop {
+                    let condition = self.expression()?;
+                    self.expect("then")?;
+                    branches.push((condition, self.block()?));
+                    if self.accept("elseif") {
+                        continue;
+                    }
+                    if self.accept("else") {
+                        otherwise = Some(self.block()?);
+                    }
+                    self.expect("end")?;
+                    return Ok(Statement::If(branches, otherwise));
+                }
+            }
+            Token::Symbol("for") => {
+                self.next();
+                let first = self.name()?;
+                if self.accept("=") {
+                    let start = self.expression()?;
+                    self.expect(",")?;
+                    let limit = self.expression()?;
+                    let step = if self.accept(",") {
+                        Some(self.expression()?)
+                    } else {
+                        None
+                    };
+                    self.expect("do")?;
+                    let body = self.block()?;
+                    self.expect("end")?;
+                    return Ok(Statement::NumericFor {
+                        variable: first,
+                        start,
+                        limit,
+                        step,
+                        body,
+                    });
+                }
+
+                let mut variables = vec![first];
+                while self.accept(",") {
+                    variables.push(self.name()?);
+                }
+                self.expect("in")?;
+                let expressions = self.expression_list()?;
+                self.expect("do")?;
+                let body = self.block()?;
+                self.expect("end")?;
+                Ok(Statement::GenericFor {
+                    variables,
+                    expressions,
+                    body,
+                })
+            }
+            _ => {
+                let expression = self.suffixed_expression()?;
+                if matches!(self.peek(), Token::Symbol("=" | ",")) {
+                    let mut targets = vec![expression];
+                    while self.accept(",") {
+                        targets.push(self.suffixed_expression()?);
+                    }
+                    if targets.iter().any(|target| {
+                        !matches!(target, Expression::Name(_) | Expression::Index(..))
+                    }) {
+                        return self.error("syntax error near '='");
+                    }
+                    self.expect("=")?;
+                    Ok(Statement::Assign(targets, self.expression_list()?))
+                } else if matches!(expression, Expression::Call(..) | Expression::Method(..)) {
+                    Ok(Statement::Call(expression))
+                } else {
+                    self.error(format!("syntax error near {}", self.peek()))
+                }
+            }
+        }
+    }
+
+    /// The parameters and body of a function, after its name. Methods get the object
+    /// they are called on as a first `self` parameter.
+    fn function_body(&mut self, is_method: bool) -> Result<Arc<Function>, (String, usize)> {
+        self.expect("(")?;
+        let mut parameters = Vec::new();
+        if is_method {
+            parameters.push("self".to_string());
+        }
+        let mut is_vararg = false;
+        if !self.accept(")") {
+            loop {
+                if self.accept("...") {
+                    is_vararg = true;
+                    break;
+                }
+                parameters.push(self.name()?);
+                if !self.accept(",") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+        }
+        let body = self.block()?;
+        self.expect("end")?;
+
+        Ok(Arc::new(Function {
+            parameters,
+            is_vararg,
+            body,
+        }))
+    }
+
+    fn expression_list(&mut self) -> Result<Vec<Expression>, (String, usize)> {
+        let mut expressions = vec![self.expression()?];
+        while self.accept(",") {
+            expressions.push(self.expression()?);
+        }
+        Ok(expressions)
+    }
+
+    fn expression(&mut self) -> Result<Expression, (String, usize)> {
+        self.binary_expression(0)
+    }
+
+    /// Parses operations whose operators have a left priority above `limit`.
+    fn binary_expression(&mut self, limit: u8) -> Result<Expression, (String, usize)> {
+        let levels = self.levels;
+        self.enter_level()?;
+        let mut left = match Operator::unary(self.peek()) {
+            Some(operator) => {
+                self.next();
+                let operand = self.binary_expression(UNARY_PRIORITY)?;
+                Expression::Unary(operator, Box::new(operand))
+            }
+            None => self.simple_expression()?,
+        };
+
+        while let Some((operator, left_priority, right_priority)) = Operator::binary(self.peek()) {
+            if left_priority <= limit {
+                break;
+            }
+            self.next();
+            // the operations on the left are nested too
+            self.enter_level()?;
+            let right = self.binary_expression(right_priority)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        self.levels = levels;
+        Ok(left)
+    }
+
+    fn simple_expression(&mut self) -> Result<Expression, (String, usize)> {
+        let expression = match self.peek() {
+            Token::Number(value) => Expression::Number(*value),
+            Token::String(value) => Expression::String(value.clone()),
+            Token::Symbol("nil") => Expression::Nil,
+            Token::Symbol("true") => Expression::Boolean(true),
+            Token::Symbol("false") => Expression::Boolean(false),
+            Token::Symbol("...") => Expression::Vararg,
+            Token::Symbol("{") => return self.table(),
+            Token::Symbol("function") => {
+                self.next();
+                return Ok(Expression::Function(self.function_body(false)?));
+            }
+            _ => return self.suffixed_expression(),
+        };
+        self.next();
+        Ok(expression)
+    }
+
+    /// A name or a parenthesized expression, followed by indexes and calls.
+    fn suffixed_expression(&mut self) -> Result<Expression, (String, usize)> {
+        let levels = self.levels;
+        let mut expression = match self.peek() {
+            Token::Name(_) => Expression::Name(self.name()?),
+            Token::Symbol("(") => {
+                self.next();
+                let inner = self.expression()?;
+                self.expect(")")?;
+                Expression::Parenthesized(Box::new(inner))
+            }
+            token => return self.error(format!("unexpected symbol near {}", token)),
+        };
+
+        loop {
+            if matches!(
+                self.peek(),
+                Token::Symbol("." | "[" | ":" | "(" | "{") | Token::String(_)
+            ) {
+                self.enter_level()?;
+            }
+            expression = match self.peek() {
+                Token::Symbol(".") => {
+                    self.next();
+                    let key = Expression::String(self.name()?.into_bytes());
+                    Expression::Index(Box::new(expression), Box::new(key))
+                }
+                Token::Symbol("[") => {
+                    self.next();
+                    let key = self.expression()?;
+                    self.expect("]")?;
+                    Expression::Index(Box::new(expression), Box::new(key))
+                }
+                Token::Symbol(":") => {
+                    self.next();
+                    let method = self.name()?;
+                    Expression::Method(Box::new(expression), method, self.call_arguments()?)
+                }
+                Token::Symbol("(" | "{") | Token::String(_) => {
+                    Expression::Call(Box::new(expression), self.call_arguments()?)
+                }
+                _ => {
+                    self.levels = levels;
+                    return Ok(expression);
+                }
+            };
+        }
+    }
+
+    /// `(arguments)`, a single table constructor or a single string.
+    fn call_arguments(&mut self) -> Result<Vec<Expression>, (String, usize)> {
+        match self.peek() {
+            Token::String(value) => {
+                let argument = Expression::String(value.clone());
+                self.next();
+                Ok(vec![argument])
+            }
+            Token::Symbol("{") => Ok(vec![self.table()?]),
+            Token::Symbol("(") => {
+                self.next();
+                if self.accept(")") {
+                    return Ok(Vec::new());
+                }
+                let arguments = self.expression_list()?;
+                self.expect(")")?;
+                Ok(arguments)
+            }
+            token => self.error(format!("function arguments expected near {}", token)),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expression, (String, usize)> {
+        self.expect("{")?;
+        let mut fields = Vec::new();
+
+        while !self.accept("}") {
+            let following = self.tokens.get(self.position + 1).map(|(token, _)| token);
+            let field = match (self.peek(), following) {
+                (Token::Symbol("["), _) => {
+                    self.next();
+                    let key = self.expression()?;
+                    self.expect("]")?;
+                    self.expect("=")?;
+                    Field::Keyed(key, self.expression()?)
+                }
+                (Token::Name(name), Some(Token::Symbol("="))) => {
+                    let key = Expression::String(name.clone().into_bytes());
+                    self.next();
+                    self.next();
+                    Field::Keyed(key, self.expression()?)
+                }
+                _ => Field::Positional(self.expression()?),
+            };
+            fields.push(field);
+
+            if !self.accept(",") && !self.accept(";") {
+                self.expect("}")?;
+                break;
+            }
+        }
+
+        Ok(Expression::Table(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operator_priorities() {
+        let block = parse(b"return 1 + 2 * 3 ^ 2 ^ 0.5 .. 'x' == y and not z").unwrap();
+        let [(Statement::Return(values), 1)] = block.0.as_slice() else {
+            panic!("expected a return");
+        };
+        assert_eq!(
+            format!("{:?}", values[0]),
+            "Binary(And, \
+             Binary(Equal, \
+             Binary(Concat, \
+             Binary(Add, Number(1.0), Binary(Multiply, Number(2.0), \
+             Binary(Power, Number(3.0), Binary(Power, Number(2.0), Number(0.5))))), \
+             String([120])), \
+             Name(\"y\")), \
+             Unary(Not, Name(\"z\")))"
+        );
+    }
+
+    #[test]
+    fn statements() {
+        let block = parse(
+            b"local a, b = 1\n\
+              function t.f:m(x, ...) return x end\n\
+              for i = 1, 10, 2 do a = a + i end\n\
+              for k, v in pairs({1, x = 2, [3] = 4}) do end\n\
+              if a then elseif b then else end\n\
+              redis.call('SET', KEYS[1], ARGV[1])",
+        )
+        .unwrap();
+        let lines = block.0.iter().map(|(_, line)| *line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 2, 3, 4, 5, 6]);
+        let (Statement::Assign(_, values), _) = &block.0[1] else {
+            panic!("expected a function definition");
+        };
+        let [Expression::Function(function)] = values.as_slice() else {
+            panic!("expected a function");
+        };
+        assert_eq!(function.parameters, vec!["self", "x"]);
+        assert!(function.is_vararg);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            parse(b"local = 1").unwrap_err(),
+            ("<name> expected near '='".to_string(), 1)
+        );
+        assert_eq!(
+            parse(b"if x then\nreturn 1").unwrap_err(),
+            ("'end' expected near <eof>".to_string(), 2)
+        );
+        assert_eq!(
+            parse(b"x").unwrap_err(),
+            ("syntax error near <eof>".to_string(), 1)
+        );
+        assert_eq!(
+            parse(b"return 1 2").unwrap_err(),
+            ("'<eof>' expected near '2'".to_string(), 1)
+        );
+        assert!(
+            parse(format!("return {}1{}", "(".repeat(150), ")".repeat(150)).as_bytes()).is_ok()
+        );
+        assert_eq!(
+            parse(format!("return {}1{}", "(".repeat(300), ")".repeat(300)).as_bytes())
+                .unwrap_err(),
+            ("chunk has too many syntax levels".to_string(), 1)
+        );
+        assert_eq!(
+            parse(format!("return 1{}", "+1".repeat(300)).as_bytes()).unwrap_err(),
+            ("chunk has too many syntax levels".to_string(), 1)
+        );
+    }
+}
@@ -1,310 +1,3319 @@
+use acl::Acl;
+use aof::{replay, AppendFsync, AppendOnlyFile};
+pub use benchmark::redis_benchmark_cli;
+use blocking::{deadline, parse_timeout, served_command, served_reply, timeout_reply, Blocking};
+use broker::PubSub;
 use build_your_own_macros::cli_options;
 use build_your_own_utils::my_own_error::MyOwnError;
-use build_your_own_utils::thread_pool::ScopedThreadPool;
+use client::Client;
+use clients::{is_valid_name, Clients, Registered};
+use commands::lists::End;
+use commands::pubsub::Target;
+use commands::table::Command;
+use commands::{
+    expire, hashes, is_subscriber_command, is_transaction_command, is_write_command, keys, lists,
+    parse_integer, pubsub, sets, sorted_sets, streams, strings, table, unix_millis,
+    with_absolute_expiry, TimeUnit,
+};
+use config::{file_options, Config};
+use database::{Database, Shards, DEFAULT_DATABASES};
+use eviction::{parse_memory, Policy};
+use keyspace::Keyspace;
+use lua::{compile, Host, Script};
+use persistence::{parse_save_rules, Persistence};
+use replication::Replication;
+use resp::{parse_command, Protocol, Reply, RespError};
+use server::{Listener, Server};
+use sha1::sha1_hex;
+use signals::{handle_termination_signals, termination_requested};
+use stats::Stats;
 use std::collections::HashMap;
-use std::str::FromStr;
-use std::sync::Mutex;
+use std::fs;
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, available_parallelism};
-use std::time::{Duration, Instant};
-use std::{
-    io::{Read, Write},
-    net::TcpListener,
-};
+use std::time::{Duration, Instant, SystemTime};
+
+mod acl;
+mod aof;
+mod benchmark;
+mod blocking;
+mod broker;
+mod client;
+mod clients;
+mod commands;
+mod config;
+mod database;
+mod eviction;
+mod glob;
+mod keyspace;
+mod lua;
+mod persistence;
+mod poller;
+mod random;
+mod rdb;
+mod replication;
+mod resp;
+mod server;
+mod sha1;
+mod sha256;
+mod signals;
+mod sorted_set;
+mod stats;
+mod stream;
+
 // https://codingchallenges.fyi/challenges/challenge-redis
 
-pub fn redis_cli(args: &[&str]) -> Result<(), MyOwnError> {
-    let redis_config = RedisConfig::from_args(args)?;
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", redis_config.port))?;
-    println!("Listening on port {}", redis_config.port);
+/// The version of redis the server is compatible with, reported by INFO and HELLO.
+const VERSION: &str = "7.2.0";
+
+/// How often expired keys are actively looked for and save rules are checked,
+/// redis does it 10 times per second.
+const CRON_PERIOD: Duration = Duration::from_millis(100);
+
+pub fn redis_cli(args: &[&str]) -> Result<(), MyOwnError> {
+    // the options of the config file come first, so that the command line overrides them
+    let config_file = match RedisConfig::from_args(args)?.config_file {
+        Some(path) => fs::read_to_string(path)?,
+        None => String::new(),
+    };
+    let options = file_options(&config_file)
+        .map_err(|e| MyOwnError::ActualError(e.into()))?
+        .into_iter()
+        .chain(args.iter().copied())
+        .collect::<Vec<_>>();
+    let redis_config = RedisConfig::from_args(&options)?;
+    let listeners = listen(&redis_config)?;
+
+    let redis = Redis::new(&redis_config, SystemTime::now())?;
+    if let Some((host, port)) = redis_config.replicaof.split_once(' ') {
+        let arguments = [b"REPLICAOF".to_vec(), host.into(), port.into()];
+        if let Reply::Error(e) = redis.replication.replicaof(&arguments) {
+            return Err(e.as_str().into());
+        }
+    }
+    let number_of_threads = available_parallelism()?.get();
+    println!("Using {} threads", number_of_threads);
+    let servers = (0..number_of_threads)
+        .map(|_| {
+            let listeners = listeners
+                .iter()
+                .map(Listener::try_clone)
+                .collect::<io::Result<Vec<_>>>()?;
+            Server::new(listeners)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    drop(listeners);
+    let stopped = AtomicBool::new(false);
+    handle_termination_signals()?;
+
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !stopped.load(Ordering::Acquire) {
+                thread::sleep(CRON_PERIOD);
+                if termination_requested() {
+                    println!("Received a termination signal, shutting down");
+                    let shards = redis.data.lock_all(0);
+                    if let Err(e) = redis.prepare_shutdown(&shards, None, SystemTime::now()) {
+                        eprintln!("Error trying to shut down the server: {}", e);
+                    }
+                }
+                redis.active_expire_cycle(&SystemTime::now());
+                redis.save_if_needed(&SystemTime::now());
+                redis.fsync_append_only_file(&SystemTime::now());
+                redis.unblock_timed_out(&SystemTime::now());
+                redis.close_idle_clients(&SystemTime::now());
+            }
+        });
+        scope.spawn(|| redis.replication.run(&redis, &stopped));
+
+        // every event loop accepts connections from the same listeners
+        let event_loops = servers
+            .into_iter()
+            .map(|mut server| {
+                let (redis, stopped) = (&redis, &stopped);
+                scope.spawn(move || {
+                    let result = server.run(redis, stopped);
+                    stopped.store(true, Ordering::Release);
+                    result
+                })
+            })
+            .collect::<Vec<_>>();
+
+        event_loops
+            .into_iter()
+            .try_for_each(|event_loop| event_loop.join().expect("Event loop panicked"))
+    });
+
+    if !redis_config.unixsocket.is_empty() {
+        let _ = fs::remove_file(redis_config.unixsocket);
+    }
+    result?;
+    println!("Redis is now ready to exit, bye bye...");
+    Ok(())
+}
+
+/// Binds the TCP port on every address of `--bind` and the unix socket of `--unixsocket`.
+fn listen(redis_config: &RedisConfig) -> Result<Vec<Listener>, MyOwnError> {
+    let mut listeners = Vec::new();
+    for address in redis_config.bind.split_whitespace() {
+        let listener = TcpListener::bind((address, redis_config.port))
+            .map_err(|e| format!("Could not bind {}:{}: {}", address, redis_config.port, e))?;
+        println!("Listening on {}", listener.local_addr()?);
+        listeners.push(Listener::Tcp(listener));
+    }
+
+    let path = redis_config.unixsocket;
+    if !path.is_empty() {
+        // left behind by a server that did not exit cleanly
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("Could not bind the unix socket {}: {}", path, e))?;
+        println!("Listening on unix socket {}", path);
+        listeners.push(Listener::Unix(listener));
+    }
+
+    if listeners.is_empty() {
+        return Err("Nothing to listen on, set --bind or --unixsocket".into());
+    }
+    Ok(listeners)
+}
+
+impl TimeProvider for SystemTime {
+    fn now(&self) -> SystemTime {
+        *self
+    }
+}
+
+cli_options! {
+    struct RedisConfig<'a> {
+        #[option()]
+        config_file: Option<&'a str>,
+        #[option(name = "-p", default = 6379)]
+        port: u16,
+        #[option(name = "--dir", default = ".")]
+        dir: &'a str,
+        #[option(name = "--dbfilename", default = "dump.rdb")]
+        dbfilename: &'a str,
+        #[option(name = "--save", default = "3600 1 300 100 60 10000")]
+        save: &'a str,
+        #[option(name = "--appendonly")]
+        appendonly: bool,
+        #[option(name = "--appendfilename", default = "appendonly.aof")]
+        appendfilename: &'a str,
+        #[option(name = "--appendfsync", default = "everysec")]
+        appendfsync: &'a str,
+        #[option(name = "--aof-load-truncated")]
+        aof_load_truncated: bool,
+        #[option(name = "--replicaof", default = "")]
+        replicaof: &'a str,
+        #[option(name = "--maxmemory", default = "0")]
+        maxmemory: &'a str,
+        #[option(name = "--maxmemory-policy", default = "noeviction")]
+        maxmemory_policy: &'a str,
+        #[option(name = "--databases", default = DEFAULT_DATABASES)]
+        databases: usize,
+        #[option(name = "--timeout", default = 0)]
+        timeout: u64,
+        #[option(name = "--requirepass", default = "")]
+        requirepass: &'a str,
+        #[option(name = "--bind", default = "127.0.0.1")]
+        bind: &'a str,
+        #[option(name = "--unixsocket", default = "")]
+        unixsocket: &'a str
+    }
+}
+
+struct Redis {
+    data: Database,
+    persistence: Arc<Persistence>,
+    aof: Option<Arc<AppendOnlyFile>>,
+    pubsub: PubSub,
+    replication: Replication,
+    blocking: Blocking,
+    clients: Clients,
+    acl: Acl,
+    /// Seconds without a command after which a client is disconnected, never when zero.
+    timeout: AtomicU64,
+    /// The memory the keys may use before being evicted, unlimited when zero.
+    maxmemory: AtomicUsize,
+    maxmemory_policy: Mutex<Policy>,
+    config: Mutex<Config>,
+    stats: Stats,
+    /// The scripts loaded by EVAL and SCRIPT LOAD, by their SHA1.
+    scripts: Mutex<HashMap<String, Arc<Script>>>,
+    started_at: SystemTime,
+    /// Set once SHUTDOWN or a termination signal prepared the server to exit, the event
+    /// loops then stop.
+    shutting_down: AtomicBool,
+}
+
+impl Redis {
+    #[cfg(test)]
+    fn default() -> Self {
+        Self {
+            data: Database::default(),
+            persistence: Arc::new(Persistence::new(
+                Path::new("dump.rdb").to_path_buf(),
+                Vec::new(),
+                SystemTime::now(),
+            )),
+            aof: None,
+            pubsub: PubSub::default(),
+            replication: Replication::new(0),
+            blocking: Blocking::default(),
+            clients: Clients::default(),
+            acl: Acl::new(""),
+            timeout: AtomicU64::new(0),
+            maxmemory: AtomicUsize::new(0),
+            maxmemory_policy: Mutex::new(Policy::NoEviction),
+            config: Mutex::new(Config::new(&RedisConfig::default(), None)),
+            stats: Stats::default(),
+            scripts: Mutex::new(HashMap::new()),
+            started_at: SystemTime::now(),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Creates the server loading the dataset from the append only file when it is enabled
+    /// and exists, otherwise from the snapshot configured by `--dir` and `--dbfilename`.
+    fn new(config: &RedisConfig, now: SystemTime) -> Result<Self, MyOwnError> {
+        let save_rules =
+            parse_save_rules(config.save).map_err(|e| MyOwnError::ActualError(e.into()))?;
+        let persistence = Persistence::new(
+            Path::new(config.dir).join(config.dbfilename),
+            save_rules,
+            now,
+        );
+        let appendfsync = config
+            .appendfsync
+            .parse::<AppendFsync>()
+            .map_err(|e| MyOwnError::ActualError(e.into()))?;
+        let aof_path = Path::new(config.dir).join(config.appendfilename);
+        if config.databases == 0 {
+            return Err("databases must be at least 1".into());
+        }
+
+        let mut redis = Self {
+            data: Database::new(config.databases),
+            persistence: Arc::new(persistence),
+            aof: None,
+            pubsub: PubSub::default(),
+            replication: Replication::new(config.port),
+            blocking: Blocking::default(),
+            clients: Clients::default(),
+            acl: Acl::new(config.requirepass),
+            timeout: AtomicU64::new(config.timeout),
+            maxmemory: AtomicUsize::new(
+                parse_memory(config.maxmemory).map_err(|e| MyOwnError::ActualError(e.into()))?,
+            ),
+            maxmemory_policy: Mutex::new(
+                config
+                    .maxmemory_policy
+                    .parse()
+                    .map_err(|e: String| MyOwnError::ActualError(e.into()))?,
+            ),
+            config: Mutex::new(Config::new(config, config.config_file.map(PathBuf::from))),
+            stats: Stats::default(),
+            scripts: Mutex::new(HashMap::new()),
+            started_at: now,
+            shutting_down: AtomicBool::new(false),
+        };
+
+        if config.appendonly && aof_path.exists() {
+            // the SELECT commands of the file change the database of the next ones
+            let mut client = Client::default();
+            let replayed = replay(&aof_path, config.aof_load_truncated, |arguments| {
+                table::normalize(arguments);
+                redis.run(&mut client, arguments, now);
+            })
+            .map_err(|e| MyOwnError::ActualError(Box::new(e)))?;
+            println!("DB loaded from append only file: {} commands", replayed);
+        } else {
+            let mut keyspaces = (0..config.databases)
+                .map(|_| Keyspace::default())
+                .collect::<Vec<_>>();
+            let loaded = redis
+                .persistence
+                .load(&mut keyspaces, now)
+                .map_err(|e| MyOwnError::ActualError(Box::new(e)))?;
+            redis.data.replace(keyspaces);
+            println!("DB loaded from disk: {} keys", loaded);
+        }
+
+        if config.appendonly {
+            let shards = redis.data.lock_all(0);
+            let aof = AppendOnlyFile::open(aof_path, appendfsync, shards.iter_all(now), now)?;
+            drop(shards);
+            redis.aof = Some(Arc::new(aof));
+        }
+
+        Ok(redis)
+    }
+
+    /// Deletes a sample of the expired keys, returning how many were deleted.
+    fn active_expire_cycle(&self, time_provider: &impl TimeProvider) -> usize {
+        self.data.active_expire_cycle(time_provider.now())
+    }
+
+    /// Starts a background save when one of the save rules is satisfied, unless another
+    /// background save or an append only file rewrite is running.
+    fn save_if_needed(&self, time_provider: &impl TimeProvider) {
+        let now = time_provider.now();
+        let rewriting = self
+            .aof
+            .as_ref()
+            .is_some_and(|aof| aof.rewrite_in_progress());
+        if !rewriting
+            && !self.persistence.background_save_in_progress()
+            && self.persistence.should_save(now)
+        {
+            self.background_save(&self.data.lock_all(0), now);
+        }
+    }
+
+    /// Copies the keyspace and writes it from another thread, so clients are only blocked
+    /// for the time of the copy.
+    fn background_save(&self, shards: &Shards, now: SystemTime) -> Reply {
+        if !self.persistence.start_background_save() {
+            return Reply::error("ERR Background save already in progress");
+        }
+
+        let snapshot = shards.snapshot(now);
+        let dirty = self.persistence.dirty();
+        let persistence = Arc::clone(&self.persistence);
+        thread::spawn(move || {
+            let entries = snapshot
+                .iter()
+                .map(|(db, key, entry)| (*db, key.as_slice(), entry));
+            if let Err(e) = persistence.save(entries, dirty, now) {
+                eprintln!("Background saving error: {}", e);
+            }
+            persistence.finish_background_save();
+        });
+
+        Reply::Simple("Background saving started".to_string())
+    }
+
+    fn save(&self, shards: &Shards, now: SystemTime) -> Reply {
+        if self.persistence.background_save_in_progress() {
+            return Reply::error("ERR Background save already in progress");
+        }
+
+        match self
+            .persistence
+            .save(shards.iter_all(now), self.persistence.dirty(), now)
+        {
+            Ok(()) => Reply::ok(),
+            Err(e) => Reply::error(format!("ERR {}", e)),
+        }
+    }
+
+    /// SHUTDOWN [SAVE|NOSAVE], nothing is replied when the server is going to exit and the
+    /// connection is closed.
+    fn shutdown(
+        &self,
+        client: &Client,
+        shards: &Shards,
+        arguments: &[Vec<u8>],
+        now: SystemTime,
+    ) -> Reply {
+        let save = match &arguments[1..] {
+            [] => None,
+            [modifier] if modifier.eq_ignore_ascii_case(b"SAVE") => Some(true),
+            [modifier] if modifier.eq_ignore_ascii_case(b"NOSAVE") => Some(false),
+            _ => return Reply::syntax_error(),
+        };
+
+        match self.prepare_shutdown(shards, save, now) {
+            Ok(()) => {
+                client.outbox.close();
+                Reply::none()
+            }
+            Err(e) => {
+                eprintln!("Error trying to shut down the server: {}", e);
+                Reply::error("ERR Errors trying to SHUTDOWN. Check logs.")
+            }
+        }
+    }
+
+    /// Saves the dataset when `save` asks for it, by default when save rules are configured,
+    /// and flushes the append only file to disk. The event loops stop unless it failed.
+    fn prepare_shutdown(
+        &self,
+        shards: &Shards,
+        save: Option<bool>,
+        now: SystemTime,
+    ) -> Result<(), String> {
+        // the final snapshot is written to the same file as the background one
+        while self.persistence.background_save_in_progress() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if save.unwrap_or_else(|| self.persistence.has_save_rules()) {
+            println!("Saving the final snapshot before exiting");
+            if let Reply::Error(e) = self.save(shards, now) {
+                return Err(e);
+            }
+        }
+        if let Some(aof) = &self.aof {
+            aof.fsync(now).map_err(|e| e.to_string())?;
+        }
+
+        self.shutting_down.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Acquire)
+    }
+
+    fn fsync_append_only_file(&self, time_provider: &impl TimeProvider) {
+        if let Some(aof) = &self.aof {
+            if let Err(e) = aof.fsync_if_needed(time_provider.now()) {
+                eprintln!("Error syncing the append only file: {}", e);
+            }
+        }
+    }
+
+    /// Copies the keyspace and rewrites the append only file from another thread, with the
+    /// fewest commands rebuilding the copy followed by the commands executed meanwhile.
+    fn rewrite_append_only_file(&self, shards: &Shards, now: SystemTime) -> Reply {
+        let Some(aof) = &self.aof else {
+            return Reply::error("ERR Append only file is disabled, start with --appendonly");
+        };
+        if !aof.start_rewrite() {
+            return Reply::error("ERR Background append only file rewriting already in progress");
+        }
+
+        let snapshot = shards.snapshot(now);
+        let aof = Arc::clone(aof);
+        thread::spawn(move || {
+            let entries = snapshot
+                .iter()
+                .map(|(db, key, entry)| (*db, key.as_slice(), entry));
+            if let Err(e) = aof.finish_rewrite(entries) {
+                eprintln!("Background append only file rewriting error: {}", e);
+            }
+        });
+
+        Reply::Simple("Background append only file rewriting started".to_string())
+    }
+
+    /// Executes every complete command found in `input`, in order, and returns how many
+    /// bytes were consumed. Trailing bytes of an incomplete command are left to the caller
+    /// to be sent again once more data has been read, and so are the commands following a
+    /// blocking one until the client is unblocked.
+    fn process(
+        &self,
+        client: &mut Client,
+        input: &[u8],
+        mut output: impl Write,
+        time_provider: &impl TimeProvider,
+    ) -> Result<usize, MyOwnError> {
+        let mut processed = 0;
+
+        while !client.is_blocked() {
+            let Some((mut arguments, used)) = (match parse_command(&input[processed..]) {
+                Ok(command) => command,
+                Err(e) => {
+                    let mut response = Vec::new();
+                    Reply::error(format!("ERR {}", e)).encode(client.protocol(), &mut response);
+                    output.write_all(&response)?;
+                    return Err(e.into());
+                }
+            }) else {
+                break;
+            };
+            processed += used;
+
+            if arguments.is_empty() {
+                continue;
+            }
+            table::normalize(&mut arguments);
+
+            let reply = self.execute(client, &arguments, time_provider);
+            if matches!(reply, Reply::Error(_)) {
+                self.stats.error_replied();
+            }
+            client.report(&arguments[0], time_provider.now());
+            let mut response = Vec::new();
+            reply.encode(client.protocol(), &mut response);
+            output.write_all(&response)?;
+        }
+
+        Ok(processed)
+    }
+
+    /// Executes a command sent by a client, or queues it when the client is in a
+    /// transaction.
+    fn execute(
+        &self,
+        client: &mut Client,
+        arguments: &[Vec<u8>],
+        time_provider: &impl TimeProvider,
+    ) -> Reply {
+        let now = time_provider.now();
+        let command = match table::check(arguments) {
+            Ok(command) => command,
+            Err(reply) => {
+                if let Some(command) = table::lookup(&arguments[0]) {
+                    self.stats.rejected(command);
+                }
+                client.transaction_failed |= client.transaction.is_some();
+                return reply;
+            }
+        };
+        if let Err(reply) = self.admit(client, command, arguments, now) {
+            self.stats.rejected(command);
+            client.transaction_failed |= client.transaction.is_some();
+            return reply;
+        }
+
+        if let Some(queued) = &mut client.transaction {
+            if !is_transaction_command(&arguments[0]) {
+                queued.push(arguments.to_vec());
+                return Reply::Simple("QUEUED".to_string());
+            }
+        }
+
+        let started = Instant::now();
+        let reply = match arguments[0].as_slice() {
+            b"MULTI" if client.transaction.is_some() => {
+                Reply::error("ERR MULTI calls can not be nested")
+            }
+            b"MULTI" => {
+                client.transaction = Some(Vec::new());
+                client.transaction_failed = false;
+                Reply::ok()
+            }
+            b"EXEC" => self.exec(client, now),
+            b"DISCARD" => match client.transaction.take() {
+                Some(_) => {
+                    client.transaction_failed = false;
+                    self.unwatch(client);
+                    Reply::ok()
+                }
+                None => Reply::error("ERR DISCARD without MULTI"),
+            },
+            b"WATCH" => self.watch(client, arguments, now),
+            b"UNWATCH" => {
+                self.unwatch(client);
+                Reply::ok()
+            }
+            b"BLPOP" | b"BRPOP" | b"BLMOVE" => {
+                let mut shards = self.lock(client.db, arguments);
+                self.blocking_pop(client, &mut shards, arguments, now, true)
+            }
+            b"XREAD" | b"XREADGROUP" => {
+                let mut shards = self.lock(client.db, arguments);
+                self.blocking_read(client, &mut shards, arguments, now)
+            }
+            _ => {
+                let mut shards = self.lock(client.db, arguments);
+                self.execute_locked(client, &mut shards, arguments, now)
+            }
+        };
+        self.stats
+            .called(command, started.elapsed(), matches!(reply, Reply::Error(_)));
+
+        self.serve_blocked(now);
+        reply
+    }
+
+    /// Refuses a command the server or the client cannot execute in its current state.
+    fn admit(
+        &self,
+        client: &Client,
+        command: &Command,
+        arguments: &[Vec<u8>],
+        now: SystemTime,
+    ) -> Result<(), Reply> {
+        if !client.authenticated && !command.has(table::NO_AUTH) {
+            return Err(Reply::error("NOAUTH Authentication required."));
+        }
+        self.acl.check(&client.user, command, arguments)?;
+        if !self.free_memory(now) && command.has(table::DENYOOM) {
+            return Err(Reply::error(
+                "OOM command not allowed when used memory > 'maxmemory'.",
+            ));
+        }
+        // RESP3 tells the messages from the replies, subscribers can send any command
+        if client.is_subscribed()
+            && client.protocol() == Protocol::Resp2
+            && !is_subscriber_command(&arguments[0])
+        {
+            return Err(Reply::error(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                String::from_utf8_lossy(&arguments[0]).to_lowercase()
+            )));
+        }
+        if is_write_command(&arguments[0]) && self.replication.is_replica() {
+            return Err(Reply::error(
+                "READONLY You can't write against a read only replica.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Executes a command on the shards locked for it and, when it changed the dataset,
+    /// counts the change for the save rules, logs it to the append only file and sends it to
+    /// the replicas. The shards stay locked while logging, so commands on the same key are
+    /// logged in the order they are executed.
+    fn execute_locked(
+        &self,
+        client: &mut Client,
+        shards: &mut Shards,
+        arguments: &[Vec<u8>],
+        now: SystemTime,
+    ) -> Reply {
+        let reply = self.dispatch(client, shards, arguments, now);
+        if is_write_command(&arguments[0]) {
+            update_sizes(shards, arguments);
+        }
+
+        if is_write_command(&arguments[0]) && !matches!(reply, Reply::Error(_)) {
+            self.persistence.add_changes(1);
+            // replayed later, relative expire times must not start again from then and
+            // stream IDs must not be generated again
+            let logged = streams::with_added_id(arguments, &reply);
+            self.log(shards.db(), &with_absolute_expiry(&logged, now));
+
+            if self.blocking.has_blocked() {
+                for key in command_keys(arguments).into_iter().flatten() {
+                    self.blocking.signal(shards.db(), key);
+                }
+            }
+        }
+
+        reply
+    }
+
+    /// Logs a command that changed the logical database `db` to the append only file and
+    /// sends it to the replicas.
+    fn log(&self, db: usize, command: &[Vec<u8>]) {
+        if let Some(aof) = &self.aof {
+            if let Err(e) = aof.append(db, command) {
+                eprintln!("Error writing to the append only file: {}", e);
+            }
+        }
+        self.replication.propagate(db, command);
+    }
+
+    /// Evicts keys while more memory than `--maxmemory` is used, logging their deletion.
+    /// Returns false when the memory is still over the limit.
+    fn free_memory(&self, now: SystemTime) -> bool {
+        let maxmemory = self.maxmemory.load(Ordering::Relaxed);
+        if maxmemory == 0 || self.replication.is_replica() {
+            return true;
+        }
+
+        let policy = *self.maxmemory_policy.lock().unwrap();
+        self.data.evict(maxmemory, policy, now, |db, key| {
+            self.stats.evicted();
+            self.persistence.add_changes(1);
+            self.log(db, &[b"DEL".to_vec(), key.to_vec()]);
+        })
+    }
+
+    /// Executes a command without logging it.
+    fn run(&self, client: &mut Client, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+        if let Err(reply) = table::check(arguments) {
+            return reply;
+        }
+        let mut shards = self.lock(client.db, arguments);
+        let reply = self.dispatch(client, &mut shards, arguments, now);
+        if is_write_command(&arguments[0]) {
+            update_sizes(&mut shards, arguments);
+        }
+        reply
+    }
+
+    /// BLPOP/BRPOP key [key ...] timeout and BLMOVE source destination LEFT|RIGHT LEFT|RIGHT
+    /// timeout: pops from the first list with elements, otherwise blocks the client until
+    /// one gets elements or the timeout expires. Inside a transaction the client is never
+    /// blocked, `can_block` is false.
+    fn blocking_pop(
+        &self,
+        client: &mut Client,
+        shards: &mut Shards,
+        arguments: &[Vec<u8>],
+        now: SystemTime,
+        can_block: bool,
+    ) -> Reply {
+        let blocking_move = arguments[0] == b"BLMOVE";
+        if arguments.len() < 3 || (blocking_move && arguments.len() != 6) {
+            return Reply::wrong_number_of_arguments(&arguments[0]);
+        }
+        let deadline = match parse_timeout(&arguments[arguments.len() - 1])
+            .and_then(|timeout| deadline(timeout, now))
+        {
+            Ok(deadline) => deadline,
+            Err(reply) => return reply,
+        };
+
+        let keys = if blocking_move {
+            &arguments[1..2]
+        } else {
+            &arguments[1..arguments.len() - 1]
+        };
+        for key in keys {
+            match shards.keyspace(key).get_typed::<lists::List>(key, now) {
+                Ok(Some(_)) => {
+                    // executed and logged as the non blocking command
+                    let command = served_command(arguments, key);
+                    let reply = self.execute_locked(client, shards, &command, now);
+                    return served_reply(arguments, key, reply);
+                }
+                Ok(None) => {}
+                Err(_) => return Reply::wrong_type(),
+            }
+        }
+
+        if !can_block {
+            return timeout_reply(arguments);
+        }
+        self.blocking.block(client, arguments, keys, deadline);
+        Reply::none()
+    }
+
+    /// XREAD and XREADGROUP with the BLOCK option: reads the streams right away, otherwise
+    /// blocks the client until one of them gets entries to read or the timeout expires.
+    fn blocking_read(
+        &self,
+        client: &mut Client,
+        shards: &mut Shards,
+        arguments: &[Vec<u8>],
+        now: SystemTime,
+    ) -> Reply {
+        let read = match streams::parse_read(arguments) {
+            Ok(read) => read,
+            Err(reply) => return reply,
+        };
+        let Some(timeout) = read.block else {
+            return self.execute_locked(client, shards, arguments, now);
+        };
+        let deadline = match deadline(timeout, now) {
+            Ok(deadline) => deadline,
+            Err(reply) => return reply,
+        };
+
+        // executed and logged without BLOCK, and waited for once blocked
+        let command = streams::non_blocking_read(shards, &read, now);
+        let reply = self.execute_locked(client, shards, &command, now);
+        if reply != Reply::NilArray {
+            return reply;
+        }
+        self.blocking.block(client, &command, read.keys, deadline);
+        Reply::none()
+    }
+
+    /// Serves the clients blocked on the keys that changed, the one waiting the longest
+    /// first, as long as the lists have elements or the streams have entries they did not
+    /// read.
+    fn serve_blocked(&self, now: SystemTime) {
+        while let Some((db, key)) = self.blocking.next_ready() {
+            for blocked in self.blocking.waiting(db, &key) {
+                let command = served_command(blocked.arguments(), &key);
+                let mut shards = self.lock(db, &command);
+                let keyspace = shards.keyspace(&key);
+                match command[0].as_slice() {
+                    // each client reads from its own ID, the next one may be served
+                    b"XREAD" | b"XREADGROUP" => {
+                        if !streams::can_serve(keyspace, &command, now) {
+                            continue;
+                        }
+                    }
+                    _ => {
+                        if !matches!(keyspace.get_typed::<lists::List>(&key, now), Ok(Some(_))) {
+                            break;
+                        }
+                    }
+                }
+                // timed out or disconnected in the meantime
+                if !self.blocking.unblock(&blocked) {
+                    continue;
+                }
+
+                let reply = self.execute_locked(&mut Client::default(), &mut shards, &command, now);
+                blocked.finish(served_reply(blocked.arguments(), &key, reply));
+            }
+        }
+    }
+
+    /// Replies to the blocked clients whose timeout expired.
+    fn unblock_timed_out(&self, time_provider: &impl TimeProvider) {
+        for blocked in self.blocking.timed_out(time_provider.now()) {
+            blocked.finish(timeout_reply(blocked.arguments()));
+        }
+    }
+
+    /// Disconnects the clients that sent no command for `--timeout` seconds, except the
+    /// ones waiting for messages: subscribers, blocked clients and replicas.
+    fn close_idle_clients(&self, time_provider: &impl TimeProvider) {
+        let timeout = self.timeout.load(Ordering::Relaxed);
+        if timeout == 0 {
+            return;
+        }
+
+        let now = time_provider.now();
+        for registered in self.clients.all() {
+            let state = registered.state();
+            let idle = now
+                .duration_since(state.last_interaction)
+                .unwrap_or_default();
+            if idle.as_secs() >= timeout
+                && state.subscriptions + state.patterns == 0
+                && !self.blocking.is_blocked(registered.id)
+                && !self.replication.serves(registered.id)
+            {
+                registered.kill();
+            }
+        }
+    }
+
+    /// INFO [section ...], the default sections when none is given. `all` and `everything`
+    /// add the commandstats section.
+    fn info(&self, shards: &Shards, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+        let sections = arguments[1..]
+            .iter()
+            .map(|section| section.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let included = |name: &str| {
+            if sections.is_empty() {
+                return name != "commandstats";
+            }
+            sections.iter().any(|section| {
+                matches!(section.as_slice(), b"all" | b"everything")
+                    || section == name.as_bytes()
+                    || (section == b"default" && name != "commandstats")
+            })
+        };
+
+        let mut info = Vec::new();
+        if included("server") {
+            let config = self.config.lock().unwrap();
+            let uptime = now
+                .duration_since(self.started_at)
+                .unwrap_or_default()
+                .as_secs();
+            info.push(format!(
+                "# Server\r\n\
+                 redis_version:{}\r\n\
+                 redis_mode:standalone\r\n\
+                 os:{} {}\r\n\
+                 process_id:{}\r\n\
+                 tcp_port:{}\r\n\
+                 uptime_in_seconds:{}\r\n\
+                 uptime_in_days:{}\r\n\
+                 config_file:{}\r\n",
+                VERSION,
+                std::env::consts::OS,
+                std::env::consts::ARCH,
+                std::process::id(),
+                config.get(b"port")[0].1,
+                uptime,
+                uptime / (24 * 60 * 60),
+                config
+                    .file()
+                    .map_or(String::new(), |file| file.display().to_string()),
+            ));
+        }
+        if included("clients") {
+            info.push(format!(
+                "# Clients\r\nconnected_clients:{}\r\nblocked_clients:{}\r\n",
+                self.stats.connected_clients(),
+                self.blocking.blocked_clients()
+            ));
+        }
+        if included("memory") {
+            let used_memory = self.data.used_memory();
+            let maxmemory = self.maxmemory.load(Ordering::Relaxed);
+            info.push(format!(
+                "# Memory\r\n\
+                 used_memory:{}\r\n\
+                 used_memory_human:{}\r\n\
+                 maxmemory:{}\r\n\
+                 maxmemory_human:{}\r\n\
+                 maxmemory_policy:{}\r\n",
+                used_memory,
+                human_bytes(used_memory),
+                maxmemory,
+                human_bytes(maxmemory),
+                self.maxmemory_policy.lock().unwrap()
+            ));
+        }
+        if included("persistence") {
+            let status = |succeeded| if succeeded { "ok" } else { "err" };
+            info.push(format!(
+                "# Persistence\r\n\
+                 loading:0\r\n\
+                 rdb_changes_since_last_save:{}\r\n\
+                 rdb_bgsave_in_progress:{}\r\n\
+                 rdb_last_save_time:{}\r\n\
+                 rdb_last_bgsave_status:{}\r\n\
+                 aof_enabled:{}\r\n\
+                 aof_rewrite_in_progress:{}\r\n",
+                self.persistence.dirty(),
+                self.persistence.background_save_in_progress() as u8,
+                unix_millis(self.persistence.last_save()) / 1000,
+                status(self.persistence.last_save_succeeded()),
+                self.aof.is_some() as u8,
+                self.aof
+                    .as_ref()
+                    .is_some_and(|aof| aof.rewrite_in_progress()) as u8,
+            ));
+        }
+        if included("stats") {
+            info.push(self.stats.info(self.data.expired_keys()));
+        }
+        if included("replication") {
+            info.push(self.replication.info());
+        }
+        if included("commandstats") {
+            info.push(self.stats.command_info());
+        }
+        if included("keyspace") {
+            let mut keyspace = String::from("# Keyspace\r\n");
+            for db in 0..self.data.databases() {
+                let (keys, expires) = shards.counts(db);
+                if keys > 0 {
+                    keyspace.push_str(&format!("db{}:keys={},expires={}\r\n", db, keys, expires));
+                }
+            }
+            info.push(keyspace);
+        }
+
+        Reply::bulk(info.join("\r\n"))
+    }
+
+    /// CONFIG GET parameter [parameter ...], CONFIG SET parameter value [parameter value ...],
+    /// CONFIG REWRITE and CONFIG RESETSTAT.
+    fn config(&self, arguments: &[Vec<u8>]) -> Reply {
+        let subcommand = arguments[1].to_ascii_uppercase();
+        let wrong_number_of_arguments = || {
+            Reply::error(format!(
+                "ERR wrong number of arguments for 'config|{}' command",
+                String::from_utf8_lossy(&subcommand).to_lowercase()
+            ))
+        };
+
+        match subcommand.as_slice() {
+            b"GET" if arguments.len() >= 3 => {
+                let config = self.config.lock().unwrap();
+                let mut found = Vec::new();
+                for pattern in &arguments[2..] {
+                    for (name, value) in config.get(pattern) {
+                        if !found.iter().any(|(found, _)| *found == name) {
+                            found.push((name, value));
+                        }
+                    }
+                }
+                Reply::Map(
+                    found
+                        .into_iter()
+                        .map(|(name, value)| (Reply::bulk(name), Reply::bulk(value)))
+                        .collect(),
+                )
+            }
+            b"SET" if arguments.len() >= 4 && arguments.len().is_multiple_of(2) => {
+                for pair in arguments[2..].chunks(2) {
+                    if let Err(e) = self.set_config(&pair[0], &pair[1]) {
+                        return Reply::error(format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                            String::from_utf8_lossy(&pair[0]),
+                            e
+                        ));
+                    }
+                }
+                Reply::ok()
+            }
+            b"REWRITE" if arguments.len() == 2 => match self.config.lock().unwrap().rewrite() {
+                Ok(()) => Reply::ok(),
+                Err(e) => Reply::error(format!("ERR {}", e)),
+            },
+            b"RESETSTAT" if arguments.len() == 2 => {
+                self.stats.reset();
+                self.data.reset_expired_keys();
+                Reply::ok()
+            }
+            b"GET" | b"SET" | b"REWRITE" | b"RESETSTAT" => wrong_number_of_arguments(),
+            _ => Reply::error(format!(
+                "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+                String::from_utf8_lossy(&arguments[1])
+            )),
+        }
+    }
+
+    /// Applies a new value of a parameter and records it for CONFIG GET.
+    fn set_config(&self, name: &[u8], value: &[u8]) -> Result<(), String> {
+        let mut config = self.config.lock().unwrap();
+        let name = config.mutable_name(name)?;
+        let value = std::str::from_utf8(value).map_err(|_| "argument must be a string")?;
+
+        let value = match name {
+            "maxmemory" => {
+                self.maxmemory
+                    .store(parse_memory(value)?, Ordering::Relaxed);
+                value.to_string()
+            }
+            "maxmemory-policy" => {
+                let policy = value.parse::<Policy>()?;
+                *self.maxmemory_policy.lock().unwrap() = policy;
+                policy.to_string()
+            }
+            "save" => {
+                self.persistence.set_save_rules(parse_save_rules(value)?);
+                value.to_string()
+            }
+            "timeout" => {
+                let timeout = value
+                    .parse::<u64>()
+                    .map_err(|_| "argument couldn't be parsed into an integer")?;
+                self.timeout.store(timeout, Ordering::Relaxed);
+                timeout.to_string()
+            }
+            "requirepass" => {
+                self.acl.set_requirepass(value);
+                value.to_string()
+            }
+            _ => unreachable!("every mutable parameter is applied"),
+        };
+        config.set(name, value);
+        Ok(())
+    }
+
+    /// Executes the commands queued since MULTI, all of them with the shards they access
+    /// locked, unless one of the watched keys changed since it was watched.
+    fn exec(&self, client: &mut Client, now: SystemTime) -> Reply {
+        let Some(queued) = client.transaction.take() else {
+            return Reply::error("ERR EXEC without MULTI");
+        };
+        if std::mem::take(&mut client.transaction_failed) {
+            self.unwatch(client);
+            return Reply::error("EXECABORT Transaction discarded because of previous errors.");
+        }
+        let watched = std::mem::take(&mut client.watched);
+
+        // the queued commands access the database selected by the SELECT before them
+        let mut keys = watched
+            .iter()
+            .map(|(db, key, _)| (*db, key.as_slice()))
+            .collect::<Vec<_>>();
+        let mut db = client.db;
+        let mut accesses_all = false;
+        for arguments in &queued {
+            if arguments[0] == b"SELECT" {
+                db = self.database_index(&arguments[1]).unwrap_or(db);
+            }
+            match command_keys(arguments) {
+                Some(command_keys) => keys.extend(command_keys.into_iter().map(|key| (db, key))),
+                None => accesses_all = true,
+            }
+        }
+        let mut shards = if accesses_all {
+            self.data.lock_all(client.db)
+        } else {
+            self.data.lock_in(client.db, keys)
+        };
+
+        let mut changed = false;
+        for (db, key, version) in &watched {
+            shards.select(*db);
+            let keyspace = shards.keyspace(key);
+            changed |= keyspace.version(key) != Some(*version);
+            keyspace.unwatch(key);
+        }
+        shards.select(client.db);
+        if changed {
+            return Reply::NilArray;
+        }
+
+        Reply::Array(
+            queued
+                .iter()
+                .map(|arguments| {
+                    let started = Instant::now();
+                    let reply = self.execute_locked(client, &mut shards, arguments, now);
+                    if let Some(command) = table::lookup(&arguments[0]) {
+                        self.stats.called(
+                            command,
+                            started.elapsed(),
+                            matches!(reply, Reply::Error(_)),
+                        );
+                    }
+                    reply
+                })
+                .collect(),
+        )
+    }
+
+    /// WATCH key [key ...]
+    fn watch(&self, client: &mut Client, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+        if client.transaction.is_some() {
+            return Reply::error("ERR WATCH inside MULTI is not allowed");
+        }
+        if arguments.len() < 2 {
+            return Reply::wrong_number_of_arguments(&arguments[0]);
+        }
+
+        let db = client.db;
+        let mut shards = self.data.lock(db, arguments[1..].iter().map(Vec::as_slice));
+        for key in &arguments[1..] {
+            if !client
+                .watched
+                .iter()
+                .any(|(watched_db, watched, _)| *watched_db == db && watched == key)
+            {
+                let version = shards.keyspace(key).watch(key, now);
+                client.watched.push((db, key.clone(), version));
+            }
+        }
+
+        Reply::ok()
+    }
+
+    /// Stops watching the keys of a client.
+    fn unwatch(&self, client: &mut Client) {
+        let watched = std::mem::take(&mut client.watched);
+        let mut shards = self.data.lock_in(
+            client.db,
+            watched.iter().map(|(db, key, _)| (*db, key.as_slice())),
+        );
+        for (db, key, _) in &watched {
+            shards.select(*db);
+            shards.keyspace(key).unwatch(key);
+        }
+    }
+
+    /// Forgets the state of a client that disconnected.
+    fn disconnect(&self, client: &mut Client) {
+        self.clients.unregister(client.id);
+        self.pubsub.disconnect(client);
+        self.replication.disconnect(client);
+        self.blocking.disconnect(client);
+        self.unwatch(client);
+    }
+
+    /// Locks the shards a command accesses in the logical database `db`.
+    fn lock(&self, db: usize, arguments: &[Vec<u8>]) -> Shards<'_> {
+        match command_keys(arguments) {
+            Some(keys) => self.data.lock(db, keys),
+            None => self.data.lock_all(db),
+        }
+    }
+
+    /// Parses the index of a logical database, as given to SELECT.
+    fn database_index(&self, argument: &[u8]) -> Result<usize, Reply> {
+        match parse_integer(argument) {
+            Some(index) if (0..self.data.databases() as i64).contains(&index) => Ok(index as usize),
+            Some(_) => Err(Reply::error("ERR DB index is out of range")),
+            None => Err(Reply::not_an_integer()),
+        }
+    }
+
+    /// SELECT index, the logical database of the next commands of the client.
+    fn select(&self, client: &mut Client, shards: &mut Shards, arguments: &[Vec<u8>]) -> Reply {
+        match self.database_index(&arguments[1]) {
+            Ok(db) => {
+                client.db = db;
+                shards.select(db);
+                Reply::ok()
+            }
+            Err(reply) => reply,
+        }
+    }
+
+    /// HELLO [protover [AUTH username password] [SETNAME clientname]], switching the
+    /// protocol of the client and describing the server.
+    fn hello(&self, client: &mut Client, arguments: &[Vec<u8>]) -> Reply {
+        let protocol = match arguments.get(1).map(|version| parse_integer(version)) {
+            None => client.protocol(),
+            Some(Some(2)) => Protocol::Resp2,
+            Some(Some(3)) => Protocol::Resp3,
+            Some(Some(_)) => return Reply::error("NOPROTO unsupported protocol version"),
+            Some(None) => {
+                return Reply::error("ERR Protocol version is not an integer or out of range")
+            }
+        };
+
+        let mut name = None;
+        let mut options = arguments.iter().skip(2);
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"AUTH" => {
+                    let (Some(username), Some(password)) = (options.next(), options.next()) else {
+                        return Reply::syntax_error();
+                    };
+                    if let Err(reply) = self.acl.authenticate(username, password) {
+                        return reply;
+                    }
+                    client.user = username.clone();
+                    client.authenticated = true;
+                }
+                b"SETNAME" => match options.next() {
+                    Some(clientname) if is_valid_name(clientname) => name = Some(clientname),
+                    Some(_) => return Reply::error(
+                        "ERR Client names cannot contain spaces, newlines or special characters.",
+                    ),
+                    None => return Reply::syntax_error(),
+                },
+                _ => return Reply::syntax_error(),
+            }
+        }
+
+        if !client.authenticated {
+            return Reply::error("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time");
+        }
+        if let Some(name) = name {
+            client.name = Some(name.clone()).filter(|name| !name.is_empty());
+        }
+        client.outbox.set_protocol(protocol);
+        let role = if self.replication.is_replica() {
+            "replica"
+        } else {
+            "master"
+        };
+        Reply::Map(vec![
+            (Reply::bulk("server"), Reply::bulk("redis")),
+            (Reply::bulk("version"), Reply::bulk(VERSION)),
+            (Reply::bulk("proto"), Reply::Integer(protocol.version())),
+            (Reply::bulk("id"), Reply::Integer(client.id as i64)),
+            (Reply::bulk("mode"), Reply::bulk("standalone")),
+            (Reply::bulk("role"), Reply::bulk(role)),
+            (Reply::bulk("modules"), Reply::Array(Vec::new())),
+        ])
+    }
+
+    /// ACL SETUSER username [rule ...], ACL GETUSER username, ACL DELUSER username
+    /// [username ...], ACL WHOAMI, ACL LIST and ACL CAT [category]. The clients authenticated
+    /// as a deleted user are disconnected.
+    fn acl(&self, client: &Client, arguments: &[Vec<u8>]) -> Reply {
+        let subcommand = arguments[1].to_ascii_uppercase();
+        match (subcommand.as_slice(), arguments.len()) {
+            (b"SETUSER", 3..) => match self.acl.set_user(&arguments[2], &arguments[3..]) {
+                Ok(()) => Reply::ok(),
+                Err(reply) => reply,
+            },
+            (b"GETUSER", 3) => self.acl.get_user(&arguments[2]),
+            (b"DELUSER", 3..) => match self.acl.delete_users(&arguments[2..]) {
+                Ok(deleted) => {
+                    for registered in self.clients.all() {
+                        if deleted.contains(&registered.state().user) {
+                            registered.kill();
+                        }
+                    }
+                    Reply::Integer(deleted.len() as i64)
+                }
+                Err(reply) => reply,
+            },
+            (b"WHOAMI", 2) => Reply::bulk(client.user.clone()),
+            (b"LIST", 2) => self.acl.list(),
+            (b"CAT", 2) => Reply::bulk_array(table::category_names()),
+            (b"CAT", 3) => match table::category(&arguments[2]) {
+                Some(commands) => {
+                    Reply::bulk_array(commands.map(|command| command.name.to_lowercase()))
+                }
+                None => Reply::error(format!(
+                    "ERR Unknown category '{}'",
+                    String::from_utf8_lossy(&arguments[2])
+                )),
+            },
+            (b"SETUSER" | b"GETUSER" | b"DELUSER" | b"WHOAMI" | b"LIST" | b"CAT", _) => {
+                Reply::error(format!(
+                    "ERR wrong number of arguments for 'acl|{}' command",
+                    String::from_utf8_lossy(&subcommand).to_lowercase()
+                ))
+            }
+            _ => Reply::error(format!(
+                "ERR unknown subcommand '{}'. Try ACL HELP.",
+                String::from_utf8_lossy(&arguments[1])
+            )),
+        }
+    }
+
+    /// CLIENT ID, CLIENT SETNAME name, CLIENT GETNAME, CLIENT LIST [ID id [id ...]],
+    /// CLIENT INFO and CLIENT KILL ip:port | CLIENT KILL [ID id] [ADDR ip:port]
+    /// [SKIPME yes|no].
+    fn client(&self, client: &mut Client, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+        let subcommand = arguments[1].to_ascii_uppercase();
+        match (subcommand.as_slice(), arguments.len()) {
+            (b"ID", 2) => Reply::Integer(client.id as i64),
+            (b"SETNAME", 3) => {
+                if !is_valid_name(&arguments[2]) {
+                    return Reply::error(
+                        "ERR Client names cannot contain spaces, newlines or special characters.",
+                    );
+                }
+                client.name = Some(arguments[2].clone()).filter(|name| !name.is_empty());
+                Reply::ok()
+            }
+            (b"GETNAME", 2) => client.name.clone().map_or(Reply::Nil, Reply::Bulk),
+            (b"LIST", _) => {
+                let ids = match arguments.get(2..) {
+                    Some([]) | None => None,
+                    Some([option, ids @ ..])
+                        if option.eq_ignore_ascii_case(b"ID") && !ids.is_empty() =>
+                    {
+                        match ids.iter().map(|id| parse_integer(id)).collect() {
+                            Some(ids) => Some(ids),
+                            None => return Reply::error("ERR Invalid client ID"),
+                        }
+                    }
+                    _ => return Reply::syntax_error(),
+                };
+                Reply::bulk(
+                    self.clients
+                        .all()
+                        .iter()
+                        .filter(|registered| {
+                            ids.as_ref()
+                                .is_none_or(|ids: &Vec<i64>| ids.contains(&(registered.id as i64)))
+                        })
+                        .map(|registered| self.describe_client(client, registered, now))
+                        .collect::<String>(),
+                )
+            }
+            (b"INFO", 2) => match &client.registered {
+                Some(registered) => Reply::bulk(self.describe_client(client, registered, now)),
+                None => Reply::Nil,
+            },
+            (b"KILL", 3) => {
+                let address = String::from_utf8_lossy(&arguments[2]);
+                match self
+                    .clients
+                    .all()
+                    .into_iter()
+                    .find(|registered| registered.address() == address)
+                {
+                    Some(registered) => {
+                        registered.kill();
+                        Reply::ok()
+                    }
+                    None => Reply::error("ERR No such client"),
+                }
+            }
+            (b"KILL", _) if arguments.len().is_multiple_of(2) => {
+                let (mut id, mut address, mut skip_me) = (None, None, true);
+                for filter in arguments[2..].chunks(2) {
+                    match filter[0].to_ascii_uppercase().as_slice() {
+                        b"ID" => match parse_integer(&filter[1]) {
+                            Some(value) if value > 0 => id = Some(value as u64),
+                            _ => return Reply::error("ERR client-id should be greater than 0"),
+                        },
+                        b"ADDR" => address = Some(String::from_utf8_lossy(&filter[1])),
+                        b"SKIPME" if filter[1].eq_ignore_ascii_case(b"YES") => skip_me = true,
+                        b"SKIPME" if filter[1].eq_ignore_ascii_case(b"NO") => skip_me = false,
+                        _ => return Reply::syntax_error(),
+                    }
+                }
+                let killed = self
+                    .clients
+                    .all()
+                    .into_iter()
+                    .filter(|registered| {
+                        id.is_none_or(|id| registered.id == id)
+                            && address
+                                .as_ref()
+                                .is_none_or(|address| registered.address() == *address)
+                            && !(skip_me && registered.id == client.id)
+                    })
+                    .inspect(|registered| registered.kill())
+                    .count();
+                Reply::Integer(killed as i64)
+            }
+            (b"ID" | b"SETNAME" | b"GETNAME" | b"INFO" | b"KILL", _) => Reply::error(format!(
+                "ERR wrong number of arguments for 'client|{}' command",
+                String::from_utf8_lossy(&subcommand).to_lowercase()
+            )),
+            _ => Reply::error(format!(
+                "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+                String::from_utf8_lossy(&arguments[1])
+            )),
+        }
+    }
+
+    /// EVAL script numkeys [key ...] [arg ...] and EVALSHA sha1 numkeys [key ...] [arg ...]:
+    /// runs a script with every shard locked, so that no other command runs meanwhile. The
+    /// commands it calls are logged and sent to the replicas one by one.
+    fn eval(
+        &self,
+        client: &mut Client,
+        shards: &mut Shards,
+        arguments: &[Vec<u8>],
+        now: SystemTime,
+    ) -> Reply {
+        let numkeys = match parse_integer(&arguments[2]) {
+            Some(numkeys) if numkeys < 0 => {
+                return Reply::error("ERR Number of keys can't be negative")
+            }
+            Some(numkeys) if numkeys as usize > arguments.len() - 3 => {
+                return Reply::error("ERR Number of keys can't be greater than number of args")
+            }
+            Some(numkeys) => numkeys as usize,
+            None => return Reply::not_an_integer(),
+        };
+        let script = if arguments[0] == b"EVAL" {
+            match self.load_script(&arguments[1]) {
+                Ok((_, script)) => script,
+                Err(reply) => return reply,
+            }
+        } else {
+            let sha = String::from_utf8_lossy(&arguments[1]).to_lowercase();
+            match self.scripts.lock().unwrap().get(&sha) {
+                Some(script) => Arc::clone(script),
+                None => return Reply::error("NOSCRIPT No matching script. Please use EVAL."),
+            }
+        };
+        let (keys, argv) = arguments[3..].split_at(numkeys);
+
+        // a SELECT in the script does not change the database of the client
+        let db = client.db;
+        let reply = script.run(
+            keys,
+            argv,
+            &mut ScriptHost {
+                redis: self,
+                client,
+                shards,
+                now,
+            },
+        );
+        client.db = db;
+        shards.select(db);
+        reply
+    }
+
+    /// Compiles a script and caches it by its SHA1, unless it already is.
+    fn load_script(&self, source: &[u8]) -> Result<(String, Arc<Script>), Reply> {
+        let sha = sha1_hex(source);
+        let mut scripts = self.scripts.lock().unwrap();
+        if let Some(script) = scripts.get(&sha) {
+            return Ok((sha, Arc::clone(script)));
+        }
+
+        let script = compile(source).map_err(|message| {
+            Reply::error(format!(
+                "ERR Error compiling script (new function): {}",
+                message
+            ))
+        })?;
+        let script = Arc::new(script);
+        scripts.insert(sha.clone(), Arc::clone(&script));
+        Ok((sha, script))
+    }
+
+    /// SCRIPT LOAD script, SCRIPT EXISTS sha1 [sha1 ...] and SCRIPT FLUSH [ASYNC|SYNC]
+    fn script(&self, arguments: &[Vec<u8>]) -> Reply {
+        let subcommand = arguments[1].to_ascii_uppercase();
+        match (subcommand.as_slice(), arguments.len()) {
+            (b"LOAD", 3) => match self.load_script(&arguments[2]) {
+                Ok((sha, _)) => Reply::bulk(sha),
+                Err(reply) => reply,
+            },
+            (b"EXISTS", 3..) => {
+                let scripts = self.scripts.lock().unwrap();
+                Reply::Array(
+                    arguments[2..]
+                        .iter()
+                        .map(|sha| {
+                            let sha = String::from_utf8_lossy(sha).to_lowercase();
+                            Reply::Integer(scripts.contains_key(&sha) as i64)
+                        })
+                        .collect(),
+                )
+            }
+            (b"FLUSH", 2 | 3) => {
+                if let Some(mode) = arguments.get(2) {
+                    if !mode.eq_ignore_ascii_case(b"ASYNC") && !mode.eq_ignore_ascii_case(b"SYNC") {
+                        return Reply::error("ERR SCRIPT FLUSH only support SYNC|ASYNC option");
+                    }
+                }
+                self.scripts.lock().unwrap().clear();
+                Reply::ok()
+            }
+            (b"LOAD" | b"EXISTS" | b"FLUSH", _) => Reply::error(format!(
+                "ERR wrong number of arguments for 'script|{}' command",
+                String::from_utf8_lossy(&subcommand).to_lowercase()
+            )),
+            _ => Reply::error(format!(
+                "ERR unknown subcommand '{}'. Try SCRIPT HELP.",
+                String::from_utf8_lossy(&arguments[1])
+            )),
+        }
+    }
+
+    /// The line of CLIENT LIST describing `registered`, as of now for the client calling.
+    fn describe_client(&self, client: &Client, registered: &Registered, now: SystemTime) -> String {
+        let state = if registered.id == client.id {
+            client.state(b"CLIENT", now)
+        } else {
+            registered.state()
+        };
+
+        let mut flags = String::new();
+        if self.replication.serves(registered.id) {
+            flags.push('S');
+        }
+        if state.subscriptions + state.patterns > 0 {
+            flags.push('P');
+        }
+        if state.multi.is_some() {
+            flags.push('x');
+        }
+        if self.blocking.is_blocked(registered.id) {
+            flags.push('b');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        registered.describe(&state, &flags, now)
+    }
+
+    fn dispatch(
+        &self,
+        client: &mut Client,
+        shards: &mut Shards,
+        arguments: &[Vec<u8>],
+        now: SystemTime,
+    ) -> Reply {
+        match arguments[0].as_slice() {
+            b"ECHO" => {
+                if arguments.len() != 2 {
+                    Reply::wrong_number_of_arguments(&arguments[0])
+                } else {
+                    Reply::bulk(arguments[1].clone())
+                }
+            }
+            b"PING" if client.is_subscribed() && client.protocol() == Protocol::Resp2 => {
+                Reply::bulk_array([&b"pong"[..], first_key(arguments)])
+            }
+            b"PING" => Reply::Simple("PONG".to_string()),
+            b"SUBSCRIBE" => pubsub::subscribe(&self.pubsub, client, arguments, Target::Channels),
+            b"PSUBSCRIBE" => pubsub::subscribe(&self.pubsub, client, arguments, Target::Patterns),
+            b"UNSUBSCRIBE" => {
+                pubsub::unsubscribe(&self.pubsub, client, arguments, Target::Channels)
+            }
+            b"PUNSUBSCRIBE" => {
+                pubsub::unsubscribe(&self.pubsub, client, arguments, Target::Patterns)
+            }
+            b"PUBLISH" => pubsub::publish(&self.pubsub, arguments),
+            b"PUBSUB" => pubsub::pubsub(&self.pubsub, arguments),
+            b"REPLICAOF" | b"SLAVEOF" => self.replication.replicaof(arguments),
+            b"REPLCONF" => self.replication.replconf(client, arguments),
+            b"PSYNC" | b"SYNC" => self.replication.full_resync(client, shards, now),
+            b"INFO" => self.info(shards, arguments, now),
+            b"CONFIG" => self.config(arguments),
+            b"SELECT" => self.select(client, shards, arguments),
+            b"CLIENT" => self.client(client, arguments, now),
+            b"HELLO" => self.hello(client, arguments),
+            b"AUTH" => match self.acl.auth(arguments) {
+                Ok(user) => {
+                    client.user = user;
+                    client.authenticated = true;
+                    Reply::ok()
+                }
+                Err(reply) => reply,
+            },
+            b"ACL" => self.acl(client, arguments),
+            b"COMMAND" => table::command(arguments),
+            b"EVAL" | b"EVALSHA" => self.eval(client, shards, arguments, now),
+            b"SCRIPT" => self.script(arguments),
+            b"BLPOP" | b"BRPOP" | b"BLMOVE" => {
+                self.blocking_pop(client, shards, arguments, now, false)
+            }
+            b"XREAD" => streams::xread(shards, arguments, now),
+            b"XREADGROUP" => streams::xreadgroup(shards, arguments, now),
+            b"XGROUP" => streams::xgroup(shards, arguments, now),
+            b"LMOVE" => lists::lmove(shards, arguments, now),
+            b"MGET" => strings::mget(shards, arguments, now),
+            b"MSET" => strings::mset(shards, arguments, now, false),
+            b"MSETNX" => strings::mset(shards, arguments, now, true),
+            b"EXISTS" => keys::exists(shards, arguments, now),
+            b"DEL" => keys::del(shards, arguments, now),
+            b"RENAME" => keys::rename(shards, arguments, now),
+            b"KEYS" => keys::keys(shards, arguments, now),
+            b"SCAN" => keys::scan(&self.data, shards.db(), arguments, now),
+            b"DBSIZE" => keys::dbsize(shards, arguments, now),
+            b"FLUSHALL" => keys::flush(shards, arguments, true),
+            b"FLUSHDB" => keys::flush(shards, arguments, false),
+            b"SAVE" => self.save(shards, now),
+            b"BGSAVE" => self.background_save(shards, now),
+            b"LASTSAVE" => Reply::Integer(unix_millis(self.persistence.last_save()) / 1000),
+            b"BGREWRITEAOF" => self.rewrite_append_only_file(shards, now),
+            b"SHUTDOWN" => self.shutdown(client, shards, arguments, now),
+            _ => dispatch_keyed(shards.keyspace(first_key(arguments)), arguments, now),
+        }
+    }
+}
+
+/// Executes the commands called by a script, on the shards locked for it.
+struct ScriptHost<'a, 'b, 'c> {
+    redis: &'a Redis,
+    client: &'b mut Client,
+    shards: &'b mut Shards<'c>,
+    now: SystemTime,
+}
+
+impl Host for ScriptHost<'_, '_, '_> {
+    fn call(&mut self, arguments: &[Vec<u8>]) -> Reply {
+        let mut arguments = arguments.to_vec();
+        table::normalize(&mut arguments);
+        let command = match table::check(&arguments) {
+            Ok(command) => command,
+            Err(_) if table::lookup(&arguments[0]).is_none() => {
+                return Reply::error("ERR Unknown Redis command called from script")
+            }
+            Err(_) => {
+                return Reply::error("ERR Wrong number of args calling Redis command from script")
+            }
+        };
+        if command.has(table::NOSCRIPT) {
+            return Reply::error("ERR This Redis command is not allowed from script");
+        }
+        if let Err(reply) = self.redis.acl.check(&self.client.user, command, &arguments) {
+            return reply;
+        }
+        if is_write_command(&arguments[0]) && self.redis.replication.is_replica() {
+            return Reply::error("READONLY You can't write against a read only replica.");
+        }
+        // no key can be evicted while the script holds the shards
+        let maxmemory = self.redis.maxmemory.load(Ordering::Relaxed);
+        if command.has(table::DENYOOM)
+            && maxmemory != 0
+            && self.redis.data.used_memory() > maxmemory
+        {
+            return Reply::error("OOM command not allowed when used memory > 'maxmemory'.");
+        }
+
+        let started = Instant::now();
+        let reply = self
+            .redis
+            .execute_locked(self.client, self.shards, &arguments, self.now);
+        self.redis
+            .stats
+            .called(command, started.elapsed(), matches!(reply, Reply::Error(_)));
+        reply
+    }
+}
+
+/// The keys a command accesses, `None` when it accesses the whole keyspace.
+fn command_keys(arguments: &[Vec<u8>]) -> Option<Vec<&[u8]>> {
+    match table::lookup(&arguments[0]) {
+        Some(command) => command.keys(arguments),
+        None => Some(Vec::new()),
+    }
+}
+
+/// Formats an amount of memory as INFO does, as `1.50M` for instance.
+fn human_bytes(bytes: usize) -> String {
+    const UNITS: [(usize, &str); 3] = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+    match UNITS.iter().find(|(size, _)| bytes >= *size) {
+        Some((size, unit)) => format!("{:.2}{}", bytes as f64 / *size as f64, unit),
+        None => format!("{}B", bytes),
+    }
+}
+
+/// Measures again the keys a write command may have changed in place.
+fn update_sizes(shards: &mut Shards, arguments: &[Vec<u8>]) {
+    for key in command_keys(arguments).into_iter().flatten() {
+        shards.keyspace(key).update_size(key);
+    }
+}
+
+/// The first argument, the key of the commands accessing a single key, empty when it is
+/// missing.
+fn first_key(arguments: &[Vec<u8>]) -> &[u8] {
+    arguments.get(1).map_or(&[], Vec::as_slice)
+}
+
+/// Executes a command accessing a single key, stored in `keyspace`.
+fn dispatch_keyed(keyspace: &mut Keyspace, arguments: &[Vec<u8>], now: SystemTime) -> Reply {
+    match arguments[0].as_slice() {
+        b"SET" => strings::set(keyspace, arguments, now),
+        b"GET" => strings::get(keyspace, arguments, now),
+        b"GETDEL" => strings::getdel(keyspace, arguments, now),
+        b"GETEX" => strings::getex(keyspace, arguments, now),
+        b"INCR" | b"INCRBY" => strings::incr_by(keyspace, arguments, now, 1),
+        b"DECR" | b"DECRBY" => strings::incr_by(keyspace, arguments, now, -1),
+        b"INCRBYFLOAT" => strings::incr_by_float(keyspace, arguments, now),
+        b"APPEND" => strings::append(keyspace, arguments, now),
+        b"STRLEN" => strings::strlen(keyspace, arguments, now),
+        b"GETRANGE" => strings::getrange(keyspace, arguments, now),
+        b"SETRANGE" => strings::setrange(keyspace, arguments, now),
+        b"EXPIRE" => expire::expire(keyspace, arguments, now, TimeUnit::Seconds, true),
+        b"PEXPIRE" => expire::expire(keyspace, arguments, now, TimeUnit::Milliseconds, true),
+        b"EXPIREAT" => expire::expire(keyspace, arguments, now, TimeUnit::Seconds, false),
+        b"PEXPIREAT" => expire::expire(keyspace, arguments, now, TimeUnit::Milliseconds, false),
+        b"TTL" => expire::ttl(keyspace, arguments, now, TimeUnit::Seconds),
+        b"PTTL" => expire::ttl(keyspace, arguments, now, TimeUnit::Milliseconds),
+        b"PERSIST" => expire::persist(keyspace, arguments, now),
+        b"TYPE" => keys::type_of(keyspace, arguments, now),
+        b"LPUSH" => lists::push(keyspace, arguments, now, End::Left),
+        b"RPUSH" => lists::push(keyspace, arguments, now, End::Right),
+        b"LPOP" => lists::pop(keyspace, arguments, now, End::Left),
+        b"RPOP" => lists::pop(keyspace, arguments, now, End::Right),
+        b"LRANGE" => lists::lrange(keyspace, arguments, now),
+        b"LLEN" => lists::llen(keyspace, arguments, now),
+        b"HSET" => hashes::hset(keyspace, arguments, now),
+        b"HGET" => hashes::hget(keyspace, arguments, now),
+        b"HDEL" => hashes::hdel(keyspace, arguments, now),
+        b"HGETALL" => hashes::hgetall(keyspace, arguments, now),
+        b"SADD" => sets::sadd(keyspace, arguments, now),
+        b"SREM" => sets::srem(keyspace, arguments, now),
+        b"SMEMBERS" => sets::smembers(keyspace, arguments, now),
+        b"SISMEMBER" => sets::sismember(keyspace, arguments, now),
+        b"ZADD" => sorted_sets::zadd(keyspace, arguments, now),
+        b"ZRANGE" => sorted_sets::zrange(keyspace, arguments, now),
+        b"ZRANGEBYSCORE" => sorted_sets::zrangebyscore(keyspace, arguments, now),
+        b"ZRANK" => sorted_sets::zrank(keyspace, arguments, now),
+        b"ZREM" => sorted_sets::zrem(keyspace, arguments, now),
+        b"XADD" => streams::xadd(keyspace, arguments, now),
+        b"XLEN" => streams::xlen(keyspace, arguments, now),
+        b"XRANGE" => streams::xrange(keyspace, arguments, now, false),
+        b"XREVRANGE" => streams::xrange(keyspace, arguments, now, true),
+        b"XTRIM" => streams::xtrim(keyspace, arguments, now),
+        b"XACK" => streams::xack(keyspace, arguments, now),
+        b"XPENDING" => streams::xpending(keyspace, arguments, now),
+        b"XCLAIM" => streams::xclaim(keyspace, arguments, now),
+        b"XSETID" => streams::xsetid(keyspace, arguments, now),
+        _ => table::unknown_command(arguments),
+    }
+}
+
+impl From<RespError> for MyOwnError {
+    fn from(e: RespError) -> Self {
+        MyOwnError::ActualError(Box::new(e))
+    }
+}
+
+trait TimeProvider {
+    fn now(&self) -> SystemTime;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn pong() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+
+        redis
+            .process(
+                &mut Client::default(),
+                b"*1\r\n$4\r\nPING\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"+PONG\r\n");
+    }
+
+    #[test]
+    fn set() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+
+        redis
+            .process(
+                &mut Client::default(),
+                b"*3\r\n$3\r\nSET\r\n$4\r\nName\r\n$4\r\nJohn\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"+OK\r\n");
+
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"$4\r\nJohn\r\n");
+    }
+
+    #[test]
+    fn set_expire() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        let instant = SystemTime::now();
+
+        redis
+            .process(
+                &mut Client::default(),
+                b"*5\r\n$3\r\nSET\r\n$4\r\nName\r\n$4\r\nJohn\r\n$2\r\nEX\r\n$2\r\n60\r\n",
+                &mut output,
+                &instant,
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"+OK\r\n");
+
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut output,
+                &(instant + Duration::from_secs(59)),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"$4\r\nJohn\r\n");
+
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut output,
+                &(instant + Duration::from_secs(60)),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"$-1\r\n");
+    }
+
+    #[test]
+    fn expire_commands() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        redis
+            .process(
+                &mut Client::default(),
+                b"SET Name John PX 2500\r\nPTTL Name\r\nEXPIREAT Name 1700000100\r\nTTL Name\r\nPERSIST Name\r\nTTL Name\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"+OK\r\n:2500\r\n:1\r\n:100\r\n:1\r\n:-1\r\n");
+
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"PEXPIRE Name 10\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        redis
+            .process(
+                &mut Client::default(),
+                b"TTL Name\r\n",
+                &mut output,
+                &(now + Duration::from_millis(10)),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b":1\r\n:-2\r\n");
+    }
+
+    #[test]
+    fn active_expire_cycle() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        redis
+            .process(
+                &mut Client::default(),
+                b"SET a 1 EX 1\r\nSET b 2 EX 1\r\nSET c 3 EX 10\r\nSET d 4\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+
+        assert_eq!(redis.active_expire_cycle(&now), 0);
+        assert_eq!(
+            redis.active_expire_cycle(&(now + Duration::from_secs(1))),
+            2
+        );
+        assert_eq!(
+            redis.active_expire_cycle(&(now + Duration::from_secs(1))),
+            0
+        );
+    }
+
+    #[test]
+    fn get_missing() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+
+        redis
+            .process(
+                &mut Client::default(),
+                b"*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+
+        assert_eq!(output, b"$-1\r\n");
+    }
+
+    #[test]
+    fn echo() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*2\r\n$4\r\nECHO\r\n$11\r\nHello World\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"$11\r\nHello World\r\n");
+    }
+
+    #[test]
+    fn echo_missing_arguments() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*1\r\n$4\r\nECHO\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            output,
+            b"-ERR wrong number of arguments for 'echo' command\r\n"
+        );
+    }
+
+    #[test]
+    fn echo_too_many_arguments() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*3\r\n$4\r\nECHO\r\n$1\r\nN\r\n$1\r\nB\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            output,
+            b"-ERR wrong number of arguments for 'echo' command\r\n"
+        );
+    }
+
+    #[test]
+    fn command_names_ignore_case() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"set k v\r\nGet k\r\nget\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            output,
+            b"+OK\r\n$1\r\nv\r\n-ERR wrong number of arguments for 'get' command\r\n"
+        );
+    }
+
+    #[test]
+    fn maxmemory_evicts_or_refuses_writes() {
+        let redis = Redis::default();
+        let now = SystemTime::now();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"set a 1\r\nset b 2\r\nset c 3\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        redis
+            .maxmemory
+            .store(redis.data.used_memory() - 1, Ordering::Relaxed);
+
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"set d 4\r\nget a\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            output,
+            b"-OOM command not allowed when used memory > 'maxmemory'.\r\n$1\r\n1\r\n"
+        );
+
+        *redis.maxmemory_policy.lock().unwrap() = Policy::AllKeysRandom;
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"set d 4\r\ndbsize\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        // every command first evicts keys while over the limit, not only the writes
+        assert_eq!(output, b"+OK\r\n:2\r\n");
+    }
+
+    #[test]
+    fn info_and_config() {
+        let redis = Redis::default();
+        let now = SystemTime::now();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"set a 1\r\nget a\r\nget\r\nincr a b\r\nconfig set maxmemory 1mb maxmemory-policy ALLKEYS-LRU\r\nconfig set port 1\r\nconfig get maxmemory*\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+OK\r\n$1\r\n1\r\n-ERR wrong number of arguments for 'get' command\r\n\
+             -ERR wrong number of arguments for 'incr' command\r\n+OK\r\n\
+             -ERR CONFIG SET failed (possibly related to argument 'port') - can't set immutable config\r\n\
+             *4\r\n$9\r\nmaxmemory\r\n$3\r\n1mb\r\n$16\r\nmaxmemory-policy\r\n$11\r\nallkeys-lru\r\n"
+        );
+
+        let info = |section: &str| {
+            let Reply::Bulk(info) = redis.info(
+                &redis.data.lock_all(0),
+                &[b"INFO".to_vec(), section.into()],
+                now,
+            ) else {
+                panic!("expected a bulk string");
+            };
+            String::from_utf8(info).unwrap()
+        };
+        assert!(info("memory").contains("maxmemory:1048576\r\nmaxmemory_human:1.00M\r\n"));
+        assert!(info("default").contains("# Keyspace\r\ndb0:keys=1,expires=0\r\n"));
+        assert!(!info("default").contains("# Commandstats"));
+        let commands = info("commandstats");
+        assert!(commands.contains("cmdstat_get:calls=1,"));
+        assert!(commands.contains(",rejected_calls=1,failed_calls=0\r\n"));
+        assert!(commands.contains("cmdstat_config:calls=3,"));
+        assert!(info("stats").contains("total_error_replies:3\r\n"));
+        assert_eq!(info("cpu"), "");
+
+        assert_eq!(
+            redis.config(&[b"CONFIG".to_vec(), b"RESETSTAT".to_vec()]),
+            Reply::ok()
+        );
+        assert_eq!(info("commandstats"), "# Commandstats\r\n");
+        assert_eq!(
+            redis.config(&[b"CONFIG".to_vec(), b"REWRITE".to_vec()]),
+            Reply::error("ERR The server is running without a config file")
+        );
+    }
+
+    #[test]
+    fn logical_databases() {
+        let redis = Redis::default();
+        let (mut client, mut blocked, mut pusher) =
+            (Client::default(), Client::default(), Client::default());
+        let now = SystemTime::now();
+        let mut output = Vec::new();
+
+        redis
+            .process(
+                &mut client,
+                b"SET k 0\r\nSELECT 1\r\nGET k\r\nSET k 1\r\nMULTI\r\nSELECT 2\r\nSET k 2\r\nEXEC\r\nGET k\r\n\
+                  SELECT 16\r\nSELECT x\r\nFLUSHDB\r\nSELECT 0\r\nGET k\r\nDBSIZE\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+OK\r\n+OK\r\n$-1\r\n+OK\r\n+OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n+OK\r\n+OK\r\n$1\r\n2\r\n\
+             -ERR DB index is out of range\r\n-ERR value is not an integer or out of range\r\n\
+             +OK\r\n+OK\r\n$1\r\n0\r\n:1\r\n"
+        );
+        let Reply::Bulk(info) = redis.info(
+            &redis.data.lock_all(0),
+            &[b"INFO".to_vec(), b"keyspace".to_vec()],
+            now,
+        ) else {
+            panic!("expected a bulk string");
+        };
+        assert_eq!(
+            info,
+            b"# Keyspace\r\ndb0:keys=1,expires=0\r\ndb1:keys=1,expires=0\r\n"
+        );
+
+        // clients blocked on a key only get the elements pushed to the same database
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut blocked,
+                b"SELECT 1\r\nBLPOP q 0\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        redis
+            .process(&mut pusher, b"RPUSH q a\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert!(blocked.is_blocked());
+        redis
+            .process(&mut pusher, b"SELECT 1\r\nRPUSH q b\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert_eq!(blocked.outbox.take(), b"*2\r\n$1\r\nq\r\n$1\r\nb\r\n");
+    }
+
+    #[test]
+    fn client_commands_and_idle_timeout() {
+        let redis = Redis::default();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut clients = [5001, 5002, 5003].map(|port| {
+            let mut client = Client {
+                address: Some(SocketAddr::from(([127, 0, 0, 1], port))),
+                ..Client::default()
+            };
+            redis.clients.register(&mut client, now);
+            client
+        });
+        let [first, second, subscriber] = &mut clients;
+        let mut output = Vec::new();
+
+        redis
+            .process(
+                first,
+                b"CLIENT SETNAME \"a b\"\r\nCLIENT SETNAME worker\r\nCLIENT GETNAME\r\nSELECT 3\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        redis
+            .process(subscriber, b"SUBSCRIBE news\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "-ERR Client names cannot contain spaces, newlines or special characters.\r\n\
+             +OK\r\n$6\r\nworker\r\n+OK\r\n\
+             *3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+
+        let later = now + Duration::from_secs(2);
+        let words = |command: &str| command.split(' ').map(Vec::from).collect::<Vec<_>>();
+        let list = format!("CLIENT LIST ID {} {}", first.id, second.id);
+        assert_eq!(
+            redis.execute(second, &words(&list), &later),
+            Reply::bulk(format!(
+                "id={} addr=127.0.0.1:5001 name=worker age=2 idle=2 flags=N db=3 sub=0 psub=0 multi=-1 cmd=select user=default resp=2\n\
+                 id={} addr=127.0.0.1:5002 name= age=2 idle=0 flags=N db=0 sub=0 psub=0 multi=-1 cmd=client user=default resp=2\n",
+                first.id, second.id
+            ))
+        );
+        assert_eq!(
+            redis.execute(second, &words("CLIENT ID"), &later),
+            Reply::Integer(second.id as i64)
+        );
+        // as done by `process` after each command
+        second.report(b"CLIENT", later);
+
+        // subscribers are never disconnected for being idle
+        redis.timeout.store(10, Ordering::Relaxed);
+        redis.close_idle_clients(&(now + Duration::from_secs(9)));
+        assert!(!first.outbox.is_closed());
+        redis.close_idle_clients(&(now + Duration::from_secs(10)));
+        assert!(first.outbox.is_closed() && !second.outbox.is_closed());
+        assert!(!subscriber.outbox.is_closed());
+
+        let mut kill = |command: &str| redis.execute(second, &words(command), &later);
+        assert_eq!(
+            kill("CLIENT KILL 127.0.0.1:9"),
+            Reply::error("ERR No such client")
+        );
+        assert_eq!(kill("CLIENT KILL 127.0.0.1:5003"), Reply::ok());
+        assert_eq!(kill("CLIENT KILL ADDR 127.0.0.1:5002"), Reply::Integer(0));
+        assert_eq!(
+            kill("CLIENT KILL ADDR 127.0.0.1:5002 SKIPME no"),
+            Reply::Integer(1)
+        );
+        assert!(subscriber.outbox.is_closed());
+        assert!(second.outbox.is_closed());
+    }
+
+    #[test]
+    fn hello_switches_protocol() {
+        let redis = Redis::default();
+        let now = SystemTime::UNIX_EPOCH;
+        let mut client = Client::default();
+        let mut subscriber = Client::default();
+        let words = |command: &str| command.split(' ').map(Vec::from).collect::<Vec<_>>();
+        let mut output = Vec::new();
+
+        redis
+            .process(
+                &mut client,
+                b"HELLO 4\r\nHELLO x\r\nHSET h f 1.5\r\nHGETALL h\r\nHELLO 3 AUTH bob secret\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            String::from_utf8(std::mem::take(&mut output)).unwrap(),
+            "-NOPROTO unsupported protocol version\r\n\
+             -ERR Protocol version is not an integer or out of range\r\n\
+             :1\r\n*2\r\n$1\r\nf\r\n$3\r\n1.5\r\n\
+             -WRONGPASS invalid username-password pair or user is disabled.\r\n"
+        );
+
+        let reply = redis.execute(&mut client, &words("HELLO 3 SETNAME worker"), &now);
+        let Reply::Map(fields) = reply else {
+            panic!("expected a map");
+        };
+        assert!(fields.contains(&(Reply::bulk("proto"), Reply::Integer(3))));
+        assert_eq!(client.protocol(), Protocol::Resp3);
+        assert_eq!(client.name, Some(b"worker".to_vec()));
+        redis
+            .process(
+                &mut client,
+                b"HGETALL h\r\nGET missing\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"%1\r\n$1\r\nf\r\n$3\r\n1.5\r\n_\r\n");
+
+        // a RESP3 subscriber gets the messages as pushes and can send any command
+        subscriber.outbox.set_protocol(Protocol::Resp3);
+        redis.execute(&mut subscriber, &words("SUBSCRIBE news"), &now);
+        assert_eq!(
+            redis.execute(&mut subscriber, &words("GET missing"), &now),
+            Reply::Nil
+        );
+        redis.execute(&mut client, &words("PUBLISH news hello"), &now);
+        assert_eq!(
+            subscriber.outbox.take(),
+            b">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n"
+        );
+    }
+
+    #[test]
+    fn authentication_and_permissions() {
+        let redis = Redis::default();
+        let now = SystemTime::UNIX_EPOCH;
+        let words = |command: &str| command.split(' ').map(Vec::from).collect::<Vec<_>>();
+        let mut admin = Client::default();
+        let execute = |client: &mut Client, command: &str| {
+            let mut arguments = words(command);
+            table::normalize(&mut arguments);
+            redis.execute(client, &arguments, &now)
+        };
+
+        assert_eq!(
+            execute(&mut admin, "AUTH secret"),
+            Reply::error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?")
+        );
+        assert_eq!(
+            execute(&mut admin, "CONFIG SET requirepass secret"),
+            Reply::ok()
+        );
+        // the clients already connected stay authenticated
+        assert_eq!(execute(&mut admin, "GET k"), Reply::Nil);
+
+        let mut client = Client {
+            authenticated: redis.acl.authenticates_new_clients(),
+            ..Client::default()
+        };
+        assert_eq!(
+            execute(&mut client, "GET k"),
+            Reply::error("NOAUTH Authentication required.")
+        );
+        assert!(matches!(
+            execute(&mut client, "HELLO 3"),
+            Reply::Error(e) if e.starts_with("NOAUTH HELLO must be called")
+        ));
+        assert_eq!(
+            execute(&mut client, "AUTH wrong"),
+            Reply::error("WRONGPASS invalid username-password pair or user is disabled.")
+        );
+        assert_eq!(execute(&mut client, "AUTH secret"), Reply::ok());
+        assert_eq!(execute(&mut client, "GET k"), Reply::Nil);
+
+        assert_eq!(
+            execute(
+                &mut admin,
+                "ACL SETUSER alice on >pw ~app:* +@string +@transaction +eval"
+            ),
+            Reply::ok()
+        );
+        let mut alice = Client::default();
+        alice.outbox.set_protocol(Protocol::Resp3);
+        redis.clients.register(&mut alice, now);
+        assert!(matches!(
+            execute(&mut alice, "HELLO 3 AUTH alice pw"),
+            Reply::Map(_)
+        ));
+        alice.report(b"HELLO", now);
+        assert_eq!(
+            execute(&mut alice, "ACL WHOAMI"),
+            Reply::error("NOPERM User alice has no permissions to run the 'acl' command")
+        );
+        assert_eq!(execute(&mut alice, "SET app:1 v"), Reply::ok());
+        assert_eq!(
+            execute(&mut alice, "SET other v"),
+            Reply::error("NOPERM No permissions to access a key")
+        );
+        assert_eq!(
+            execute(&mut alice, "LPUSH app:l v"),
+            Reply::error("NOPERM User alice has no permissions to run the 'lpush' command")
+        );
+        // checked when queued, and by the scripts for every command they call
+        assert_eq!(execute(&mut alice, "MULTI"), Reply::ok());
+        assert!(matches!(execute(&mut alice, "DEL app:1"), Reply::Error(_)));
+        assert_eq!(execute(&mut alice, "DISCARD"), Reply::ok());
+        assert_eq!(
+            redis.execute(
+                &mut alice,
+                &[
+                    b"EVAL".to_vec(),
+                    b"return redis.call('GET', 'other')".to_vec(),
+                    b"0".to_vec()
+                ],
+                &now
+            ),
+            Reply::error("NOPERM No permissions to access a key")
+        );
+
+        assert_eq!(
+            execute(&mut admin, "ACL LIST"),
+            Reply::bulk_array([
+                format!(
+                    "user alice on #{} ~app:* -@all +@string +@transaction +eval",
+                    sha256::sha256_hex(b"pw")
+                ),
+                format!(
+                    "user default on #{} ~* +@all",
+                    sha256::sha256_hex(b"secret")
+                ),
+            ])
+        );
+        assert_eq!(execute(&mut admin, "ACL WHOAMI"), Reply::bulk("default"));
+        assert_eq!(
+            execute(&mut admin, "ACL DELUSER alice bob"),
+            Reply::Integer(1)
+        );
+        assert!(alice.outbox.is_closed());
+        assert_eq!(
+            execute(&mut admin, "ACL DELUSER default"),
+            Reply::error("ERR The 'default' user cannot be removed")
+        );
+        assert_eq!(
+            execute(&mut admin, "ACL CAT nope"),
+            Reply::error("ERR Unknown category 'nope'")
+        );
+        assert_eq!(
+            execute(&mut admin, "ACL NOPE"),
+            Reply::error("ERR unknown subcommand 'NOPE'. Try ACL HELP.")
+        );
+    }
+
+    #[test]
+    fn scripts() {
+        let redis = Redis::default();
+        let now = SystemTime::UNIX_EPOCH;
+        let mut client = Client::default();
+        let mut execute = |arguments: &[&str]| {
+            let arguments = arguments
+                .iter()
+                .map(|a| a.as_bytes().to_vec())
+                .collect::<Vec<_>>();
+            redis.execute(&mut client, &arguments, &now)
+        };
+        let set = "return redis.call('SET', KEYS[1], ARGV[1])";
+
+        assert_eq!(execute(&["EVAL", set, "1", "k", "v"]), Reply::ok());
+        assert_eq!(
+            execute(&[
+                "EVAL",
+                "return {redis.call('get', KEYS[1]), ARGV[1]}",
+                "1",
+                "k",
+                "x"
+            ]),
+            Reply::Array(vec![Reply::bulk("v"), Reply::bulk("x")])
+        );
+        assert_eq!(
+            execute(&[
+                "EVALSHA",
+                &sha1_hex(set.as_bytes()).to_uppercase(),
+                "1",
+                "k",
+                "w"
+            ]),
+            Reply::ok()
+        );
+        assert_eq!(
+            execute(&["EVALSHA", "ffffffffffffffffffffffffffffffffffffffff", "0"]),
+            Reply::error("NOSCRIPT No matching script. Please use EVAL.")
+        );
+        assert_eq!(
+            execute(&["EVAL", "return 1", "2", "k"]),
+            Reply::error("ERR Number of keys can't be greater than number of args")
+        );
+        assert_eq!(
+            execute(&["EVAL", "return 1", "-1"]),
+            Reply::error("ERR Number of keys can't be negative")
+        );
+        assert_eq!(
+            execute(&["EVAL", "return (", "0"]),
+            Reply::error(
+                "ERR Error compiling script (new function): user_script:1: unexpected symbol near <eof>"
+            )
+        );
+
+        // the errors of the commands are raised, unless called with pcall
+        assert_eq!(
+            execute(&["EVAL", "redis.call('INCR', KEYS[1]) return 1", "1", "k"]),
+            Reply::not_an_integer()
+        );
+        assert_eq!(
+            execute(&[
+                "EVAL",
+                "return redis.pcall('INCR', KEYS[1])['err']",
+                "1",
+                "k"
+            ]),
+            Reply::bulk("ERR value is not an integer or out of range")
+        );
+        assert_eq!(
+            execute(&["EVAL", "return redis.call('SUBSCRIBE', 'c')", "0"]),
+            Reply::error("ERR This Redis command is not allowed from script")
+        );
+        assert_eq!(
+            execute(&["EVAL", "return redis.call('NOPE')", "0"]),
+            Reply::error("ERR Unknown Redis command called from script")
+        );
+
+        // a SELECT in a script does not change the database of the client
+        assert_eq!(
+            execute(&[
+                "EVAL",
+                "redis.call('SELECT', 1) return redis.call('SET', 'k', 1)",
+                "0"
+            ]),
+            Reply::ok()
+        );
+        assert_eq!(execute(&["GET", "k"]), Reply::bulk("w"));
+        assert_eq!(execute(&["SELECT", "1"]), Reply::ok());
+        assert_eq!(execute(&["GET", "k"]), Reply::bulk("1"));
+
+        let sha = sha1_hex(b"return 2");
+        assert_eq!(
+            execute(&["SCRIPT", "LOAD", "return 2"]),
+            Reply::bulk(sha.clone())
+        );
+        assert_eq!(
+            execute(&["SCRIPT", "EXISTS", &sha, "abc"]),
+            Reply::Array(vec![Reply::Integer(1), Reply::Integer(0)])
+        );
+        assert_eq!(execute(&["MULTI"]), Reply::ok());
+        assert_eq!(
+            execute(&["EVALSHA", &sha, "0"]),
+            Reply::Simple("QUEUED".to_string())
+        );
+        assert_eq!(execute(&["EXEC"]), Reply::Array(vec![Reply::Integer(2)]));
+        assert_eq!(execute(&["SCRIPT", "FLUSH"]), Reply::ok());
+        assert_eq!(
+            execute(&["SCRIPT", "EXISTS", &sha]),
+            Reply::Array(vec![Reply::Integer(0)])
+        );
+        assert_eq!(
+            execute(&["SCRIPT", "KILL"]),
+            Reply::error("ERR unknown subcommand 'KILL'. Try SCRIPT HELP.")
+        );
+    }
+
+    #[test]
+    fn unknown_command() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*1\r\n$4\r\nCIAO\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            output,
+            b"-ERR unknown command 'CIAO', with args beginning with: \r\n"
+        );
+    }
+
+    #[test]
+    fn pipelined_commands() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+
+        let processed = redis
+            .process(
+                &mut Client::default(),
+                b"*1\r\n$4\r\nPING\r\n*3\r\n$3\r\nSET\r\n$4\r\nName\r\n$4\r\nJohn\r\nPING\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(processed, 53);
+        assert_eq!(output, b"+PONG\r\n+OK\r\n+PONG\r\n");
+    }
+
+    #[test]
+    fn partial_command_is_left_unprocessed() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        let input = b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$4\r\nNa";
+
+        let processed = redis
+            .process(
+                &mut Client::default(),
+                input,
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(processed, 14);
+        assert_eq!(output, b"+PONG\r\n");
+
+        let mut output = Vec::new();
+        let mut input = input[processed..].to_vec();
+        input.extend_from_slice(b"me\r\n");
+
+        let processed = redis
+            .process(
+                &mut Client::default(),
+                &input,
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(processed, input.len());
+        assert_eq!(output, b"$-1\r\n");
+    }
+
+    #[test]
+    fn value_with_crlf() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+
+        redis
+            .process(
+                &mut Client::default(),
+                b"*3\r\n$3\r\nSET\r\n$4\r\nName\r\n$6\r\nJo\r\nhn\r\n*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"+OK\r\n$6\r\nJo\r\nhn\r\n");
+    }
 
-    let redis = Redis::default();
+    #[test]
+    fn binary_key_and_value() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
 
-    thread::scope(|scope| {
-        let number_of_threads = available_parallelism().expect("Failed to get number of threads");
-        println!("Using {} threads", number_of_threads);
+        redis
+            .process(
+                &mut Client::default(),
+                b"*3\r\n$3\r\nSET\r\n$2\r\n\xff\x00\r\n$3\r\n\x08\xc3\x28\r\n*2\r\n$3\r\nGET\r\n$2\r\n\xff\x00\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"+OK\r\n$3\r\n\x08\xc3\x28\r\n");
+    }
 
-        let thread_pool = ScopedThreadPool::new(number_of_threads.into(), scope);
+    #[test]
+    fn wrong_type() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
 
-        for stream in listener.incoming() {
-            thread_pool.execute(|| {
-                let mut stream = stream.expect("Expect stream to be valid");
+        redis
+            .process(
+                &mut Client::default(),
+                b"*3\r\n$5\r\nRPUSH\r\n$4\r\nlist\r\n$1\r\na\r\n*2\r\n$3\r\nGET\r\n$4\r\nlist\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            output,
+            b":1\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
 
-                loop {
-                    let mut buffer = [0; 1024];
-                    let bytes_read = stream
-                        .read(&mut buffer)
-                        .expect("Failed to read from stream");
+    #[test]
+    fn protocol_error() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
 
-                    if bytes_read == 0 {
-                        break;
-                    }
+        let result = redis.process(
+            &mut Client::default(),
+            b"*1\r\n:1\r\n",
+            &mut output,
+            &SystemTime::now(),
+        );
+        assert!(result.is_err());
+        assert_eq!(output, b"-ERR Protocol error: unexpected ':'\r\n");
+    }
 
-                    let request = String::from_utf8_lossy(&buffer);
+    #[test]
+    fn save_and_load_on_startup() {
+        let dir = std::env::temp_dir().join(format!("redis-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let config =
+            RedisConfig::from_args(&["--dir", dir, "--dbfilename", "test.rdb", "--save", ""])
+                .unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
 
-                    redis
-                        .process(&request, &mut stream, &Instant::now())
-                        .expect("Failed to process request");
-                }
-            });
-        }
-    });
+        let redis = Redis::new(&config, now).unwrap();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*3\r\n$5\r\nRPUSH\r\n$1\r\nl\r\n$1\r\na\r\n*1\r\n$4\r\nSAVE\r\n*1\r\n$8\r\nLASTSAVE\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b":1\r\n+OK\r\n:1700000000\r\n");
+        assert_eq!(redis.persistence.dirty(), 0);
 
-    Ok(())
-}
+        let redis = Redis::new(&config, now).unwrap();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*4\r\n$6\r\nLRANGE\r\n$1\r\nl\r\n$1\r\n0\r\n$2\r\n-1\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"*1\r\n$1\r\na\r\n");
 
-impl TimeProvider for Instant {
-    fn now(&self) -> Instant {
-        *self
+        std::fs::remove_dir_all(dir).unwrap();
     }
-}
 
-cli_options! {
-    struct RedisConfig {
-        #[option(name = "-p", default = 6379)]
-        port: u16
-    }
-}
+    #[test]
+    fn background_save() {
+        let dir = std::env::temp_dir().join(format!("redis-bgsave-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let config = RedisConfig::from_args(&["--dir", dir, "--save", "60 1"]).unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let redis = Redis::new(&config, now).unwrap();
 
-struct Redis {
-    data: Mutex<HashMap<String, (String, Option<Instant>)>>,
-}
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(redis.persistence.dirty(), 1);
 
-impl Redis {
-    fn default() -> Self {
-        Self {
-            data: Mutex::new(HashMap::new()),
+        redis.save_if_needed(&(now + Duration::from_secs(59)));
+        assert!(!redis.persistence.background_save_in_progress());
+        redis.save_if_needed(&(now + Duration::from_secs(60)));
+        while redis.persistence.background_save_in_progress() {
+            thread::sleep(Duration::from_millis(1));
         }
-    }
 
-    fn process(
-        &self,
-        input: &str,
-        mut output: impl Write,
-        time_provider: &impl TimeProvider,
-    ) -> Result<(), MyOwnError> {
-        let arguments = parse_input(input);
+        assert_eq!(redis.persistence.dirty(), 0);
+        assert_eq!(redis.persistence.last_save(), now + Duration::from_secs(60));
+        assert!(Path::new(dir).join("dump.rdb").exists());
 
-        let first_argument = arguments[0];
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 
-        let response: &[u8] = match first_argument {
-            "ECHO" => {
-                if arguments.len() != 2 {
-                    b"-ERR wrong number of arguments for command\r\n"
-                } else {
-                    &format!("+{}\r\n", arguments[1]).into_bytes()
-                }
-            }
-            "PING" => b"+PONG\r\n",
-            "SET" => {
-                if arguments.len() > 3 {
-                    let expire = arguments[4];
-                    match u64::from_str(expire) {
-                        Ok(expire) => {
-                            self.data.lock().unwrap().insert(
-                                arguments[1].to_string(),
-                                (
-                                    arguments[2].to_string(),
-                                    Some(time_provider.now() + Duration::from_secs(expire)),
-                                ),
-                            );
-                        }
-                        Err(_) => {
-                            todo!()
-                        }
-                    }
-                } else {
-                    self.data
-                        .lock()
-                        .unwrap()
-                        .insert(arguments[1].to_string(), (arguments[2].to_string(), None));
-                }
+    #[test]
+    fn append_only_file() {
+        let dir = std::env::temp_dir().join(format!("redis-aof-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let config = RedisConfig::from_args(&[
+            "--dir",
+            dir,
+            "--save",
+            "",
+            "--appendonly",
+            "--appendfsync",
+            "always",
+        ])
+        .unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let get_ttl = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n*2\r\n$4\r\nPTTL\r\n$1\r\nk\r\n";
 
-                b"+OK\r\n"
-            }
-            "GET" => {
-                let hash_map = self.data.lock().unwrap();
-                let value = hash_map.get(&arguments[1].to_string());
+        let redis = Redis::new(&config, now).unwrap();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*3\r\n$6\r\nEXPIRE\r\n$1\r\nk\r\n$2\r\n10\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"+OK\r\n:1\r\n");
 
-                match value {
-                    Some((value, None)) => &format!("+{}\r\n", value).into_bytes(),
-                    Some((value, Some(exp))) if exp > &time_provider.now() => {
-                        &format!("+{}\r\n", value).into_bytes()
-                    }
-                    _ => b"$-1\r\n",
-                }
-            }
-            _ => &format!("-unknown command '{}'\r\n", first_argument).into_bytes(),
-        };
+        // replayed one second later, the expire time is not reset
+        let later = now + Duration::from_secs(1);
+        let redis = Redis::new(&config, later).unwrap();
+        let mut output = Vec::new();
+        redis
+            .process(&mut Client::default(), get_ttl, &mut output, &later)
+            .expect("Failed to process");
+        assert_eq!(output, b"$1\r\nv\r\n:9000\r\n");
+
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"*1\r\n$12\r\nBGREWRITEAOF\r\n",
+                &mut output,
+                &later,
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            output,
+            b"+Background append only file rewriting started\r\n"
+        );
+        while redis.aof.as_ref().unwrap().rewrite_in_progress() {
+            thread::sleep(Duration::from_millis(1));
+        }
 
-        output.write_all(response)?;
+        let redis = Redis::new(&config, later).unwrap();
+        let mut output = Vec::new();
+        redis
+            .process(&mut Client::default(), get_ttl, &mut output, &later)
+            .expect("Failed to process");
+        assert_eq!(output, b"$1\r\nv\r\n:9000\r\n");
 
-        Ok(())
+        std::fs::remove_dir_all(dir).unwrap();
     }
-}
 
-fn parse_input(input: &str) -> Vec<&str> {
-    let mut result = vec![];
+    #[test]
+    fn xgroup_checks_its_arguments_before_accessing_the_key() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"XGROUP HELP\r\nXGROUP CREATE\r\nXGROUP CREATE s\r\nXGROUP HELP s\r\n\
+                  MULTI\r\nXGROUP HELP\r\nXGROUP DESTROY\r\nEXEC\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        let output = String::from_utf8(output).unwrap();
+        let help = "*15\r\n+XGROUP <subcommand> [<arg> [value] [opt] ...]. Subcommands are:\r\n";
+        assert!(output.starts_with(help));
+        assert!(output.contains(
+            "+    Print this help.\r\n\
+             -ERR wrong number of arguments for 'xgroup|create' command\r\n\
+             -ERR wrong number of arguments for 'xgroup|create' command\r\n\
+             -ERR wrong number of arguments for 'xgroup|help' command\r\n\
+             +OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n*15\r\n"
+        ));
+        assert!(output.ends_with(
+            "+    Print this help.\r\n-ERR wrong number of arguments for 'xgroup|destroy' command\r\n"
+        ));
+    }
 
-    input.split("\r\n").skip(1).enumerate().for_each(|(i, x)| {
-        if i % 2 == 1 {
-            result.push(x);
-        }
-    });
+    #[test]
+    fn groups_and_consumers_named_streams() {
+        let redis = Redis::default();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"XGROUP CREATE k streams 0 MKSTREAM\r\nXADD k 1-0 f v\r\n\
+                  XREADGROUP GROUP streams c STREAMS k >\r\n\
+                  XREADGROUP GROUP streams STREAMS STREAMS k 0\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+OK\r\n$3\r\n1-0\r\n\
+             *1\r\n*2\r\n$1\r\nk\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n\
+             *1\r\n*2\r\n$1\r\nk\r\n*0\r\n"
+        );
+    }
 
-    result
-}
+    #[test]
+    fn streams_in_append_only_file() {
+        let dir = std::env::temp_dir().join(format!("redis-aof-streams-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let config = RedisConfig::from_args(&["--dir", dir, "--save", "", "--appendonly"]).unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let query = b"XRANGE s - +\r\nXPENDING s g - + 10\r\nXLEN e\r\nXADD e 5-0 a b\r\n";
+        let state = |redis: &Redis| {
+            let mut output = Vec::new();
+            redis
+                .process(&mut Client::default(), query, &mut output, &now)
+                .expect("Failed to process");
+            output
+        };
 
-trait TimeProvider {
-    fn now(&self) -> Instant;
-}
+        let redis = Redis::new(&config, now).unwrap();
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut Client::default(),
+                b"XADD s * f v\r\nXADD s * f w\r\nXGROUP CREATE s g 0\r\n\
+                  XREADGROUP GROUP g alice COUNT 1 STREAMS s >\r\nXADD e MAXLEN 0 5-0 x y\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        let expected = state(&redis);
+        assert!(expected.starts_with(b"*2\r\n*2\r\n$15\r\n1700000000000-0\r\n"));
+        assert!(expected.ends_with(
+            b":0\r\n-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n"
+        ));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::time::{Duration, Instant};
+        // generated IDs are logged as such
+        let redis = Redis::new(&config, now).unwrap();
+        assert_eq!(state(&redis), expected);
 
-    #[test]
-    fn parse_input_test() {
-        let result = parse_input("*1\r\n$4\r\nPING\r\n");
-        assert_eq!(result, vec!["PING"]);
+        redis
+            .process(
+                &mut Client::default(),
+                b"BGREWRITEAOF\r\n",
+                &mut Vec::new(),
+                &now,
+            )
+            .expect("Failed to process");
+        while redis.aof.as_ref().unwrap().rewrite_in_progress() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let redis = Redis::new(&config, now).unwrap();
+        assert_eq!(state(&redis), expected);
 
-        let result = parse_input("*2\r\n$4\r\nECHO\r\n$11\r\nHello World");
-        assert_eq!(result, vec!["ECHO", "Hello World"]);
+        std::fs::remove_dir_all(dir).unwrap();
     }
 
     #[test]
-    fn pong() {
+    fn transactions() {
         let redis = Redis::default();
+        let mut client = Client::default();
         let mut output = Vec::new();
 
         redis
-            .process("*1\r\n$4\r\nPING", &mut output, &Instant::now())
+            .process(
+                &mut client,
+                b"EXEC\r\nMULTI\r\nSET k v\r\nRPUSH k a\r\nGET k\r\nMULTI\r\nEXEC\r\nGET k\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
             .expect("Failed to process");
-        assert_eq!(output, b"+PONG\r\n");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "-ERR EXEC without MULTI\r\n+OK\r\n+QUEUED\r\n+QUEUED\r\n+QUEUED\r\n\
+             -ERR MULTI calls can not be nested\r\n\
+             *3\r\n+OK\r\n-WRONGTYPE Operation against a key holding the wrong kind of value\r\n$1\r\nv\r\n\
+             $1\r\nv\r\n"
+        );
+
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut client,
+                b"MULTI\r\nSET k w\r\nDISCARD\r\nGET k\r\nDISCARD\r\n",
+                &mut output,
+                &SystemTime::now(),
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+OK\r\n+QUEUED\r\n+OK\r\n$1\r\nv\r\n-ERR DISCARD without MULTI\r\n"
+        );
     }
 
     #[test]
-    fn set() {
+    fn queuing_errors_abort_exec() {
         let redis = Redis::default();
+        let mut client = Client::default();
+        let now = SystemTime::now();
         let mut output = Vec::new();
 
         redis
             .process(
-                "*3\r\n$3\r\nSET\r\n$4\r\nName\r\n$4\r\nJohn\r\n",
+                &mut client,
+                b"WATCH w\r\nMULTI\r\nSET k 2\r\nNOSUCHCMD\r\nGET\r\nEXEC\r\nGET k\r\n",
                 &mut output,
-                &Instant::now(),
+                &now,
             )
             .expect("Failed to process");
-        assert_eq!(output, b"+OK\r\n");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+OK\r\n+OK\r\n+QUEUED\r\n\
+             -ERR unknown command 'NOSUCHCMD', with args beginning with: \r\n\
+             -ERR wrong number of arguments for 'get' command\r\n\
+             -EXECABORT Transaction discarded because of previous errors.\r\n$-1\r\n"
+        );
+        assert!(client.watched.is_empty() && !client.transaction_failed);
 
+        // the next transaction starts afresh
         let mut output = Vec::new();
         redis
             .process(
-                "*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut client,
+                b"MULTI\r\nNOSUCHCMD\r\nDISCARD\r\nMULTI\r\nSET k 3\r\nEXEC\r\n",
                 &mut output,
-                &Instant::now(),
+                &now,
             )
             .expect("Failed to process");
-        assert_eq!(output, b"+John\r\n");
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("+OK\r\n+OK\r\n+QUEUED\r\n*1\r\n+OK\r\n"));
     }
 
     #[test]
-    fn set_expire() {
+    fn watch_aborts_exec_when_a_watched_key_changed() {
         let redis = Redis::default();
+        let mut client = Client::default();
+        let mut other = Client::default();
+        let now = SystemTime::now();
         let mut output = Vec::new();
-        let instant = Instant::now();
 
         redis
             .process(
-                "*5\r\n$3\r\nSET\r\n$4\r\nName\r\n$4\r\nJohn\r\n$2\r\nEX\r\n$2\r\n60\r\n",
+                &mut client,
+                b"WATCH k\r\nGET k\r\nMULTI\r\nSET k 1\r\nWATCH k\r\nEXEC\r\n",
                 &mut output,
-                &instant,
+                &now,
             )
             .expect("Failed to process");
-        assert_eq!(output, b"+OK\r\n");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+OK\r\n$-1\r\n+OK\r\n+QUEUED\r\n-ERR WATCH inside MULTI is not allowed\r\n*1\r\n+OK\r\n"
+        );
 
         let mut output = Vec::new();
         redis
             .process(
-                "*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut client,
+                b"WATCH k\r\nMULTI\r\nSET k 2\r\n",
                 &mut output,
-                &(instant + Duration::from_secs(59)),
+                &now,
             )
             .expect("Failed to process");
-        assert_eq!(output, b"+John\r\n");
+        redis
+            .process(&mut other, b"SET k 3\r\n", &mut output, &now)
+            .expect("Failed to process");
+        redis
+            .process(&mut client, b"EXEC\r\nGET k\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+OK\r\n+OK\r\n+QUEUED\r\n+OK\r\n*-1\r\n$1\r\n3\r\n"
+        );
 
+        // EXEC forgets the watched keys, UNWATCH and disconnecting too
         let mut output = Vec::new();
         redis
             .process(
-                "*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut client,
+                b"WATCH k\r\nUNWATCH\r\nWATCH other\r\n",
                 &mut output,
-                &(instant + Duration::from_secs(60)),
+                &now,
             )
             .expect("Failed to process");
-        assert_eq!(output, b"$-1\r\n");
+        redis
+            .process(
+                &mut other,
+                b"SET k 4\r\nMULTI\r\nEXEC\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(output, b"+OK\r\n+OK\r\n+OK\r\n+OK\r\n+OK\r\n*0\r\n");
+        redis.disconnect(&mut client);
+        assert!(redis
+            .data
+            .lock(0, [&b"other"[..]])
+            .keyspace(b"other")
+            .version(b"other")
+            .is_none());
     }
 
     #[test]
-    fn get_missing() {
+    fn blocking_pops() {
         let redis = Redis::default();
+        let (mut first, mut second, mut pusher) =
+            (Client::default(), Client::default(), Client::default());
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
         let mut output = Vec::new();
 
+        // the commands after a blocking one wait for it to be served
+        let input = b"BLPOP q1 q2 0\r\nGET x\r\n";
+        let processed = redis
+            .process(&mut first, input, &mut output, &now)
+            .expect("Failed to process");
+        assert_eq!(processed, b"BLPOP q1 q2 0\r\n".len());
+        redis
+            .process(&mut second, b"BRPOP q2 0\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert!(first.is_blocked() && second.is_blocked());
+        assert!(output.is_empty());
+
+        redis
+            .process(&mut pusher, b"RPUSH q2 a\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert_eq!(output, b":1\r\n");
+        assert_eq!(first.outbox.take(), b"*2\r\n$2\r\nq2\r\n$1\r\na\r\n");
+        assert!(!first.is_blocked() && second.is_blocked());
+
+        redis
+            .process(&mut pusher, b"RPUSH q2 b c\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert_eq!(second.outbox.take(), b"*2\r\n$2\r\nq2\r\n$1\r\nc\r\n");
+        assert!(!second.is_blocked());
+
+        // served right away when a list has elements
+        let mut output = Vec::new();
         redis
             .process(
-                "*2\r\n$3\r\nGET\r\n$4\r\nName\r\n",
+                &mut first,
+                b"BRPOP q1 q2 0\r\nBRPOP q2 0\r\n",
                 &mut output,
-                &Instant::now(),
+                &now,
             )
             .expect("Failed to process");
-
-        assert_eq!(output, b"$-1\r\n");
+        assert_eq!(output, b"*2\r\n$2\r\nq2\r\n$1\r\nb\r\n");
+        redis.disconnect(&mut first);
+        redis
+            .process(&mut pusher, b"LPUSH q2 d\r\nLLEN q2\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert!(output.ends_with(b":1\r\n:1\r\n"));
     }
 
     #[test]
-    fn echo() {
+    fn blocking_stream_reads() {
         let redis = Redis::default();
+        let (mut reader, mut consumer, mut writer) =
+            (Client::default(), Client::default(), Client::default());
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
         let mut output = Vec::new();
+
         redis
             .process(
-                "*2\r\n$4\r\nECHO\r\n$11\r\nHello World",
+                &mut writer,
+                b"XGROUP CREATE s g $ MKSTREAM\r\n",
                 &mut output,
-                &Instant::now(),
+                &now,
             )
             .expect("Failed to process");
-        assert_eq!(output, b"+Hello World\r\n");
-    }
+        redis
+            .process(
+                &mut reader,
+                b"XREAD BLOCK 0 STREAMS s $\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        redis
+            .process(
+                &mut consumer,
+                b"XREADGROUP GROUP g c BLOCK 0 STREAMS s >\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert!(reader.is_blocked() && consumer.is_blocked());
+        assert_eq!(output, b"+OK\r\n");
 
-    #[test]
-    fn echo_missing_arguments() {
-        let redis = Redis::default();
+        // every reader waiting for a stream is served by an entry
         let mut output = Vec::new();
         redis
-            .process("*1\r\n$4\r\nECHO\r\n", &mut output, &Instant::now())
+            .process(
+                &mut writer,
+                b"XADD s 1-0 f v\r\nXPENDING s g\r\n",
+                &mut output,
+                &now,
+            )
             .expect("Failed to process");
-        assert_eq!(output, b"-ERR wrong number of arguments for command\r\n");
-    }
+        let entry = b"*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n";
+        assert_eq!(reader.outbox.take(), entry);
+        assert_eq!(consumer.outbox.take(), entry);
+        assert!(!reader.is_blocked() && !consumer.is_blocked());
+        assert_eq!(
+            output,
+            b"$3\r\n1-0\r\n*4\r\n:1\r\n$3\r\n1-0\r\n$3\r\n1-0\r\n*1\r\n*2\r\n$1\r\nc\r\n$1\r\n1\r\n"
+        );
 
-    #[test]
-    fn echo_too_many_arguments() {
-        let redis = Redis::default();
+        // served right away by the entries after the given ID, or given up on
         let mut output = Vec::new();
         redis
             .process(
-                "*3\r\n$4\r\nECHO\r\n$1\r\nN\r\n$1\r\nB\r\n",
+                &mut reader,
+                b"XREAD BLOCK 0 STREAMS s 0\r\nXREAD BLOCK 100 STREAMS s other 1-0 $\r\n",
                 &mut output,
-                &Instant::now(),
+                &now,
             )
             .expect("Failed to process");
-        assert_eq!(output, b"-ERR wrong number of arguments for command\r\n");
+        assert_eq!(output, entry);
+        assert!(reader.is_blocked());
+        redis.unblock_timed_out(&(now + Duration::from_millis(100)));
+        assert_eq!(reader.outbox.take(), b"*-1\r\n");
     }
 
     #[test]
-    fn unknown_command() {
+    fn blocking_move_and_timeouts() {
         let redis = Redis::default();
+        let (mut client, mut pusher) = (Client::default(), Client::default());
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut output = Vec::new();
+
+        redis
+            .process(&mut client, b"BLPOP q 1.5\r\n", &mut output, &now)
+            .expect("Failed to process");
+        redis.unblock_timed_out(&(now + Duration::from_millis(1499)));
+        assert!(client.is_blocked());
+        redis.unblock_timed_out(&(now + Duration::from_millis(1500)));
+        assert!(!client.is_blocked());
+        assert_eq!(client.outbox.take(), b"*-1\r\n");
+
+        redis
+            .process(
+                &mut client,
+                b"BLMOVE src dst LEFT RIGHT 0\r\nBLMOVE other dst LEFT RIGHT 1\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        redis
+            .process(&mut pusher, b"RPUSH src x y\r\n", &mut output, &now)
+            .expect("Failed to process");
+        assert_eq!(client.outbox.take(), b"$1\r\nx\r\n");
+        redis
+            .process(
+                &mut client,
+                b"BLMOVE other dst LEFT RIGHT 1\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        redis.unblock_timed_out(&(now + Duration::from_secs(1)));
+        assert_eq!(client.outbox.take(), b"$-1\r\n");
+
+        // a transaction never blocks
+        let mut output = Vec::new();
+        redis
+            .process(
+                &mut client,
+                b"MULTI\r\nBLPOP empty 0\r\nEXEC\r\nLRANGE dst 0 -1\r\nBLPOP q -1\r\n",
+                &mut output,
+                &now,
+            )
+            .expect("Failed to process");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "+OK\r\n+QUEUED\r\n*1\r\n*-1\r\n*1\r\n$1\r\nx\r\n-ERR timeout is negative\r\n"
+        );
+
+        // a deadline past what the clock can represent
         let mut output = Vec::new();
         redis
-            .process("*1\r\n$4\r\nCIAO\r\n", &mut output, &Instant::now())
+            .process(
+                &mut client,
+                b"BLPOP q 9223372036854775807\r\nMULTI\r\nBLMOVE q dst LEFT LEFT 9223372036854775807\r\nEXEC\r\n",
+                &mut output,
+                &now,
+            )
             .expect("Failed to process");
-        assert_eq!(output, b"-unknown command 'CIAO'\r\n");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "-ERR timeout is out of range\r\n+OK\r\n+QUEUED\r\n*1\r\n-ERR timeout is out of range\r\n"
+        );
+        assert!(!client.is_blocked());
+    }
+
+    #[test]
+    fn shutdown_saves_unless_nosave() {
+        let dir = std::env::temp_dir().join(format!("redis-shutdown-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        let now = SystemTime::now();
+        let run = |redis: &Redis, client: &mut Client, input: &[u8]| {
+            let mut output = Vec::new();
+            redis
+                .process(client, input, &mut output, &now)
+                .expect("Failed to process");
+            output
+        };
+
+        let config = RedisConfig::from_args(&["--dir", dir, "--save", "60 1"]).unwrap();
+        let redis = Redis::new(&config, now).unwrap();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&redis, &mut client, b"SET k v\r\nSHUTDOWN NOW\r\n"),
+            b"+OK\r\n-ERR syntax error\r\n"
+        );
+        assert!(!redis.is_shutting_down());
+        // nothing is replied, the connection is closed instead
+        assert_eq!(run(&redis, &mut client, b"SHUTDOWN\r\n"), b"");
+        assert!(client.outbox.is_closed() && redis.is_shutting_down());
+        let redis = Redis::new(&config, now).unwrap();
+        assert_eq!(
+            run(&redis, &mut Client::default(), b"GET k\r\n"),
+            b"$1\r\nv\r\n"
+        );
+
+        run(
+            &redis,
+            &mut Client::default(),
+            b"SET k w\r\nSHUTDOWN NOSAVE\r\n",
+        );
+        let redis = Redis::new(&config, now).unwrap();
+        assert_eq!(
+            run(&redis, &mut Client::default(), b"GET k\r\n"),
+            b"$1\r\nv\r\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+
+        // without save rules only SAVE saves, the server keeps running when it fails
+        let config = RedisConfig::from_args(&["--dir", dir, "--save", ""]).unwrap();
+        let redis = Redis::new(&config, now).unwrap();
+        assert_eq!(
+            run(&redis, &mut Client::default(), b"SHUTDOWN SAVE\r\n"),
+            b"-ERR Errors trying to SHUTDOWN. Check logs.\r\n"
+        );
+        assert!(!redis.is_shutting_down());
+        assert_eq!(run(&redis, &mut Client::default(), b"SHUTDOWN\r\n"), b"");
+        assert!(redis.is_shutting_down());
     }
 }
@@ -0,0 +1,257 @@
+use super::keyspace::{Entry, Keyspace};
+use super::rdb::{read_rdb, write_rdb, RdbError};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// After a failed background save, save rules are not checked again before this delay.
+const SAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A `save <seconds> <changes>` rule: snapshot when at least `changes` writes happened
+/// in the last `seconds` since the previous save.
+#[derive(Debug, PartialEq)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Parses save rules written as pairs of `<seconds> <changes>`, an empty string disables them.
+pub fn parse_save_rules(rules: &str) -> Result<Vec<SaveRule>, String> {
+    let values = rules
+        .split_whitespace()
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid save rule value: {}", value))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if values.len() % 2 != 0 {
+        return Err("save rules must be pairs of <seconds> <changes>".to_string());
+    }
+
+    Ok(values
+        .chunks(2)
+        .map(|pair| SaveRule {
+            seconds: pair[0],
+            changes: pair[1],
+        })
+        .collect())
+}
+
+/// State of the RDB snapshots: where they are written, when and how many changes
+/// happened since the last one.
+pub struct Persistence {
+    path: PathBuf,
+    save_rules: Mutex<Vec<SaveRule>>,
+    dirty: AtomicU64,
+    last_save: Mutex<SystemTime>,
+    last_save_attempt: Mutex<SystemTime>,
+    last_save_succeeded: AtomicBool,
+    background_save_in_progress: AtomicBool,
+}
+
+impl Persistence {
+    pub fn new(path: PathBuf, save_rules: Vec<SaveRule>, now: SystemTime) -> Self {
+        Self {
+            path,
+            save_rules: Mutex::new(save_rules),
+            dirty: AtomicU64::new(0),
+            last_save: Mutex::new(now),
+            last_save_attempt: Mutex::new(now),
+            last_save_succeeded: AtomicBool::new(true),
+            background_save_in_progress: AtomicBool::new(false),
+        }
+    }
+
+    /// Loads the snapshot into the keyspaces of the logical databases, a missing file is an
+    /// empty dataset.
+    pub fn load(&self, keyspaces: &mut [Keyspace], now: SystemTime) -> Result<usize, RdbError> {
+        match File::open(&self.path) {
+            Ok(file) => read_rdb(BufReader::new(file), keyspaces, now),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn add_changes(&self, changes: u64) {
+        self.dirty.fetch_add(changes, Ordering::Relaxed);
+    }
+
+    pub fn dirty(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    pub fn last_save(&self) -> SystemTime {
+        *self.last_save.lock().unwrap()
+    }
+
+    /// Whether the last save, in the foreground or in the background, succeeded.
+    pub fn last_save_succeeded(&self) -> bool {
+        self.last_save_succeeded.load(Ordering::Relaxed)
+    }
+
+    /// Replaces the save rules, as with CONFIG SET save.
+    pub fn set_save_rules(&self, save_rules: Vec<SaveRule>) {
+        *self.save_rules.lock().unwrap() = save_rules;
+    }
+
+    pub fn has_save_rules(&self) -> bool {
+        !self.save_rules.lock().unwrap().is_empty()
+    }
+
+    /// Marks a background save as started, returning false when one is already running.
+    pub fn start_background_save(&self) -> bool {
+        !self
+            .background_save_in_progress
+            .swap(true, Ordering::AcqRel)
+    }
+
+    pub fn finish_background_save(&self) {
+        self.background_save_in_progress
+            .store(false, Ordering::Release);
+    }
+
+    pub fn background_save_in_progress(&self) -> bool {
+        self.background_save_in_progress.load(Ordering::Acquire)
+    }
+
+    /// Whether a save rule is satisfied at `now`.
+    pub fn should_save(&self, now: SystemTime) -> bool {
+        let dirty = self.dirty();
+        let since_last_save = now
+            .duration_since(self.last_save())
+            .unwrap_or_default()
+            .as_secs();
+        let retry_allowed = self.last_save_succeeded.load(Ordering::Relaxed)
+            || now
+                .duration_since(*self.last_save_attempt.lock().unwrap())
+                .unwrap_or_default()
+                >= SAVE_RETRY_DELAY;
+
+        retry_allowed
+            && self
+                .save_rules
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|rule| dirty >= rule.changes && since_last_save >= rule.seconds)
+    }
+
+    /// Writes `entries` to a temporary file and then renames it over the snapshot, so
+    /// a crash in the middle of a save never leaves a truncated file behind.
+    /// `dirty` is the number of changes the entries include.
+    pub fn save<'a>(
+        &self,
+        entries: impl Iterator<Item = (usize, &'a [u8], &'a Entry)>,
+        dirty: u64,
+        now: SystemTime,
+    ) -> io::Result<()> {
+        *self.last_save_attempt.lock().unwrap() = now;
+
+        let result = self.write_snapshot(entries, now);
+        match result {
+            Ok(()) => {
+                self.dirty.fetch_sub(dirty, Ordering::Relaxed);
+                *self.last_save.lock().unwrap() = now;
+                self.last_save_succeeded.store(true, Ordering::Relaxed);
+            }
+            Err(_) => self.last_save_succeeded.store(false, Ordering::Relaxed),
+        }
+
+        result
+    }
+
+    fn write_snapshot<'a>(
+        &self,
+        entries: impl Iterator<Item = (usize, &'a [u8], &'a Entry)>,
+        now: SystemTime,
+    ) -> io::Result<()> {
+        let mut temporary_path = self.path.clone().into_os_string();
+        temporary_path.push(format!(".temp-{}", std::process::id()));
+        let temporary_path = PathBuf::from(temporary_path);
+
+        let written = File::create(&temporary_path).and_then(|file| {
+            let mut output = BufWriter::new(file);
+            write_rdb(entries, &mut output, now)?;
+            output.into_inner().map_err(|e| e.into_error())?.sync_all()
+        });
+
+        match written.and_then(|_| fs::rename(&temporary_path, &self.path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temporary_path);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::keyspace::Value;
+    use std::slice;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn parse_rules() {
+        assert_eq!(
+            parse_save_rules("3600 1 300 100"),
+            Ok(vec![
+                SaveRule {
+                    seconds: 3600,
+                    changes: 1
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 100
+                },
+            ])
+        );
+        assert_eq!(parse_save_rules(""), Ok(vec![]));
+        assert!(parse_save_rules("3600").is_err());
+        assert!(parse_save_rules("3600 x").is_err());
+    }
+
+    #[test]
+    fn save_rules_and_dirty_counter() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let path =
+            std::env::temp_dir().join(format!("redis-persistence-{}.rdb", std::process::id()));
+        let persistence = Persistence::new(path.clone(), parse_save_rules("60 2").unwrap(), now);
+
+        persistence.add_changes(1);
+        assert!(!persistence.should_save(now + Duration::from_secs(60)));
+        persistence.add_changes(1);
+        assert!(!persistence.should_save(now + Duration::from_secs(59)));
+        assert!(persistence.should_save(now + Duration::from_secs(60)));
+
+        let mut keyspace = Keyspace::default();
+        keyspace.set(b"key".to_vec(), Value::String(b"value".to_vec()), None, now);
+        let saved_at = now + Duration::from_secs(60);
+        persistence
+            .save(
+                keyspace.iter(saved_at).map(|(key, entry)| (0, key, entry)),
+                2,
+                saved_at,
+            )
+            .unwrap();
+
+        assert_eq!(persistence.dirty(), 0);
+        assert_eq!(persistence.last_save(), saved_at);
+        assert!(!persistence.should_save(saved_at + Duration::from_secs(120)));
+
+        let mut loaded = Keyspace::default();
+        assert_eq!(
+            persistence
+                .load(slice::from_mut(&mut loaded), saved_at)
+                .unwrap(),
+            1
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
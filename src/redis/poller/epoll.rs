@@ -0,0 +1,116 @@
+use std::ffi::c_int;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+use super::{check, Event, Interest};
+
+// https://man7.org/linux/man-pages/man7/epoll.7.html
+
+const EPOLL_CLOEXEC: c_int = 0o2000000;
+const EPOLL_CTL_ADD: c_int = 1;
+const EPOLL_CTL_DEL: c_int = 2;
+const EPOLL_CTL_MOD: c_int = 3;
+const EPOLLIN: u32 = 0x001;
+const EPOLLOUT: u32 = 0x004;
+const EPOLLERR: u32 = 0x008;
+const EPOLLHUP: u32 = 0x010;
+const EPOLLRDHUP: u32 = 0x2000;
+
+/// Maximum number of events returned by a single wait.
+const MAX_EVENTS: usize = 1024;
+
+/// Mirrors `struct epoll_event`, which the kernel packs on x86_64.
+#[cfg_attr(target_arch = "x86_64", repr(C, packed))]
+#[cfg_attr(not(target_arch = "x86_64"), repr(C))]
+#[derive(Clone, Copy)]
+struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+extern "C" {
+    fn epoll_create1(flags: c_int) -> c_int;
+    fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
+    fn epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int;
+}
+
+fn flags(interest: Interest) -> u32 {
+    match interest {
+        Interest::Readable => EPOLLIN | EPOLLRDHUP,
+        Interest::Writable => EPOLLOUT,
+        Interest::ReadableWritable => EPOLLIN | EPOLLRDHUP | EPOLLOUT,
+    }
+}
+
+/// Level triggered readiness notifications for many file descriptors, backed by epoll.
+pub struct Poller {
+    epoll: OwnedFd,
+    buffer: Vec<EpollEvent>,
+}
+
+impl Poller {
+    pub fn new() -> io::Result<Self> {
+        let fd = check(unsafe { epoll_create1(EPOLL_CLOEXEC) })?;
+
+        Ok(Self {
+            // SAFETY: the descriptor was just created and nothing else owns it
+            epoll: unsafe { OwnedFd::from_raw_fd(fd) },
+            buffer: vec![EpollEvent { events: 0, data: 0 }; MAX_EVENTS],
+        })
+    }
+
+    pub fn register(&self, fd: &impl AsRawFd, token: u64, interest: Interest) -> io::Result<()> {
+        self.control(EPOLL_CTL_ADD, fd.as_raw_fd(), token, interest)
+    }
+
+    pub fn reregister(&self, fd: &impl AsRawFd, token: u64, interest: Interest) -> io::Result<()> {
+        self.control(EPOLL_CTL_MOD, fd.as_raw_fd(), token, interest)
+    }
+
+    pub fn deregister(&self, fd: &impl AsRawFd) -> io::Result<()> {
+        self.control(EPOLL_CTL_DEL, fd.as_raw_fd(), 0, Interest::Readable)
+    }
+
+    /// Blocks until at least one registered file descriptor is ready or `timeout` expires,
+    /// replacing the content of `events` with the ready ones.
+    pub fn wait(&mut self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(c_int::MAX as u128) as c_int
+        });
+
+        let ready = loop {
+            let result = unsafe {
+                epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    self.buffer.as_mut_ptr(),
+                    self.buffer.len() as c_int,
+                    timeout,
+                )
+            };
+            match check(result) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+
+        events.clear();
+        events.extend(self.buffer[..ready as usize].iter().map(|event| {
+            let flags = event.events;
+            Event {
+                token: event.data,
+                readable: flags & (EPOLLIN | EPOLLRDHUP | EPOLLHUP | EPOLLERR) != 0,
+                writable: flags & EPOLLOUT != 0,
+            }
+        }));
+        Ok(())
+    }
+
+    fn control(&self, op: c_int, fd: RawFd, token: u64, interest: Interest) -> io::Result<()> {
+        let mut event = EpollEvent {
+            events: flags(interest),
+            data: token,
+        };
+        check(unsafe { epoll_ctl(self.epoll.as_raw_fd(), op, fd, &mut event) }).map(|_| ())
+    }
+}
@@ -0,0 +1,100 @@
+use std::ffi::c_int;
+use std::io;
+
+// epoll is Linux only, the other unix systems fall back to poll, which is also built for
+// the tests on Linux so that both stay exercised.
+#[cfg(target_os = "linux")]
+mod epoll;
+#[cfg(any(not(target_os = "linux"), test))]
+mod poll;
+
+#[cfg(target_os = "linux")]
+pub use epoll::Poller;
+#[cfg(not(target_os = "linux"))]
+pub use poll::Poller;
+
+/// What a registered file descriptor is waited on for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interest {
+    Readable,
+    Writable,
+    ReadableWritable,
+}
+
+/// A file descriptor that became ready, identified by the token it was registered with.
+/// Errors and hang ups are reported as readable, so that the next read surfaces them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub token: u64,
+    pub readable: bool,
+    pub writable: bool,
+}
+
+fn check(result: c_int) -> io::Result<c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    macro_rules! readiness {
+        ($name:ident, $poller:ty) => {
+            #[test]
+            fn $name() {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+                let (server, _) = listener.accept().unwrap();
+                let mut poller = <$poller>::new().unwrap();
+                let mut events = Vec::new();
+
+                poller.register(&server, 7, Interest::Readable).unwrap();
+                poller
+                    .wait(&mut events, Some(Duration::from_millis(10)))
+                    .unwrap();
+                assert!(events.is_empty());
+
+                client.write_all(b"PING\r\n").unwrap();
+                poller.wait(&mut events, None).unwrap();
+                assert_eq!(
+                    events,
+                    vec![Event {
+                        token: 7,
+                        readable: true,
+                        writable: false
+                    }]
+                );
+
+                poller
+                    .reregister(&server, 8, Interest::ReadableWritable)
+                    .unwrap();
+                poller.wait(&mut events, None).unwrap();
+                assert_eq!(
+                    events,
+                    vec![Event {
+                        token: 8,
+                        readable: true,
+                        writable: true
+                    }]
+                );
+
+                poller.deregister(&server).unwrap();
+                poller
+                    .wait(&mut events, Some(Duration::from_millis(10)))
+                    .unwrap();
+                assert!(events.is_empty());
+            }
+        };
+    }
+
+    #[cfg(target_os = "linux")]
+    readiness!(epoll_readiness, epoll::Poller);
+    readiness!(poll_readiness, poll::Poller);
+}
@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_int, c_short};
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use super::{check, Event, Interest};
+
+// https://pubs.opengroup.org/onlinepubs/9699919799/functions/poll.html
+
+const POLLIN: c_short = 0x001;
+const POLLOUT: c_short = 0x004;
+const POLLERR: c_short = 0x008;
+const POLLHUP: c_short = 0x010;
+const POLLNVAL: c_short = 0x020;
+
+#[cfg(target_os = "linux")]
+#[allow(non_camel_case_types)]
+type nfds_t = std::ffi::c_ulong;
+#[cfg(not(target_os = "linux"))]
+#[allow(non_camel_case_types)]
+type nfds_t = std::ffi::c_uint;
+
+/// Mirrors `struct pollfd`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: nfds_t, timeout: c_int) -> c_int;
+}
+
+fn flags(interest: Interest) -> c_short {
+    match interest {
+        Interest::Readable => POLLIN,
+        Interest::Writable => POLLOUT,
+        Interest::ReadableWritable => POLLIN | POLLOUT,
+    }
+}
+
+/// Level triggered readiness notifications for many file descriptors, backed by poll.
+/// The kernel keeps no registrations, so they are kept here and handed over on each wait.
+pub struct Poller {
+    registrations: RefCell<HashMap<RawFd, (u64, Interest)>>,
+    buffer: Vec<PollFd>,
+}
+
+impl Poller {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            registrations: RefCell::new(HashMap::new()),
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn register(&self, fd: &impl AsRawFd, token: u64, interest: Interest) -> io::Result<()> {
+        let mut registrations = self.registrations.borrow_mut();
+        if registrations.contains_key(&fd.as_raw_fd()) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        registrations.insert(fd.as_raw_fd(), (token, interest));
+        Ok(())
+    }
+
+    pub fn reregister(&self, fd: &impl AsRawFd, token: u64, interest: Interest) -> io::Result<()> {
+        match self.registrations.borrow_mut().get_mut(&fd.as_raw_fd()) {
+            Some(registration) => {
+                *registration = (token, interest);
+                Ok(())
+            }
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    pub fn deregister(&self, fd: &impl AsRawFd) -> io::Result<()> {
+        match self.registrations.borrow_mut().remove(&fd.as_raw_fd()) {
+            Some(_) => Ok(()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    /// Blocks until at least one registered file descriptor is ready or `timeout` expires,
+    /// replacing the content of `events` with the ready ones.
+    pub fn wait(&mut self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout.as_millis().min(c_int::MAX as u128) as c_int
+        });
+        let registrations = self.registrations.get_mut();
+
+        self.buffer.clear();
+        self.buffer
+            .extend(registrations.iter().map(|(&fd, &(_, interest))| PollFd {
+                fd,
+                events: flags(interest),
+                revents: 0,
+            }));
+
+        let ready = loop {
+            let result = unsafe {
+                poll(
+                    self.buffer.as_mut_ptr(),
+                    self.buffer.len() as nfds_t,
+                    timeout,
+                )
+            };
+            match check(result) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+
+        events.clear();
+        events.extend(
+            self.buffer
+                .iter()
+                .filter(|pollfd| pollfd.revents != 0)
+                .take(ready as usize)
+                .map(|pollfd| Event {
+                    token: registrations[&pollfd.fd].0,
+                    readable: pollfd.revents & (POLLIN | POLLHUP | POLLERR | POLLNVAL) != 0,
+                    writable: pollfd.revents & POLLOUT != 0,
+                }),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,1163 @@
+use super::commands::{from_unix_millis, unix_millis};
+use super::keyspace::{Entry, Keyspace, Value};
+use super::sorted_set::SortedSet;
+use super::stream::{Consumer, Fields, PendingEntry, Stream, StreamId};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::time::SystemTime;
+
+// https://rdb.fnordig.de/file_format.html
+
+const MAGIC: &[u8] = b"REDIS";
+const VERSION: &[u8] = b"0009";
+
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+/// Streams with their first ID, largest deleted ID and number of entries ever added, and
+/// the number of entries each group read.
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+/// Streams with the last time each consumer was active.
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+/// Entries of a stream stored in each node, the listpack of entries sharing a master ID.
+const STREAM_NODE_ENTRIES: usize = 100;
+const STREAM_ITEM_FLAG_DELETED: i64 = 1 << 0;
+/// The entry has the same fields as the master entry of its node, only the values are
+/// stored.
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 1 << 1;
+
+#[derive(Debug)]
+pub enum RdbError {
+    Io(io::Error),
+    InvalidFormat(String),
+    ChecksumMismatch,
+}
+
+impl Display for RdbError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RdbError::Io(e) => write!(f, "RDB I/O error: {}", e),
+            RdbError::InvalidFormat(reason) => write!(f, "invalid RDB file: {}", reason),
+            RdbError::ChecksumMismatch => write!(f, "invalid RDB file: wrong checksum"),
+        }
+    }
+}
+
+impl Error for RdbError {}
+
+impl From<io::Error> for RdbError {
+    fn from(e: io::Error) -> Self {
+        RdbError::Io(e)
+    }
+}
+
+fn invalid<T>(reason: impl Into<String>) -> Result<T, RdbError> {
+    Err(RdbError::InvalidFormat(reason.into()))
+}
+
+/// Writes all the not expired keys in the RDB format, with the logical database of each.
+pub fn write_rdb<'a>(
+    entries: impl Iterator<Item = (usize, &'a [u8], &'a Entry)>,
+    output: impl Write,
+    now: SystemTime,
+) -> io::Result<()> {
+    let mut writer = RdbWriter::new(output);
+
+    writer.write(MAGIC)?;
+    writer.write(VERSION)?;
+    writer.write_aux(b"redis-ver", b"7.0.0")?;
+    writer.write_aux(b"redis-bits", b"64")?;
+    writer.write_aux(b"ctime", (unix_millis(now) / 1000).to_string().as_bytes())?;
+
+    let mut entries = entries.collect::<Vec<_>>();
+    entries.sort_by_key(|(db, _, _)| *db);
+    for database in entries.chunk_by(|(db, _, _), (other, _, _)| db == other) {
+        writer.write(&[OPCODE_SELECTDB])?;
+        writer.write_length(database[0].0 as u64)?;
+
+        let expires = database
+            .iter()
+            .filter(|(_, _, entry)| entry.expires_at().is_some())
+            .count();
+        writer.write(&[OPCODE_RESIZEDB])?;
+        writer.write_length(database.len() as u64)?;
+        writer.write_length(expires as u64)?;
+
+        for (_, key, entry) in database {
+            if let Some(expires_at) = entry.expires_at() {
+                writer.write(&[OPCODE_EXPIRETIME_MS])?;
+                writer.write(&unix_millis(expires_at).to_le_bytes())?;
+            }
+            writer.write_value(key, &entry.value)?;
+        }
+    }
+
+    writer.write(&[OPCODE_EOF])?;
+    let checksum = writer.checksum;
+    writer.write(&checksum.to_le_bytes())?;
+    writer.output.flush()
+}
+
+/// Loads the keys stored in the RDB format into the keyspaces of their logical database,
+/// skipping the ones already expired at `now`.
+pub fn read_rdb(
+    input: impl Read,
+    keyspaces: &mut [Keyspace],
+    now: SystemTime,
+) -> Result<usize, RdbError> {
+    let mut reader = RdbReader::new(input);
+
+    let header = reader.read_bytes(9)?;
+    if &header[..5] != MAGIC {
+        return invalid("wrong signature");
+    }
+    let version = std::str::from_utf8(&header[5..])
+        .ok()
+        .and_then(|version| version.parse::<u32>().ok());
+    if !version.is_some_and(|version| (1..=11).contains(&version)) {
+        return invalid("unsupported version");
+    }
+
+    let mut loaded = 0;
+    let mut db = 0;
+    let mut expires_at = None;
+    loop {
+        let opcode = reader.read_u8()?;
+        match opcode {
+            OPCODE_AUX => {
+                reader.read_string()?;
+                reader.read_string()?;
+            }
+            OPCODE_RESIZEDB => {
+                reader.read_length()?;
+                reader.read_length()?;
+            }
+            OPCODE_SELECTDB => {
+                db = reader.read_length()? as usize;
+                if db >= keyspaces.len() {
+                    return invalid(format!("database {} is out of range", db));
+                }
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let millis = i64::from_le_bytes(reader.read_array()?);
+                expires_at = Some(from_unix_millis(millis));
+            }
+            OPCODE_EXPIRETIME => {
+                let seconds = i32::from_le_bytes(reader.read_array()?);
+                expires_at = Some(from_unix_millis(seconds as i64 * 1000));
+            }
+            OPCODE_EOF => break,
+            value_type => {
+                let key = reader.read_string()?;
+                let value = reader.read_value(value_type)?;
+
+                if expires_at.is_none_or(|expires_at| expires_at > now) {
+                    let Some(keyspace) = keyspaces.get_mut(db) else {
+                        return invalid("no database to load the keys into");
+                    };
+                    keyspace.set(key, value, expires_at, now);
+                    loaded += 1;
+                }
+                expires_at = None;
+            }
+        }
+    }
+
+    let checksum = reader.checksum;
+    let expected = u64::from_le_bytes(reader.read_array()?);
+    // a zero checksum means the file was written with checksums disabled
+    if expected != 0 && expected != checksum {
+        return Err(RdbError::ChecksumMismatch);
+    }
+
+    Ok(loaded)
+}
+
+struct RdbWriter<W: Write> {
+    output: W,
+    checksum: u64,
+}
+
+impl<W: Write> RdbWriter<W> {
+    fn new(output: W) -> Self {
+        Self {
+            output,
+            checksum: 0,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.checksum = crc64(self.checksum, bytes);
+        self.output.write_all(bytes)
+    }
+
+    fn write_length(&mut self, length: u64) -> io::Result<()> {
+        if length < 1 << 6 {
+            self.write(&[length as u8])
+        } else if length < 1 << 14 {
+            self.write(&[0x40 | (length >> 8) as u8, length as u8])
+        } else if length <= u32::MAX as u64 {
+            self.write(&[0x80])?;
+            self.write(&(length as u32).to_be_bytes())
+        } else {
+            self.write(&[0x81])?;
+            self.write(&length.to_be_bytes())
+        }
+    }
+
+    fn write_string(&mut self, value: &[u8]) -> io::Result<()> {
+        self.write_length(value.len() as u64)?;
+        self.write(value)
+    }
+
+    fn write_aux(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.write(&[OPCODE_AUX])?;
+        self.write_string(key)?;
+        self.write_string(value)
+    }
+
+    fn write_value(&mut self, key: &[u8], value: &Value) -> io::Result<()> {
+        match value {
+            Value::String(string) => {
+                self.write(&[TYPE_STRING])?;
+                self.write_string(key)?;
+                self.write_string(string)
+            }
+            Value::List(list) => {
+                self.write(&[TYPE_LIST])?;
+                self.write_string(key)?;
+                self.write_length(list.len() as u64)?;
+                list.iter()
+                    .try_for_each(|element| self.write_string(element))
+            }
+            Value::Set(set) => {
+                self.write(&[TYPE_SET])?;
+                self.write_string(key)?;
+                self.write_length(set.len() as u64)?;
+                set.iter().try_for_each(|member| self.write_string(member))
+            }
+            Value::Hash(hash) => {
+                self.write(&[TYPE_HASH])?;
+                self.write_string(key)?;
+                self.write_length(hash.len() as u64)?;
+                hash.iter().try_for_each(|(field, value)| {
+                    self.write_string(field)?;
+                    self.write_string(value)
+                })
+            }
+            Value::SortedSet(sorted_set) => {
+                self.write(&[TYPE_ZSET_2])?;
+                self.write_string(key)?;
+                self.write_length(sorted_set.len() as u64)?;
+                sorted_set.iter().try_for_each(|(member, score)| {
+                    self.write_string(member)?;
+                    self.write(&score.to_le_bytes())
+                })
+            }
+            Value::Stream(stream) => {
+                self.write(&[TYPE_STREAM_LISTPACKS])?;
+                self.write_string(key)?;
+                self.write_stream(stream)
+            }
+        }
+    }
+
+    fn write_stream(&mut self, stream: &Stream) -> io::Result<()> {
+        let entries = stream.iter().collect::<Vec<_>>();
+        let nodes = entries.chunks(STREAM_NODE_ENTRIES);
+        self.write_length(nodes.len() as u64)?;
+        for node in nodes {
+            let master_id = node[0].0;
+            self.write_string(&master_id.to_bytes())?;
+            self.write_string(&stream_node(master_id, node))?;
+        }
+        self.write_length(stream.len() as u64)?;
+        self.write_length(stream.last_id().ms)?;
+        self.write_length(stream.last_id().seq)?;
+
+        self.write_length(stream.groups().count() as u64)?;
+        for (name, group) in stream.groups() {
+            self.write_string(name)?;
+            self.write_length(group.last_delivered.ms)?;
+            self.write_length(group.last_delivered.seq)?;
+            self.write_length(group.pending.len() as u64)?;
+            for (id, pending) in &group.pending {
+                self.write(&id.to_bytes())?;
+                self.write(&unix_millis(pending.delivered_at).to_le_bytes())?;
+                self.write_length(pending.deliveries)?;
+            }
+            self.write_length(group.consumers.len() as u64)?;
+            for (name, consumer) in &group.consumers {
+                self.write_string(name)?;
+                self.write(&unix_millis(consumer.seen_at).to_le_bytes())?;
+                let pending = group.pending_of(name).collect::<Vec<_>>();
+                self.write_length(pending.len() as u64)?;
+                for (id, _) in pending {
+                    self.write(&id.to_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct RdbReader<R: Read> {
+    input: R,
+    checksum: u64,
+}
+
+/// A length prefix, which can also announce a specially encoded string.
+enum Length {
+    Plain(u64),
+    Encoded(u8),
+}
+
+impl<R: Read> RdbReader<R> {
+    fn new(input: R) -> Self {
+        Self { input, checksum: 0 }
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, RdbError> {
+        let mut bytes = Vec::new();
+        (&mut self.input)
+            .take(length as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() != length {
+            return invalid("unexpected end of file");
+        }
+        self.checksum = crc64(self.checksum, &bytes);
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], RdbError> {
+        let bytes = self.read_bytes(N)?;
+        Ok(bytes.try_into().expect("read exactly N bytes"))
+    }
+
+    fn read_u8(&mut self) -> Result<u8, RdbError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    fn read_length_or_encoding(&mut self) -> Result<Length, RdbError> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0b00 => Ok(Length::Plain((first & 0x3f) as u64)),
+            0b01 => {
+                let second = self.read_u8()?;
+                Ok(Length::Plain(
+                    (((first & 0x3f) as u64) << 8) | second as u64,
+                ))
+            }
+            0b10 if first == 0x80 => {
+                Ok(Length::Plain(u32::from_be_bytes(self.read_array()?) as u64))
+            }
+            0b10 if first == 0x81 => Ok(Length::Plain(u64::from_be_bytes(self.read_array()?))),
+            0b11 => Ok(Length::Encoded(first & 0x3f)),
+            _ => invalid("unknown length encoding"),
+        }
+    }
+
+    fn read_length(&mut self) -> Result<u64, RdbError> {
+        match self.read_length_or_encoding()? {
+            Length::Plain(length) => Ok(length),
+            Length::Encoded(_) => invalid("unexpected string encoding"),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Vec<u8>, RdbError> {
+        match self.read_length_or_encoding()? {
+            Length::Plain(length) => self.read_bytes(length as usize),
+            Length::Encoded(ENCODING_INT8) => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            Length::Encoded(ENCODING_INT16) => Ok(i16::from_le_bytes(self.read_array()?)
+                .to_string()
+                .into_bytes()),
+            Length::Encoded(ENCODING_INT32) => Ok(i32::from_le_bytes(self.read_array()?)
+                .to_string()
+                .into_bytes()),
+            Length::Encoded(ENCODING_LZF) => {
+                let compressed_length = self.read_length()? as usize;
+                let length = self.read_length()? as usize;
+                let compressed = self.read_bytes(compressed_length)?;
+                lzf_decompress(&compressed, length)
+            }
+            Length::Encoded(_) => invalid("unknown string encoding"),
+        }
+    }
+
+    fn read_value(&mut self, value_type: u8) -> Result<Value, RdbError> {
+        match value_type {
+            TYPE_STRING => Ok(Value::String(self.read_string()?)),
+            TYPE_LIST => {
+                let length = self.read_length()?;
+                let list = (0..length)
+                    .map(|_| self.read_string())
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::List(list))
+            }
+            TYPE_SET => {
+                let length = self.read_length()?;
+                let set = (0..length)
+                    .map(|_| self.read_string())
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Set(set))
+            }
+            TYPE_HASH => {
+                let length = self.read_length()?;
+                let hash = (0..length)
+                    .map(|_| Ok((self.read_string()?, self.read_string()?)))
+                    .collect::<Result<_, RdbError>>()?;
+                Ok(Value::Hash(hash))
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let length = self.read_length()?;
+                let mut sorted_set = SortedSet::default();
+                for _ in 0..length {
+                    let member = self.read_string()?;
+                    let score = if value_type == TYPE_ZSET_2 {
+                        f64::from_le_bytes(self.read_array()?)
+                    } else {
+                        self.read_string_score()?
+                    };
+                    sorted_set.insert(member, score);
+                }
+                Ok(Value::SortedSet(sorted_set))
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                self.read_stream(value_type).map(Value::Stream)
+            }
+            other => invalid(format!("unsupported value type {}", other)),
+        }
+    }
+
+    fn read_stream_id(&mut self) -> Result<StreamId, RdbError> {
+        Ok(StreamId::new(self.read_length()?, self.read_length()?))
+    }
+
+    fn read_stream(&mut self, value_type: u8) -> Result<Stream, RdbError> {
+        let mut stream = Stream::default();
+
+        let nodes = self.read_length()?;
+        for _ in 0..nodes {
+            let Ok(master_id) = self.read_string()?.try_into() else {
+                return invalid("invalid stream node ID");
+            };
+            let listpack = self.read_string()?;
+            for (id, fields) in read_stream_node(StreamId::from_bytes(master_id), &listpack)? {
+                if id <= stream.last_id() {
+                    return invalid("stream IDs are not increasing");
+                }
+                stream.add(id, fields);
+            }
+        }
+        self.read_length()?;
+        let last_id = self.read_stream_id()?;
+        if value_type != TYPE_STREAM_LISTPACKS {
+            // first ID, largest deleted ID and number of entries ever added
+            self.read_stream_id()?;
+            self.read_stream_id()?;
+            self.read_length()?;
+        }
+        stream.set_last_id(last_id);
+
+        let groups = self.read_length()?;
+        for _ in 0..groups {
+            let name = self.read_string()?;
+            let last_delivered = self.read_stream_id()?;
+            if value_type != TYPE_STREAM_LISTPACKS {
+                // entries read
+                self.read_length()?;
+            }
+            if !stream.create_group(&name, last_delivered) {
+                return invalid("duplicate consumer group");
+            }
+            let group = stream
+                .group_mut(&name)
+                .expect("group has just been created");
+
+            let pending = self.read_length()?;
+            for _ in 0..pending {
+                let id = StreamId::from_bytes(self.read_array()?);
+                let delivered_at = from_unix_millis(i64::from_le_bytes(self.read_array()?));
+                let deliveries = self.read_length()?;
+                // the consumer is told by the pending entries of each consumer
+                group.pending.insert(
+                    id,
+                    PendingEntry {
+                        consumer: Vec::new(),
+                        delivered_at,
+                        deliveries,
+                    },
+                );
+            }
+
+            let consumers = self.read_length()?;
+            for _ in 0..consumers {
+                let name = self.read_string()?;
+                let seen_at = from_unix_millis(i64::from_le_bytes(self.read_array()?));
+                if value_type == TYPE_STREAM_LISTPACKS_3 {
+                    // active time
+                    self.read_array::<8>()?;
+                }
+                let pending = self.read_length()?;
+                for _ in 0..pending {
+                    let id = StreamId::from_bytes(self.read_array()?);
+                    let Some(entry) = group.pending.get_mut(&id) else {
+                        return invalid("consumer pending entry not pending in its group");
+                    };
+                    entry.consumer = name.clone();
+                }
+                group.consumers.insert(name, Consumer { seen_at });
+            }
+        }
+
+        Ok(stream)
+    }
+
+    /// Scores of the old sorted set type are stored as a length prefixed string,
+    /// with the special lengths 253, 254 and 255 for nan, +inf and -inf.
+    fn read_string_score(&mut self) -> Result<f64, RdbError> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            length => {
+                let score = self.read_bytes(length as usize)?;
+                std::str::from_utf8(&score)
+                    .ok()
+                    .and_then(|score| score.parse().ok())
+                    .map_or_else(|| invalid("invalid sorted set score"), Ok)
+            }
+        }
+    }
+}
+
+/// A back reference of at most 264 bytes takes 3 bytes of input, so LZF never expands
+/// its input more than that.
+const LZF_MAX_EXPANSION: usize = 88;
+
+// http://oldhome.schmorp.de/marc/liblzf.html
+fn lzf_decompress(input: &[u8], length: usize) -> Result<Vec<u8>, RdbError> {
+    // The length comes from the file, trust it only as far as the input can expand.
+    let mut output = Vec::with_capacity(length.min(input.len().saturating_mul(LZF_MAX_EXPANSION)));
+    let mut position = 0;
+
+    while position < input.len() {
+        let control = input[position] as usize;
+        position += 1;
+
+        if control < 32 {
+            let literal_length = control + 1;
+            let Some(literal) = input.get(position..position + literal_length) else {
+                return invalid("corrupted LZF string");
+            };
+            if output.len() + literal_length > length {
+                return invalid("corrupted LZF string");
+            }
+            output.extend_from_slice(literal);
+            position += literal_length;
+        } else {
+            let mut reference_length = control >> 5;
+            if reference_length == 7 {
+                let Some(extra) = input.get(position) else {
+                    return invalid("corrupted LZF string");
+                };
+                reference_length += *extra as usize;
+                position += 1;
+            }
+            let Some(low) = input.get(position) else {
+                return invalid("corrupted LZF string");
+            };
+            position += 1;
+
+            let offset = ((control & 0x1f) << 8) + *low as usize + 1;
+            if offset > output.len() || output.len() + reference_length + 2 > length {
+                return invalid("corrupted LZF string");
+            }
+            let start = output.len() - offset;
+            for i in 0..reference_length + 2 {
+                output.push(output[start + i]);
+            }
+        }
+    }
+
+    if output.len() != length {
+        return invalid("corrupted LZF string");
+    }
+
+    Ok(output)
+}
+
+/// Encodes the entries of a stream node as redis does: a master entry with the number of
+/// entries and the fields of the first one, then each entry with its flags, its ID
+/// relative to the master ID, its fields unless they are the master ones, its values and
+/// the number of elements it used.
+fn stream_node(master_id: StreamId, entries: &[(StreamId, &Fields)]) -> Vec<u8> {
+    let mut listpack = Listpack::default();
+    let master_fields = entries[0].1;
+    listpack.push_integer(entries.len() as i64);
+    listpack.push_integer(0);
+    listpack.push_integer(master_fields.len() as i64);
+    for (field, _) in master_fields {
+        listpack.push_string(field);
+    }
+    listpack.push_integer(0);
+
+    for (id, fields) in entries {
+        let same_fields = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(master_fields)
+                .all(|((field, _), (master, _))| field == master);
+        listpack.push_integer(if same_fields {
+            STREAM_ITEM_FLAG_SAMEFIELDS
+        } else {
+            0
+        });
+        listpack.push_integer(id.ms.wrapping_sub(master_id.ms) as i64);
+        listpack.push_integer(id.seq.wrapping_sub(master_id.seq) as i64);
+        if same_fields {
+            for (_, value) in fields.iter() {
+                listpack.push_string(value);
+            }
+            listpack.push_integer(fields.len() as i64 + 3);
+        } else {
+            listpack.push_integer(fields.len() as i64);
+            for (field, value) in fields.iter() {
+                listpack.push_string(field);
+                listpack.push_string(value);
+            }
+            listpack.push_integer(2 * fields.len() as i64 + 4);
+        }
+    }
+
+    listpack.into_bytes()
+}
+
+/// Decodes the entries of a stream node, skipping the deleted ones.
+fn read_stream_node(
+    master_id: StreamId,
+    listpack: &[u8],
+) -> Result<Vec<(StreamId, Fields)>, RdbError> {
+    let mut elements = ListpackElements(read_listpack(listpack)?.into_iter());
+    let count = elements.integer()?;
+    let deleted = elements.integer()?;
+    let master_fields = (0..elements.integer()?)
+        .map(|_| elements.string())
+        .collect::<Result<Vec<_>, _>>()?;
+    elements.string()?;
+
+    let mut entries = Vec::new();
+    for _ in 0..count.saturating_add(deleted) {
+        let flags = elements.integer()?;
+        let id = StreamId::new(
+            master_id.ms.wrapping_add(elements.integer()? as u64),
+            master_id.seq.wrapping_add(elements.integer()? as u64),
+        );
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), elements.string()?)))
+                .collect::<Result<Fields, RdbError>>()?
+        } else {
+            (0..elements.integer()?)
+                .map(|_| Ok((elements.string()?, elements.string()?)))
+                .collect::<Result<Fields, RdbError>>()?
+        };
+        elements.string()?;
+
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            entries.push((id, fields));
+        }
+    }
+    Ok(entries)
+}
+
+// https://github.com/antirez/listpack/blob/master/listpack.md
+
+/// A listpack being built: the compact list of strings and integers redis stores small
+/// collections in.
+#[derive(Default)]
+struct Listpack {
+    elements: Vec<u8>,
+    count: usize,
+}
+
+impl Listpack {
+    fn push_integer(&mut self, value: i64) {
+        let start = self.elements.len();
+        match value {
+            0..=127 => self.elements.push(value as u8),
+            -4096..=4095 => {
+                let value = value as u16 & 0x1fff;
+                self.elements
+                    .extend_from_slice(&[0xc0 | (value >> 8) as u8, value as u8]);
+            }
+            _ if i16::try_from(value).is_ok() => {
+                self.elements.push(0xf1);
+                self.elements
+                    .extend_from_slice(&(value as i16).to_le_bytes());
+            }
+            -0x80_0000..=0x7f_ffff => {
+                self.elements.push(0xf2);
+                self.elements
+                    .extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+            }
+            _ if i32::try_from(value).is_ok() => {
+                self.elements.push(0xf3);
+                self.elements
+                    .extend_from_slice(&(value as i32).to_le_bytes());
+            }
+            _ => {
+                self.elements.push(0xf4);
+                self.elements.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        self.finish_element(start);
+    }
+
+    fn push_string(&mut self, value: &[u8]) {
+        let start = self.elements.len();
+        let length = value.len();
+        if length < 1 << 6 {
+            self.elements.push(0x80 | length as u8);
+        } else if length < 1 << 12 {
+            self.elements
+                .extend_from_slice(&[0xe0 | (length >> 8) as u8, length as u8]);
+        } else {
+            self.elements.push(0xf0);
+            self.elements
+                .extend_from_slice(&(length as u32).to_le_bytes());
+        }
+        self.elements.extend_from_slice(value);
+        self.finish_element(start);
+    }
+
+    /// Appends the length of the element starting at `start`, so that the listpack can
+    /// be read backwards: 7 bits per byte, the most significant first, each byte but the
+    /// first with its high bit set.
+    fn finish_element(&mut self, start: usize) {
+        let length = self.elements.len() - start;
+        let bytes = backlen_size(length);
+        for i in (0..bytes).rev() {
+            let byte = ((length >> (7 * i)) & 0x7f) as u8;
+            self.elements
+                .push(if i == bytes - 1 { byte } else { byte | 0x80 });
+        }
+        self.count += 1;
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let total = 6 + self.elements.len() + 1;
+        let mut bytes = Vec::with_capacity(total);
+        bytes.extend_from_slice(&(total as u32).to_le_bytes());
+        // the count saturates, telling to count the elements instead
+        bytes.extend_from_slice(&(self.count.min(u16::MAX as usize) as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.elements);
+        bytes.push(0xff);
+        bytes
+    }
+}
+
+/// The number of bytes encoding the length of an element after it.
+fn backlen_size(length: usize) -> usize {
+    match length {
+        0..=127 => 1,
+        128..16383 => 2,
+        16383..2097151 => 3,
+        2097151..268435455 => 4,
+        _ => 5,
+    }
+}
+
+/// Decodes the elements of a listpack, integers as their decimal representation.
+fn read_listpack(bytes: &[u8]) -> Result<Vec<Vec<u8>>, RdbError> {
+    let corrupted = || RdbError::InvalidFormat("corrupted listpack".to_string());
+    let slice =
+        |start: usize, length: usize| bytes.get(start..start + length).ok_or_else(corrupted);
+    let signed = |value: u64, bits: u32| {
+        let value = value as i64;
+        if value >= 1 << (bits - 1) {
+            value - (1 << bits)
+        } else {
+            value
+        }
+    };
+    let little_endian = |start: usize, length: usize| {
+        Ok::<_, RdbError>(
+            slice(start, length)?
+                .iter()
+                .rev()
+                .fold(0u64, |value, byte| (value << 8) | *byte as u64),
+        )
+    };
+
+    let mut elements = Vec::new();
+    let mut position = 6;
+    loop {
+        let encoding = *bytes.get(position).ok_or_else(corrupted)?;
+        let (element, length) = match encoding {
+            0xff => break,
+            0x00..=0x7f => (encoding.to_string().into_bytes(), 1),
+            0x80..=0xbf => {
+                let length = (encoding & 0x3f) as usize;
+                (slice(position + 1, length)?.to_vec(), 1 + length)
+            }
+            0xc0..=0xdf => {
+                let value = ((encoding as u64 & 0x1f) << 8) | slice(position + 1, 1)?[0] as u64;
+                (signed(value, 13).to_string().into_bytes(), 2)
+            }
+            0xe0..=0xef => {
+                let length =
+                    ((encoding as usize & 0x0f) << 8) | slice(position + 1, 1)?[0] as usize;
+                (slice(position + 2, length)?.to_vec(), 2 + length)
+            }
+            0xf0 => {
+                let length = little_endian(position + 1, 4)? as usize;
+                (slice(position + 5, length)?.to_vec(), 5 + length)
+            }
+            0xf1..=0xf4 => {
+                let size = match encoding {
+                    0xf1 => 2,
+                    0xf2 => 3,
+                    0xf3 => 4,
+                    _ => 8,
+                };
+                let value = little_endian(position + 1, size)?;
+                let value = if size == 8 {
+                    value as i64
+                } else {
+                    signed(value, 8 * size as u32)
+                };
+                (value.to_string().into_bytes(), 1 + size)
+            }
+            _ => return Err(corrupted()),
+        };
+        elements.push(element);
+        position += length + backlen_size(length);
+    }
+
+    Ok(elements)
+}
+
+/// The decoded elements of a listpack, consumed in order.
+struct ListpackElements(std::vec::IntoIter<Vec<u8>>);
+
+impl ListpackElements {
+    fn string(&mut self) -> Result<Vec<u8>, RdbError> {
+        self.0
+            .next()
+            .map_or_else(|| invalid("truncated listpack"), Ok)
+    }
+
+    fn integer(&mut self) -> Result<i64, RdbError> {
+        let element = self.string()?;
+        std::str::from_utf8(&element)
+            .ok()
+            .and_then(|integer| integer.parse().ok())
+            .map_or_else(|| invalid("listpack integer expected"), Ok)
+    }
+}
+
+/// CRC-64/Jones as used by redis: reflected, polynomial 0xad93d23594c935a9.
+pub fn crc64(mut crc: u64, bytes: &[u8]) -> u64 {
+    const REFLECTED_POLYNOMIAL: u64 = 0x95ac_9329_ac4b_c9b5;
+
+    for byte in bytes {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ REFLECTED_POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::slice;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    fn with_db(
+        keyspace: &Keyspace,
+        now: SystemTime,
+    ) -> impl Iterator<Item = (usize, &[u8], &Entry)> {
+        keyspace.iter(now).map(|(key, entry)| (0, key, entry))
+    }
+
+    #[test]
+    fn crc64_check_value() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut keyspace = Keyspace::default();
+        let now = now();
+        let mut sorted_set = SortedSet::default();
+        sorted_set.insert(b"a".to_vec(), 1.5);
+        sorted_set.insert(b"b".to_vec(), f64::INFINITY);
+
+        keyspace.set(
+            b"string".to_vec(),
+            Value::String(vec![0, 255, 13, 10]),
+            None,
+            now,
+        );
+        keyspace.set(
+            b"list".to_vec(),
+            Value::List(VecDeque::from([b"x".to_vec(), b"y".to_vec()])),
+            Some(now + Duration::from_millis(1500)),
+            now,
+        );
+        keyspace.set(
+            b"set".to_vec(),
+            Value::Set(HashSet::from([b"m".to_vec()])),
+            None,
+            now,
+        );
+        keyspace.set(
+            b"hash".to_vec(),
+            Value::Hash(HashMap::from([(b"f".to_vec(), vec![b'v'; 20000])])),
+            None,
+            now,
+        );
+        keyspace.set(b"zset".to_vec(), Value::SortedSet(sorted_set), None, now);
+        keyspace.set(
+            b"expired".to_vec(),
+            Value::String(b"gone".to_vec()),
+            Some(now),
+            now,
+        );
+
+        let mut rdb = Vec::new();
+        write_rdb(with_db(&keyspace, now), &mut rdb, now).unwrap();
+        assert!(rdb.starts_with(b"REDIS0009"));
+
+        let mut loaded = Keyspace::default();
+        assert_eq!(
+            read_rdb(rdb.as_slice(), slice::from_mut(&mut loaded), now).unwrap(),
+            5
+        );
+
+        assert_eq!(
+            loaded.get_typed::<Vec<u8>>(b"string", now),
+            Ok(Some(&vec![0, 255, 13, 10]))
+        );
+        assert_eq!(
+            loaded.get(b"list", now).unwrap().expires_at(),
+            Some(now + Duration::from_millis(1500))
+        );
+        assert_eq!(
+            loaded
+                .get_typed::<HashMap<Vec<u8>, Vec<u8>>>(b"hash", now)
+                .unwrap()
+                .unwrap()[b"f".as_slice()]
+            .len(),
+            20000
+        );
+        let sorted_set = loaded
+            .get_typed::<SortedSet>(b"zset", now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(sorted_set.score(b"b"), Some(f64::INFINITY));
+        assert!(loaded.get(b"expired", now).is_none());
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let mut keyspace = Keyspace::default();
+        let now = now();
+        let mut stream = Stream::default();
+        // enough entries for several nodes, with fields changing between them and
+        // values of each listpack encoding
+        for i in 0..250u64 {
+            let fields = match i % 3 {
+                0 => vec![(b"n".to_vec(), i.to_string().into_bytes())],
+                1 => vec![(b"n".to_vec(), (-(i as i64) * 1000).to_string().into_bytes())],
+                _ => vec![
+                    (b"text".to_vec(), vec![b'x'; i as usize * 20]),
+                    (b"big".to_vec(), i64::MIN.to_string().into_bytes()),
+                ],
+            };
+            stream.add(StreamId::new(1000 + i / 2, i % 2), fields);
+        }
+        stream.trim_to_len(240);
+        stream.set_last_id(StreamId::new(5000, 7));
+        stream.create_group(b"g", StreamId::new(1010, 0));
+        let group = stream.group_mut(b"g").unwrap();
+        for consumer in [&b"idle"[..], b"alice", b"bob"] {
+            group.see(consumer, now);
+        }
+        group.deliver(StreamId::new(1006, 0), b"alice", now);
+        group.deliver(StreamId::new(1010, 0), b"alice", now);
+        group.deliver(StreamId::new(1010, 0), b"alice", now);
+        group.deliver(StreamId::new(1008, 1), b"bob", now);
+        stream.create_group(b"empty", StreamId::MAX);
+        keyspace.set(b"stream".to_vec(), Value::Stream(stream.clone()), None, now);
+
+        let mut rdb = Vec::new();
+        write_rdb(with_db(&keyspace, now), &mut rdb, now).unwrap();
+        let mut loaded = Keyspace::default();
+        assert_eq!(
+            read_rdb(rdb.as_slice(), slice::from_mut(&mut loaded), now).unwrap(),
+            1
+        );
+
+        let loaded = loaded.get_typed::<Stream>(b"stream", now).unwrap().unwrap();
+        assert_eq!(loaded.len(), 240);
+        assert_eq!(loaded.last_id(), StreamId::new(5000, 7));
+        assert!(loaded.iter().eq(stream.iter()));
+        let names = loaded.groups().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, [&b"empty"[..], b"g"]);
+        let group = loaded.group(b"g").unwrap();
+        assert_eq!(group.last_delivered, StreamId::new(1010, 0));
+        let pending = group
+            .pending
+            .iter()
+            .map(|(id, entry)| (*id, entry.consumer.as_slice(), entry.deliveries))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pending,
+            [
+                (StreamId::new(1006, 0), &b"alice"[..], 1),
+                (StreamId::new(1008, 1), b"bob", 1),
+                (StreamId::new(1010, 0), b"alice", 2),
+            ]
+        );
+        assert_eq!(group.pending[&StreamId::new(1006, 0)].delivered_at, now);
+        let consumers = group.consumers.keys().collect::<Vec<_>>();
+        assert_eq!(
+            consumers,
+            [&b"alice".to_vec(), &b"bob".to_vec(), &b"idle".to_vec()]
+        );
+        assert_eq!(group.consumers[&b"idle"[..]].seen_at, now);
+    }
+
+    #[test]
+    fn several_databases() {
+        let now = now();
+        let mut keyspace = Keyspace::default();
+        for key in ["a", "c", "d"] {
+            keyspace.set(key.into(), Value::String(b"v".to_vec()), None, now);
+        }
+        // the keys other than a go to database 3
+        let entries = keyspace
+            .iter(now)
+            .map(|(key, entry)| (if key == b"a" { 0 } else { 3 }, key, entry));
+        let mut rdb = Vec::new();
+        write_rdb(entries, &mut rdb, now).unwrap();
+
+        let mut loaded = (0..4).map(|_| Keyspace::default()).collect::<Vec<_>>();
+        assert_eq!(read_rdb(rdb.as_slice(), &mut loaded, now).unwrap(), 3);
+        assert_eq!(loaded[0].iter(now).count(), 1);
+        assert_eq!(loaded[3].iter(now).count(), 2);
+        assert!(matches!(
+            read_rdb(rdb.as_slice(), &mut loaded[..2], now),
+            Err(RdbError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn detect_corruption() {
+        let mut keyspace = Keyspace::default();
+        let now = now();
+        keyspace.set(b"key".to_vec(), Value::String(b"value".to_vec()), None, now);
+
+        let mut rdb = Vec::new();
+        write_rdb(with_db(&keyspace, now), &mut rdb, now).unwrap();
+
+        let mut corrupted = rdb.clone();
+        let position = corrupted.len() - 12;
+        corrupted[position] ^= 0xff;
+        assert!(matches!(
+            read_rdb(corrupted.as_slice(), &mut [Keyspace::default()], now),
+            Err(RdbError::ChecksumMismatch)
+        ));
+
+        let truncated = &rdb[..rdb.len() - 4];
+        assert!(matches!(
+            read_rdb(truncated, &mut [Keyspace::default()], now),
+            Err(RdbError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn read_encoded_strings() {
+        let mut rdb = b"REDIS0009".to_vec();
+        rdb.extend_from_slice(&[OPCODE_SELECTDB, 0]);
+        // int8 encoded value
+        rdb.extend_from_slice(&[TYPE_STRING, 1, b'a', 0xc0, 0xfe]);
+        // int32 encoded value
+        rdb.extend_from_slice(&[TYPE_STRING, 1, b'b', 0xc2, 0x40, 0xe2, 0x01, 0x00]);
+        // lzf compressed "aaaaaaaaaa"
+        rdb.extend_from_slice(&[
+            TYPE_STRING,
+            1,
+            b'c',
+            0xc3,
+            5,
+            10,
+            0x00,
+            b'a',
+            0xe0,
+            0x00,
+            0x00,
+        ]);
+        rdb.push(OPCODE_EOF);
+        rdb.extend_from_slice(&[0; 8]);
+
+        let mut keyspace = Keyspace::default();
+        let now = now();
+        assert_eq!(
+            read_rdb(rdb.as_slice(), slice::from_mut(&mut keyspace), now).unwrap(),
+            3
+        );
+
+        let mut value_of = |key: &[u8]| keyspace.get_typed::<Vec<u8>>(key, now).unwrap().cloned();
+        assert_eq!(value_of(b"a"), Some(b"-2".to_vec()));
+        assert_eq!(value_of(b"b"), Some(b"123456".to_vec()));
+        assert_eq!(value_of(b"c"), Some(b"aaaaaaaaaa".to_vec()));
+    }
+
+    #[test]
+    fn corrupted_lzf_strings() {
+        let compressed = [0x00, b'a', 0xe0, 0x00, 0x00];
+        assert_eq!(lzf_decompress(&compressed, 10).unwrap(), b"aaaaaaaaaa");
+
+        // the announced length is not allocated upfront
+        assert!(lzf_decompress(&compressed, usize::MAX).is_err());
+        // the output would outgrow the announced length
+        assert!(lzf_decompress(&compressed, 5).is_err());
+        assert!(lzf_decompress(&compressed, 0).is_err());
+        // the back reference points before the start of the output
+        assert!(lzf_decompress(&[0x20, 0x00], 3).is_err());
+    }
+}
@@ -0,0 +1,747 @@
+use super::client::{Client, Outbox};
+use super::commands::{parse_integer, table};
+use super::database::Shards;
+use super::keyspace::Keyspace;
+use super::random::Random;
+use super::rdb::{read_rdb, write_rdb};
+use super::resp::{parse_command, parse_value, Protocol, Reply, RespValue};
+use super::Redis;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// https://redis.io/docs/latest/operate/oss_and_stack/management/replication/
+
+/// How often a replica acknowledges the offset it processed, and checks whether it should
+/// stop replicating, while the primary has nothing to send.
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+/// Longest wait for the primary to answer during the handshake and the snapshot transfer.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time between two attempts to connect to an unreachable primary.
+const RECONNECT_PERIOD: Duration = Duration::from_secs(1);
+/// Longest time waiting for a primary to be set without checking whether the server is
+/// stopping.
+const STOPPED_CHECK_PERIOD: Duration = Duration::from_millis(100);
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// The replicas of this server and, when it is a replica itself, the primary it copies.
+pub struct Replication {
+    /// Port of this server, announced to the primary.
+    port: u16,
+    state: Mutex<State>,
+    /// Notified when REPLICAOF sets a primary to connect to.
+    primary_set: Condvar,
+    /// Whether some replicas are connected, checked without locking on every write.
+    has_replicas: AtomicBool,
+    /// Whether this server is a replica, rejecting the writes of its clients.
+    is_replica: AtomicBool,
+}
+
+struct State {
+    replid: String,
+    /// Bytes of the command stream sent to the replicas, or received from the primary.
+    offset: u64,
+    replicas: Vec<Replica>,
+    primary: Option<Primary>,
+    /// Changed by every REPLICAOF, so that the link to a previous primary stops.
+    generation: u64,
+    /// The logical database the last command propagated applies to, `None` when a SELECT
+    /// must come before the next one.
+    selected_db: Option<usize>,
+}
+
+struct Replica {
+    client_id: u64,
+    outbox: Arc<Outbox>,
+    ip: Option<IpAddr>,
+    port: Option<u16>,
+    acknowledged_offset: u64,
+    last_ack: Instant,
+}
+
+struct Primary {
+    host: String,
+    port: u16,
+    link: Link,
+    /// Clone of the connection to the primary, shut down to interrupt the link.
+    stream: Option<TcpStream>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Link {
+    Connecting,
+    Syncing,
+    Up,
+}
+
+impl Replication {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            state: Mutex::new(State {
+                replid: new_replid(),
+                offset: 0,
+                replicas: Vec::new(),
+                primary: None,
+                generation: 0,
+                selected_db: None,
+            }),
+            primary_set: Condvar::new(),
+            has_replicas: AtomicBool::new(false),
+            is_replica: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.is_replica.load(Ordering::Acquire)
+    }
+
+    /// REPLICAOF host port | NO ONE
+    pub fn replicaof(&self, arguments: &[Vec<u8>]) -> Reply {
+        if arguments.len() != 3 {
+            return Reply::wrong_number_of_arguments(&arguments[0]);
+        }
+
+        let mut state = self.lock();
+        if arguments[1].eq_ignore_ascii_case(b"NO") && arguments[2].eq_ignore_ascii_case(b"ONE") {
+            if let Some(primary) = state.primary.take() {
+                primary.disconnect();
+                // the history of the primary is not shared anymore
+                state.replid = new_replid();
+                state.generation += 1;
+                self.is_replica.store(false, Ordering::Release);
+            }
+            return Reply::ok();
+        }
+
+        let host = String::from_utf8_lossy(&arguments[1]).into_owned();
+        let Some(port) = parse_integer(&arguments[2]).and_then(|port| u16::try_from(port).ok())
+        else {
+            return Reply::error("ERR Invalid master port");
+        };
+        if let Some(primary) = &state.primary {
+            if primary.host == host && primary.port == port {
+                return Reply::Simple("OK Already connected to specified master".to_string());
+            }
+            primary.disconnect();
+        }
+
+        state.primary = Some(Primary {
+            host,
+            port,
+            link: Link::Connecting,
+            stream: None,
+        });
+        state.generation += 1;
+        self.is_replica.store(true, Ordering::Release);
+        self.primary_set.notify_all();
+        Reply::ok()
+    }
+
+    /// REPLCONF option value [option value ...], sent by a replica to its primary.
+    pub fn replconf(&self, client: &mut Client, arguments: &[Vec<u8>]) -> Reply {
+        if arguments.len() < 3 || arguments.len().is_multiple_of(2) {
+            return Reply::syntax_error();
+        }
+
+        for pair in arguments[1..].chunks(2) {
+            let (option, value) = (pair[0].to_ascii_lowercase(), &pair[1]);
+            match option.as_slice() {
+                b"listening-port" => {
+                    match parse_integer(value).and_then(|port| u16::try_from(port).ok()) {
+                        Some(port) => client.listening_port = Some(port),
+                        None => return Reply::not_an_integer(),
+                    }
+                }
+                b"capa" => {}
+                // acknowledgments are not replied to, not to mix with the command stream
+                b"ack" => {
+                    if let Some(offset) = parse_integer(value) {
+                        self.acknowledge(client.id, offset as u64);
+                    }
+                    return Reply::none();
+                }
+                b"getack" => return Reply::none(),
+                _ => {
+                    return Reply::error(format!(
+                        "ERR Unrecognized REPLCONF option: {}",
+                        String::from_utf8_lossy(&pair[0])
+                    ))
+                }
+            }
+        }
+
+        Reply::ok()
+    }
+
+    /// PSYNC replid offset, always answered with a full resynchronization: the snapshot of
+    /// `shards`, which must hold the whole dataset, followed by the write commands.
+    pub fn full_resync(&self, client: &Client, shards: &Shards, now: SystemTime) -> Reply {
+        let mut snapshot = Vec::new();
+        if let Err(e) = write_rdb(shards.iter_all(now), &mut snapshot, now) {
+            return Reply::error(format!("ERR {}", e));
+        }
+
+        let mut state = self.lock();
+        if state
+            .primary
+            .as_ref()
+            .is_some_and(|primary| primary.link != Link::Up)
+        {
+            return Reply::error("NOMASTERLINK Can't SYNC while not connected with my master");
+        }
+
+        // written directly to the outbox, before any command propagated once the replica
+        // is registered
+        let mut outbox = client.outbox.lock();
+        outbox.extend_from_slice(
+            format!(
+                "+FULLRESYNC {} {}\r\n${}\r\n",
+                state.replid,
+                state.offset,
+                snapshot.len()
+            )
+            .as_bytes(),
+        );
+        outbox.extend_from_slice(&snapshot);
+        drop(outbox);
+
+        let offset = state.offset;
+        state
+            .replicas
+            .retain(|replica| replica.client_id != client.id);
+        state.replicas.push(Replica {
+            client_id: client.id,
+            outbox: Arc::clone(&client.outbox),
+            ip: client.address.map(|address| address.ip()),
+            port: client.listening_port,
+            acknowledged_offset: offset,
+            last_ack: Instant::now(),
+        });
+        // the new replica starts in the first database, whatever the others selected
+        state.selected_db = None;
+        self.has_replicas.store(true, Ordering::Release);
+        Reply::none()
+    }
+
+    /// Sends a write command executed in the logical database `db` to the replicas. Called
+    /// with the keys of the command locked, so commands on the same key reach the replicas
+    /// in the order they were executed.
+    pub fn propagate(&self, db: usize, arguments: &[Vec<u8>]) {
+        if !self.has_replicas.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut state = self.lock();
+        let mut command = Vec::new();
+        if state.selected_db != Some(db) {
+            Reply::bulk_array([b"SELECT".to_vec(), db.to_string().into_bytes()])
+                .encode(Protocol::Resp2, &mut command);
+            state.selected_db = Some(db);
+        }
+        Reply::bulk_array(arguments.iter().map(Vec::as_slice))
+            .encode(Protocol::Resp2, &mut command);
+
+        for replica in &state.replicas {
+            replica.outbox.push_bytes(&command);
+        }
+        // the offset of a replica is the one of its primary
+        if state.primary.is_none() {
+            state.offset += command.len() as u64;
+        }
+    }
+
+    /// Whether the client is a replica of this server.
+    pub fn serves(&self, client_id: u64) -> bool {
+        self.has_replicas.load(Ordering::Acquire)
+            && self
+                .lock()
+                .replicas
+                .iter()
+                .any(|replica| replica.client_id == client_id)
+    }
+
+    /// Forgets a client that disconnected, if it was a replica.
+    pub fn disconnect(&self, client: &Client) {
+        if !self.has_replicas.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut state = self.lock();
+        state
+            .replicas
+            .retain(|replica| replica.client_id != client.id);
+        self.has_replicas
+            .store(!state.replicas.is_empty(), Ordering::Release);
+    }
+
+    /// The replication section of INFO.
+    pub fn info(&self) -> String {
+        let state = self.lock();
+        let mut info = String::from("# Replication\r\n");
+
+        match &state.primary {
+            None => info.push_str("role:master\r\n"),
+            Some(primary) => {
+                info.push_str("role:slave\r\n");
+                info.push_str(&format!("master_host:{}\r\n", primary.host));
+                info.push_str(&format!("master_port:{}\r\n", primary.port));
+                let status = if primary.link == Link::Up {
+                    "up"
+                } else {
+                    "down"
+                };
+                info.push_str(&format!("master_link_status:{}\r\n", status));
+                let syncing = primary.link == Link::Syncing;
+                info.push_str(&format!("master_sync_in_progress:{}\r\n", syncing as u8));
+                info.push_str(&format!("slave_repl_offset:{}\r\n", state.offset));
+                info.push_str("slave_read_only:1\r\n");
+            }
+        }
+
+        info.push_str(&format!("connected_slaves:{}\r\n", state.replicas.len()));
+        for (index, replica) in state.replicas.iter().enumerate() {
+            info.push_str(&format!(
+                "slave{}:ip={},port={},state=online,offset={},lag={}\r\n",
+                index,
+                replica.ip.map_or("?".to_string(), |ip| ip.to_string()),
+                replica.port.unwrap_or_default(),
+                replica.acknowledged_offset,
+                replica.last_ack.elapsed().as_secs()
+            ));
+        }
+        info.push_str(&format!("master_replid:{}\r\n", state.replid));
+        info.push_str(&format!("master_repl_offset:{}\r\n", state.offset));
+        info
+    }
+
+    /// Replicates the primary set by REPLICAOF, reconnecting when the link fails, until
+    /// `stopped` is set.
+    pub fn run(&self, redis: &Redis, stopped: &AtomicBool) {
+        while let Some((host, port, generation)) = self.wait_for_primary(stopped) {
+            if let Err(e) = self.replicate(redis, &host, port, generation, stopped) {
+                if self.update(generation, |primary| primary.link = Link::Connecting) {
+                    eprintln!("Error replicating {}:{}: {}", host, port, e);
+                    thread::sleep(RECONNECT_PERIOD);
+                }
+            }
+        }
+    }
+
+    /// Waits until a primary is set, returning `None` when the server is stopping.
+    fn wait_for_primary(&self, stopped: &AtomicBool) -> Option<(String, u16, u64)> {
+        let mut state = self.lock();
+        while !stopped.load(Ordering::Acquire) {
+            if let Some(primary) = &state.primary {
+                return Some((primary.host.clone(), primary.port, state.generation));
+            }
+            state = self
+                .primary_set
+                .wait_timeout(state, STOPPED_CHECK_PERIOD)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        None
+    }
+
+    /// Synchronizes with the primary and then executes the commands it sends, until the
+    /// server stops or another primary is set.
+    fn replicate(
+        &self,
+        redis: &Redis,
+        host: &str,
+        port: u16,
+        generation: u64,
+        stopped: &AtomicBool,
+    ) -> io::Result<()> {
+        let stream = TcpStream::connect((host, port))?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(SYNC_TIMEOUT))?;
+        let attached = stream.try_clone()?;
+        if !self.update(generation, |primary| primary.stream = Some(attached)) {
+            return Ok(());
+        }
+
+        let mut link = PrimaryLink {
+            stream,
+            buffer: Vec::new(),
+        };
+        link.handshake(self.port)?;
+        let (replid, mut offset) = link.psync()?;
+        self.update(generation, |primary| primary.link = Link::Syncing);
+
+        let snapshot = link.snapshot()?;
+        let mut keyspaces = (0..redis.data.databases())
+            .map(|_| Keyspace::default())
+            .collect::<Vec<_>>();
+        let loaded = read_rdb(snapshot.as_slice(), &mut keyspaces, SystemTime::now())
+            .map_err(io::Error::other)?;
+        redis.data.replace(keyspaces);
+        println!(
+            "Synchronized with primary {}:{}: {} keys",
+            host, port, loaded
+        );
+
+        {
+            let mut state = self.lock();
+            if state.generation != generation {
+                return Ok(());
+            }
+            state.replid = replid;
+            state.offset = offset;
+            if let Some(primary) = &mut state.primary {
+                primary.link = Link::Up;
+            }
+        }
+
+        link.stream.set_read_timeout(Some(ACK_PERIOD))?;
+        let mut client = Client::default();
+        let mut last_ack = Instant::now();
+        while !stopped.load(Ordering::Acquire) && self.lock().generation == generation {
+            let mut processed = 0;
+            while let Some((mut arguments, used)) =
+                parse_command(&link.buffer[processed..]).map_err(io::Error::other)?
+            {
+                processed += used;
+                table::normalize(&mut arguments);
+                if is_getack(&arguments) {
+                    // the acknowledged offset does not include the request for it
+                    link.acknowledge(offset)?;
+                    last_ack = Instant::now();
+                } else if !arguments.is_empty() {
+                    match table::check(&arguments) {
+                        Ok(_) => {
+                            let now = SystemTime::now();
+                            let mut shards = redis.lock(client.db, &arguments);
+                            redis.execute_locked(&mut client, &mut shards, &arguments, now);
+                            drop(shards);
+                            redis.serve_blocked(now);
+                        }
+                        Err(reply) => eprintln!("Invalid command from the primary: {:?}", reply),
+                    }
+                }
+                offset += used as u64;
+            }
+
+            if processed > 0 {
+                link.buffer.drain(..processed);
+                self.lock().offset = offset;
+            }
+            if last_ack.elapsed() >= ACK_PERIOD {
+                link.acknowledge(offset)?;
+                last_ack = Instant::now();
+            }
+            link.fill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes the primary if it is still the one set by REPLICAOF `generation`, returning
+    /// whether it was.
+    fn update(&self, generation: u64, change: impl FnOnce(&mut Primary)) -> bool {
+        let mut state = self.lock();
+        if state.generation != generation {
+            return false;
+        }
+        match &mut state.primary {
+            Some(primary) => {
+                change(primary);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn acknowledge(&self, client_id: u64, offset: u64) {
+        let mut state = self.lock();
+        if let Some(replica) = state
+            .replicas
+            .iter_mut()
+            .find(|replica| replica.client_id == client_id)
+        {
+            replica.acknowledged_offset = offset;
+            replica.last_ack = Instant::now();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Primary {
+    /// Interrupts the link to the primary, which notices it is not current anymore.
+    fn disconnect(&self) {
+        if let Some(stream) = &self.stream {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Connection of a replica to its primary, with the bytes received and not processed yet.
+struct PrimaryLink {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl PrimaryLink {
+    /// Checks that the primary answers and announces the port of this server.
+    fn handshake(&mut self, port: u16) -> io::Result<()> {
+        self.send(&[b"PING"])?;
+        self.reply()?;
+        self.send(&[b"REPLCONF", b"listening-port", port.to_string().as_bytes()])?;
+        self.reply()?;
+        self.send(&[b"REPLCONF", b"capa", b"psync2"])?;
+        self.reply()?;
+        Ok(())
+    }
+
+    /// Asks for a full resynchronization, returning the replication id and offset of the
+    /// primary.
+    fn psync(&mut self) -> io::Result<(String, u64)> {
+        self.send(&[b"PSYNC", b"?", b"-1"])?;
+        let RespValue::SimpleString(reply) = self.reply()? else {
+            return Err(io::Error::other("unexpected reply to PSYNC"));
+        };
+
+        match reply.split(' ').collect::<Vec<_>>().as_slice() {
+            ["FULLRESYNC", replid, offset] => offset
+                .parse()
+                .map(|offset| (replid.to_string(), offset))
+                .map_err(|_| io::Error::other("invalid offset in FULLRESYNC")),
+            _ => Err(io::Error::other(format!(
+                "unexpected reply to PSYNC: {}",
+                reply
+            ))),
+        }
+    }
+
+    /// Receives the snapshot, sent as a bulk string without the final CRLF.
+    fn snapshot(&mut self) -> io::Result<Vec<u8>> {
+        let (length, header) = loop {
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") {
+                let length = std::str::from_utf8(&self.buffer[..end])
+                    .ok()
+                    .and_then(|line| line.strip_prefix('$'))
+                    .and_then(|length| length.parse::<usize>().ok())
+                    .ok_or_else(|| io::Error::other("invalid snapshot length"))?;
+                break (length, end + 2);
+            }
+            self.fill_before_timeout()?;
+        };
+
+        while self.buffer.len() < header + length {
+            self.fill_before_timeout()?;
+        }
+        let snapshot = self.buffer[header..header + length].to_vec();
+        self.buffer.drain(..header + length);
+        Ok(snapshot)
+    }
+
+    fn send(&mut self, arguments: &[&[u8]]) -> io::Result<()> {
+        let mut command = Vec::new();
+        Reply::bulk_array(arguments.iter().copied()).encode(Protocol::Resp2, &mut command);
+        self.stream.write_all(&command)
+    }
+
+    fn acknowledge(&mut self, offset: u64) -> io::Result<()> {
+        self.send(&[b"REPLCONF", b"ACK", offset.to_string().as_bytes()])
+    }
+
+    /// Waits for the reply to a command sent during the handshake.
+    fn reply(&mut self) -> io::Result<RespValue> {
+        loop {
+            if let Some((value, used)) = parse_value(&self.buffer).map_err(io::Error::other)? {
+                self.buffer.drain(..used);
+                return match value {
+                    RespValue::Error(message) => Err(io::Error::other(message)),
+                    value => Ok(value),
+                };
+            }
+            self.fill_before_timeout()?;
+        }
+    }
+
+    /// Reads what the primary sent, returning without data when the read timed out.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut buffer = [0; READ_BUFFER_SIZE];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(bytes_read) => {
+                self.buffer.extend_from_slice(&buffer[..bytes_read]);
+                Ok(())
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads what the primary sent, failing when it does not answer in time.
+    fn fill_before_timeout(&mut self) -> io::Result<()> {
+        let received = self.buffer.len();
+        self.fill()?;
+        if self.buffer.len() == received {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        Ok(())
+    }
+}
+
+fn is_getack(arguments: &[Vec<u8>]) -> bool {
+    arguments.len() == 3
+        && arguments[0].eq_ignore_ascii_case(b"REPLCONF")
+        && arguments[1].eq_ignore_ascii_case(b"GETACK")
+}
+
+/// A random identifier of the history of the dataset, 40 hexadecimal characters.
+fn new_replid() -> String {
+    let mut random = Random::default();
+    (0..5)
+        .map(|_| format!("{:08x}", random.next_u64() as u32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::server::{Listener, Server};
+    use std::net::{SocketAddr, TcpListener};
+
+    /// Sends an inline command and waits for its reply.
+    fn request(stream: &mut TcpStream, command: &str) -> RespValue {
+        write!(stream, "{}\r\n", command).unwrap();
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            if let Some((value, _)) = parse_value(&response).unwrap() {
+                return value;
+            }
+            let bytes_read = stream.read(&mut buffer).unwrap();
+            assert!(bytes_read > 0, "connection closed");
+            response.extend_from_slice(&buffer[..bytes_read]);
+        }
+    }
+
+    fn bulk(value: &str) -> RespValue {
+        RespValue::BulkString(Some(value.as_bytes().to_vec()))
+    }
+
+    fn info(stream: &mut TcpStream) -> String {
+        match request(stream, "INFO replication") {
+            RespValue::BulkString(Some(info)) => String::from_utf8(info).unwrap(),
+            other => panic!("unexpected INFO reply: {:?}", other),
+        }
+    }
+
+    /// Repeats `check` until it succeeds, the replica applies changes asynchronously.
+    fn eventually(mut check: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !check() {
+            assert!(Instant::now() < deadline, "condition not met in time");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn serve(listener: &TcpListener) -> (Server, SocketAddr) {
+        let address = listener.local_addr().unwrap();
+        (
+            Server::new(vec![Listener::Tcp(listener.try_clone().unwrap())]).unwrap(),
+            address,
+        )
+    }
+
+    #[test]
+    fn replica_copies_the_primary() {
+        let primary_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let replica_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (mut primary_server, primary_address) = serve(&primary_listener);
+        let (mut replica_server, replica_address) = serve(&replica_listener);
+        let primary = Redis::default();
+        let mut replica = Redis::default();
+        replica.replication = Replication::new(replica_address.port());
+        let stopped = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let (primary, replica, stopped) = (&primary, &replica, &stopped);
+            scope.spawn(move || primary_server.run(primary, stopped).unwrap());
+            scope.spawn(move || replica_server.run(replica, stopped).unwrap());
+            scope.spawn(move || replica.replication.run(replica, stopped));
+
+            let mut to_primary = TcpStream::connect(primary_address).unwrap();
+            let mut to_replica = TcpStream::connect(replica_address).unwrap();
+            assert_eq!(
+                request(&mut to_primary, "SET before 1"),
+                RespValue::SimpleString("OK".to_string())
+            );
+            let replicaof = format!("REPLICAOF 127.0.0.1 {}", primary_address.port());
+            assert_eq!(
+                request(&mut to_replica, &replicaof),
+                RespValue::SimpleString("OK".to_string())
+            );
+
+            // the snapshot brings the keys written before, the command stream the others
+            eventually(|| info(&mut to_replica).contains("master_link_status:up"));
+            request(&mut to_primary, "SET after 2");
+            request(&mut to_primary, "EXPIRE after 100");
+            eventually(|| request(&mut to_replica, "GET after") == bulk("2"));
+            assert_eq!(request(&mut to_replica, "GET before"), bulk("1"));
+            request(&mut to_primary, "SELECT 2");
+            request(&mut to_primary, "SET other 3");
+            request(&mut to_replica, "SELECT 2");
+            eventually(|| request(&mut to_replica, "GET other") == bulk("3"));
+            request(&mut to_replica, "SELECT 0");
+            assert_eq!(
+                request(&mut to_replica, "GET other"),
+                RespValue::BulkString(None)
+            );
+            assert!(matches!(
+                request(&mut to_replica, "PTTL after"),
+                RespValue::Integer(ttl) if ttl > 90_000
+            ));
+            assert_eq!(
+                request(&mut to_replica, "SET k v"),
+                RespValue::Error("READONLY You can't write against a read only replica.".into())
+            );
+
+            let primary_info = info(&mut to_primary);
+            assert!(primary_info.contains("role:master\r\nconnected_slaves:1\r\n"));
+            assert!(primary_info.contains(&format!(
+                "slave0:ip=127.0.0.1,port={},state=online",
+                replica_address.port()
+            )));
+            // the replica acknowledges every command it received
+            eventually(|| {
+                let primary_info = info(&mut to_primary);
+                let offset = primary_info
+                    .lines()
+                    .find_map(|line| line.strip_prefix("master_repl_offset:"))
+                    .unwrap();
+                primary_info.contains(&format!("state=online,offset={},", offset))
+            });
+
+            assert_eq!(
+                request(&mut to_replica, "REPLICAOF NO ONE"),
+                RespValue::SimpleString("OK".to_string())
+            );
+            assert!(info(&mut to_replica).contains("role:master"));
+            assert_eq!(
+                request(&mut to_replica, "SET k v"),
+                RespValue::SimpleString("OK".to_string())
+            );
+            eventually(|| info(&mut to_primary).contains("connected_slaves:0"));
+
+            stopped.store(true, Ordering::Release);
+        });
+    }
+}
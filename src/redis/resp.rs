@@ -0,0 +1,584 @@
+use super::commands::format_float;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+// https://redis.io/docs/latest/develop/reference/protocol-spec/
+
+const MAX_BULK_LENGTH: i64 = 512 * 1024 * 1024;
+const MAX_ARRAY_LENGTH: i64 = 1024 * 1024;
+const MAX_INLINE_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum RespValue {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Option<Vec<u8>>),
+    Array(Option<Vec<RespValue>>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RespError {
+    UnexpectedByte(u8),
+    InvalidLength,
+    InvalidInteger,
+    MissingCrlf,
+    InlineTooBig,
+    UnbalancedQuotes,
+}
+
+impl Display for RespError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RespError::UnexpectedByte(byte) => {
+                write!(f, "Protocol error: unexpected '{}'", byte.escape_ascii())
+            }
+            RespError::InvalidLength => write!(f, "Protocol error: invalid length"),
+            RespError::InvalidInteger => write!(f, "Protocol error: invalid integer"),
+            RespError::MissingCrlf => write!(f, "Protocol error: expected '\\r\\n'"),
+            RespError::InlineTooBig => write!(f, "Protocol error: too big inline request"),
+            RespError::UnbalancedQuotes => {
+                write!(f, "Protocol error: unbalanced quotes in request")
+            }
+        }
+    }
+}
+
+impl Error for RespError {}
+
+/// A decoded item and how many bytes of the input it used, `None` if the input is incomplete.
+pub type Parsed<T> = Result<Option<(T, usize)>, RespError>;
+
+/// The version of the protocol spoken with a client, RESP2 until it asks for RESP3 with
+/// HELLO.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn version(self) -> i64 {
+        match self {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        }
+    }
+}
+
+/// A reply to a command, encoded in RESP when written back to the client. The types added
+/// by RESP3 are encoded as their closest RESP2 type for the clients speaking RESP2.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Nil,
+    Array(Vec<Reply>),
+    NilArray,
+    /// A flat array of keys and values in RESP2.
+    Map(Vec<(Reply, Reply)>),
+    /// An array in RESP2.
+    Set(Vec<Reply>),
+    /// A bulk string in RESP2.
+    Double(f64),
+    /// The integer 1 or 0 in RESP2.
+    Boolean(bool),
+    /// A message sent outside of the replies to the commands, e.g. by PUBLISH. An array
+    /// in RESP2.
+    Push(Vec<Reply>),
+    /// Several replies to a single command, e.g. one per channel of a SUBSCRIBE.
+    Multiple(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply::Simple("OK".to_string())
+    }
+
+    /// Nothing is sent back, e.g. for the acknowledgments of a replica.
+    pub fn none() -> Self {
+        Reply::Multiple(Vec::new())
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Reply::Error(message.into())
+    }
+
+    pub fn wrong_type() -> Self {
+        Reply::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    }
+
+    pub fn syntax_error() -> Self {
+        Reply::error("ERR syntax error")
+    }
+
+    pub fn not_an_integer() -> Self {
+        Reply::error("ERR value is not an integer or out of range")
+    }
+
+    pub fn not_a_float() -> Self {
+        Reply::error("ERR value is not a valid float")
+    }
+
+    pub fn wrong_number_of_arguments(command: &[u8]) -> Self {
+        Reply::error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            String::from_utf8_lossy(command).to_lowercase()
+        ))
+    }
+
+    pub fn bulk(value: impl Into<Vec<u8>>) -> Self {
+        Reply::Bulk(value.into())
+    }
+
+    pub fn bulk_array<T: Into<Vec<u8>>>(values: impl IntoIterator<Item = T>) -> Self {
+        Reply::Array(values.into_iter().map(Reply::bulk).collect())
+    }
+
+    /// Encodes the reply in the protocol of the client, RESP2 for the commands sent to the
+    /// replicas and written to the append only file.
+    pub fn encode(&self, protocol: Protocol, output: &mut Vec<u8>) {
+        let resp3 = protocol == Protocol::Resp3;
+        let aggregate = |output: &mut Vec<u8>, prefix: char, length: usize| {
+            output.extend_from_slice(format!("{}{}\r\n", prefix, length).as_bytes());
+        };
+
+        match self {
+            Reply::Simple(value) => {
+                output.push(b'+');
+                output.extend_from_slice(value.as_bytes());
+            }
+            Reply::Error(message) => {
+                output.push(b'-');
+                output.extend_from_slice(message.as_bytes());
+            }
+            Reply::Integer(value) => output.extend_from_slice(format!(":{}", value).as_bytes()),
+            Reply::Bulk(value) => {
+                output.extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+                output.extend_from_slice(value);
+            }
+            Reply::Nil | Reply::NilArray if resp3 => output.push(b'_'),
+            Reply::Nil => output.extend_from_slice(b"$-1"),
+            Reply::NilArray => output.extend_from_slice(b"*-1"),
+            Reply::Array(values) | Reply::Set(values) | Reply::Push(values) => {
+                let prefix = match self {
+                    Reply::Set(_) if resp3 => '~',
+                    Reply::Push(_) if resp3 => '>',
+                    _ => '*',
+                };
+                aggregate(output, prefix, values.len());
+                values
+                    .iter()
+                    .for_each(|value| value.encode(protocol, output));
+                return;
+            }
+            Reply::Map(pairs) => {
+                if resp3 {
+                    aggregate(output, '%', pairs.len());
+                } else {
+                    aggregate(output, '*', pairs.len() * 2);
+                }
+                for (key, value) in pairs {
+                    key.encode(protocol, output);
+                    value.encode(protocol, output);
+                }
+                return;
+            }
+            Reply::Double(value) if resp3 => {
+                output.extend_from_slice(format!(",{}", format_float(*value)).as_bytes())
+            }
+            Reply::Double(value) => {
+                return Reply::bulk(format_float(*value)).encode(protocol, output);
+            }
+            Reply::Boolean(value) if resp3 => {
+                output.extend_from_slice(if *value { b"#t" } else { b"#f" })
+            }
+            Reply::Boolean(value) => return Reply::Integer(*value as i64).encode(protocol, output),
+            Reply::Multiple(replies) => {
+                replies
+                    .iter()
+                    .for_each(|reply| reply.encode(protocol, output));
+                return;
+            }
+        }
+
+        output.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Decodes one RESP value from the start of `input`.
+/// Returns `Ok(None)` when `input` does not contain a whole value yet, otherwise
+/// the value together with the number of bytes it used.
+pub fn parse_value(input: &[u8]) -> Parsed<RespValue> {
+    let Some((line, mut consumed)) = read_line(input)? else {
+        return Ok(None);
+    };
+
+    let (prefix, content) = match line.split_first() {
+        Some(split) => split,
+        None => return Err(RespError::UnexpectedByte(b'\r')),
+    };
+
+    let value = match prefix {
+        b'+' => RespValue::SimpleString(String::from_utf8_lossy(content).into_owned()),
+        b'-' => RespValue::Error(String::from_utf8_lossy(content).into_owned()),
+        b':' => RespValue::Integer(parse_integer(content)?),
+        b'$' => {
+            let length = parse_length(content, MAX_BULK_LENGTH)?;
+            match length {
+                None => RespValue::BulkString(None),
+                Some(length) => {
+                    let end = consumed + length;
+                    if input.len() < end + 2 {
+                        return Ok(None);
+                    }
+                    if &input[end..end + 2] != b"\r\n" {
+                        return Err(RespError::MissingCrlf);
+                    }
+                    let data = input[consumed..end].to_vec();
+                    consumed = end + 2;
+                    RespValue::BulkString(Some(data))
+                }
+            }
+        }
+        b'*' => {
+            let length = parse_length(content, MAX_ARRAY_LENGTH)?;
+            match length {
+                None => RespValue::Array(None),
+                Some(length) => {
+                    let mut values = Vec::with_capacity(length.min(1024));
+                    for _ in 0..length {
+                        let Some((value, used)) = parse_value(&input[consumed..])? else {
+                            return Ok(None);
+                        };
+                        values.push(value);
+                        consumed += used;
+                    }
+                    RespValue::Array(Some(values))
+                }
+            }
+        }
+        other => return Err(RespError::UnexpectedByte(*other)),
+    };
+
+    Ok(Some((value, consumed)))
+}
+
+/// Decodes one client request from the start of `input`, either a RESP array of
+/// bulk strings or an inline command (space separated arguments ending with a newline).
+/// An empty request (e.g. a blank inline line) is returned as an empty vector.
+pub fn parse_command(input: &[u8]) -> Parsed<Vec<Vec<u8>>> {
+    match input.first() {
+        None => Ok(None),
+        Some(b'*') => parse_multibulk_command(input),
+        Some(_) => parse_inline_command(input),
+    }
+}
+
+fn parse_multibulk_command(input: &[u8]) -> Parsed<Vec<Vec<u8>>> {
+    let Some((line, mut consumed)) = read_line(input)? else {
+        return Ok(None);
+    };
+
+    let Some(length) = parse_length(&line[1..], MAX_ARRAY_LENGTH)? else {
+        return Ok(Some((vec![], consumed)));
+    };
+
+    let mut arguments = Vec::with_capacity(length.min(1024));
+    for _ in 0..length {
+        match input.get(consumed) {
+            None => return Ok(None),
+            Some(b'$') => {}
+            Some(other) => return Err(RespError::UnexpectedByte(*other)),
+        }
+
+        match parse_value(&input[consumed..])? {
+            None => return Ok(None),
+            Some((RespValue::BulkString(Some(argument)), used)) => {
+                arguments.push(argument);
+                consumed += used;
+            }
+            Some(_) => return Err(RespError::InvalidLength),
+        }
+    }
+
+    Ok(Some((arguments, consumed)))
+}
+
+fn parse_inline_command(input: &[u8]) -> Parsed<Vec<Vec<u8>>> {
+    let Some(newline) = input.iter().position(|b| *b == b'\n') else {
+        if input.len() > MAX_INLINE_LENGTH {
+            return Err(RespError::InlineTooBig);
+        }
+        return Ok(None);
+    };
+
+    let line = &input[..newline];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    Ok(Some((split_inline_arguments(line)?, newline + 1)))
+}
+
+fn split_inline_arguments(line: &[u8]) -> Result<Vec<Vec<u8>>, RespError> {
+    let mut arguments = vec![];
+    let mut bytes = line.iter().copied().peekable();
+
+    loop {
+        while bytes.next_if(|b| b.is_ascii_whitespace()).is_some() {}
+
+        let Some(first) = bytes.next() else {
+            return Ok(arguments);
+        };
+
+        let mut argument = vec![];
+        match first {
+            b'"' => loop {
+                match bytes.next() {
+                    None => return Err(RespError::UnbalancedQuotes),
+                    Some(b'"') => break,
+                    Some(b'\\') => match bytes.next() {
+                        Some(b'n') => argument.push(b'\n'),
+                        Some(b'r') => argument.push(b'\r'),
+                        Some(b't') => argument.push(b'\t'),
+                        Some(b'x') => {
+                            let hex = [bytes.next(), bytes.next()];
+                            let [Some(high), Some(low)] = hex else {
+                                return Err(RespError::UnbalancedQuotes);
+                            };
+                            match u8::from_str_radix(
+                                &format!("{}{}", high as char, low as char),
+                                16,
+                            ) {
+                                Ok(byte) => argument.push(byte),
+                                Err(_) => argument.extend_from_slice(&[b'x', high, low]),
+                            }
+                        }
+                        Some(other) => argument.push(other),
+                        None => return Err(RespError::UnbalancedQuotes),
+                    },
+                    Some(other) => argument.push(other),
+                }
+            },
+            b'\'' => loop {
+                match bytes.next() {
+                    None => return Err(RespError::UnbalancedQuotes),
+                    Some(b'\'') => break,
+                    Some(b'\\') if bytes.peek() == Some(&b'\'') => {
+                        argument.push(b'\'');
+                        bytes.next();
+                    }
+                    Some(other) => argument.push(other),
+                }
+            },
+            other => {
+                argument.push(other);
+                while let Some(byte) = bytes.next_if(|b| !b.is_ascii_whitespace()) {
+                    argument.push(byte);
+                }
+            }
+        }
+
+        if bytes.peek().is_some_and(|b| !b.is_ascii_whitespace()) {
+            return Err(RespError::UnbalancedQuotes);
+        }
+
+        arguments.push(argument);
+    }
+}
+
+fn read_line(input: &[u8]) -> Result<Option<(&[u8], usize)>, RespError> {
+    match input.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => Ok(Some((&input[..end], end + 2))),
+        None if input.len() > MAX_INLINE_LENGTH => Err(RespError::MissingCrlf),
+        None => Ok(None),
+    }
+}
+
+fn parse_integer(content: &[u8]) -> Result<i64, RespError> {
+    std::str::from_utf8(content)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or(RespError::InvalidInteger)
+}
+
+/// Parses the length of a bulk string or array, `-1` being the null value.
+fn parse_length(content: &[u8], max: i64) -> Result<Option<usize>, RespError> {
+    let length = parse_integer(content).map_err(|_| RespError::InvalidLength)?;
+
+    match length {
+        -1 => Ok(None),
+        length if (0..=max).contains(&length) => Ok(Some(length as usize)),
+        _ => Err(RespError::InvalidLength),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_simple_values() {
+        assert_eq!(
+            parse_value(b"+OK\r\n"),
+            Ok(Some((RespValue::SimpleString("OK".to_string()), 5)))
+        );
+        assert_eq!(
+            parse_value(b"-ERR boom\r\n"),
+            Ok(Some((RespValue::Error("ERR boom".to_string()), 11)))
+        );
+        assert_eq!(
+            parse_value(b":-42\r\n"),
+            Ok(Some((RespValue::Integer(-42), 6)))
+        );
+        assert_eq!(
+            parse_value(b"$-1\r\n"),
+            Ok(Some((RespValue::BulkString(None), 5)))
+        );
+        assert_eq!(
+            parse_value(b"*-1\r\n"),
+            Ok(Some((RespValue::Array(None), 5)))
+        );
+    }
+
+    #[test]
+    fn parse_bulk_string_with_crlf() {
+        assert_eq!(
+            parse_value(b"$4\r\na\r\nb\r\n"),
+            Ok(Some((RespValue::BulkString(Some(b"a\r\nb".to_vec())), 10)))
+        );
+    }
+
+    #[test]
+    fn parse_nested_array() {
+        assert_eq!(
+            parse_value(b"*2\r\n:1\r\n*1\r\n$2\r\nhi\r\n"),
+            Ok(Some((
+                RespValue::Array(Some(vec![
+                    RespValue::Integer(1),
+                    RespValue::Array(Some(vec![RespValue::BulkString(Some(b"hi".to_vec()))]))
+                ])),
+                20
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_partial_input() {
+        let input = b"*2\r\n$4\r\nECHO\r\n$11\r\nHello World\r\n";
+
+        for end in 0..input.len() {
+            assert_eq!(parse_command(&input[..end]), Ok(None));
+        }
+
+        assert_eq!(
+            parse_command(input),
+            Ok(Some((
+                vec![b"ECHO".to_vec(), b"Hello World".to_vec()],
+                input.len()
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_pipelined_commands() {
+        let input = b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$1\r\na\r\n";
+
+        let (first, used) = parse_command(input).unwrap().unwrap();
+        assert_eq!(first, vec![b"PING".to_vec()]);
+
+        let (second, _) = parse_command(&input[used..]).unwrap().unwrap();
+        assert_eq!(second, vec![b"GET".to_vec(), b"a".to_vec()]);
+    }
+
+    #[test]
+    fn parse_inline_commands() {
+        assert_eq!(
+            parse_command(b"SET key value\r\n"),
+            Ok(Some((
+                vec![b"SET".to_vec(), b"key".to_vec(), b"value".to_vec()],
+                15
+            )))
+        );
+        assert_eq!(
+            parse_command(b"ECHO \"hello\\r\\n world\" 'it''\n"),
+            Err(RespError::UnbalancedQuotes)
+        );
+        assert_eq!(
+            parse_command(b"ECHO \"hello\\r\\n world\" 'it\\'s'\n"),
+            Ok(Some((
+                vec![
+                    b"ECHO".to_vec(),
+                    b"hello\r\n world".to_vec(),
+                    b"it's".to_vec()
+                ],
+                31
+            )))
+        );
+        assert_eq!(parse_command(b"\r\n"), Ok(Some((vec![], 2))));
+        assert_eq!(parse_command(b"PING"), Ok(None));
+    }
+
+    #[test]
+    fn encode_replies() {
+        let mut output = Vec::new();
+        Reply::Array(vec![
+            Reply::ok(),
+            Reply::Integer(3),
+            Reply::bulk("a\r\nb"),
+            Reply::Nil,
+            Reply::Array(vec![]),
+            Reply::error("ERR boom"),
+        ])
+        .encode(Protocol::Resp2, &mut output);
+
+        assert_eq!(
+            output,
+            b"*6\r\n+OK\r\n:3\r\n$4\r\na\r\nb\r\n$-1\r\n*0\r\n-ERR boom\r\n"
+        );
+
+        let mut output = Vec::new();
+        Reply::Multiple(vec![Reply::Integer(1), Reply::bulk_array(["a"])])
+            .encode(Protocol::Resp2, &mut output);
+        assert_eq!(output, b":1\r\n*1\r\n$1\r\na\r\n");
+    }
+
+    #[test]
+    fn encode_resp3_replies() {
+        let reply = Reply::Array(vec![
+            Reply::Map(vec![(Reply::bulk("a"), Reply::Double(1.5))]),
+            Reply::Set(vec![Reply::bulk("b")]),
+            Reply::Boolean(true),
+            Reply::Nil,
+            Reply::Double(f64::NEG_INFINITY),
+            Reply::Push(vec![Reply::Integer(1)]),
+        ]);
+
+        let mut output = Vec::new();
+        reply.encode(Protocol::Resp3, &mut output);
+        assert_eq!(
+            output,
+            b"*6\r\n%1\r\n$1\r\na\r\n,1.5\r\n~1\r\n$1\r\nb\r\n#t\r\n_\r\n,-inf\r\n>1\r\n:1\r\n"
+        );
+
+        let mut output = Vec::new();
+        reply.encode(Protocol::Resp2, &mut output);
+        assert_eq!(
+            output,
+            b"*6\r\n*2\r\n$1\r\na\r\n$3\r\n1.5\r\n*1\r\n$1\r\nb\r\n:1\r\n$-1\r\n$4\r\n-inf\r\n*1\r\n:1\r\n"
+        );
+    }
+
+    #[test]
+    fn parse_invalid_input() {
+        assert_eq!(
+            parse_command(b"*1\r\n:1\r\n"),
+            Err(RespError::UnexpectedByte(b':'))
+        );
+        assert_eq!(parse_command(b"*x\r\n"), Err(RespError::InvalidLength));
+        assert_eq!(parse_value(b"$2\r\nhello\r\n"), Err(RespError::MissingCrlf));
+        assert_eq!(parse_value(b"?\r\n"), Err(RespError::UnexpectedByte(b'?')));
+    }
+}
@@ -0,0 +1,716 @@
+use super::client::{Client, Outbox, Waker};
+use super::poller::{Event, Interest, Poller};
+use super::Redis;
+use build_your_own_utils::my_own_error::MyOwnError;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Token of the notifier, readable when messages were pushed to some connections.
+const NOTIFIER: u64 = u64::MAX;
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+/// Longest time waiting for clients without checking whether the server is stopping.
+const STOPPED_CHECK_PERIOD: Duration = Duration::from_millis(100);
+/// Longest time waiting for the pending replies to be sent once the server stops, as the
+/// shutdown-timeout of redis.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A socket clients connect to, over TCP or a unix domain socket.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// The socket of a client accepted by a `Listener`.
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// A client connection with the bytes received but not processed yet. The replies not
+/// sent yet are in the outbox of the client.
+struct Connection {
+    stream: Stream,
+    request: Vec<u8>,
+    client: Client,
+    /// Set on a protocol error, when the client closed its side or when it was killed, the
+    /// connection is dropped once the pending replies are sent.
+    closing: bool,
+    interest: Interest,
+}
+
+/// Wakes up an event loop from other threads with the tokens of the connections that
+/// got messages, through a socket pair the event loop waits on.
+struct Notifier {
+    pending: Mutex<Vec<u64>>,
+    sender: UnixStream,
+    receiver: UnixStream,
+}
+
+/// Notifies the event loop serving a connection when a message is pushed to its outbox.
+struct ConnectionWaker {
+    notifier: Arc<Notifier>,
+    token: u64,
+}
+
+/// Single threaded event loop serving every client of the listeners, waiting for sockets
+/// to be ready instead of blocking on any of them.
+pub struct Server {
+    /// Registered with their index as token, connections get increasing tokens starting
+    /// after them.
+    listeners: Vec<Listener>,
+    poller: Poller,
+    notifier: Arc<Notifier>,
+    connections: HashMap<u64, Connection>,
+    next_token: u64,
+    events: Vec<Event>,
+}
+
+impl Server {
+    pub fn new(listeners: Vec<Listener>) -> io::Result<Self> {
+        let poller = Poller::new()?;
+        for (token, listener) in listeners.iter().enumerate() {
+            listener.set_nonblocking(true)?;
+            poller.register(listener, token as u64, Interest::Readable)?;
+        }
+        let notifier = Notifier::new()?;
+        poller.register(&notifier.receiver, NOTIFIER, Interest::Readable)?;
+
+        Ok(Self {
+            next_token: listeners.len() as u64,
+            listeners,
+            poller,
+            notifier: Arc::new(notifier),
+            connections: HashMap::new(),
+            events: Vec::new(),
+        })
+    }
+
+    /// Serves clients until `stopped` is set or the server is shutting down, then drains
+    /// the connections.
+    pub fn run(&mut self, redis: &Redis, stopped: &AtomicBool) -> io::Result<()> {
+        while !stopped.load(Ordering::Acquire) && !redis.is_shutting_down() {
+            self.run_once(redis, Some(STOPPED_CHECK_PERIOD))?;
+        }
+        self.drain(redis)
+    }
+
+    /// Stops accepting clients and closes the connections once the replies already
+    /// produced are sent, without executing the commands received meanwhile. The clients
+    /// still not done after `DRAIN_TIMEOUT` are disconnected anyway.
+    fn drain(&mut self, redis: &Redis) -> io::Result<()> {
+        for listener in self.listeners.drain(..) {
+            let _ = self.poller.deregister(&listener);
+        }
+
+        let tokens = self.connections.keys().copied().collect::<Vec<_>>();
+        for &token in &tokens {
+            if let Some(connection) = self.connections.get_mut(&token) {
+                connection.closing = true;
+            }
+            self.flush(redis, token);
+        }
+
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        while !self.connections.is_empty() && Instant::now() < deadline {
+            self.run_once(redis, Some(STOPPED_CHECK_PERIOD))?;
+        }
+        for token in tokens {
+            self.close(redis, token);
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for sockets to be ready and serves them.
+    pub fn run_once(&mut self, redis: &Redis, timeout: Option<Duration>) -> io::Result<()> {
+        let mut events = std::mem::take(&mut self.events);
+        self.poller.wait(&mut events, timeout)?;
+
+        for event in &events {
+            match event.token {
+                token if token < self.listeners.len() as u64 => {
+                    self.accept(redis, token as usize)?
+                }
+                NOTIFIER => {
+                    for token in self.notifier.take() {
+                        self.resume(redis, token);
+                    }
+                }
+                _ => self.serve(redis, event),
+            }
+        }
+
+        self.events = events;
+        Ok(())
+    }
+
+    fn accept(&mut self, redis: &Redis, listener: usize) -> io::Result<()> {
+        loop {
+            let (stream, address) = match self.listeners[listener].accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // the client went away before being accepted, or the process ran out of
+                // file descriptors: the other connections can still be served
+                Err(e) => {
+                    eprintln!("Error accepting a connection: {}", e);
+                    return Ok(());
+                }
+            };
+
+            let token = self.next_token;
+            self.next_token += 1;
+            let registered = stream
+                .set_nonblocking(true)
+                .and_then(|_| stream.set_nodelay(true))
+                .and_then(|_| self.poller.register(&stream, token, Interest::Readable));
+            if let Err(e) = registered {
+                eprintln!(
+                    "Error accepting a connection from {}: {}",
+                    describe(address),
+                    e
+                );
+                continue;
+            }
+
+            let waker = ConnectionWaker {
+                notifier: Arc::clone(&self.notifier),
+                token,
+            };
+            let mut client = Client::new(Outbox::new(waker));
+            client.address = address;
+            client.authenticated = redis.acl.authenticates_new_clients();
+            redis.clients.register(&mut client, SystemTime::now());
+            redis.stats.connection_opened();
+            self.connections.insert(
+                token,
+                Connection {
+                    stream,
+                    request: Vec::new(),
+                    client,
+                    closing: false,
+                    interest: Interest::Readable,
+                },
+            );
+        }
+    }
+
+    /// Serves a ready connection, closing it when it fails without affecting the others.
+    fn serve(&mut self, redis: &Redis, event: &Event) {
+        let Some(connection) = self.connections.get_mut(&event.token) else {
+            return;
+        };
+
+        connection.closing |= connection.client.outbox.is_closed();
+        if event.readable && !connection.closing {
+            // the replies already produced, e.g. the error for a protocol error, are
+            // still sent before closing
+            if let Err(e) = connection.read(redis) {
+                connection.log(&e);
+                connection.closing = true;
+            }
+        }
+
+        self.flush(redis, event.token);
+    }
+
+    /// Continues serving a connection another thread pushed replies to: when one of them
+    /// unblocked the client, the commands it sent meanwhile are executed.
+    fn resume(&mut self, redis: &Redis, token: u64) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        // killed by another client
+        connection.closing |= connection.client.outbox.is_closed();
+        if !connection.closing {
+            if let Err(e) = connection.execute(redis) {
+                connection.log(&e);
+                connection.closing = true;
+            }
+        }
+
+        self.flush(redis, token);
+    }
+
+    /// Sends what the outbox of a connection holds and waits for the socket to accept
+    /// the rest, closing the connection when it fails or once a closing one is done.
+    fn flush(&mut self, redis: &Redis, token: u64) {
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        if connection.client.outbox.overflowed() {
+            connection.log(&MyOwnError::from("output buffer limit reached"));
+            self.close(redis, token);
+            return;
+        }
+
+        // a client killing itself gets the reply first
+        connection.closing |= connection.client.outbox.is_closed();
+        let result = connection.write().and_then(|_| {
+            let is_empty = connection.client.outbox.lock().is_empty();
+            let interest = match (connection.closing, is_empty) {
+                (true, true) => return Ok(false),
+                (true, false) => Interest::Writable,
+                (false, true) => Interest::Readable,
+                (false, false) => Interest::ReadableWritable,
+            };
+            if interest != connection.interest {
+                connection.interest = interest;
+                self.poller
+                    .reregister(&connection.stream, token, interest)?;
+            }
+            Ok(true)
+        });
+
+        match result {
+            Ok(true) => {}
+            Ok(false) => self.close(redis, token),
+            Err(e) => {
+                connection.log(&e);
+                self.close(redis, token);
+            }
+        }
+    }
+
+    fn close(&mut self, redis: &Redis, token: u64) {
+        if let Some(mut connection) = self.connections.remove(&token) {
+            let _ = self.poller.deregister(&connection.stream);
+            redis.disconnect(&mut connection.client);
+            redis.stats.connection_closed();
+        }
+    }
+}
+
+impl Connection {
+    /// Reads what is available and executes the complete commands received so far.
+    fn read(&mut self, redis: &Redis) -> Result<(), MyOwnError> {
+        let mut buffer = [0; READ_BUFFER_SIZE];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => self.closing = true,
+            Ok(bytes_read) => self.request.extend_from_slice(&buffer[..bytes_read]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        self.execute(redis)
+    }
+
+    /// Executes the complete commands received so far.
+    fn execute(&mut self, redis: &Redis) -> Result<(), MyOwnError> {
+        // a bug in a command must not take down the event loop and its other clients
+        let outbox = Arc::clone(&self.client.outbox);
+        let processed = panic::catch_unwind(AssertUnwindSafe(|| {
+            redis.process(
+                &mut self.client,
+                &self.request,
+                &*outbox,
+                &SystemTime::now(),
+            )
+        }))
+        .map_err(|_| MyOwnError::from("Internal error executing a command"))
+        .and_then(|processed| processed);
+
+        match processed {
+            Ok(processed) => {
+                self.request.drain(..processed);
+                Ok(())
+            }
+            // the rest of the input is meaningless after a protocol error
+            Err(e) => {
+                self.request.clear();
+                Err(e)
+            }
+        }
+    }
+
+    /// Sends as much of the pending replies as the socket accepts.
+    fn write(&mut self) -> Result<(), MyOwnError> {
+        let mut response = self.client.outbox.lock();
+        while !response.is_empty() {
+            match self.stream.write(&response) {
+                Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(written) => {
+                    response.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn log(&self, error: &MyOwnError) {
+        eprintln!(
+            "Closing connection from {}: {}",
+            describe(self.client.address),
+            error
+        );
+    }
+}
+
+/// The peer of a connection in the logs, unix socket clients have no address.
+fn describe(address: Option<SocketAddr>) -> String {
+    address.map_or_else(
+        || "the unix socket".to_string(),
+        |address| address.to_string(),
+    )
+}
+
+impl Listener {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Listener::Tcp(listener) => listener.try_clone().map(Listener::Tcp),
+            Listener::Unix(listener) => listener.try_clone().map(Listener::Unix),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Accepts a client, with its address when it connected over TCP.
+    fn accept(&self) -> io::Result<(Stream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, address)| (Stream::Tcp(stream), Some(address))),
+            Listener::Unix(listener) => listener
+                .accept()
+                .map(|(stream, _)| (Stream::Unix(stream), None)),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+impl Stream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Disables Nagle's algorithm, unix sockets have nothing alike.
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buffer),
+            Stream::Unix(stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(bytes),
+            Stream::Unix(stream) => stream.write(bytes),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Notifier {
+    fn new() -> io::Result<Self> {
+        let (sender, receiver) = UnixStream::pair()?;
+        sender.set_nonblocking(true)?;
+        receiver.set_nonblocking(true)?;
+        Ok(Self {
+            pending: Mutex::default(),
+            sender,
+            receiver,
+        })
+    }
+
+    fn notify(&self, token: u64) {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let was_empty = pending.is_empty();
+        pending.push(token);
+        drop(pending);
+
+        // one byte is enough until the event loop takes the pending tokens, a full
+        // socket means the event loop is going to wake up anyway
+        if was_empty {
+            let _ = (&self.sender).write(&[1]);
+        }
+    }
+
+    /// Takes the tokens notified since the last call.
+    fn take(&self) -> Vec<u64> {
+        // the wake up bytes are consumed first, a token notified in the meantime is
+        // taken now and its byte only causes a spurious wake up
+        let mut buffer = [0; 64];
+        while matches!((&self.receiver).read(&mut buffer), Ok(read) if read > 0) {}
+
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut tokens = std::mem::take(&mut *pending);
+        drop(pending);
+        tokens.sort_unstable();
+        tokens.dedup();
+        tokens
+    }
+}
+
+impl Waker for ConnectionWaker {
+    fn wake(&self) {
+        self.notifier.notify(self.token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn serves_more_clients_than_threads() {
+        const CLIENTS: usize = 200;
+        let redis = Redis::default();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = Server::new(vec![Listener::Tcp(listener)]).unwrap();
+        let finished = AtomicUsize::new(0);
+        let connected = Barrier::new(CLIENTS);
+
+        thread::scope(|scope| {
+            for i in 0..CLIENTS {
+                let (finished, connected) = (&finished, &connected);
+                scope.spawn(move || {
+                    let mut stream = TcpStream::connect(address).unwrap();
+                    // every client stays connected until all of them are
+                    connected.wait();
+
+                    let key = format!("key:{}", i);
+                    write!(stream, "SET {} {}\r\nGET {}\r\n", key, i, key).unwrap();
+                    let expected = format!("+OK\r\n${}\r\n{}\r\n", i.to_string().len(), i);
+                    let mut response = vec![0; expected.len()];
+                    stream.read_exact(&mut response).unwrap();
+                    assert_eq!(String::from_utf8(response).unwrap(), expected);
+
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+
+            while finished.load(Ordering::SeqCst) < CLIENTS {
+                server
+                    .run_once(&redis, Some(Duration::from_millis(10)))
+                    .unwrap();
+            }
+        });
+        assert!(redis
+            .stats
+            .info(0)
+            .contains(&format!("total_connections_received:{}\r\n", CLIENTS)));
+    }
+
+    #[test]
+    fn protocol_error_closes_only_the_offending_connection() {
+        let redis = Redis::default();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut hostile = TcpStream::connect(address).unwrap();
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut server = Server::new(vec![Listener::Tcp(listener)]).unwrap();
+
+        let run_until = |server: &mut Server, done: &dyn Fn() -> bool| {
+            while !done() {
+                server
+                    .run_once(&redis, Some(Duration::from_millis(10)))
+                    .unwrap();
+            }
+        };
+
+        // the server closes the connection by itself after replying with the error
+        hostile.write_all(b"*1\r\n+PING\r\nGET k\r\n").unwrap();
+        let mut response = Vec::new();
+        thread::scope(|scope| {
+            let reader = scope.spawn(|| hostile.read_to_end(&mut response).unwrap());
+            run_until(&mut server, &|| reader.is_finished());
+        });
+        assert_eq!(response, b"-ERR Protocol error: unexpected '+'\r\n");
+        assert_eq!(server.connections.len(), 1);
+
+        client.write_all(b"PING\r\n").unwrap();
+        let mut response = [0; 7];
+        thread::scope(|scope| {
+            let reader = scope.spawn(|| client.read_exact(&mut response).unwrap());
+            run_until(&mut server, &|| reader.is_finished());
+        });
+        assert_eq!(&response, b"+PONG\r\n");
+    }
+
+    #[test]
+    fn messages_are_pushed_to_subscribers_of_other_event_loops() {
+        let redis = Redis::default();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let stopped = AtomicBool::new(false);
+
+        let read_reply = |stream: &mut TcpStream, expected: &[u8]| {
+            let mut response = vec![0; expected.len()];
+            stream.read_exact(&mut response).unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&response),
+                String::from_utf8_lossy(expected)
+            );
+        };
+
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                let mut server =
+                    Server::new(vec![Listener::Tcp(listener.try_clone().unwrap())]).unwrap();
+                let (redis, stopped) = (&redis, &stopped);
+                scope.spawn(move || server.run(redis, stopped).unwrap());
+            }
+
+            let mut subscriber = TcpStream::connect(address).unwrap();
+            subscriber.write_all(b"SUBSCRIBE news\r\n").unwrap();
+            read_reply(
+                &mut subscriber,
+                b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n",
+            );
+
+            let mut publisher = TcpStream::connect(address).unwrap();
+            publisher.write_all(b"PUBLISH news hello\r\n").unwrap();
+            read_reply(&mut publisher, b":1\r\n");
+            read_reply(
+                &mut subscriber,
+                b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n",
+            );
+
+            subscriber.write_all(b"GET news\r\n").unwrap();
+            read_reply(
+                &mut subscriber,
+                b"-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n",
+            );
+
+            // the subscription ends with the connection
+            drop(subscriber);
+            let mut unsubscribed = false;
+            while !unsubscribed {
+                publisher.write_all(b"PUBLISH news again\r\n").unwrap();
+                let mut response = [0; 4];
+                publisher.read_exact(&mut response).unwrap();
+                unsubscribed = &response == b":0\r\n";
+            }
+            stopped.store(true, Ordering::Release);
+        });
+    }
+
+    #[test]
+    fn blocked_client_resumes_once_served() {
+        let redis = Redis::default();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = Server::new(vec![Listener::Tcp(listener)]).unwrap();
+        let stopped = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let (redis, stopped) = (&redis, &stopped);
+            scope.spawn(move || server.run(redis, stopped).unwrap());
+
+            let mut blocked = TcpStream::connect(address).unwrap();
+            blocked.write_all(b"BLPOP queue 0\r\nPING\r\n").unwrap();
+            // the blocked client is registered once its connection has been served
+            let mut pusher = TcpStream::connect(address).unwrap();
+            let mut response = [0; 4];
+            loop {
+                pusher.write_all(b"LLEN queue\r\n").unwrap();
+                pusher.read_exact(&mut response).unwrap();
+                if redis.blocking.has_blocked() {
+                    break;
+                }
+            }
+
+            pusher.write_all(b"RPUSH queue job\r\n").unwrap();
+            let expected = b"*2\r\n$5\r\nqueue\r\n$3\r\njob\r\n+PONG\r\n";
+            let mut response = vec![0; expected.len()];
+            blocked.read_exact(&mut response).unwrap();
+            assert_eq!(response, expected);
+
+            stopped.store(true, Ordering::Release);
+        });
+    }
+
+    #[test]
+    fn shutdown_drains_every_listener() {
+        let redis = Redis::default();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = tcp.local_addr().unwrap();
+        let path = std::env::temp_dir().join(format!("redis-server-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let unix = UnixListener::bind(&path).unwrap();
+        let mut server = Server::new(vec![Listener::Tcp(tcp), Listener::Unix(unix)]).unwrap();
+        let stopped = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let (redis, stopped) = (&redis, &stopped);
+            let event_loop = scope.spawn(move || server.run(redis, stopped));
+
+            let mut idle = TcpStream::connect(address).unwrap();
+            let mut client = UnixStream::connect(&path).unwrap();
+            client.write_all(b"SET k v\r\nCLIENT LIST\r\n").unwrap();
+            let mut response = [0; 6];
+            client.read_exact(&mut response).unwrap();
+            assert_eq!(&response, b"+OK\r\n$");
+
+            // every connection gets the replies produced before the server stops
+            client.write_all(b"SHUTDOWN NOSAVE\r\n").unwrap();
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).unwrap();
+            assert!(String::from_utf8(response).unwrap().contains(" addr= "));
+            let mut response = Vec::new();
+            idle.read_to_end(&mut response).unwrap();
+            assert!(response.is_empty());
+
+            event_loop.join().unwrap().unwrap();
+            assert!(TcpStream::connect(address).is_err());
+        });
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+use std::ffi::c_int;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// https://man7.org/linux/man-pages/man2/signal.2.html
+
+const SIGINT: c_int = 2;
+const SIGTERM: c_int = 15;
+/// Returned by `signal` on failure.
+const SIG_ERR: usize = usize::MAX;
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+}
+
+/// Set by the handler, only async-signal-safe operations are allowed there.
+static TERMINATION_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_termination(_signum: c_int) {
+    TERMINATION_REQUESTED.store(true, Ordering::Release);
+}
+
+/// Records SIGINT and SIGTERM instead of killing the process, so that the server shuts
+/// down as with SHUTDOWN once `termination_requested` notices them.
+pub fn handle_termination_signals() -> io::Result<()> {
+    for signum in [SIGINT, SIGTERM] {
+        if unsafe { signal(signum, request_termination) } == SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Whether SIGINT or SIGTERM was received since the last call.
+pub fn termination_requested() -> bool {
+    TERMINATION_REQUESTED.swap(false, Ordering::AcqRel)
+}
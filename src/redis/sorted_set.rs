@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+/// A score with a total order so it can be used as part of a `BTreeSet` key.
+#[derive(Debug, Clone, Copy)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by score and then lexicographically, as in redis.
+#[derive(Default, Clone)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    ordered: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets the score of `member`, returning the previous one if it was already present.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> Option<f64> {
+        let previous = self.scores.insert(member.clone(), score);
+
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+
+        previous
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.ordered.remove(&(Score(score), member.to_vec()));
+        Some(score)
+    }
+
+    /// Zero-based position of `member` in ascending order.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(
+            self.ordered
+                .range(..(Score(score), member.to_vec()))
+                .count(),
+        )
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        self.ordered
+            .iter()
+            .map(|(score, member)| (member.as_slice(), score.0))
+    }
+
+    /// Members with a score within `min` and `max`, in ascending order.
+    pub fn range_by_score(
+        &self,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        self.iter().filter(move |(_, score)| {
+            let above_min = match min {
+                Bound::Included(min) => *score >= min,
+                Bound::Excluded(min) => *score > min,
+                Bound::Unbounded => true,
+            };
+            let below_max = match max {
+                Bound::Included(max) => *score <= max,
+                Bound::Excluded(max) => *score < max,
+                Bound::Unbounded => true,
+            };
+            above_min && below_max
+        })
+    }
+
+    /// Members within `min` and `max` compared byte by byte, meaningful when all scores are equal.
+    pub fn range_by_lex<'a>(
+        &'a self,
+        min: Bound<&'a [u8]>,
+        max: Bound<&'a [u8]>,
+    ) -> impl DoubleEndedIterator<Item = (&'a [u8], f64)> {
+        self.iter().filter(move |(member, _)| {
+            let above_min = match min {
+                Bound::Included(min) => *member >= min,
+                Bound::Excluded(min) => *member > min,
+                Bound::Unbounded => true,
+            };
+            let below_max = match max {
+                Bound::Included(max) => *member <= max,
+                Bound::Excluded(max) => *member < max,
+                Bound::Unbounded => true,
+            };
+            above_min && below_max
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordered_by_score_then_member() {
+        let mut sorted_set = SortedSet::default();
+        sorted_set.insert(b"b".to_vec(), 1.0);
+        sorted_set.insert(b"a".to_vec(), 1.0);
+        sorted_set.insert(b"c".to_vec(), 0.5);
+
+        let members = sorted_set.iter().map(|(m, _)| m).collect::<Vec<_>>();
+        assert_eq!(members, vec![b"c".as_slice(), b"a", b"b"]);
+        assert_eq!(sorted_set.rank(b"b"), Some(2));
+    }
+
+    #[test]
+    fn update_score() {
+        let mut sorted_set = SortedSet::default();
+        assert_eq!(sorted_set.insert(b"a".to_vec(), 1.0), None);
+        assert_eq!(sorted_set.insert(b"a".to_vec(), 3.0), Some(1.0));
+        assert_eq!(sorted_set.len(), 1);
+        assert_eq!(sorted_set.iter().next(), Some((b"a".as_slice(), 3.0)));
+
+        assert_eq!(sorted_set.remove(b"a"), Some(3.0));
+        assert!(sorted_set.is_empty());
+        assert_eq!(sorted_set.iter().next(), None);
+    }
+
+    #[test]
+    fn range_by_score() {
+        let mut sorted_set = SortedSet::default();
+        for (member, score) in [("a", 1.0), ("b", 2.0), ("c", 3.0)] {
+            sorted_set.insert(member.as_bytes().to_vec(), score);
+        }
+
+        let members = sorted_set
+            .range_by_score(Bound::Excluded(1.0), Bound::Unbounded)
+            .map(|(m, _)| m)
+            .collect::<Vec<_>>();
+        assert_eq!(members, vec![b"b".as_slice(), b"c"]);
+    }
+}
@@ -0,0 +1,182 @@
+use super::commands::table::{Command, COMMANDS};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Counters of the server activity since it started or since CONFIG RESETSTAT, reported
+/// by INFO.
+pub struct Stats {
+    connected_clients: AtomicUsize,
+    connections_received: AtomicU64,
+    commands_processed: AtomicU64,
+    evicted_keys: AtomicU64,
+    error_replies: AtomicU64,
+    /// The counters of every command of the table, by name.
+    commands: HashMap<&'static str, CommandStats>,
+}
+
+#[derive(Default)]
+struct CommandStats {
+    calls: AtomicU64,
+    /// Total time spent executing the command, in microseconds.
+    usec: AtomicU64,
+    /// Calls refused before being executed, as with the wrong number of arguments.
+    rejected_calls: AtomicU64,
+    /// Calls executed but replying with an error.
+    failed_calls: AtomicU64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            connected_clients: AtomicUsize::new(0),
+            connections_received: AtomicU64::new(0),
+            commands_processed: AtomicU64::new(0),
+            evicted_keys: AtomicU64::new(0),
+            error_replies: AtomicU64::new(0),
+            commands: COMMANDS
+                .iter()
+                .map(|command| (command.name, CommandStats::default()))
+                .collect(),
+        }
+    }
+}
+
+impl Stats {
+    pub fn connection_opened(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        self.connections_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.connected_clients.load(Ordering::Relaxed)
+    }
+
+    /// Records an executed command, `failed` when it replied with an error.
+    pub fn called(&self, command: &Command, duration: Duration, failed: bool) {
+        self.commands_processed.fetch_add(1, Ordering::Relaxed);
+        let stats = &self.commands[command.name];
+        stats.calls.fetch_add(1, Ordering::Relaxed);
+        stats
+            .usec
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        if failed {
+            stats.failed_calls.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a command refused before being executed.
+    pub fn rejected(&self, command: &Command) {
+        self.commands[command.name]
+            .rejected_calls
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn evicted(&self) {
+        self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn error_replied(&self) {
+        self.error_replies.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Resets the counters, the connected clients are still counted.
+    pub fn reset(&self) {
+        for counter in [
+            &self.connections_received,
+            &self.commands_processed,
+            &self.evicted_keys,
+            &self.error_replies,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        for stats in self.commands.values() {
+            for counter in [
+                &stats.calls,
+                &stats.usec,
+                &stats.rejected_calls,
+                &stats.failed_calls,
+            ] {
+                counter.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The stats section of INFO, with the keys expired as counted by the database.
+    pub fn info(&self, expired_keys: u64) -> String {
+        let mut info = String::from("# Stats\r\n");
+        let _ = write!(
+            info,
+            "total_connections_received:{}\r\n\
+             total_commands_processed:{}\r\n\
+             expired_keys:{}\r\n\
+             evicted_keys:{}\r\n\
+             total_error_replies:{}\r\n",
+            self.connections_received.load(Ordering::Relaxed),
+            self.commands_processed.load(Ordering::Relaxed),
+            expired_keys,
+            self.evicted_keys.load(Ordering::Relaxed),
+            self.error_replies.load(Ordering::Relaxed),
+        );
+        info
+    }
+
+    /// The commandstats section of INFO, with the commands called at least once.
+    pub fn command_info(&self) -> String {
+        let mut info = String::from("# Commandstats\r\n");
+        for command in COMMANDS {
+            let stats = &self.commands[command.name];
+            let calls = stats.calls.load(Ordering::Relaxed);
+            let rejected_calls = stats.rejected_calls.load(Ordering::Relaxed);
+            if calls == 0 && rejected_calls == 0 {
+                continue;
+            }
+
+            let usec = stats.usec.load(Ordering::Relaxed);
+            let _ = write!(
+                info,
+                "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}\r\n",
+                command.name.to_ascii_lowercase(),
+                calls,
+                usec,
+                if calls == 0 { 0.0 } else { usec as f64 / calls as f64 },
+                rejected_calls,
+                stats.failed_calls.load(Ordering::Relaxed),
+            );
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::commands::table::lookup;
+
+    #[test]
+    fn command_counters() {
+        let stats = Stats::default();
+        let get = lookup(b"GET").unwrap();
+        stats.called(get, Duration::from_micros(3), false);
+        stats.called(get, Duration::from_micros(2), true);
+        stats.rejected(lookup(b"SET").unwrap());
+
+        assert_eq!(
+            stats.command_info(),
+            "# Commandstats\r\n\
+             cmdstat_set:calls=0,usec=0,usec_per_call=0.00,rejected_calls=1,failed_calls=0\r\n\
+             cmdstat_get:calls=2,usec=5,usec_per_call=2.50,rejected_calls=0,failed_calls=1\r\n"
+        );
+        assert!(stats
+            .info(7)
+            .contains("total_commands_processed:2\r\nexpired_keys:7\r\n"));
+
+        stats.reset();
+        assert_eq!(stats.command_info(), "# Commandstats\r\n");
+    }
+}
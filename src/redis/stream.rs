@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::time::SystemTime;
+
+/// The ID of a stream entry: the unix time in milliseconds it was added at, and a sequence
+/// number telling apart the entries added during the same millisecond.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId::new(0, 0);
+    pub const MAX: StreamId = StreamId::new(u64::MAX, u64::MAX);
+
+    pub const fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Parses `ms-seq`, or `ms` alone with `missing_seq` as the sequence number.
+    pub fn parse(argument: &[u8], missing_seq: u64) -> Option<StreamId> {
+        let number = |digits: &[u8]| {
+            if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            std::str::from_utf8(digits).ok()?.parse().ok()
+        };
+
+        match argument.iter().position(|&byte| byte == b'-') {
+            Some(dash) => Some(StreamId::new(
+                number(&argument[..dash])?,
+                number(&argument[dash + 1..])?,
+            )),
+            None => Some(StreamId::new(number(argument)?, missing_seq)),
+        }
+    }
+
+    /// The smallest ID greater than this one, `None` for the largest possible ID.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The largest ID smaller than this one, `None` for 0-0.
+    pub fn previous(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+
+    /// The 128 bits big endian form, as stored in RDB files.
+    pub fn to_bytes(self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.ms.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.seq.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        let (ms, seq) = bytes.split_at(8);
+        StreamId::new(
+            u64::from_be_bytes(ms.try_into().expect("8 bytes")),
+            u64::from_be_bytes(seq.try_into().expect("8 bytes")),
+        )
+    }
+}
+
+impl Display for StreamId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The field value pairs of an entry, in the order they were given.
+pub type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// An append only log of entries ordered by ID, read by consumer groups.
+#[derive(Default, Clone)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    /// The largest ID ever added: new entries need a greater one, even once it was
+    /// trimmed.
+    last_id: StreamId,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+/// Consumers sharing the entries of a stream, each entry delivered to one of them until
+/// it is acknowledged.
+#[derive(Default, Clone)]
+pub struct ConsumerGroup {
+    /// The ID of the last entry delivered to one of the consumers.
+    pub last_delivered: StreamId,
+    /// The entries delivered and not acknowledged yet.
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+#[derive(Clone)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    pub delivered_at: SystemTime,
+    pub deliveries: u64,
+}
+
+#[derive(Clone)]
+pub struct Consumer {
+    /// When the consumer last read or claimed entries.
+    pub seen_at: SystemTime,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn set_last_id(&mut self, id: StreamId) {
+        self.last_id = id;
+    }
+
+    /// The ID of the newest entry still in the stream.
+    pub fn last_entry_id(&self) -> Option<StreamId> {
+        self.entries.keys().next_back().copied()
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<&Fields> {
+        self.entries.get(&id)
+    }
+
+    /// The ID of an entry added at the unix time `ms`: the time itself when it is past
+    /// the last ID, otherwise the next sequence number. `None` once every ID is used.
+    pub fn next_id(&self, ms: u64) -> Option<StreamId> {
+        if ms > self.last_id.ms {
+            Some(StreamId::new(ms, 0))
+        } else {
+            self.last_id.next()
+        }
+    }
+
+    /// Adds an entry, with an ID greater than the last one.
+    pub fn add(&mut self, id: StreamId, fields: Fields) {
+        debug_assert!(id > self.last_id);
+        self.entries.insert(id, fields);
+        self.last_id = id;
+    }
+
+    /// Deletes the oldest entries until at most `max_len` remain, returning how many were
+    /// deleted.
+    pub fn trim_to_len(&mut self, max_len: usize) -> usize {
+        let deleted = self.entries.len().saturating_sub(max_len);
+        for _ in 0..deleted {
+            self.entries.pop_first();
+        }
+        deleted
+    }
+
+    /// Deletes the entries with an ID smaller than `min_id`, returning how many were
+    /// deleted.
+    pub fn trim_before(&mut self, min_id: StreamId) -> usize {
+        let kept = self.entries.split_off(&min_id);
+        let deleted = self.entries.len();
+        self.entries = kept;
+        deleted
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (StreamId, &Fields)> {
+        self.entries.iter().map(|(id, fields)| (*id, fields))
+    }
+
+    /// The entries with an ID within `first` and `last` included, in ascending order.
+    pub fn range(
+        &self,
+        first: StreamId,
+        last: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (StreamId, &Fields)> {
+        // the range of a BTreeMap panics when it starts past its end
+        let valid = first <= last;
+        self.entries
+            .range(first..=last.max(first))
+            .filter(move |_| valid)
+            .map(|(id, fields)| (*id, fields))
+    }
+
+    /// The entries with an ID greater than `id`.
+    pub fn after(&self, id: StreamId) -> impl DoubleEndedIterator<Item = (StreamId, &Fields)> {
+        let first = id.next();
+        self.range(first.unwrap_or(StreamId::MAX), StreamId::MAX)
+            .filter(move |_| first.is_some())
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&[u8], &ConsumerGroup)> {
+        self.groups
+            .iter()
+            .map(|(name, group)| (name.as_slice(), group))
+    }
+
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Creates a group having been delivered the entries up to `last_delivered`,
+    /// returning false when it already exists.
+    pub fn create_group(&mut self, name: &[u8], last_delivered: StreamId) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        self.groups.insert(
+            name.to_vec(),
+            ConsumerGroup {
+                last_delivered,
+                ..ConsumerGroup::default()
+            },
+        );
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+}
+
+impl ConsumerGroup {
+    /// Marks `consumer` as seen at `now`, creating it when needed. Returns whether it
+    /// was created.
+    pub fn see(&mut self, consumer: &[u8], now: SystemTime) -> bool {
+        match self.consumers.get_mut(consumer) {
+            Some(existing) => {
+                existing.seen_at = now;
+                false
+            }
+            None => {
+                self.consumers
+                    .insert(consumer.to_vec(), Consumer { seen_at: now });
+                true
+            }
+        }
+    }
+
+    /// Records that entry `id` was delivered to `consumer` at `now`, one more time if it
+    /// was already pending.
+    pub fn deliver(&mut self, id: StreamId, consumer: &[u8], now: SystemTime) {
+        let deliveries = self
+            .pending
+            .get(&id)
+            .map_or(0, |pending| pending.deliveries);
+        self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_vec(),
+                delivered_at: now,
+                deliveries: deliveries + 1,
+            },
+        );
+    }
+
+    /// The entries pending for `consumer`, in ascending order.
+    pub fn pending_of<'a>(
+        &'a self,
+        consumer: &'a [u8],
+    ) -> impl Iterator<Item = (StreamId, &'a PendingEntry)> {
+        self.pending
+            .iter()
+            .filter(move |(_, pending)| pending.consumer == consumer)
+            .map(|(id, pending)| (*id, pending))
+    }
+
+    /// Deletes a consumer along with its pending entries, returning how many it had.
+    pub fn remove_consumer(&mut self, consumer: &[u8]) -> Option<usize> {
+        self.consumers.remove(consumer)?;
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, pending| pending.consumer != consumer);
+        Some(before - self.pending.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &str) -> Fields {
+        vec![(b"f".to_vec(), value.as_bytes().to_vec())]
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(StreamId::parse(b"5-3", 0), Some(StreamId::new(5, 3)));
+        assert_eq!(
+            StreamId::parse(b"5", u64::MAX),
+            Some(StreamId::new(5, u64::MAX))
+        );
+        assert_eq!(StreamId::parse(b"5-", 0), None);
+        assert_eq!(StreamId::parse(b"-1", 0), None);
+        assert_eq!(StreamId::parse(b"1-2-3", 0), None);
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(
+            StreamId::new(2, 0).previous(),
+            Some(StreamId::new(1, u64::MAX))
+        );
+        assert_eq!(StreamId::MIN.previous(), None);
+        let id = StreamId::new(1_700_000_000_000, 7);
+        assert_eq!(StreamId::from_bytes(id.to_bytes()), id);
+        assert_eq!(id.to_string(), "1700000000000-7");
+    }
+
+    #[test]
+    fn add_range_and_trim() {
+        let mut stream = Stream::default();
+        assert_eq!(stream.next_id(10), Some(StreamId::new(10, 0)));
+        for value in ["a", "b", "c"] {
+            let id = stream.next_id(10).unwrap();
+            stream.add(id, fields(value));
+        }
+        assert_eq!(stream.last_id(), StreamId::new(10, 2));
+        // a clock going backwards keeps the IDs increasing
+        assert_eq!(stream.next_id(5), Some(StreamId::new(10, 3)));
+
+        let ids = |entries: Vec<(StreamId, &Fields)>| {
+            entries
+                .into_iter()
+                .map(|(id, _)| id.seq)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(stream.after(StreamId::new(10, 0)).collect()),
+            vec![1, 2]
+        );
+        assert_eq!(
+            ids(stream
+                .range(StreamId::new(10, 1), StreamId::MAX)
+                .rev()
+                .collect()),
+            vec![2, 1]
+        );
+        assert!(stream.range(StreamId::MAX, StreamId::MIN).next().is_none());
+        assert!(stream.after(StreamId::MAX).next().is_none());
+
+        assert_eq!(stream.trim_before(StreamId::new(10, 1)), 1);
+        assert_eq!(stream.trim_to_len(1), 1);
+        assert_eq!(stream.len(), 1);
+        assert_eq!(stream.trim_to_len(0), 1);
+        assert!(stream.is_empty());
+        assert_eq!(stream.last_id(), StreamId::new(10, 2));
+    }
+
+    #[test]
+    fn consumer_groups() {
+        let now = SystemTime::UNIX_EPOCH;
+        let mut stream = Stream::default();
+        assert!(stream.create_group(b"g", StreamId::MIN));
+        assert!(!stream.create_group(b"g", StreamId::MAX));
+
+        let group = stream.group_mut(b"g").unwrap();
+        assert!(group.see(b"alice", now));
+        assert!(!group.see(b"alice", now));
+        group.deliver(StreamId::new(1, 0), b"alice", now);
+        group.deliver(StreamId::new(1, 0), b"alice", now);
+        group.deliver(StreamId::new(2, 0), b"bob", now);
+        assert_eq!(group.pending[&StreamId::new(1, 0)].deliveries, 2);
+        assert_eq!(group.pending_of(b"bob").count(), 1);
+        assert_eq!(group.remove_consumer(b"alice"), Some(1));
+        assert_eq!(group.remove_consumer(b"alice"), None);
+        assert_eq!(group.pending.len(), 1);
+
+        assert!(stream.destroy_group(b"g"));
+        assert!(stream.group(b"g").is_none());
+    }
+}
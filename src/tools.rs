@@ -4,6 +4,7 @@ macro_rules! tools {
         enum Tool {
             $(
                 #[tool(command = $command:expr, description = $description:expr, function = $function:path)]
+                $(#[$attr:meta])*
                 $variant:ident,
             )+
         }
@@ -11,6 +12,7 @@ macro_rules! tools {
         #[derive(Debug)]
         enum Tool {
             $(
+                $(#[$attr])*
                 $variant,
             )+
         }
@@ -19,6 +21,7 @@ macro_rules! tools {
             fn from_str(s: Option<&str>) -> Option<Self> {
                 match s {
                     $(
+                        $(#[$attr])*
                         Some($command) => Some(Tool::$variant),
                     )+
                     Some(other) => panic!("Tool [{}] not configured", other),
@@ -29,6 +32,7 @@ macro_rules! tools {
             fn list() {
                 println!("Tools:");
                 $(
+                    $(#[$attr])*
                     println!("{}", $description);
                 )+
             }
@@ -50,6 +54,7 @@ macro_rules! tools {
             match tool {
                 Some(tool) => match (match tool {
                     $(
+                        $(#[$attr])*
                         Tool::$variant => $function(&args.iter().skip(1).map(|s| &**s).collect::<Vec<&str>>()),
                     )+
                 }) {
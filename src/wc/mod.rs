@@ -43,7 +43,7 @@ fn wc_cli_impl(args: &[&str], stdin: impl Read, mut stdout: impl Write) -> Resul
         write!(stdout, "{}", filepath)?;
     }
 
-    writeln!(stdout, "")?;
+    writeln!(stdout)?;
 
     Ok(())
 }
@@ -162,66 +162,66 @@ mod test {
         // All true
         let cli_options = WcCliOptions::from_args(&["-c", "-l", "-w", "-m", "test.txt"])?;
         assert_eq!(cli_options.filepath, Some("test.txt"));
-        assert_eq!(cli_options.options().bytes, true);
-        assert_eq!(cli_options.options().lines, true);
-        assert_eq!(cli_options.options().words, true);
-        assert_eq!(cli_options.options().characters, true);
+        assert!(cli_options.options().bytes);
+        assert!(cli_options.options().lines);
+        assert!(cli_options.options().words);
+        assert!(cli_options.options().characters);
 
         // No filename
         let cli_options = WcCliOptions::from_args(&["-c", "-l", "-w", "-m"])?;
         assert_eq!(cli_options.filepath, None);
-        assert_eq!(cli_options.options().bytes, true);
-        assert_eq!(cli_options.options().lines, true);
-        assert_eq!(cli_options.options().words, true);
-        assert_eq!(cli_options.options().characters, true);
+        assert!(cli_options.options().bytes);
+        assert!(cli_options.options().lines);
+        assert!(cli_options.options().words);
+        assert!(cli_options.options().characters);
 
         // Only lines
         let cli_options = WcCliOptions::from_args(&["-l", "test.txt"])?;
         assert_eq!(cli_options.filepath, Some("test.txt"));
-        assert_eq!(cli_options.options().bytes, false);
-        assert_eq!(cli_options.options().lines, true);
-        assert_eq!(cli_options.options().words, false);
-        assert_eq!(cli_options.options().characters, false);
+        assert!(!cli_options.options().bytes);
+        assert!(cli_options.options().lines);
+        assert!(!cli_options.options().words);
+        assert!(!cli_options.options().characters);
 
         // Only bytes
         let cli_options = WcCliOptions::from_args(&["-c", "test.txt"])?;
         assert_eq!(cli_options.filepath, Some("test.txt"));
-        assert_eq!(cli_options.options().bytes, true);
-        assert_eq!(cli_options.options().lines, false);
-        assert_eq!(cli_options.options().words, false);
-        assert_eq!(cli_options.options().characters, false);
+        assert!(cli_options.options().bytes);
+        assert!(!cli_options.options().lines);
+        assert!(!cli_options.options().words);
+        assert!(!cli_options.options().characters);
 
         // Only words
         let cli_options = WcCliOptions::from_args(&["-w", "test.txt"])?;
         assert_eq!(cli_options.filepath, Some("test.txt"));
-        assert_eq!(cli_options.options().bytes, false);
-        assert_eq!(cli_options.options().lines, false);
-        assert_eq!(cli_options.options().words, true);
-        assert_eq!(cli_options.options().characters, false);
+        assert!(!cli_options.options().bytes);
+        assert!(!cli_options.options().lines);
+        assert!(cli_options.options().words);
+        assert!(!cli_options.options().characters);
 
         // Only characters
         let cli_options = WcCliOptions::from_args(&["-m", "test.txt"])?;
         assert_eq!(cli_options.filepath, Some("test.txt"));
-        assert_eq!(cli_options.options().bytes, false);
-        assert_eq!(cli_options.options().lines, false);
-        assert_eq!(cli_options.options().words, false);
-        assert_eq!(cli_options.options().characters, true);
+        assert!(!cli_options.options().bytes);
+        assert!(!cli_options.options().lines);
+        assert!(!cli_options.options().words);
+        assert!(cli_options.options().characters);
 
         // Only filepath should have default options
         let cli_options = WcCliOptions::from_args(&["test.txt"])?;
         assert_eq!(cli_options.filepath, Some("test.txt"));
-        assert_eq!(cli_options.options().bytes, true);
-        assert_eq!(cli_options.options().lines, true);
-        assert_eq!(cli_options.options().words, true);
-        assert_eq!(cli_options.options().characters, false);
+        assert!(cli_options.options().bytes);
+        assert!(cli_options.options().lines);
+        assert!(cli_options.options().words);
+        assert!(!cli_options.options().characters);
 
         // All true in inverted order
         let cli_options = WcCliOptions::from_args(&["test.txt", "-l", "-c", "-w", "-m"])?;
         assert_eq!(cli_options.filepath, Some("test.txt"));
-        assert_eq!(cli_options.options().bytes, true);
-        assert_eq!(cli_options.options().lines, true);
-        assert_eq!(cli_options.options().words, true);
-        assert_eq!(cli_options.options().characters, true);
+        assert!(cli_options.options().bytes);
+        assert!(cli_options.options().lines);
+        assert!(cli_options.options().words);
+        assert!(cli_options.options().characters);
 
         Ok(())
     }
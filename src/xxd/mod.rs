@@ -66,7 +66,7 @@ fn xxd_to_hex(
     let mut offset = options.start_offset;
     let grouping = options
         .grouping
-        .unwrap_or_else(|| if options.little_endian { 4 } else { 2 }) as usize;
+        .unwrap_or(if options.little_endian { 4 } else { 2 }) as usize;
     let mut octets_to_output = options.octets_to_output;
 
     if options.little_endian && grouping != 2 && grouping != 4 && grouping != 8 {
@@ -74,7 +74,7 @@ fn xxd_to_hex(
     }
 
     let mut buffer = vec![0; offset];
-    input.read(&mut buffer)?;
+    let _ = input.read(&mut buffer)?;
 
     loop {
         let mut buffer = [0; 65536];
@@ -85,7 +85,7 @@ fn xxd_to_hex(
                 bytes_read = *octets_to_output;
             }
 
-            *octets_to_output = *octets_to_output - bytes_read;
+            *octets_to_output -= bytes_read;
         }
 
         if bytes_read == 0 {
@@ -128,11 +128,11 @@ fn xxd_to_hex(
                     };
                 }
             } else {
-                for i in 0..buffer.len() {
+                for (i, byte) in buffer.iter().enumerate() {
                     if i % grouping == 0 {
                         write!(output, " ")?;
                     }
-                    write!(output, "{:02x}", buffer[i])?;
+                    write!(output, "{:02x}", byte)?;
                 }
             }
 
@@ -153,7 +153,7 @@ fn xxd_to_hex(
                 }
             }
 
-            write!(output, "\n")?;
+            writeln!(output)?;
         }
     }
 
@@ -164,7 +164,7 @@ fn xxd_to_binary(input: impl Read, mut output: impl Write) -> Result<(), MyOwnEr
     let mut reader = BufReader::new(input);
     loop {
         let mut prefix = [0; 10];
-        reader.read(&mut prefix)?;
+        let _ = reader.read(&mut prefix)?;
 
         let mut buffer = Vec::new();
         let bytes_read = reader.read_until(b'\n', &mut buffer)?;
@@ -1,4 +1,9 @@
-use std::{error::Error, num::ParseIntError, string::FromUtf8Error};
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    num::ParseIntError,
+    string::FromUtf8Error,
+};
 
 macro_rules! ActualError {
     ($e:ty) => {
@@ -17,6 +22,18 @@ pub enum MyOwnError {
     ActualErrorWithDescription(Box<dyn Error>, String),
 }
 
+impl Display for MyOwnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MyOwnError::EarlyExit => write!(f, "Broken pipe"),
+            MyOwnError::ActualError(e) => write!(f, "{}", e),
+            MyOwnError::ActualErrorWithDescription(e, description) => {
+                write!(f, "{}: {}", description, e)
+            }
+        }
+    }
+}
+
 pub trait DescribableError<T, E: Error> {
     fn describe_error<TDescription: Into<String>>(
         self,
@@ -24,7 +41,7 @@ pub trait DescribableError<T, E: Error> {
     ) -> Result<T, MyOwnError>;
 }
 
-impl<'a, T, E: Error + 'static> DescribableError<T, E> for Result<T, E> {
+impl<T, E: Error + 'static> DescribableError<T, E> for Result<T, E> {
     fn describe_error<TDescription: Into<String>>(
         self,
         description: TDescription,